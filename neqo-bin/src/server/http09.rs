@@ -233,7 +233,11 @@ impl super::HttpServer for HttpServer {
                     ConnectionEvent::StateChange(_)
                     | ConnectionEvent::SendStreamCreatable { .. }
                     | ConnectionEvent::SendStreamComplete { .. }
-                    | ConnectionEvent::PathMigrated { .. } => (),
+                    | ConnectionEvent::SendStreamExpired { .. }
+                    | ConnectionEvent::SendBudgetAvailable
+                    | ConnectionEvent::PathMigrated { .. }
+                    | ConnectionEvent::PathFailover { .. }
+                    | ConnectionEvent::VersionNegotiated(_) => (),
                     e => qwarn!("unhandled event {e:?}"),
                 }
             }
@@ -967,9 +967,13 @@ impl Http3Client {
                     self.base_handler.handle_datagram(dgram);
                 }
                 ConnectionEvent::SendStreamComplete { .. }
+                | ConnectionEvent::SendStreamExpired { .. }
+                | ConnectionEvent::SendBudgetAvailable
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
                 | ConnectionEvent::SconeUpdated(_)
-                | ConnectionEvent::PathMigrated { .. } => {}
+                | ConnectionEvent::PathMigrated { .. }
+                | ConnectionEvent::PathFailover { .. }
+                | ConnectionEvent::VersionNegotiated(_) => {}
             }
         }
         Ok(())
@@ -289,10 +289,14 @@ impl Http3ServerHandler {
                 | ConnectionEvent::ZeroRttRejected
                 | ConnectionEvent::ResumptionToken(..) => return Err(Error::HttpInternal(4)),
                 ConnectionEvent::SendStreamComplete { .. }
+                | ConnectionEvent::SendStreamExpired { .. }
+                | ConnectionEvent::SendBudgetAvailable
                 | ConnectionEvent::SendStreamCreatable { .. }
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
                 | ConnectionEvent::SconeUpdated(_)
-                | ConnectionEvent::PathMigrated { .. } => {}
+                | ConnectionEvent::PathMigrated { .. }
+                | ConnectionEvent::PathFailover { .. }
+                | ConnectionEvent::VersionNegotiated(_) => {}
             }
         }
         Ok(())
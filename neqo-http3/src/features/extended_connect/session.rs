@@ -425,7 +425,7 @@ impl Session {
         self.protocol.write_datagram_prefix(&mut dgram_data);
         dgram_data.encode(buf);
 
-        conn.send_datagram(dgram_data.into(), id)?;
+        conn.send_datagram(dgram_data.into(), id, now)?;
         qtrace!("[{self}] sent datagram via QUIC datagram");
         Ok(())
     }
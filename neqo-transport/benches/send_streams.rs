@@ -16,8 +16,8 @@ use neqo_common::{Encoder, to_u64};
 use neqo_transport::{
     ConnectionEvents, FrameStats, SenderFlowControl,
     packet::{self, Builder},
-    recovery,
-    send_stream::{SendStream, SendStreams, TransmissionPriority},
+    recovery::{self, StreamRecoveryToken, Token},
+    send_stream::{SendChunk, SendStream, SendStreams, TransmissionPriority},
     stream_id::StreamId,
     streams::SendGroupId,
 };
@@ -210,6 +210,80 @@ fn write_frames_3_groups_9_sendordered(c: &mut Criterion) {
     });
 }
 
+const LARGE_STREAM_DATA: usize = 1 << 18; // 256 KiB
+
+/// Make a stream with enough credit for [`LARGE_STREAM_DATA`].
+fn make_large_stream() -> SendStreams {
+    let conn_fc = Rc::new(RefCell::new(SenderFlowControl::new((), u64::MAX)));
+    let mut ss = SendStreams::default();
+    let id = StreamId::from(0);
+    let s = SendStream::new(id, MAX_STREAM_DATA, conn_fc, ConnectionEvents::default());
+    ss.insert(id, s);
+    ss
+}
+
+/// Write all of the buffered data into packets, then acknowledge all of it.
+fn write_and_ack_all(ss: &mut SendStreams) {
+    let mut tokens = recovery::Tokens::new();
+    let mut stats = FrameStats::default();
+    loop {
+        let before = tokens.len();
+        let mut builder = Builder::short(Encoder::default(), false, None::<&[u8]>, packet::LIMIT);
+        ss.write_frames(
+            TransmissionPriority::default(),
+            &mut builder,
+            &mut tokens,
+            &mut stats,
+        );
+        black_box(builder);
+        if tokens.len() == before {
+            break;
+        }
+    }
+    for t in &tokens {
+        if let Token::Stream(StreamRecoveryToken::Stream(t)) = t {
+            ss.acked(t);
+        }
+    }
+}
+
+/// Copy a large buffer into a stream with `SendStream::send`, then send and acknowledge it.
+fn send_large_copied(c: &mut Criterion) {
+    let data = vec![0x5a; LARGE_STREAM_DATA];
+    c.bench_function("SendStream::send 256KiB copied", |b| {
+        b.iter_batched_ref(
+            make_large_stream,
+            |ss| {
+                let s = ss.get_mut(StreamId::from(0)).expect("stream exists");
+                assert_eq!(s.send(&data).expect("send failed"), LARGE_STREAM_DATA);
+                write_and_ack_all(ss);
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+/// Pass a large shared buffer to a stream with `SendStream::send_chunk`,
+/// then send and acknowledge it.
+fn send_large_shared(c: &mut Criterion) {
+    let data: Rc<[u8]> = Rc::from(vec![0x5a; LARGE_STREAM_DATA]);
+    c.bench_function("SendStream::send_chunk 256KiB shared", |b| {
+        b.iter_batched_ref(
+            make_large_stream,
+            |ss| {
+                let s = ss.get_mut(StreamId::from(0)).expect("stream exists");
+                let mut chunk = SendChunk::new(Rc::clone(&data));
+                assert_eq!(
+                    s.send_chunk(&mut chunk).expect("send failed"),
+                    LARGE_STREAM_DATA
+                );
+                write_and_ack_all(ss);
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
 criterion_group! {
     name = benches;
     config = { neqo_common::log::init(None); Criterion::default() };
@@ -220,6 +294,8 @@ criterion_group! {
         write_frames_20_fair_all_active,
         write_frames_3_groups_9_streams,
         write_frames_5_sendordered,
-        write_frames_3_groups_9_sendordered
+        write_frames_3_groups_9_sendordered,
+        send_large_copied,
+        send_large_shared
 }
 criterion_main!(benches);
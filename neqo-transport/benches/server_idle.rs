@@ -0,0 +1,98 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![expect(
+    clippy::significant_drop_tightening,
+    reason = "Inherent in codspeed criterion_group! macro."
+)]
+
+use std::{cell::RefCell, hint::black_box, rc::Rc};
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use neqo_transport::{Connection, ConnectionParameters, DatagramTracking, State, server::Server};
+use nss::AllowZeroRtt;
+use test_fixture::{CountingConnectionIdGenerator, default_client, maybe_authenticate, now};
+
+/// The number of idle connections that the server holds.
+const IDLE_CONNECTIONS: [usize; 2] = [100, 1_000];
+
+fn new_server() -> Server {
+    Server::new(
+        now(),
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        test_fixture::anti_replay(),
+        Box::new(AllowZeroRtt {}),
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default())),
+        ConnectionParameters::default(),
+    )
+    .expect("should create a server")
+}
+
+/// Complete a handshake with `server`, then let both ends go quiet.
+fn connect(server: &mut Server) -> Connection {
+    let mut client = default_client();
+    let mut dgram = None;
+    while *client.state() != State::Confirmed {
+        _ = maybe_authenticate(&mut client);
+        let out = client.process(dgram, now());
+        dgram = server.process(out.dgram(), now()).dgram();
+    }
+    while let Some(d) = dgram {
+        let out = client.process(Some(d), now());
+        dgram = server.process(out.dgram(), now()).dgram();
+    }
+    client
+}
+
+/// A server with `n` idle connections, plus the client for one of them.
+fn idle_server(n: usize) -> (Server, Connection) {
+    let mut server = new_server();
+    let client = connect(&mut server);
+    for _ in 1..n {
+        connect(&mut server);
+    }
+    while server.process_output(now()).dgram().is_some() {}
+    (server, client)
+}
+
+/// `process_output()` when no connection has anything to do.  This should not
+/// depend on the number of connections.
+fn server_process_output_idle(c: &mut Criterion) {
+    for n in IDLE_CONNECTIONS {
+        let (mut server, _client) = idle_server(n);
+        c.bench_function(&format!("Server::process_output {n} idle"), |b| {
+            b.iter(|| black_box(server.process_output(now())));
+        });
+    }
+}
+
+/// `process()` for a datagram on one connection, with many others idle.
+fn server_process_input_idle(c: &mut Criterion) {
+    for n in IDLE_CONNECTIONS {
+        let (mut server, mut client) = idle_server(n);
+        c.bench_function(&format!("Server::process {n} idle"), |b| {
+            b.iter_batched(
+                || {
+                    // A DATAGRAM frame is not subject to flow control.
+                    client
+                        .send_datagram(vec![0; 100], DatagramTracking::None, now())
+                        .expect("datagram queued");
+                    client.process_output(now()).dgram().expect("a datagram")
+                },
+                |d| black_box(server.process(Some(d), now())),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    server_process_output_idle,
+    server_process_input_idle
+);
+criterion_main!(benches);
@@ -7,20 +7,21 @@
 // This file implements functions necessary for address validation.
 
 use std::{
+    fmt::{self, Debug, Formatter},
     net::{IpAddr, SocketAddr},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use neqo_common::{Buffer, Decoder, Encoder, Role, expect_usize, qinfo, qtrace};
+use neqo_common::{Buffer, Decoder, Encoder, Role, expect_usize, qdebug, qinfo, qtrace};
 use nss::{
-    constants::{TLS_AES_128_GCM_SHA256, TLS_VERSION_1_3},
-    selfencrypt::SelfEncrypt,
+    Mode, RecordProtection as Aead, RecordProtectionOps as _, SymKey, TLS_AES_128_GCM_SHA256,
+    TLS_VERSION_1_3, hkdf, random,
 };
 use smallvec::SmallVec;
 use static_assertions::const_assert;
 
 use crate::{
-    Res,
+    Error, Res,
     cid::ConnectionId,
     frame::{FrameEncoder as _, FrameType},
     packet, recovery,
@@ -44,6 +45,18 @@ const MAX_NEW_TOKEN: usize = 4;
 /// retransmissions.  It should be at least `MAX_NEW_TOKEN`.
 const MAX_SAVED_TOKENS: usize = 8;
 
+/// How long a Retry token is valid for.
+const EXPIRATION_RETRY: Duration = Duration::from_secs(5);
+/// How long a `NEW_TOKEN` token is valid for.
+const EXPIRATION_NEW_TOKEN: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// The default interval between rotations of the keys that protect tokens.
+pub const DEFAULT_KEY_ROTATION_PERIOD: Duration = EXPIRATION_NEW_TOKEN;
+/// The default time for which the previous key is accepted after a rotation.
+/// This is long enough that a `NEW_TOKEN` token minted just before the
+/// rotation remains usable for its entire lifetime.
+pub const DEFAULT_KEY_GRACE_PERIOD: Duration = EXPIRATION_NEW_TOKEN;
+
 /// `ValidateAddress` determines what sort of address validation is performed.
 /// In short, this determines when a Retry packet is sent.
 #[derive(Debug, PartialEq, Eq)]
@@ -58,29 +71,231 @@ pub enum ValidateAddress {
 
 pub enum AddressValidationResult {
     Pass,
+    /// A valid `NEW_TOKEN` token validated the address.
+    ValidToken,
     ValidRetry(ConnectionId),
     Validate,
     Invalid,
 }
 
+/// A key that protects Retry and `NEW_TOKEN` tokens.
+///
+/// Each token carries the identifier of the key that protected it, so that
+/// a server can hold several keys at once.  Servers that are configured with
+/// the same keys (identifier and secret) can validate tokens that were minted
+/// by any of them.
+pub struct TokenKey {
+    /// The identifier that is carried in tokens.
+    id: u8,
+    /// The secret from which per-token keys are derived.
+    secret: SymKey,
+}
+
+impl TokenKey {
+    /// The length of the random salt that is included in each token.
+    const SALT_LEN: usize = 16;
+    /// The label used when deriving per-token keys.
+    const LABEL_PREFIX: &'static str = "neqo token ";
+    /// The length of the secrets that are generated for rotation.
+    const SECRET_LEN: usize = 32;
+
+    /// Create a key from a secret that is shared with other servers.
+    ///
+    /// Token expiry times are encoded as wall-clock time, so servers that
+    /// share this key need to have reasonably well synchronized clocks.
+    ///
+    /// # Errors
+    ///
+    /// When the secret can't be imported.
+    pub fn new(id: u8, secret: &[u8]) -> Res<Self> {
+        Ok(Self {
+            id,
+            secret: hkdf::import_key(TLS_VERSION_1_3, secret)?,
+        })
+    }
+
+    /// Generate a random key.
+    fn generate(id: u8) -> Res<Self> {
+        Self::new(id, &random::<{ Self::SECRET_LEN }>())
+    }
+
+    /// The identifier of this key.
+    #[must_use]
+    pub const fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Create an AEAD for a single token, keyed with the salt from that token.
+    fn aead(&self, salt: &[u8], mode: Mode) -> Res<Aead> {
+        let salt = hkdf::import_key(TLS_VERSION_1_3, salt)?;
+        let secret = hkdf::extract(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            Some(&salt),
+            &self.secret,
+        )?;
+        Ok(Aead::new(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            &secret,
+            Self::LABEL_PREFIX,
+            mode,
+        )?)
+    }
+
+    /// Protect `plaintext`, producing the key identifier, a salt, and the ciphertext.
+    /// Using a fresh key for each token means that servers sharing a key
+    /// don't need to coordinate on nonces.
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Res<Vec<u8>> {
+        let salt = random::<{ Self::SALT_LEN }>();
+        let aead = self.aead(&salt, Mode::Encrypt)?;
+        let offset = 1 + Self::SALT_LEN;
+        let mut sealed = vec![0; offset + plaintext.len() + aead.expansion()];
+        sealed[0] = self.id;
+        sealed[1..offset].copy_from_slice(&salt);
+        let len = aead
+            .encrypt(0, aad, plaintext, &mut sealed[offset..])?
+            .len();
+        sealed.truncate(offset + len);
+        Ok(sealed)
+    }
+
+    /// Remove protection from the output of `seal`, minus the key identifier.
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Res<Vec<u8>> {
+        if sealed.len() < Self::SALT_LEN {
+            return Err(Error::InvalidToken);
+        }
+        let (salt, ciphertext) = sealed.split_at(Self::SALT_LEN);
+        let aead = self.aead(salt, Mode::Decrypt)?;
+        let mut plaintext = vec![0; ciphertext.len()];
+        let len = aead.decrypt(0, aad, ciphertext, &mut plaintext)?.len();
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+impl Debug for TokenKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "TokenKey {}", self.id)
+    }
+}
+
 pub struct AddressValidation {
     /// What sort of validation is performed.
     validation: ValidateAddress,
-    /// A self-encryption object used for protecting Retry tokens.
-    self_encrypt: SelfEncrypt,
-    /// When this object was created.
-    start_time: Instant,
+    /// The keys used for protecting tokens.  The first is used for new tokens;
+    /// all of them are accepted when validating tokens.
+    keys: Vec<TokenKey>,
+    /// How often keys are rotated, or `None` if the application provided them.
+    rotation_period: Option<Duration>,
+    /// How long the previous key is accepted after a rotation.
+    grace_period: Duration,
+    /// When to stop accepting the previous key.
+    retire_previous: Option<Instant>,
+    /// When the current key was generated.
+    generated: Instant,
+    /// The number of times that keys have changed.
+    rotations: usize,
+    /// A reference point for converting `Instant` to wall-clock time,
+    /// which is what token expiry times are encoded in.
+    wall_clock: (Instant, SystemTime),
 }
 
 impl AddressValidation {
     pub fn new(now: Instant, validation: ValidateAddress) -> Res<Self> {
         Ok(Self {
             validation,
-            self_encrypt: SelfEncrypt::new(TLS_VERSION_1_3, TLS_AES_128_GCM_SHA256)?,
-            start_time: now,
+            keys: vec![TokenKey::generate(0)?],
+            rotation_period: Some(DEFAULT_KEY_ROTATION_PERIOD),
+            grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            retire_previous: None,
+            generated: now,
+            rotations: 0,
+            wall_clock: (now, SystemTime::now()),
         })
     }
 
+    /// Convert `t` to milliseconds since the UNIX epoch.
+    fn unix_millis(&self, t: Instant) -> u64 {
+        let (base, wall) = self.wall_clock;
+        let wall = if t >= base {
+            wall.checked_add(t - base)
+        } else {
+            wall.checked_sub(base - t)
+        };
+        wall.and_then(|w| w.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Set how often keys are generated and for how long the previous key
+    /// remains valid.  Setting `None` stops rotation.
+    pub fn set_key_rotation(&mut self, period: Option<Duration>, grace: Duration) {
+        qtrace!("AddressValidation {self:p}: rotate every {period:?}, grace {grace:?}");
+        self.rotation_period = period;
+        self.grace_period = grace;
+    }
+
+    /// Replace the keys that protect tokens with keys from the application.
+    /// The first key is used for new tokens and all of the keys are accepted.
+    /// This stops scheduled rotation; the application is responsible for
+    /// replacing keys from then on.
+    ///
+    /// # Errors
+    ///
+    /// When no keys are provided, or when two keys have the same identifier.
+    pub fn set_keys(&mut self, keys: Vec<TokenKey>) -> Res<()> {
+        if keys.is_empty()
+            || keys
+                .iter()
+                .enumerate()
+                .any(|(i, k)| keys[..i].iter().any(|x| x.id == k.id))
+        {
+            return Err(Error::InvalidInput);
+        }
+        qdebug!("AddressValidation {self:p}: set keys {keys:?}");
+        self.keys = keys;
+        self.rotation_period = None;
+        self.retire_previous = None;
+        self.rotations += 1;
+        Ok(())
+    }
+
+    /// Rotate keys if the rotation period has passed, and drop the previous key
+    /// once its grace period ends.  Returns the identifier of the new key
+    /// if there was a rotation.
+    ///
+    /// # Errors
+    ///
+    /// When a new key can't be generated.
+    pub fn maybe_rotate(&mut self, now: Instant) -> Res<Option<u8>> {
+        let mut rotated = None;
+        if let Some(period) = self.rotation_period
+            && now >= self.generated + period
+        {
+            let key = TokenKey::generate(self.keys[0].id.wrapping_add(1))?;
+            qdebug!("AddressValidation {self:p}: rotate to key {}", key.id);
+            rotated = Some(key.id);
+            self.keys.insert(0, key);
+            self.keys.truncate(2);
+            self.retire_previous = Some(now + self.grace_period);
+            self.generated = now;
+            self.rotations += 1;
+        }
+        if self.retire_previous.is_some_and(|t| t <= now) {
+            qdebug!("AddressValidation {self:p}: retire previous key");
+            self.keys.truncate(1);
+            self.retire_previous = None;
+        }
+        Ok(rotated)
+    }
+
+    /// The number of times that keys have changed, either through scheduled
+    /// rotation or because the application provided keys.
+    #[must_use]
+    pub const fn rotations(&self) -> usize {
+        self.rotations
+    }
+
     fn encode_aad(peer_address: SocketAddr, retry: bool) -> Encoder {
         // Let's be "clever" by putting the peer's address in the AAD.
         // We don't need to encode these into the token as they should be
@@ -113,10 +328,7 @@ impl AddressValidation {
         peer_address: SocketAddr,
         now: Instant,
     ) -> Res<Vec<u8>> {
-        const EXPIRATION_RETRY: Duration = Duration::from_secs(5);
-        const EXPIRATION_NEW_TOKEN: Duration = Duration::from_secs(60 * 60 * 24);
-
-        // TODO(mt) rotate keys on a fixed schedule.
+        let key = &self.keys[0];
         let retry = dcid.is_some();
         let mut data = Encoder::default();
         let end = now
@@ -125,15 +337,14 @@ impl AddressValidation {
             } else {
                 EXPIRATION_NEW_TOKEN
             };
-        let end_millis = u32::try_from(end.duration_since(self.start_time).as_millis())?;
-        data.encode_uint(4, end_millis);
+        data.encode_uint(8, self.unix_millis(end));
         if let Some(dcid) = dcid {
             data.encode(dcid);
         }
 
         // Include the token identifier ("Retry"/~) in the AAD, then keep it for plaintext.
         let mut buf = Self::encode_aad(peer_address, retry);
-        let encrypted = self.self_encrypt.seal(buf.as_ref(), data.as_ref())?;
+        let encrypted = key.seal(buf.as_ref(), data.as_ref())?;
         #[cfg(feature = "build-fuzzing-corpus")]
         let mut corpus_data = buf.as_ref()[TOKEN_IDENTIFIER_RETRY.len()..].to_vec();
         buf.truncate(TOKEN_IDENTIFIER_RETRY.len());
@@ -182,14 +393,19 @@ impl AddressValidation {
         retry: bool,
         now: Instant,
     ) -> Option<ConnectionId> {
+        let (&id, sealed) = token.split_first()?;
+        let Some(key) = self.keys.iter().find(|k| k.id == id) else {
+            qtrace!("Token for unknown key {id}");
+            return None;
+        };
         let peer_addr = Self::encode_aad(peer_address, retry);
-        let data = self.self_encrypt.open(peer_addr.as_ref(), token).ok()?;
+        let data = key.open(peer_addr.as_ref(), sealed).ok()?;
         let mut dec = Decoder::new(&data);
         {
-            let d = dec.decode_uint::<u32>()?;
-            let end = self.start_time + Duration::from_millis(u64::from(d));
+            let end = dec.decode_uint::<u64>()?;
+            let now = self.unix_millis(now);
             if end < now {
-                qtrace!("Expired token: {end:?} vs. {now:?}");
+                qtrace!("Expired token: {end} vs. {now}");
                 return None;
             }
         }
@@ -254,7 +470,7 @@ impl AddressValidation {
                         AddressValidationResult::Validate
                     } else {
                         qinfo!("AddressValidation: valid NEW_TOKEN token; accepting");
-                        AddressValidationResult::Pass
+                        AddressValidationResult::ValidToken
                     }
                 } else {
                     panic!("AddressValidation: NEW_TOKEN token with CID {cid}");
@@ -461,9 +677,16 @@ impl NewTokenSender {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
     use neqo_common::{Encoder, Role};
+    use test_fixture::{fixture_init, now};
 
-    use super::{NewTokenFrameStatus, NewTokenSender, NewTokenState};
+    use super::{
+        AddressValidation, AddressValidationResult, EXPIRATION_RETRY, NewTokenFrameStatus,
+        NewTokenSender, NewTokenState, TokenKey, ValidateAddress,
+    };
+    use crate::{ConnectionId, Error};
 
     const ONE: &[u8] = &[1, 2, 3];
     const TWO: &[u8] = &[4, 5];
@@ -554,4 +777,111 @@ mod tests {
         assert!(!tokens.has_token());
         assert!(tokens.take_token().is_none());
     }
+
+    const PEER: SocketAddr = SocketAddr::new(
+        std::net::IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+        443,
+    );
+
+    fn validation() -> AddressValidation {
+        fixture_init();
+        AddressValidation::new(now(), ValidateAddress::Always).unwrap()
+    }
+
+    fn is_valid_retry(av: &AddressValidation, token: &[u8], t: std::time::Instant) -> bool {
+        matches!(
+            av.validate(token, PEER, t),
+            AddressValidationResult::ValidRetry(_)
+        )
+    }
+
+    #[test]
+    fn rotation_grace() {
+        const PERIOD: Duration = Duration::from_secs(60);
+        const GRACE: Duration = Duration::from_secs(2);
+        let dcid = ConnectionId::from(&[7; 8][..]);
+        let mut av = validation();
+        av.set_key_rotation(Some(PERIOD), GRACE);
+        let t = now() + PERIOD - Duration::from_secs(1);
+        assert_eq!(av.maybe_rotate(t).unwrap(), None);
+        let old = av.generate_retry_token(&dcid, PEER, t).unwrap();
+
+        let t = now() + PERIOD;
+        assert_eq!(av.maybe_rotate(t).unwrap(), Some(1));
+        assert_eq!(av.rotations(), 1);
+        let new = av.generate_retry_token(&dcid, PEER, t).unwrap();
+        assert_ne!(old[5], new[5], "key identifiers differ");
+        assert!(is_valid_retry(&av, &old, t));
+        assert!(is_valid_retry(&av, &new, t));
+
+        // Once the grace period ends, tokens from the old key are rejected,
+        // even if they haven't expired.
+        let t = t + GRACE;
+        assert!(t < now() + PERIOD + EXPIRATION_RETRY - Duration::from_secs(1));
+        assert_eq!(av.maybe_rotate(t).unwrap(), None);
+        assert!(!is_valid_retry(&av, &old, t));
+        assert!(is_valid_retry(&av, &new, t));
+    }
+
+    #[test]
+    fn shared_keys() {
+        let dcid = ConnectionId::from(&[7; 8][..]);
+        let mut a = validation();
+        let mut b = AddressValidation::new(now(), ValidateAddress::Always).unwrap();
+        let secret = [0x5a; 32];
+        a.set_keys(vec![TokenKey::new(9, &secret).unwrap()])
+            .unwrap();
+        b.set_keys(vec![
+            TokenKey::new(10, &[0xa5; 32]).unwrap(),
+            TokenKey::new(9, &secret).unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(a.rotations(), 1);
+
+        let token = a.generate_retry_token(&dcid, PEER, now()).unwrap();
+        assert!(is_valid_retry(&b, &token, now()));
+        let token = b.generate_retry_token(&dcid, PEER, now()).unwrap();
+        assert!(!is_valid_retry(&a, &token, now()));
+
+        // Application keys are not rotated.
+        let later = now() + super::DEFAULT_KEY_ROTATION_PERIOD;
+        assert_eq!(a.maybe_rotate(later).unwrap(), None);
+    }
+
+    /// Expiry times are wall-clock times, so a server with a different
+    /// monotonic clock can validate tokens from a server that shares its key.
+    #[test]
+    fn shared_keys_other_clock() {
+        const OFFSET: Duration = Duration::from_secs(60 * 60);
+        let dcid = ConnectionId::from(&[7; 8][..]);
+        let secret = [0x5a; 32];
+        let mut a = validation();
+        a.set_keys(vec![TokenKey::new(9, &secret).unwrap()])
+            .unwrap();
+        let mut b = AddressValidation::new(now() + OFFSET, ValidateAddress::Always).unwrap();
+        b.set_keys(vec![TokenKey::new(9, &secret).unwrap()])
+            .unwrap();
+
+        let token = a.generate_retry_token(&dcid, PEER, now()).unwrap();
+        assert!(is_valid_retry(&b, &token, now() + OFFSET));
+        assert!(!is_valid_retry(
+            &b,
+            &token,
+            now() + OFFSET + EXPIRATION_RETRY + Duration::from_secs(1)
+        ));
+    }
+
+    #[test]
+    fn set_keys_invalid() {
+        let mut av = validation();
+        assert_eq!(av.set_keys(Vec::new()), Err(Error::InvalidInput));
+        assert_eq!(
+            av.set_keys(vec![
+                TokenKey::new(1, &[1; 32]).unwrap(),
+                TokenKey::new(1, &[2; 32]).unwrap(),
+            ]),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(av.rotations(), 0);
+    }
 }
@@ -0,0 +1,383 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Admission control for new connections on a server.
+
+use std::{
+    collections::hash_map::Entry,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    cid::ConnectionId,
+    server::{ConnectionKey, InitialDetails},
+};
+
+/// What a server does with an Initial packet that would start a new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Accept the connection, subject to the address validation policy.
+    Accept,
+    /// Send a Retry, unless the Initial carries a valid Retry token.
+    Retry,
+    /// Refuse the connection with a `CONNECTION_REFUSED` error.
+    /// Note that the server still has to process the client's first flight
+    /// to be able to send the error, so this costs about as much as accepting
+    /// the connection.  Dropping is cheaper.
+    Refuse,
+    /// Drop the packet without responding.
+    Drop,
+}
+
+/// The peer addresses of connections that haven't completed their handshake,
+/// including those that are waiting for the rest of their `ClientHello`.
+/// These are also counted by source prefix, for the prefix lengths that the
+/// [`AdmissionPolicy`] uses, so that checking a prefix doesn't need to look at
+/// every connection.
+#[derive(Debug, Default)]
+pub struct HalfOpen {
+    peers: HashMap<ConnectionKey, IpAddr>,
+    /// Connections that are waiting for the rest of their `ClientHello`,
+    /// by the connection ID that the client chose.
+    pending: HashMap<ConnectionId, IpAddr>,
+    /// The IPv4 and IPv6 prefix lengths that are counted.
+    prefix_lens: Option<(u8, u8)>,
+    /// The number of peers with each prefix, keyed by the prefix.
+    by_prefix: HashMap<IpAddr, usize>,
+}
+
+impl HalfOpen {
+    pub fn insert(&mut self, key: ConnectionKey, peer: IpAddr) {
+        if let Some(old) = self.peers.insert(key, peer) {
+            self.uncount(old);
+        }
+        self.count(peer);
+    }
+
+    pub fn remove(&mut self, key: &ConnectionKey) {
+        if let Some(peer) = self.peers.remove(key) {
+            self.uncount(peer);
+        }
+    }
+
+    pub fn contains_key(&self, key: &ConnectionKey) -> bool {
+        self.peers.contains_key(key)
+    }
+
+    pub fn insert_pending(&mut self, dcid: ConnectionId, peer: IpAddr) {
+        if let Some(old) = self.pending.insert(dcid, peer) {
+            self.uncount(old);
+        }
+        self.count(peer);
+    }
+
+    pub fn remove_pending(&mut self, dcid: &ConnectionId) {
+        if let Some(peer) = self.pending.remove(dcid) {
+            self.uncount(peer);
+        }
+    }
+
+    /// The number of connections that haven't completed their handshake.
+    pub fn handshakes(&self) -> usize {
+        self.peers.len() + self.pending.len()
+    }
+
+    fn all_peers(&self) -> impl Iterator<Item = IpAddr> {
+        self.peers.values().chain(self.pending.values()).copied()
+    }
+
+    /// Count peers by prefix with the given IPv4 and IPv6 prefix lengths, or stop
+    /// counting them with `None`.
+    pub fn set_prefix_lens(&mut self, prefix_lens: Option<(u8, u8)>) {
+        self.prefix_lens = prefix_lens;
+        self.by_prefix.clear();
+        if let Some(lens) = prefix_lens {
+            let peers = self.peers.values().chain(self.pending.values());
+            for &peer in peers {
+                *self.by_prefix.entry(prefix(peer, lens)).or_default() += 1;
+            }
+        }
+    }
+
+    fn count(&mut self, peer: IpAddr) {
+        if let Some(lens) = self.prefix_lens {
+            *self.by_prefix.entry(prefix(peer, lens)).or_default() += 1;
+        }
+    }
+
+    fn uncount(&mut self, peer: IpAddr) {
+        if let Some(lens) = self.prefix_lens
+            && let Entry::Occupied(mut e) = self.by_prefix.entry(prefix(peer, lens))
+        {
+            *e.get_mut() -= 1;
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
+    }
+
+    /// The number of peers that share the first `prefix_len` bits with `peer`.
+    /// This is only slow if `prefix_len` isn't one of the lengths that are counted.
+    pub fn handshakes_from(&self, peer: IpAddr, prefix_len: u8) -> usize {
+        if let Some(lens) = self.prefix_lens
+            && prefix_len == family_len(peer, lens)
+        {
+            return self
+                .by_prefix
+                .get(&mask(peer, prefix_len))
+                .copied()
+                .unwrap_or(0);
+        }
+        self.all_peers()
+            .filter(|&a| same_prefix(a, peer, prefix_len))
+            .count()
+    }
+}
+
+/// The load on a server, as seen by an [`AdmissionPolicy`].
+pub struct ServerLoad<'a> {
+    /// The number of connections, in any state.
+    connections: usize,
+    /// The connections that haven't completed their handshake.
+    half_open: &'a HalfOpen,
+}
+
+impl<'a> ServerLoad<'a> {
+    pub(crate) const fn new(connections: usize, half_open: &'a HalfOpen) -> Self {
+        Self {
+            connections,
+            half_open,
+        }
+    }
+
+    /// The number of connections, in any state.
+    #[must_use]
+    pub const fn connections(&self) -> usize {
+        self.connections
+    }
+
+    /// The number of connections that haven't completed their handshake.
+    #[must_use]
+    pub fn handshakes(&self) -> usize {
+        self.half_open.handshakes()
+    }
+
+    /// The number of connections that haven't completed their handshake,
+    /// from peers with addresses that share the first `prefix_len` bits with `peer`.
+    /// This is fast for the prefix lengths from [`AdmissionPolicy::prefix_lens`].
+    #[must_use]
+    pub fn handshakes_from(&self, peer: IpAddr, prefix_len: u8) -> usize {
+        self.half_open.handshakes_from(peer, prefix_len)
+    }
+}
+
+/// Choose the IPv4 or IPv6 prefix length for `addr`.
+const fn family_len(addr: IpAddr, (v4_len, v6_len): (u8, u8)) -> u8 {
+    if addr.is_ipv4() { v4_len } else { v6_len }
+}
+
+/// The prefix of `addr`, using the prefix length for its family.
+fn prefix(addr: IpAddr, lens: (u8, u8)) -> IpAddr {
+    mask(addr, family_len(addr, lens))
+}
+
+/// Keep the first `prefix_len` bits of `addr` and clear the rest.
+fn mask(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(a) => {
+            let mask = u32::MAX
+                .checked_shl(32_u32.saturating_sub(u32::from(prefix_len)))
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+        }
+        IpAddr::V6(a) => {
+            let mask = u128::MAX
+                .checked_shl(128_u32.saturating_sub(u32::from(prefix_len)))
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(a) & mask))
+        }
+    }
+}
+
+/// Whether two addresses share the first `prefix_len` bits.
+/// Addresses from different families never match.
+fn same_prefix(a: IpAddr, b: IpAddr, prefix_len: u8) -> bool {
+    a.is_ipv4() == b.is_ipv4() && mask(a, prefix_len) == mask(b, prefix_len)
+}
+
+/// Decides what a server does with each Initial packet that would start a new connection.
+pub trait AdmissionPolicy {
+    fn admit(&mut self, initial: &InitialDetails, peer: SocketAddr, load: &ServerLoad)
+    -> Admission;
+
+    /// The IPv4 and IPv6 prefix lengths that this policy passes to
+    /// [`ServerLoad::handshakes_from`], if any.  The server keeps a count of
+    /// handshakes for each prefix of these lengths.
+    fn prefix_lens(&self) -> Option<(u8, u8)> {
+        None
+    }
+}
+
+impl<F> AdmissionPolicy for F
+where
+    F: FnMut(&InitialDetails, SocketAddr, &ServerLoad) -> Admission,
+{
+    fn admit(
+        &mut self,
+        initial: &InitialDetails,
+        peer: SocketAddr,
+        load: &ServerLoad,
+    ) -> Admission {
+        self(initial, peer, load)
+    }
+}
+
+/// A limit on the number of handshakes from a source prefix.
+#[derive(Debug, Clone, Copy)]
+struct PrefixLimit {
+    max: usize,
+    v4_len: u8,
+    v6_len: u8,
+}
+
+/// An [`AdmissionPolicy`] that limits connections that are still handshaking.
+/// Once the number of handshakes reaches a threshold, this starts to require Retry
+/// and, at a higher threshold, refuses connections.  It can also cap the number of
+/// handshakes from each source prefix, dropping Initial packets beyond that.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeLimits {
+    retry_threshold: Option<usize>,
+    refuse_threshold: Option<usize>,
+    prefix_limit: Option<PrefixLimit>,
+}
+
+impl HandshakeLimits {
+    /// Require Retry once there are at least `threshold` handshakes.
+    #[must_use]
+    pub const fn retry_threshold(mut self, threshold: usize) -> Self {
+        self.retry_threshold = Some(threshold);
+        self
+    }
+
+    /// Refuse connections once there are at least `threshold` handshakes.
+    #[must_use]
+    pub const fn refuse_threshold(mut self, threshold: usize) -> Self {
+        self.refuse_threshold = Some(threshold);
+        self
+    }
+
+    /// Drop Initial packets from a source once there are `max` handshakes from
+    /// addresses that share its IPv4 or IPv6 prefix, of the given lengths.
+    #[must_use]
+    pub const fn prefix_limit(mut self, max: usize, v4_len: u8, v6_len: u8) -> Self {
+        self.prefix_limit = Some(PrefixLimit {
+            max,
+            v4_len,
+            v6_len,
+        });
+        self
+    }
+}
+
+impl AdmissionPolicy for HandshakeLimits {
+    fn admit(&mut self, _: &InitialDetails, peer: SocketAddr, load: &ServerLoad) -> Admission {
+        if let Some(limit) = self.prefix_limit {
+            let prefix_len = if peer.is_ipv4() {
+                limit.v4_len
+            } else {
+                limit.v6_len
+            };
+            if load.handshakes_from(peer.ip(), prefix_len) >= limit.max {
+                return Admission::Drop;
+            }
+        }
+        let handshakes = load.handshakes();
+        if self.refuse_threshold.is_some_and(|t| handshakes >= t) {
+            Admission::Refuse
+        } else if self.retry_threshold.is_some_and(|t| handshakes >= t) {
+            Admission::Retry
+        } else {
+            Admission::Accept
+        }
+    }
+
+    fn prefix_lens(&self) -> Option<(u8, u8)> {
+        self.prefix_limit.map(|l| (l.v4_len, l.v6_len))
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{net::IpAddr, ptr};
+
+    use super::{HalfOpen, same_prefix};
+    use crate::{cid::ConnectionId, server::ConnectionKey};
+
+    fn key(n: usize) -> ConnectionKey {
+        ptr::without_provenance(n)
+    }
+
+    #[test]
+    fn prefixes() {
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.200".parse().unwrap();
+        let c: IpAddr = "198.51.100.1".parse().unwrap();
+        let d: IpAddr = "2001:db8::1".parse().unwrap();
+        let e: IpAddr = "2001:db8:0:1::1".parse().unwrap();
+        assert!(same_prefix(a, b, 24));
+        assert!(!same_prefix(a, b, 25));
+        assert!(!same_prefix(a, c, 8));
+        assert!(same_prefix(a, c, 0));
+        assert!(same_prefix(a, a, 32));
+        assert!(same_prefix(a, a, 200));
+        assert!(same_prefix(d, e, 48));
+        assert!(!same_prefix(d, e, 64));
+        assert!(!same_prefix(a, d, 0));
+    }
+
+    /// Counts by prefix match a scan of every peer.
+    #[test]
+    fn half_open_counts() {
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.200".parse().unwrap();
+        let c: IpAddr = "198.51.100.1".parse().unwrap();
+        let mut half_open = HalfOpen::default();
+        half_open.insert(key(1), a);
+        half_open.set_prefix_lens(Some((24, 48)));
+        half_open.insert(key(2), b);
+        half_open.insert(key(3), c);
+        assert_eq!(half_open.handshakes(), 3);
+        assert_eq!(half_open.handshakes_from(a, 24), 2);
+        assert_eq!(half_open.handshakes_from(a, 8), 2);
+        assert_eq!(half_open.handshakes_from(c, 24), 1);
+
+        half_open.remove(&key(1));
+        assert_eq!(half_open.handshakes_from(b, 24), 1);
+        half_open.remove(&key(2));
+        assert_eq!(half_open.handshakes_from(b, 24), 0);
+        assert_eq!(half_open.by_prefix.len(), 1);
+    }
+
+    /// Connections that are waiting for their `ClientHello` are counted too.
+    #[test]
+    fn half_open_pending() {
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let dcid = ConnectionId::from(&[1; 8]);
+        let mut half_open = HalfOpen::default();
+        half_open.set_prefix_lens(Some((24, 48)));
+        half_open.insert(key(1), a);
+        half_open.insert_pending(dcid.clone(), a);
+        assert_eq!(half_open.handshakes(), 2);
+        assert_eq!(half_open.handshakes_from(a, 24), 2);
+        assert_eq!(half_open.handshakes_from(a, 16), 2);
+        half_open.remove_pending(&dcid);
+        assert_eq!(half_open.handshakes(), 1);
+        assert_eq!(half_open.handshakes_from(a, 24), 1);
+    }
+}
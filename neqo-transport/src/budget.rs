@@ -0,0 +1,124 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// How much a connection can send, for applications that adapt their sending rate.
+
+use std::time::Duration;
+
+/// What the connection can send now, and the conditions that limit that, on the primary path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SendBudget {
+    /// The space in the congestion window, in bytes.
+    pub cwnd_avail: usize,
+    /// The congestion window, in bytes.
+    pub cwnd: usize,
+    /// The rate that packets are paced at, in bytes per second, if it is known.
+    pub pacing_rate: Option<u64>,
+    /// The smoothed RTT.
+    pub rtt: Duration,
+    /// The fraction of recent packets that were declared lost.
+    pub loss_rate: f64,
+    /// The fraction of recently acknowledged packets that were marked ECN-CE.
+    pub ecn_ce_rate: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    acked: usize,
+    lost: usize,
+    ce: usize,
+}
+
+impl Counts {
+    const fn total(&self) -> usize {
+        self.acked.saturating_add(self.lost)
+    }
+}
+
+/// Counts packet outcomes over the current and the previous window of packets,
+/// so that rates only reflect recent packets.
+#[derive(Debug, Default)]
+pub struct RecentLoss {
+    current: Counts,
+    previous: Counts,
+}
+
+impl RecentLoss {
+    /// The number of packets in each window.
+    const WINDOW: usize = 256;
+
+    pub fn on_packets_acked(&mut self, acked: usize, ce_marks: u64) {
+        self.current.acked = self.current.acked.saturating_add(acked);
+        let ce_marks = usize::try_from(ce_marks).unwrap_or(usize::MAX);
+        self.current.ce = self.current.ce.saturating_add(ce_marks);
+        self.maybe_rotate();
+    }
+
+    pub fn on_packets_lost(&mut self, lost: usize) {
+        self.current.lost = self.current.lost.saturating_add(lost);
+        self.maybe_rotate();
+    }
+
+    fn maybe_rotate(&mut self) {
+        if self.current.total() >= Self::WINDOW {
+            self.previous = self.current;
+            self.current = Counts::default();
+        }
+    }
+
+    #[expect(clippy::cast_precision_loss, reason = "Counts are small.")]
+    fn rate(n: usize, d: usize) -> f64 {
+        if d == 0 { 0.0 } else { n as f64 / d as f64 }
+    }
+
+    /// The fraction of packets that were lost.
+    pub fn loss_rate(&self) -> f64 {
+        Self::rate(
+            self.current.lost.saturating_add(self.previous.lost),
+            self.current.total().saturating_add(self.previous.total()),
+        )
+    }
+
+    /// The fraction of acknowledged packets that were marked ECN-CE.
+    pub fn ecn_ce_rate(&self) -> f64 {
+        Self::rate(
+            self.current.ce.saturating_add(self.previous.ce),
+            self.current.acked.saturating_add(self.previous.acked),
+        )
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::RecentLoss;
+
+    #[test]
+    fn no_packets() {
+        let loss = RecentLoss::default();
+        assert!(loss.loss_rate().abs() < f64::EPSILON);
+        assert!(loss.ecn_ce_rate().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn rates() {
+        let mut loss = RecentLoss::default();
+        loss.on_packets_acked(30, 3);
+        loss.on_packets_lost(10);
+        assert!((loss.loss_rate() - 0.25).abs() < f64::EPSILON);
+        assert!((loss.ecn_ce_rate() - 0.1).abs() < f64::EPSILON);
+    }
+
+    /// Only the last two windows of packets count.
+    #[test]
+    fn old_packets_forgotten() {
+        let mut loss = RecentLoss::default();
+        loss.on_packets_lost(RecentLoss::WINDOW);
+        assert!((loss.loss_rate() - 1.0).abs() < f64::EPSILON);
+        loss.on_packets_acked(RecentLoss::WINDOW, 0);
+        assert!((loss.loss_rate() - 0.0).abs() < f64::EPSILON);
+    }
+}
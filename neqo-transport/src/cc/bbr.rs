@@ -0,0 +1,1160 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// BBRv3 congestion control, as specified in draft-ietf-ccwg-bbr.
+
+use std::{
+    cmp::{max, min},
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, to_u64};
+use nss::random;
+
+use super::{
+    CongestionController,
+    classic_cc::{cwnd_initial, persistent_congestion},
+};
+use crate::{
+    Pmtud,
+    cc::CongestionTrigger,
+    qlog,
+    recovery::{RateSample, rate::DeliveryState, sent},
+    rtt::RttEstimate,
+    sender::PACING_BURST_SIZE,
+    stats::{CongestionControlStats, SlowStartExitReason, SlowStartExitStats},
+};
+
+/// Gains are expressed in percent.
+const GAIN_UNIT: u64 = 100;
+/// The pacing gain in Startup, 4 * ln(2).
+const STARTUP_PACING_GAIN: u64 = 277;
+/// The congestion window gain in Startup.
+const STARTUP_CWND_GAIN: u64 = 200;
+/// The pacing gain in Drain, which drains the queue created in Startup.
+const DRAIN_PACING_GAIN: u64 = 35;
+/// The pacing gain in `ProbeBW_DOWN`.
+const PROBE_BW_DOWN_PACING_GAIN: u64 = 90;
+/// The pacing gain in `ProbeBW_UP`.
+const PROBE_BW_UP_PACING_GAIN: u64 = 125;
+/// The congestion window gain in `ProbeBW_UP`.
+const PROBE_BW_UP_CWND_GAIN: u64 = 225;
+/// The congestion window gain in all other `ProbeBW` states.
+const PROBE_BW_CWND_GAIN: u64 = 200;
+/// The congestion window gain in `ProbeRTT`.
+const PROBE_RTT_CWND_GAIN: u64 = 50;
+/// The pacing rate is set this many percent below the estimated bandwidth.
+const PACING_MARGIN_PERCENT: u64 = 1;
+/// The loss rate, in percent, above which the model considers the path congested.
+const LOSS_THRESH_PERCENT: usize = 2;
+/// The multiplicative decrease, in percent, of the lower bounds on loss.
+const BETA_PERCENT: usize = 70;
+/// The fraction of `inflight_hi`, in percent, left unused to make room for other flows.
+const HEADROOM_PERCENT: usize = 15;
+/// The minimum congestion window, in packets.
+const MIN_PIPE_CWND_PKTS: usize = 4;
+/// The minimum growth of the delivery rate, in percent, per round that keeps Startup going.
+const FULL_BW_GROWTH_PERCENT: u64 = 125;
+/// The number of rounds without sufficient delivery rate growth after which Startup ends.
+const FULL_BW_COUNT: usize = 3;
+/// The number of packets lost in a round that can end Startup.
+const STARTUP_FULL_LOSS_COUNT: usize = 6;
+/// The number of rounds over which the maximum ACK aggregation is tracked.
+const EXTRA_ACKED_FILTER_ROUNDS: u32 = 5;
+/// The upper bound of the Reno-coexistence probing interval, in rounds.
+const MAX_RENO_ROUNDS: u64 = 63;
+/// The maximum exponent for the growth of `inflight_hi` in `ProbeBW_UP`.
+const MAX_PROBE_UP_ROUNDS: u32 = 30;
+/// The time that the minimum RTT estimate remains valid.
+const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+/// How often to enter `ProbeRTT` when the RTT does not drop.
+const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(5);
+/// The minimum time spent in `ProbeRTT`.
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+/// The minimum wait between bandwidth probes; a random value of up to a second is added.
+const PROBE_BW_WAIT_BASE: Duration = Duration::from_secs(2);
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// `value * gain / GAIN_UNIT`.
+fn apply_gain(value: u64, gain: u64) -> u64 {
+    u64::try_from(u128::from(value) * u128::from(gain) / u128::from(GAIN_UNIT)).unwrap_or(u64::MAX)
+}
+
+/// `value * percent / 100`, for byte counts.
+const fn percent(value: usize, percent: usize) -> usize {
+    value.saturating_mul(percent) / 100
+}
+
+/// The number of bytes delivered at `bw` bytes per second over `interval`.
+fn bytes_at(bw: u64, interval: Duration) -> usize {
+    usize::try_from(u128::from(bw) * interval.as_nanos() / NANOS_PER_SEC).unwrap_or(usize::MAX)
+}
+
+/// The rate in bytes per second at which `bytes` are delivered over `interval`.
+fn rate(bytes: usize, interval: Duration) -> Option<u64> {
+    let ns = interval.as_nanos();
+    (ns > 0)
+        .then(|| u64::try_from(u128::from(to_u64(bytes)) * NANOS_PER_SEC / ns).unwrap_or(u64::MAX))
+}
+
+/// The delivery rate of a sample, where zero means that the sample is not reliable.
+fn delivery_rate(rs: &RateSample) -> u64 {
+    rs.delivery_rate().unwrap_or(0)
+}
+
+/// The states of the BBR state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+pub enum State {
+    /// Rapidly probe for bandwidth until the pipe is full.
+    #[strum(to_string = "startup")]
+    Startup,
+    /// Drain the queue created during Startup.
+    #[strum(to_string = "drain")]
+    Drain,
+    /// Drain any queue created while probing for bandwidth.
+    #[strum(to_string = "probe_bw_down")]
+    ProbeBwDown,
+    /// Cruise at the estimated bandwidth, leaving headroom for other flows.
+    #[strum(to_string = "probe_bw_cruise")]
+    ProbeBwCruise,
+    /// Refill the pipe before probing for more bandwidth.
+    #[strum(to_string = "probe_bw_refill")]
+    ProbeBwRefill,
+    /// Probe for more bandwidth.
+    #[strum(to_string = "probe_bw_up")]
+    ProbeBwUp,
+    /// Reduce the amount of data in flight to measure the minimum RTT.
+    #[strum(to_string = "probe_rtt")]
+    ProbeRtt,
+}
+
+impl State {
+    /// The pacing and congestion window gains for this state.
+    const fn gains(self) -> (u64, u64) {
+        match self {
+            Self::Startup => (STARTUP_PACING_GAIN, STARTUP_CWND_GAIN),
+            Self::Drain => (DRAIN_PACING_GAIN, STARTUP_CWND_GAIN),
+            Self::ProbeBwDown => (PROBE_BW_DOWN_PACING_GAIN, PROBE_BW_CWND_GAIN),
+            Self::ProbeBwCruise | Self::ProbeBwRefill => (GAIN_UNIT, PROBE_BW_CWND_GAIN),
+            Self::ProbeBwUp => (PROBE_BW_UP_PACING_GAIN, PROBE_BW_UP_CWND_GAIN),
+            Self::ProbeRtt => (GAIN_UNIT, PROBE_RTT_CWND_GAIN),
+        }
+    }
+
+    pub const fn is_probe_bw(self) -> bool {
+        matches!(
+            self,
+            Self::ProbeBwDown | Self::ProbeBwCruise | Self::ProbeBwRefill | Self::ProbeBwUp
+        )
+    }
+
+    /// Whether the sender is deliberately sending faster than the estimated bandwidth.
+    const fn is_probing_bw(self) -> bool {
+        matches!(self, Self::Startup | Self::ProbeBwRefill | Self::ProbeBwUp)
+    }
+}
+
+/// Where the sender is in a bandwidth probing cycle, as seen by arriving ACKs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AckPhase {
+    Init,
+    ProbeStopping,
+    Refilling,
+    ProbeStarting,
+    ProbeFeedback,
+}
+
+/// A congestion controller implementing version 3 of BBR.
+///
+/// BBR builds a model of the path from its delivery rate and minimum RTT, and
+/// paces at the estimated bottleneck bandwidth rather than reacting to every
+/// loss.  The model is bounded by loss and ECN signals, so that BBR coexists
+/// with loss-based congestion controllers.
+#[derive(Debug)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "The state variables mirror those of the specification."
+)]
+pub struct Bbr {
+    pmtud: Pmtud,
+    qlog: Qlog,
+    state: State,
+    pacing_gain: u64,
+    cwnd_gain: u64,
+    cwnd: usize,
+    prior_cwnd: usize,
+    bytes_in_flight: usize,
+    /// The pacing rate in bytes per second, if there is an RTT sample.
+    pacing_rate: Option<u64>,
+
+    // Delivery accounting, which matches that of the delivery rate estimator.
+    /// The total bytes delivered.
+    delivered: usize,
+    /// The total bytes lost, not counting PMTUD probes.
+    lost: usize,
+
+    // Round counting.
+    next_round_delivered: usize,
+    round_start: bool,
+    rounds_since_bw_probe: u64,
+
+    // The path model.
+    /// The windowed maximum of the delivery rate, over two `ProbeBW` cycles.
+    max_bw_filter: [u64; 2],
+    max_bw: u64,
+    /// The lower bound on the bandwidth, if any.
+    bw_lo: Option<u64>,
+    /// The bandwidth used by the model, the lower of `max_bw` and `bw_lo`.
+    bw: u64,
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Instant,
+    /// The upper bound on the data in flight, if any.
+    inflight_hi: Option<usize>,
+    /// The lower bound on the data in flight, if any.
+    inflight_lo: Option<usize>,
+    max_inflight: usize,
+
+    // Congestion signals.
+    bw_latest: u64,
+    inflight_latest: usize,
+    loss_round_delivered: usize,
+    loss_round_start: bool,
+    loss_in_round: bool,
+    lost_in_round: usize,
+
+    // Startup.
+    filled_pipe: bool,
+    full_bw: u64,
+    full_bw_count: usize,
+    full_bw_now: bool,
+
+    // ProbeBW.
+    cycle_stamp: Instant,
+    ack_phase: AckPhase,
+    bw_probe_wait: Duration,
+    bw_probe_samples: bool,
+    bw_probe_up_rounds: u32,
+    bw_probe_up_acks: usize,
+    probe_up_cnt: usize,
+    is_cwnd_limited: bool,
+
+    // ProbeRTT.
+    probe_rtt_min_delay: Option<Duration>,
+    probe_rtt_min_stamp: Instant,
+    probe_rtt_expired: bool,
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    idle_restart: bool,
+
+    // ACK aggregation.
+    extra_acked: [usize; 2],
+    extra_acked_idx: usize,
+    extra_acked_rounds: u32,
+    extra_acked_interval_start: Instant,
+    extra_acked_delivered: usize,
+}
+
+impl Display for Bbr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BBRv3 [state {:?}, bw {}, min_rtt {:?}, cwnd {}, bif {}]",
+            self.state, self.bw, self.min_rtt, self.cwnd, self.bytes_in_flight
+        )
+    }
+}
+
+impl Bbr {
+    #[must_use]
+    pub fn new(pmtud: Pmtud, now: Instant) -> Self {
+        let cwnd = cwnd_initial(pmtud.plpmtu());
+        let (pacing_gain, cwnd_gain) = State::Startup.gains();
+        Self {
+            pmtud,
+            qlog: Qlog::default(),
+            state: State::Startup,
+            pacing_gain,
+            cwnd_gain,
+            cwnd,
+            prior_cwnd: cwnd,
+            bytes_in_flight: 0,
+            pacing_rate: None,
+            delivered: 0,
+            lost: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            rounds_since_bw_probe: 0,
+            max_bw_filter: [0; 2],
+            max_bw: 0,
+            bw_lo: None,
+            bw: 0,
+            min_rtt: None,
+            min_rtt_stamp: now,
+            inflight_hi: None,
+            inflight_lo: None,
+            max_inflight: cwnd,
+            bw_latest: 0,
+            inflight_latest: 0,
+            loss_round_delivered: 0,
+            loss_round_start: false,
+            loss_in_round: false,
+            lost_in_round: 0,
+            filled_pipe: false,
+            full_bw: 0,
+            full_bw_count: 0,
+            full_bw_now: false,
+            cycle_stamp: now,
+            ack_phase: AckPhase::Init,
+            bw_probe_wait: PROBE_BW_WAIT_BASE,
+            bw_probe_samples: false,
+            bw_probe_up_rounds: 0,
+            bw_probe_up_acks: 0,
+            probe_up_cnt: usize::MAX,
+            is_cwnd_limited: false,
+            probe_rtt_min_delay: None,
+            probe_rtt_min_stamp: now,
+            probe_rtt_expired: false,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            idle_restart: false,
+            extra_acked: [0; 2],
+            extra_acked_idx: 0,
+            extra_acked_rounds: 0,
+            extra_acked_interval_start: now,
+            extra_acked_delivered: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub const fn state(&self) -> State {
+        self.state
+    }
+
+    #[cfg(test)]
+    pub const fn bw(&self) -> u64 {
+        self.bw
+    }
+
+    #[cfg(test)]
+    pub const fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+
+    #[cfg(test)]
+    pub const fn inflight_hi(&self) -> Option<usize> {
+        self.inflight_hi
+    }
+
+    const fn mtu(&self) -> usize {
+        self.pmtud.plpmtu()
+    }
+
+    fn set_state(&mut self, state: State, now: Instant) {
+        if self.state == state {
+            return;
+        }
+        qdebug!("[{self}] state -> {state:?}");
+        qlog::congestion_state_updated(
+            &mut self.qlog,
+            Some(self.state.into()),
+            state.into(),
+            None,
+            now,
+        );
+        self.state = state;
+        (self.pacing_gain, self.cwnd_gain) = state.gains();
+    }
+
+    fn update_model_and_state(
+        &mut self,
+        rs: &RateSample,
+        rtt: Duration,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        self.update_latest_delivery_signals(rs);
+        self.update_congestion_signals(rs);
+        self.update_ack_aggregation(rs, now);
+        self.check_full_bw_reached(rs);
+        self.check_startup_done(now, cc_stats);
+        self.check_drain_done(now);
+        self.update_probe_bw_cycle_phase(rs, now);
+        self.update_min_rtt(rtt, now);
+        self.check_probe_rtt(rs, now);
+        self.advance_latest_delivery_signals(rs);
+        self.bound_bw_for_model();
+    }
+
+    fn update_control_parameters(&mut self, rs: &RateSample) {
+        self.set_pacing_rate(self.pacing_gain);
+        self.set_cwnd(rs);
+    }
+
+    fn update_latest_delivery_signals(&mut self, rs: &RateSample) {
+        self.loss_round_start = false;
+        self.bw_latest = max(self.bw_latest, delivery_rate(rs));
+        self.inflight_latest = max(self.inflight_latest, rs.delivered());
+        if rs.prior_delivered() >= self.loss_round_delivered {
+            self.loss_round_delivered = self.delivered;
+            self.loss_round_start = true;
+        }
+    }
+
+    fn advance_latest_delivery_signals(&mut self, rs: &RateSample) {
+        if self.loss_round_start {
+            self.bw_latest = delivery_rate(rs);
+            self.inflight_latest = rs.delivered();
+        }
+    }
+
+    fn update_congestion_signals(&mut self, rs: &RateSample) {
+        self.update_max_bw(rs);
+        if !self.loss_round_start {
+            return;
+        }
+        self.adapt_lower_bounds_from_congestion();
+        self.loss_in_round = false;
+        self.lost_in_round = 0;
+    }
+
+    const fn start_round(&mut self) {
+        self.next_round_delivered = self.delivered;
+    }
+
+    const fn update_round(&mut self, rs: &RateSample) {
+        self.round_start = rs.prior_delivered() >= self.next_round_delivered;
+        if self.round_start {
+            self.start_round();
+            self.rounds_since_bw_probe += 1;
+        }
+    }
+
+    fn update_max_bw(&mut self, rs: &RateSample) {
+        self.update_round(rs);
+        if delivery_rate(rs) >= self.max_bw || !rs.is_app_limited() {
+            self.max_bw_filter[1] = max(self.max_bw_filter[1], delivery_rate(rs));
+            self.max_bw = max(self.max_bw_filter[0], self.max_bw_filter[1]);
+        }
+    }
+
+    const fn advance_max_bw_filter(&mut self) {
+        self.max_bw_filter = [self.max_bw_filter[1], 0];
+        self.max_bw = self.max_bw_filter[0];
+    }
+
+    fn bound_bw_for_model(&mut self) {
+        self.bw = self
+            .bw_lo
+            .map_or(self.max_bw, |bw_lo| min(self.max_bw, bw_lo));
+    }
+
+    fn adapt_lower_bounds_from_congestion(&mut self) {
+        if self.state.is_probing_bw() || !self.loss_in_round {
+            return;
+        }
+        let bw_lo = self.bw_lo.unwrap_or(self.max_bw);
+        let inflight_lo = self.inflight_lo.unwrap_or(self.cwnd);
+        self.bw_lo = Some(max(self.bw_latest, apply_gain(bw_lo, to_u64(BETA_PERCENT))));
+        self.inflight_lo = Some(max(
+            self.inflight_latest,
+            percent(inflight_lo, BETA_PERCENT),
+        ));
+    }
+
+    const fn reset_lower_bounds(&mut self) {
+        self.bw_lo = None;
+        self.inflight_lo = None;
+    }
+
+    const fn reset_congestion_signals(&mut self) {
+        self.loss_in_round = false;
+        self.lost_in_round = 0;
+        self.bw_latest = 0;
+        self.inflight_latest = 0;
+    }
+
+    fn update_ack_aggregation(&mut self, rs: &RateSample, now: Instant) {
+        if self.round_start {
+            self.extra_acked_rounds += 1;
+            if self.extra_acked_rounds >= EXTRA_ACKED_FILTER_ROUNDS {
+                self.extra_acked_rounds = 0;
+                self.extra_acked_idx = 1 - self.extra_acked_idx;
+                self.extra_acked[self.extra_acked_idx] = 0;
+            }
+        }
+        // Compare the data acknowledged to what the model says should have been
+        // delivered since the start of the current aggregation interval.
+        let mut expected = bytes_at(
+            self.bw,
+            now.saturating_duration_since(self.extra_acked_interval_start),
+        );
+        if self.extra_acked_delivered <= expected {
+            self.extra_acked_delivered = 0;
+            self.extra_acked_interval_start = now;
+            expected = 0;
+        }
+        self.extra_acked_delivered += rs.newly_acked();
+        let extra = min(
+            self.extra_acked_delivered.saturating_sub(expected),
+            self.cwnd,
+        );
+        let idx = self.extra_acked_idx;
+        self.extra_acked[idx] = max(self.extra_acked[idx], extra);
+    }
+
+    fn extra_acked(&self) -> usize {
+        max(self.extra_acked[0], self.extra_acked[1])
+    }
+
+    const fn reset_full_bw(&mut self) {
+        self.full_bw = 0;
+        self.full_bw_count = 0;
+        self.full_bw_now = false;
+    }
+
+    fn check_full_bw_reached(&mut self, rs: &RateSample) {
+        if self.full_bw_now || rs.is_app_limited() {
+            return;
+        }
+        if delivery_rate(rs) >= apply_gain(self.full_bw, FULL_BW_GROWTH_PERCENT) {
+            self.reset_full_bw();
+            self.full_bw = delivery_rate(rs);
+            return;
+        }
+        if !self.round_start {
+            return;
+        }
+        self.full_bw_count += 1;
+        self.full_bw_now = self.full_bw_count >= FULL_BW_COUNT;
+    }
+
+    fn check_startup_done(&mut self, now: Instant, cc_stats: &mut CongestionControlStats) {
+        if self.state == State::Startup && self.full_bw_now {
+            self.exit_startup(SlowStartExitReason::Heuristic, now, cc_stats);
+        }
+    }
+
+    fn exit_startup(
+        &mut self,
+        reason: SlowStartExitReason,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        qdebug!("[{self}] exiting startup: {reason:?}");
+        self.filled_pipe = true;
+        cc_stats.slow_start_exit = Some(SlowStartExitStats {
+            reason,
+            detection_cwnd: self.cwnd,
+            exit_cwnd: self.cwnd,
+            bytes_in_flight: self.bytes_in_flight,
+        });
+        self.set_state(State::Drain, now);
+    }
+
+    fn check_drain_done(&mut self, now: Instant) {
+        if self.state == State::Drain && self.bytes_in_flight <= self.inflight(self.bw, GAIN_UNIT) {
+            self.start_probe_bw_down(now);
+        }
+    }
+
+    fn update_probe_bw_cycle_phase(&mut self, rs: &RateSample, now: Instant) {
+        if !self.filled_pipe {
+            return;
+        }
+        self.adapt_upper_bounds(rs, now);
+        match self.state {
+            State::ProbeBwDown => {
+                if !self.maybe_start_probe_bw(now) && self.is_time_to_cruise() {
+                    self.set_state(State::ProbeBwCruise, now);
+                }
+            }
+            State::ProbeBwCruise => {
+                self.maybe_start_probe_bw(now);
+            }
+            State::ProbeBwRefill => {
+                // After one round of refilling, start probing.
+                if self.round_start {
+                    self.bw_probe_samples = true;
+                    self.start_probe_bw_up(rs, now);
+                }
+            }
+            State::ProbeBwUp => {
+                if self.is_time_to_go_down(rs) {
+                    self.start_probe_bw_down(now);
+                }
+            }
+            State::Startup | State::Drain | State::ProbeRtt => {}
+        }
+    }
+
+    fn adapt_upper_bounds(&mut self, rs: &RateSample, now: Instant) {
+        if self.ack_phase == AckPhase::ProbeStarting && self.round_start {
+            // Starting to get feedback on the bandwidth probe.
+            self.ack_phase = AckPhase::ProbeFeedback;
+        }
+        if self.ack_phase == AckPhase::ProbeStopping && self.round_start {
+            // End of the feedback from the last bandwidth probe.
+            self.bw_probe_samples = false;
+            self.ack_phase = AckPhase::Init;
+            if self.state.is_probe_bw() && !rs.is_app_limited() {
+                self.advance_max_bw_filter();
+            }
+        }
+        if Self::is_inflight_too_high(rs.lost(), rs.tx_in_flight()) {
+            if self.bw_probe_samples {
+                self.handle_inflight_too_high(rs.tx_in_flight(), rs.is_app_limited(), now);
+            }
+            return;
+        }
+        let Some(inflight_hi) = self.inflight_hi else {
+            return;
+        };
+        if rs.tx_in_flight() > inflight_hi {
+            self.inflight_hi = Some(rs.tx_in_flight());
+        }
+        if self.state == State::ProbeBwUp {
+            self.probe_inflight_hi_upward(rs);
+        }
+    }
+
+    fn probe_inflight_hi_upward(&mut self, rs: &RateSample) {
+        if !self.is_cwnd_limited || self.inflight_hi.is_none_or(|hi| self.cwnd < hi) {
+            // Not fully using `inflight_hi`, so don't grow it.
+            return;
+        }
+        self.bw_probe_up_acks += rs.newly_acked();
+        if self.bw_probe_up_acks >= self.probe_up_cnt {
+            let delta = self.bw_probe_up_acks / self.probe_up_cnt;
+            self.bw_probe_up_acks -= delta * self.probe_up_cnt;
+            let mtu = self.mtu();
+            self.inflight_hi = self.inflight_hi.map(|hi| hi + delta * mtu);
+        }
+        if self.round_start {
+            self.raise_inflight_hi_slope();
+        }
+    }
+
+    /// Grow `inflight_hi` exponentially with each round of probing.
+    fn raise_inflight_hi_slope(&mut self) {
+        let growth_this_round = 1 << self.bw_probe_up_rounds;
+        self.bw_probe_up_rounds = min(self.bw_probe_up_rounds + 1, MAX_PROBE_UP_ROUNDS);
+        self.probe_up_cnt = max(self.cwnd / growth_this_round, 1);
+    }
+
+    const fn is_inflight_too_high(lost: usize, tx_in_flight: usize) -> bool {
+        lost.saturating_mul(100) > tx_in_flight.saturating_mul(LOSS_THRESH_PERCENT)
+    }
+
+    fn handle_inflight_too_high(
+        &mut self,
+        tx_in_flight: usize,
+        is_app_limited: bool,
+        now: Instant,
+    ) {
+        self.bw_probe_samples = false;
+        if !is_app_limited {
+            self.inflight_hi = Some(max(
+                tx_in_flight,
+                percent(self.target_inflight(), BETA_PERCENT),
+            ));
+        }
+        if self.state == State::ProbeBwUp {
+            self.start_probe_bw_down(now);
+        }
+    }
+
+    /// Estimate the data in flight at the point where the loss rate crossed
+    /// `LOSS_THRESH_PERCENT`, given a lost packet of `len` bytes.
+    const fn inflight_hi_from_lost_packet(len: usize, tx_in_flight: usize, lost: usize) -> usize {
+        let inflight_prev = tx_in_flight.saturating_sub(len);
+        let lost_prev = lost.saturating_sub(len);
+        let lost_prefix = percent(inflight_prev, LOSS_THRESH_PERCENT).saturating_sub(lost_prev)
+            * 100
+            / (100 - LOSS_THRESH_PERCENT);
+        inflight_prev + lost_prefix
+    }
+
+    fn handle_lost_packet(&mut self, pkt: &sent::Packet, state: &DeliveryState, now: Instant) {
+        if !self.bw_probe_samples {
+            return;
+        }
+        let lost = self.lost.saturating_sub(state.lost());
+        if !Self::is_inflight_too_high(lost, state.tx_in_flight()) {
+            return;
+        }
+        let tx_in_flight =
+            Self::inflight_hi_from_lost_packet(pkt.len(), state.tx_in_flight(), lost);
+        self.handle_inflight_too_high(tx_in_flight, state.is_app_limited(), now);
+    }
+
+    fn pick_probe_wait(&mut self) {
+        let [r0, r1] = random::<2>();
+        // Randomize the round-based and the wall-clock based probing
+        // intervals, to avoid synchronizing with other flows.
+        self.rounds_since_bw_probe = u64::from(r0 & 1);
+        self.bw_probe_wait = PROBE_BW_WAIT_BASE + Duration::from_millis(u64::from(r1) * 1000 / 256);
+    }
+
+    fn start_probe_bw_down(&mut self, now: Instant) {
+        self.reset_congestion_signals();
+        self.probe_up_cnt = usize::MAX;
+        self.pick_probe_wait();
+        self.cycle_stamp = now;
+        self.ack_phase = AckPhase::ProbeStopping;
+        self.start_round();
+        self.set_state(State::ProbeBwDown, now);
+    }
+
+    fn start_probe_bw_refill(&mut self, now: Instant) {
+        self.reset_lower_bounds();
+        self.bw_probe_up_rounds = 0;
+        self.bw_probe_up_acks = 0;
+        self.ack_phase = AckPhase::Refilling;
+        self.start_round();
+        self.set_state(State::ProbeBwRefill, now);
+    }
+
+    fn start_probe_bw_up(&mut self, rs: &RateSample, now: Instant) {
+        self.ack_phase = AckPhase::ProbeStarting;
+        self.start_round();
+        self.reset_full_bw();
+        self.full_bw = delivery_rate(rs);
+        self.set_state(State::ProbeBwUp, now);
+        self.raise_inflight_hi_slope();
+    }
+
+    /// Start refilling the pipe, if it is time to probe for bandwidth.
+    fn maybe_start_probe_bw(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.cycle_stamp) > self.bw_probe_wait
+            || self.is_reno_coexistence_probe_time()
+        {
+            self.start_probe_bw_refill(now);
+            return true;
+        }
+        false
+    }
+
+    /// Probe at least as often as Reno would grow its window to the current BDP.
+    fn is_reno_coexistence_probe_time(&self) -> bool {
+        let reno_rounds = to_u64(self.target_inflight() / self.mtu());
+        self.rounds_since_bw_probe >= min(reno_rounds, MAX_RENO_ROUNDS)
+    }
+
+    fn is_time_to_cruise(&self) -> bool {
+        if self.bytes_in_flight > self.inflight_with_headroom() {
+            // Not enough headroom.
+            return false;
+        }
+        self.bytes_in_flight <= self.inflight(self.max_bw, GAIN_UNIT)
+    }
+
+    fn is_time_to_go_down(&mut self, rs: &RateSample) -> bool {
+        if self.is_cwnd_limited && self.inflight_hi.is_some_and(|hi| self.cwnd >= hi) {
+            // Limited by `inflight_hi`, so the bandwidth probe is not yet conclusive.
+            self.reset_full_bw();
+            self.full_bw = delivery_rate(rs);
+        } else if self.full_bw_now {
+            // The bandwidth estimate did not grow.
+            return true;
+        }
+        false
+    }
+
+    fn target_inflight(&self) -> usize {
+        min(self.bdp_multiple(self.bw, GAIN_UNIT), self.cwnd)
+    }
+
+    fn inflight_with_headroom(&self) -> usize {
+        let Some(inflight_hi) = self.inflight_hi else {
+            return usize::MAX;
+        };
+        let headroom = max(self.mtu(), percent(inflight_hi, HEADROOM_PERCENT));
+        max(inflight_hi.saturating_sub(headroom), self.cwnd_min())
+    }
+
+    /// The bandwidth-delay product at `bw` scaled by `gain`.
+    fn bdp_multiple(&self, bw: u64, gain: u64) -> usize {
+        self.min_rtt.map_or_else(
+            || cwnd_initial(self.mtu()),
+            |min_rtt| bytes_at(apply_gain(bw, gain), min_rtt),
+        )
+    }
+
+    /// The bytes in flight needed to fully use the pipe at `bw` scaled by `gain`.
+    fn inflight(&self, bw: u64, gain: u64) -> usize {
+        self.quantization_budget(self.bdp_multiple(bw, gain))
+    }
+
+    /// Allow for the bursts the sender and receiver produce.
+    fn quantization_budget(&self, inflight: usize) -> usize {
+        let offload_budget = 3 * PACING_BURST_SIZE * self.mtu();
+        let inflight = max(max(inflight, offload_budget), self.cwnd_min());
+        if self.state == State::ProbeBwUp {
+            inflight + 2 * self.mtu()
+        } else {
+            inflight
+        }
+    }
+
+    fn update_min_rtt(&mut self, rtt: Duration, now: Instant) {
+        self.probe_rtt_expired = now > self.probe_rtt_min_stamp + PROBE_RTT_INTERVAL;
+        if self.probe_rtt_min_delay.is_none_or(|d| rtt < d) || self.probe_rtt_expired {
+            self.probe_rtt_min_delay = Some(rtt);
+            self.probe_rtt_min_stamp = now;
+        }
+        let min_rtt_expired = now > self.min_rtt_stamp + MIN_RTT_FILTER_LEN;
+        if let Some(delay) = self.probe_rtt_min_delay
+            && (self.min_rtt.is_none_or(|min_rtt| delay < min_rtt) || min_rtt_expired)
+        {
+            if self.min_rtt.is_none() {
+                self.init_pacing_rate(delay);
+            }
+            self.min_rtt = Some(delay);
+            self.min_rtt_stamp = self.probe_rtt_min_stamp;
+        }
+    }
+
+    /// Start pacing at the Startup gain applied to the initial window over the first RTT sample.
+    fn init_pacing_rate(&mut self, rtt: Duration) {
+        let rate = rate(cwnd_initial(self.mtu()), rtt).unwrap_or(u64::MAX);
+        self.pacing_rate = Some(apply_gain(rate, STARTUP_PACING_GAIN));
+    }
+
+    fn check_probe_rtt(&mut self, rs: &RateSample, now: Instant) {
+        if self.state != State::ProbeRtt && self.probe_rtt_expired && !self.idle_restart {
+            self.save_cwnd();
+            self.probe_rtt_done_stamp = None;
+            self.ack_phase = AckPhase::ProbeStopping;
+            self.start_round();
+            self.set_state(State::ProbeRtt, now);
+        }
+        if self.state == State::ProbeRtt {
+            self.handle_probe_rtt(now);
+        }
+        if rs.delivered() > 0 {
+            self.idle_restart = false;
+        }
+    }
+
+    fn handle_probe_rtt(&mut self, now: Instant) {
+        if let Some(done_stamp) = self.probe_rtt_done_stamp {
+            if self.round_start {
+                self.probe_rtt_round_done = true;
+            }
+            if self.probe_rtt_round_done && now > done_stamp {
+                self.probe_rtt_min_stamp = now;
+                self.restore_cwnd();
+                self.exit_probe_rtt(now);
+            }
+        } else if self.bytes_in_flight <= self.probe_rtt_cwnd() {
+            // Wait for at least `PROBE_RTT_DURATION` and one round at the reduced window.
+            self.probe_rtt_done_stamp = Some(now + PROBE_RTT_DURATION);
+            self.probe_rtt_round_done = false;
+            self.start_round();
+        }
+    }
+
+    fn exit_probe_rtt(&mut self, now: Instant) {
+        self.reset_lower_bounds();
+        if self.filled_pipe {
+            self.start_probe_bw_down(now);
+            self.set_state(State::ProbeBwCruise, now);
+        } else {
+            self.set_state(State::Startup, now);
+        }
+    }
+
+    fn probe_rtt_cwnd(&self) -> usize {
+        max(
+            self.bdp_multiple(self.bw, PROBE_RTT_CWND_GAIN),
+            self.cwnd_min(),
+        )
+    }
+
+    fn save_cwnd(&mut self) {
+        self.prior_cwnd = if self.state == State::ProbeRtt {
+            max(self.prior_cwnd, self.cwnd)
+        } else {
+            self.cwnd
+        };
+    }
+
+    fn restore_cwnd(&mut self) {
+        self.cwnd = max(self.cwnd, self.prior_cwnd);
+    }
+
+    /// After persistent congestion, nothing that the model learned about the path
+    /// can be trusted, so start again from Startup with the minimum window.
+    fn on_persistent_congestion(&mut self, now: Instant) {
+        qinfo!("[{self}] persistent congestion");
+        self.cwnd = self.cwnd_min();
+        self.prior_cwnd = self.cwnd;
+        self.max_bw_filter = [0; 2];
+        self.max_bw = 0;
+        self.bw = 0;
+        self.inflight_hi = None;
+        self.reset_lower_bounds();
+        self.reset_congestion_signals();
+        self.reset_full_bw();
+        self.filled_pipe = false;
+        self.extra_acked = [0; 2];
+        self.extra_acked_rounds = 0;
+        self.probe_rtt_done_stamp = None;
+        self.set_state(State::Startup, now);
+        if let Some(min_rtt) = self.min_rtt {
+            self.init_pacing_rate(min_rtt);
+        }
+    }
+
+    fn handle_restart_from_idle(&mut self, now: Instant) {
+        qdebug!("[{self}] restart from idle");
+        self.idle_restart = true;
+        self.extra_acked_interval_start = now;
+        if self.state.is_probe_bw() {
+            // Don't send a burst after being idle.
+            self.set_pacing_rate(GAIN_UNIT);
+        } else if self.state == State::ProbeRtt
+            && self.probe_rtt_done_stamp.is_some_and(|t| now > t)
+        {
+            self.probe_rtt_min_stamp = now;
+            self.restore_cwnd();
+            self.exit_probe_rtt(now);
+        }
+    }
+
+    fn set_pacing_rate(&mut self, gain: u64) {
+        if self.bw == 0 {
+            return;
+        }
+        let rate = apply_gain(apply_gain(self.bw, gain), GAIN_UNIT - PACING_MARGIN_PERCENT);
+        // Before the pipe is full, the bandwidth estimate might be low because
+        // of the small window, so only ever increase the pacing rate.
+        if self.filled_pipe || self.pacing_rate.is_none_or(|r| rate > r) {
+            self.pacing_rate = Some(rate);
+        }
+    }
+
+    fn set_cwnd(&mut self, rs: &RateSample) {
+        self.max_inflight = self
+            .quantization_budget(self.bdp_multiple(self.bw, self.cwnd_gain) + self.extra_acked());
+        if self.filled_pipe {
+            self.cwnd = min(self.cwnd + rs.newly_acked(), self.max_inflight);
+        } else if self.cwnd < self.max_inflight || self.delivered < cwnd_initial(self.mtu()) {
+            self.cwnd += rs.newly_acked();
+        }
+        self.cwnd = max(self.cwnd, self.cwnd_min());
+        if self.state == State::ProbeRtt {
+            self.cwnd = min(self.cwnd, self.probe_rtt_cwnd());
+        }
+        self.bound_cwnd_for_model();
+    }
+
+    fn bound_cwnd_for_model(&mut self) {
+        let mut cap = match self.state {
+            State::ProbeBwCruise | State::ProbeRtt => self.inflight_with_headroom(),
+            s if s.is_probe_bw() => self.inflight_hi.unwrap_or(usize::MAX),
+            _ => usize::MAX,
+        };
+        if let Some(inflight_lo) = self.inflight_lo {
+            cap = min(cap, inflight_lo);
+        }
+        self.cwnd = min(self.cwnd, max(cap, self.cwnd_min()));
+    }
+
+    fn qlog_metrics(&mut self, now: Instant) {
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [
+                qlog::Metric::CongestionWindow(self.cwnd),
+                qlog::Metric::BytesInFlight(self.bytes_in_flight),
+            ],
+            now,
+        );
+    }
+}
+
+impl CongestionController for Bbr {
+    fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    fn cwnd_avail(&self) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight)
+    }
+
+    fn cwnd_min(&self) -> usize {
+        MIN_PIPE_CWND_PKTS * self.mtu()
+    }
+
+    fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rate_sample: &RateSample,
+        _rtt_est: &RttEstimate,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        for pkt in acked_pkts.iter().filter(|p| p.cc_outstanding()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+        self.delivered += rate_sample.newly_acked();
+        let Some(rtt) = rate_sample.rtt() else {
+            return;
+        };
+        qtrace!("[{self}] rate sample {rate_sample:?}");
+        self.update_model_and_state(rate_sample, rtt, now, cc_stats);
+        self.update_control_parameters(rate_sample);
+        self.qlog_metrics(now);
+    }
+
+    fn on_packets_lost(
+        &mut self,
+        first_rtt_sample_time: Option<Instant>,
+        prev_largest_acked_sent: Option<Instant>,
+        pto: Duration,
+        lost_packets: &[sent::Packet],
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let cwnd = self.cwnd;
+        let bytes_in_flight = self.bytes_in_flight;
+        let mut lost_count = 0;
+        let mut too_high = false;
+        for pkt in lost_packets.iter().filter(|p| p.cc_in_flight()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            // Lost PMTUD probes say nothing about congestion.
+            if pkt.is_pmtud_probe() {
+                continue;
+            }
+            lost_count += 1;
+            if let Some(state) = pkt.delivery_state() {
+                self.lost += pkt.len();
+                too_high |= Self::is_inflight_too_high(
+                    self.lost.saturating_sub(state.lost()),
+                    state.tx_in_flight(),
+                );
+                self.handle_lost_packet(pkt, &state, now);
+            }
+        }
+        if lost_count == 0 {
+            return false;
+        }
+
+        if !self.loss_in_round {
+            cc_stats.congestion_events.loss += 1;
+        }
+        self.loss_in_round = true;
+        self.lost_in_round += lost_count;
+        if self.state == State::Startup && too_high && self.lost_in_round >= STARTUP_FULL_LOSS_COUNT
+        {
+            // Persistent high loss means that Startup has filled the pipe.
+            self.inflight_hi = Some(max(
+                self.bdp_multiple(self.bw, GAIN_UNIT),
+                self.inflight_latest,
+            ));
+            self.exit_startup(
+                SlowStartExitReason::CongestionEvent(CongestionTrigger::Loss(lost_count)),
+                now,
+                cc_stats,
+            );
+            // Report the path saturation at the time of the loss.
+            if let Some(exit) = cc_stats.slow_start_exit.as_mut() {
+                exit.bytes_in_flight = bytes_in_flight;
+            }
+        }
+        if persistent_congestion(
+            first_rtt_sample_time,
+            prev_largest_acked_sent,
+            pto,
+            lost_packets.iter().filter(|p| !p.is_pmtud_probe()),
+        ) {
+            self.on_persistent_congestion(now);
+        }
+        self.bound_cwnd_for_model();
+        qdebug!("[{self}] {lost_count} packets lost");
+        self.qlog_metrics(now);
+        self.cwnd < cwnd
+    }
+
+    fn on_ecn_ce_received(
+        &mut self,
+        _largest_acked_pkt: &sent::Packet,
+        _ce_marks: u64,
+        _now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        // BBRv3 treats CE marks like loss when adapting the lower bounds.
+        if !self.loss_in_round {
+            cc_stats.congestion_events.ecn += 1;
+        }
+        self.loss_in_round = true;
+        false
+    }
+
+    fn recovery_packet(&self) -> bool {
+        false
+    }
+
+    fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
+        if !pkt.cc_outstanding() {
+            return;
+        }
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn on_packet_sent(&mut self, pkt: &sent::Packet, now: Instant) {
+        if !pkt.cc_in_flight() {
+            return;
+        }
+        if self.bytes_in_flight == 0 && self.delivered > 0 {
+            self.handle_restart_from_idle(now);
+        }
+        self.bytes_in_flight += pkt.len();
+        self.is_cwnd_limited = self.bytes_in_flight + self.mtu() > self.cwnd;
+        qtrace!("[{self}] packet_sent pn={} len={}", pkt.pn(), pkt.len());
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn discard_in_flight(&mut self, now: Instant) {
+        self.bytes_in_flight = 0;
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        self.pacing_rate
+    }
+}
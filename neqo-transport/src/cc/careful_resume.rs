@@ -0,0 +1,400 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Careful resume, see <https://datatracker.ietf.org/doc/draft-ietf-tsvwg-careful-resume/>.
+//
+// A sender that observed the capacity of a path on an earlier connection can use that to
+// jump-start the congestion window, rather than working its way up from the initial window.
+// The saved parameters are first checked against the current RTT.  The jump window is then
+// used for one round trip, after which the acknowledgments show whether the capacity is
+// still available.  Congestion in that round trip causes a retreat to half of what was
+// actually delivered.
+
+use std::time::Duration;
+
+use neqo_common::{qdebug, qinfo};
+
+use crate::{packet, rtt::RttEstimate};
+
+/// Congestion control parameters observed on a previous connection, for use with careful
+/// resume.  Applications should only use these for connections to the same peer and should
+/// not keep them for long, as the capacity of a path changes over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarefulResumeParams {
+    /// The bottleneck bandwidth, in bytes per second.
+    bandwidth: u64,
+    /// The minimum RTT.
+    rtt: Duration,
+}
+
+impl CarefulResumeParams {
+    #[must_use]
+    pub const fn new(bandwidth: u64, rtt: Duration) -> Self {
+        Self { bandwidth, rtt }
+    }
+
+    /// The bottleneck bandwidth, in bytes per second.
+    #[must_use]
+    pub const fn bandwidth(&self) -> u64 {
+        self.bandwidth
+    }
+
+    /// The minimum RTT.
+    #[must_use]
+    pub const fn rtt(&self) -> Duration {
+        self.rtt
+    }
+
+    /// The congestion window that these parameters support: the bandwidth-delay product.
+    #[must_use]
+    pub fn cwnd(&self) -> usize {
+        usize::try_from(u128::from(self.bandwidth) * self.rtt.as_micros() / 1_000_000)
+            .unwrap_or(usize::MAX)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting to confirm that the saved parameters apply to this path.
+    Reconnaissance,
+    /// Using the jump window, until data sent with it is first acknowledged.
+    Unvalidated,
+    /// Waiting for the rest of the data sent with the jump window to be acknowledged.
+    Validating,
+    /// Congestion was detected before the jump window was validated.
+    SafeRetreat,
+    /// Careful resume is over.
+    Normal,
+}
+
+/// A change that careful resume makes to the congestion controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Jump to a larger congestion window.
+    Jump(usize),
+    /// Set the congestion window.
+    Cwnd(usize),
+    /// Careful resume is over; set the congestion window and the slow start threshold.
+    Done { cwnd: usize, ssthresh: usize },
+}
+
+#[derive(Debug)]
+pub struct CarefulResume {
+    saved: CarefulResumeParams,
+    phase: Phase,
+    /// The number of bytes that the path has been shown to deliver since the jump.
+    pipesize: usize,
+    /// The first packet sent with the jump window.
+    first_unvalidated: Option<packet::Number>,
+    /// The last packet sent with the jump window.
+    last_unvalidated: Option<packet::Number>,
+}
+
+impl CarefulResume {
+    #[must_use]
+    pub const fn new(saved: CarefulResumeParams) -> Self {
+        Self {
+            saved,
+            phase: Phase::Reconnaissance,
+            pipesize: 0,
+            first_unvalidated: None,
+            last_unvalidated: None,
+        }
+    }
+
+    /// Whether careful resume determines the congestion window, in which case the congestion
+    /// controller must not increase it.
+    #[must_use]
+    pub const fn holds_cwnd(&self) -> bool {
+        matches!(
+            self.phase,
+            Phase::Unvalidated | Phase::Validating | Phase::SafeRetreat
+        )
+    }
+
+    fn done(&mut self) {
+        qdebug!("Careful resume: {:?} -> Normal", self.phase);
+        self.phase = Phase::Normal;
+    }
+
+    pub fn on_packet_sent(&mut self, pn: packet::Number) {
+        if self.phase == Phase::Unvalidated {
+            self.first_unvalidated.get_or_insert(pn);
+            self.last_unvalidated = Some(pn);
+        }
+    }
+
+    /// Process an acknowledgment.  `largest_acked` is the largest packet number acknowledged
+    /// and `new_acked` the number of bytes that were newly acknowledged.
+    pub fn on_ack(
+        &mut self,
+        largest_acked: packet::Number,
+        new_acked: usize,
+        rtt_est: &RttEstimate,
+        app_limited: bool,
+        bytes_in_flight: usize,
+        cwnd: usize,
+    ) -> Option<Change> {
+        match self.phase {
+            Phase::Reconnaissance => {
+                if rtt_est.is_guesstimate() {
+                    return None;
+                }
+                let rtt = rtt_est.minimum();
+                if rtt < self.saved.rtt / 2 || rtt > self.saved.rtt * 10 {
+                    qinfo!(
+                        "Careful resume: RTT {rtt:?} does not match saved RTT {:?}",
+                        self.saved.rtt
+                    );
+                    self.done();
+                    return None;
+                }
+                // Only jump when the sender can use the larger window.
+                if app_limited {
+                    return None;
+                }
+                let jump = self.saved.cwnd() / 2;
+                if jump <= cwnd {
+                    self.done();
+                    return None;
+                }
+                qinfo!("Careful resume: jump from {cwnd} to {jump}");
+                self.phase = Phase::Unvalidated;
+                self.pipesize = bytes_in_flight;
+                Some(Change::Jump(jump))
+            }
+            Phase::Unvalidated => {
+                self.pipesize += new_acked;
+                if self.first_unvalidated.is_none_or(|pn| largest_acked < pn) {
+                    return None;
+                }
+                qdebug!("Careful resume: Unvalidated -> Validating");
+                self.phase = Phase::Validating;
+                // Limit the window to what was actually used of the jump window.
+                self.validate(largest_acked, bytes_in_flight + new_acked)
+            }
+            Phase::Validating => {
+                self.pipesize += new_acked;
+                self.validate(largest_acked, cwnd + new_acked)
+            }
+            Phase::SafeRetreat => {
+                self.pipesize += new_acked;
+                if self.last_unvalidated.is_none_or(|pn| largest_acked >= pn) {
+                    self.done();
+                    // Only retreat once.
+                    self.last_unvalidated = None;
+                    Some(Change::Done {
+                        cwnd,
+                        ssthresh: self.pipesize,
+                    })
+                } else {
+                    None
+                }
+            }
+            Phase::Normal => None,
+        }
+    }
+
+    /// In the Validating phase, the congestion window grows with acknowledgments until all
+    /// of the data sent with the jump window is acknowledged.
+    fn validate(&mut self, largest_acked: packet::Number, cwnd: usize) -> Option<Change> {
+        if self.last_unvalidated.is_none_or(|pn| largest_acked >= pn) {
+            self.done();
+            Some(Change::Done {
+                cwnd,
+                ssthresh: cwnd,
+            })
+        } else {
+            Some(Change::Cwnd(cwnd))
+        }
+    }
+
+    /// Process a congestion event for packet `pn`.  This returns the congestion window to
+    /// retreat to if the packet was sent with the jump window before it was validated.
+    pub fn on_congestion(&mut self, pn: packet::Number) -> Option<usize> {
+        let retreat = match self.phase {
+            Phase::Reconnaissance => {
+                self.done();
+                false
+            }
+            Phase::Unvalidated | Phase::Validating => true,
+            // The acknowledgment that completes validation can also reveal a loss.
+            Phase::Normal => self.last_unvalidated.is_some_and(|last| pn <= last),
+            Phase::SafeRetreat => false,
+        };
+        if !retreat {
+            return None;
+        }
+        let cwnd = self.pipesize / 2;
+        qinfo!("Careful resume: congestion, retreat to {cwnd}");
+        self.phase = Phase::SafeRetreat;
+        Some(cwnd)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::Duration;
+
+    use neqo_common::qlog::Qlog;
+    use test_fixture::now;
+
+    use super::{CarefulResume, CarefulResumeParams, Change};
+    use crate::rtt::{RttEstimate, RttSource};
+
+    const RTT: Duration = Duration::from_millis(100);
+    /// A saved window of 200,000 bytes.
+    const SAVED: CarefulResumeParams = CarefulResumeParams::new(2_000_000, RTT);
+    const CWND: usize = 12_000;
+
+    fn rtt(rtt: Duration) -> RttEstimate {
+        let mut est = RttEstimate::new(rtt);
+        est.update(
+            &mut Qlog::disabled(),
+            rtt,
+            Duration::ZERO,
+            RttSource::Ack,
+            now(),
+        );
+        est
+    }
+
+    #[test]
+    fn saved_cwnd() {
+        assert_eq!(SAVED.cwnd(), 200_000);
+        assert_eq!(SAVED.bandwidth(), 2_000_000);
+        assert_eq!(SAVED.rtt(), RTT);
+    }
+
+    /// Jump, then validate the jump window.
+    #[test]
+    fn validate() {
+        let mut cr = CarefulResume::new(SAVED);
+        // No jump without an RTT sample or while app-limited.
+        assert_eq!(
+            cr.on_ack(1, 1_000, &RttEstimate::new(RTT), false, 0, CWND),
+            None
+        );
+        assert_eq!(cr.on_ack(1, 1_000, &rtt(RTT), true, 0, CWND), None);
+        assert!(!cr.holds_cwnd());
+
+        assert_eq!(
+            cr.on_ack(2, 1_000, &rtt(RTT), false, 1_000, CWND),
+            Some(Change::Jump(100_000))
+        );
+        assert!(cr.holds_cwnd());
+        for pn in 3..10 {
+            cr.on_packet_sent(pn);
+        }
+        // Acknowledging packets from before the jump changes nothing.
+        assert_eq!(cr.on_ack(2, 1_000, &rtt(RTT), false, 8_000, 100_000), None);
+        // The window is limited to what was used.
+        assert_eq!(
+            cr.on_ack(3, 1_000, &rtt(RTT), false, 7_000, 100_000),
+            Some(Change::Cwnd(8_000))
+        );
+        assert_eq!(
+            cr.on_ack(5, 2_000, &rtt(RTT), false, 5_000, 8_000),
+            Some(Change::Cwnd(10_000))
+        );
+        assert_eq!(
+            cr.on_ack(9, 4_000, &rtt(RTT), false, 0, 10_000),
+            Some(Change::Done {
+                cwnd: 14_000,
+                ssthresh: 14_000
+            })
+        );
+        assert!(!cr.holds_cwnd());
+        assert_eq!(cr.on_congestion(10), None);
+    }
+
+    /// Congestion before the jump window is validated causes a retreat.
+    #[test]
+    fn retreat() {
+        let mut cr = CarefulResume::new(SAVED);
+        assert_eq!(
+            cr.on_ack(1, 1_000, &rtt(RTT), false, 0, CWND),
+            Some(Change::Jump(100_000))
+        );
+        for pn in 2..10 {
+            cr.on_packet_sent(pn);
+        }
+        assert_eq!(
+            cr.on_ack(3, 20_000, &rtt(RTT), false, 0, 100_000),
+            Some(Change::Cwnd(20_000))
+        );
+        assert_eq!(cr.on_congestion(4), Some(10_000));
+        assert!(cr.holds_cwnd());
+        assert_eq!(cr.on_ack(5, 2_000, &rtt(RTT), false, 0, 10_000), None);
+        assert_eq!(
+            cr.on_ack(9, 2_000, &rtt(RTT), false, 0, 10_000),
+            Some(Change::Done {
+                cwnd: 10_000,
+                ssthresh: 24_000
+            })
+        );
+        assert!(!cr.holds_cwnd());
+    }
+
+    /// Careful resume is abandoned if the RTT is too different.
+    #[test]
+    fn rtt_mismatch() {
+        for r in [RTT / 3, RTT * 11] {
+            let mut cr = CarefulResume::new(SAVED);
+            assert_eq!(cr.on_ack(1, 1_000, &rtt(r), false, 0, CWND), None);
+            assert_eq!(cr.on_ack(2, 1_000, &rtt(RTT), false, 0, CWND), None);
+        }
+    }
+
+    /// Congestion before the jump abandons careful resume.
+    #[test]
+    fn congestion_before_jump() {
+        let mut cr = CarefulResume::new(SAVED);
+        assert_eq!(cr.on_congestion(1), None);
+        assert_eq!(cr.on_ack(1, 1_000, &rtt(RTT), false, 0, CWND), None);
+    }
+
+    /// There is no jump if the saved window is too small.
+    #[test]
+    fn small_saved_window() {
+        let mut cr = CarefulResume::new(CarefulResumeParams::new(100_000, RTT));
+        assert_eq!(cr.on_ack(1, 1_000, &rtt(RTT), false, 0, CWND), None);
+        assert!(!cr.holds_cwnd());
+    }
+
+    /// A single acknowledgment can validate the whole jump window.
+    #[test]
+    fn validate_at_once() {
+        let mut cr = CarefulResume::new(SAVED);
+        assert_eq!(
+            cr.on_ack(1, 1_000, &rtt(RTT), false, 0, CWND),
+            Some(Change::Jump(100_000))
+        );
+        for pn in 2..10 {
+            cr.on_packet_sent(pn);
+        }
+        assert_eq!(
+            cr.on_ack(9, 90_000, &rtt(RTT), false, 0, 100_000),
+            Some(Change::Done {
+                cwnd: 90_000,
+                ssthresh: 90_000
+            })
+        );
+
+        // A loss that the same acknowledgment reveals still causes a retreat, but only once.
+        assert_eq!(cr.on_congestion(2), Some(45_000));
+        assert!(cr.holds_cwnd());
+        assert_eq!(
+            cr.on_ack(10, 1_000, &rtt(RTT), false, 0, 45_000),
+            Some(Change::Done {
+                cwnd: 45_000,
+                ssthresh: 91_000
+            })
+        );
+        assert_eq!(cr.on_congestion(2), None);
+    }
+}
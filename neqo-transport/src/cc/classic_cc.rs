@@ -15,12 +15,15 @@ use std::{
 use neqo_common::{const_max, const_min, qdebug, qinfo, qlog::Qlog, qtrace};
 use rustc_hash::FxHashMap as HashMap;
 
-use super::CongestionController;
+use super::{
+    CongestionController,
+    careful_resume::{CarefulResume, CarefulResumeParams, Change},
+};
 use crate::{
     Pmtud,
     cc::CongestionTrigger::{self, Ecn, Loss},
     packet, qlog,
-    recovery::sent,
+    recovery::{RateSample, sent},
     rtt::RttEstimate,
     sender::PACING_BURST_SIZE,
     stats::{CongestionControlStats, SlowStartExitReason, SlowStartExitStats},
@@ -216,6 +219,8 @@ pub struct ClassicCongestionController<S, T> {
     stored: Option<State>,
     /// Whether to recover from spurious congestion events by restoring prior state.
     spurious_recovery: bool,
+    /// Careful resume, if it was started with parameters from an earlier connection.
+    careful_resume: Option<CarefulResume>,
 }
 
 impl<S: Display, T: Display> Display for ClassicCongestionController<S, T> {
@@ -284,6 +289,7 @@ where
     fn on_packets_acked(
         &mut self,
         acked_pkts: &[sent::Packet],
+        _rate_sample: &RateSample,
         rtt_est: &RttEstimate,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
@@ -336,6 +342,25 @@ where
             self.slow_start.record_acked_bytes(new_acked);
         }
 
+        if self.on_careful_resume_ack(
+            largest_packet_acked.pn(),
+            new_acked,
+            rtt_est,
+            is_app_limited,
+            now,
+            cc_stats,
+        ) {
+            qlog::metrics_updated(
+                &mut self.qlog,
+                [
+                    qlog::Metric::CongestionWindow(self.current.congestion_window),
+                    qlog::Metric::BytesInFlight(self.bytes_in_flight),
+                ],
+                now,
+            );
+            return;
+        }
+
         if is_app_limited {
             self.congestion_control.on_app_limited();
             qdebug!(
@@ -539,6 +564,7 @@ where
     fn on_ecn_ce_received(
         &mut self,
         largest_acked_pkt: &sent::Packet,
+        _ce_marks: u64,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) -> bool {
@@ -579,6 +605,10 @@ where
             return;
         }
 
+        if let Some(cr) = &mut self.careful_resume {
+            cr.on_packet_sent(pkt.pn());
+        }
+
         // Pass next packet number to send into slow start algorithm during slow start.
         if self.current.phase.in_slow_start() {
             self.slow_start.on_packet_sent(pkt.pn(), pkt.len());
@@ -609,12 +639,76 @@ where
     fn recovery_packet(&self) -> bool {
         self.current.phase == Phase::RecoveryStart
     }
+
+    fn careful_resume(&mut self, params: CarefulResumeParams) {
+        // Careful resume is only useful before the first congestion event.
+        if !self.current.phase.in_slow_start() || self.current.ssthresh.is_some() {
+            qdebug!("[{self}] Ignoring careful resume after slow start");
+            return;
+        }
+        self.careful_resume = Some(CarefulResume::new(params));
+    }
 }
 
-const fn cwnd_initial(mtu: usize) -> usize {
+/// The initial congestion window for the given MTU, per RFC 9002.
+pub const fn cwnd_initial(mtu: usize) -> usize {
     const_min(CWND_INITIAL_PKTS * mtu, const_max(2 * mtu, 14_720))
 }
 
+/// Whether `lost_packets` show persistent congestion, per Section 7.6 of RFC 9002.
+/// This is the case if the ack-eliciting packets in a contiguous range of lost packets
+/// were sent over a period that is longer than `PERSISTENT_CONG_THRESH` times the PTO.
+///
+/// Callers filter out lost PMTUD probes, but not lost packets that aren't in flight,
+/// as those are still needed to check that the range is contiguous.
+pub fn persistent_congestion<'a>(
+    first_rtt_sample_time: Option<Instant>,
+    prev_largest_acked_sent: Option<Instant>,
+    pto: Duration,
+    lost_packets: impl IntoIterator<Item = &'a sent::Packet>,
+) -> bool {
+    if first_rtt_sample_time.is_none() {
+        return false;
+    }
+
+    let pc_period = pto * PERSISTENT_CONG_THRESH;
+
+    let mut last_pn: Option<packet::Number> = None;
+    let mut start = None;
+
+    // Look for the first lost packet after the previous largest acknowledged.
+    // Ignore packets that weren't ack-eliciting for the start of this range.
+    // Also, make sure to ignore any packets sent before we got an RTT estimate
+    // as we might not have sent PTO packets soon enough after those.
+    let cutoff = max(first_rtt_sample_time, prev_largest_acked_sent);
+    for p in lost_packets
+        .into_iter()
+        .skip_while(|p| Some(p.time_sent()) < cutoff)
+    {
+        if last_pn.is_none_or(|l| p.pn() != l + 1) {
+            // Not a contiguous range of lost packets, start over.
+            start = None;
+        }
+        last_pn = Some(p.pn());
+        if !p.cc_in_flight() {
+            // Not interesting, keep looking.
+            continue;
+        }
+        if let Some(t) = start {
+            let elapsed = p
+                .time_sent()
+                .checked_duration_since(t)
+                .expect("time is monotonic");
+            if elapsed > pc_period {
+                return true;
+            }
+        } else {
+            start = Some(p.time_sent());
+        }
+    }
+    false
+}
+
 impl<S, T> ClassicCongestionController<S, T>
 where
     S: SlowStart,
@@ -638,6 +732,7 @@ where
             current: State::new(mtu),
             stored: None,
             spurious_recovery,
+            careful_resume: None,
         }
     }
 
@@ -797,71 +892,40 @@ where
         lost_packets: impl IntoIterator<Item = &'a sent::Packet>,
         now: Instant,
     ) -> bool {
-        if first_rtt_sample_time.is_none() {
+        if !persistent_congestion(
+            first_rtt_sample_time,
+            prev_largest_acked_sent,
+            pto,
+            lost_packets,
+        ) {
             return false;
         }
 
-        let pc_period = pto * PERSISTENT_CONG_THRESH;
-
-        let mut last_pn: Option<packet::Number> = None;
-        let mut start = None;
+        qinfo!("[{self}] persistent congestion");
+        self.current.congestion_window = self.cwnd_min();
+        self.current.acked_bytes = 0;
+        self.set_phase(
+            Phase::PersistentCongestion,
+            Some(qlog::CongestionStateTrigger::PersistentCongestion),
+            now,
+        );
+        // We re-enter slow start after persistent congestion, so we need to reset any
+        // state leftover from initial slow start to have it perform correctly.
+        self.slow_start.reset();
 
-        // Look for the first lost packet after the previous largest acknowledged.
-        // Ignore packets that weren't ack-eliciting for the start of this range.
-        // Also, make sure to ignore any packets sent before we got an RTT estimate
-        // as we might not have sent PTO packets soon enough after those.
-        let cutoff = max(first_rtt_sample_time, prev_largest_acked_sent);
-        for p in lost_packets
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [
+                Some(qlog::Metric::CongestionWindow(
+                    self.current.congestion_window,
+                )),
+                self.current.ssthresh.map(qlog::Metric::SsThresh),
+            ]
             .into_iter()
-            .skip_while(|p| Some(p.time_sent()) < cutoff)
-        {
-            if last_pn.is_none_or(|l| p.pn() != l + 1) {
-                // Not a contiguous range of lost packets, start over.
-                start = None;
-            }
-            last_pn = Some(p.pn());
-            if !p.cc_in_flight() {
-                // Not interesting, keep looking.
-                continue;
-            }
-            if let Some(t) = start {
-                let elapsed = p
-                    .time_sent()
-                    .checked_duration_since(t)
-                    .expect("time is monotonic");
-                if elapsed > pc_period {
-                    qinfo!("[{self}] persistent congestion");
-                    self.current.congestion_window = self.cwnd_min();
-                    self.current.acked_bytes = 0;
-                    self.set_phase(
-                        Phase::PersistentCongestion,
-                        Some(qlog::CongestionStateTrigger::PersistentCongestion),
-                        now,
-                    );
-                    // We re-enter slow start after persistent congestion, so we need to reset any
-                    // state leftover from initial slow start to have it perform correctly.
-                    self.slow_start.reset();
-
-                    qlog::metrics_updated(
-                        &mut self.qlog,
-                        [
-                            Some(qlog::Metric::CongestionWindow(
-                                self.current.congestion_window,
-                            )),
-                            self.current.ssthresh.map(qlog::Metric::SsThresh),
-                        ]
-                        .into_iter()
-                        .flatten(),
-                        now,
-                    );
-
-                    return true;
-                }
-            } else {
-                start = Some(p.time_sent());
-            }
-        }
-        false
+            .flatten(),
+            now,
+        );
+        true
     }
 
     #[must_use]
@@ -915,6 +979,16 @@ where
         );
         self.current.congestion_window = max(cwnd, self.cwnd_min());
         self.current.acked_bytes = acked_bytes;
+        if let Some(retreat) = self
+            .careful_resume
+            .as_mut()
+            .and_then(|cr| cr.on_congestion(last_packet.pn()))
+        {
+            // The retreat is based on what the path delivered, so it is never undone.
+            self.stored = None;
+            self.current.congestion_window = max(retreat, self.cwnd_min());
+            cc_stats.careful_resume_retreat = true;
+        }
         self.current.ssthresh = Some(self.current.congestion_window);
         qinfo!(
             "[{self}] Cong event -> recovery; cwnd {}, ssthresh {:?}",
@@ -954,6 +1028,51 @@ where
         true
     }
 
+    /// Let careful resume process an acknowledgment.  Returns true if careful resume
+    /// determines the congestion window, so that it must not grow as usual.
+    fn on_careful_resume_ack(
+        &mut self,
+        largest_acked: packet::Number,
+        new_acked: usize,
+        rtt_est: &RttEstimate,
+        app_limited: bool,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let Some(cr) = &mut self.careful_resume else {
+            return false;
+        };
+        let change = cr.on_ack(
+            largest_acked,
+            new_acked,
+            rtt_est,
+            app_limited,
+            self.bytes_in_flight,
+            self.current.congestion_window,
+        );
+        let holds_cwnd = cr.holds_cwnd();
+        match change {
+            Some(Change::Jump(cwnd)) => {
+                self.current.congestion_window = cwnd;
+                cc_stats.careful_resume_jump = Some(cwnd);
+            }
+            Some(Change::Cwnd(cwnd)) => {
+                self.current.congestion_window = max(cwnd, self.cwnd_min());
+            }
+            Some(Change::Done { cwnd, ssthresh }) => {
+                self.current.congestion_window = max(cwnd, self.cwnd_min());
+                let ssthresh = max(ssthresh, self.current.congestion_window);
+                self.current.ssthresh = Some(ssthresh);
+                if self.current.phase.in_slow_start() && self.current.congestion_window >= ssthresh
+                {
+                    self.set_phase(Phase::CongestionAvoidance, None, now);
+                }
+            }
+            None => {}
+        }
+        holds_cwnd
+    }
+
     fn app_limited(&self) -> bool {
         if self.bytes_in_flight >= self.current.congestion_window {
             false
@@ -991,7 +1110,7 @@ mod tests {
             tests::{IP_ADDR, MTU, RTT, make_cc_cubic, make_cc_hystart, make_cc_newreno},
         },
         packet,
-        recovery::{self, sent},
+        recovery::{self, RateSample, sent},
         rtt::RttEstimate,
         stats::{CongestionControlStats, SlowStartExitReason},
     };
@@ -1418,6 +1537,7 @@ mod tests {
             now += RTT;
             cc.on_packets_acked(
                 &pkts,
+                &RateSample::default(),
                 &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
                 now,
                 &mut cc_stats,
@@ -1453,6 +1573,7 @@ mod tests {
         for (i, pkt) in pkts.into_iter().enumerate() {
             cc.on_packets_acked(
                 &[pkt],
+                &RateSample::default(),
                 &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
                 now,
                 &mut cc_stats,
@@ -1517,6 +1638,7 @@ mod tests {
         now += RTT;
         cc.on_packets_acked(
             &[p_not_lost],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1553,6 +1675,7 @@ mod tests {
             for (i, pkt) in pkts.into_iter().enumerate() {
                 cc.on_packets_acked(
                     &[pkt],
+                    &RateSample::default(),
                     &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
                     now,
                     &mut cc_stats,
@@ -1593,6 +1716,7 @@ mod tests {
         for (i, pkt) in pkts.into_iter().enumerate() {
             cc.on_packets_acked(
                 &[pkt],
+                &RateSample::default(),
                 &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
                 now,
                 &mut cc_stats,
@@ -1631,7 +1755,7 @@ mod tests {
         assert_eq!(cc_stats.congestion_events.ecn, 0);
 
         // Signal congestion (ECN CE) and thus change phase to recovery start.
-        cc.on_ecn_ce_received(&p_ce, now, &mut cc_stats);
+        cc.on_ecn_ce_received(&p_ce, 1, now, &mut cc_stats);
         assert_eq!(cc.cwnd(), cc.cwnd_initial() * 85 / 100);
         assert_eq!(cc.ssthresh(), Some(cc.cwnd_initial() * 85 / 100));
         assert_eq!(cc.current.phase, Phase::RecoveryStart);
@@ -1702,6 +1826,7 @@ mod tests {
         // 4. Ack packet (3)      --> `CongestionAvoidance`, 1 event
         cc.on_packets_acked(
             &[pkt3],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1713,6 +1838,7 @@ mod tests {
         //    all lost packets were recovered
         cc.on_packets_acked(
             &[pkt1],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1725,6 +1851,7 @@ mod tests {
         //    spurious congestion event and reset to previous state
         cc.on_packets_acked(
             &[pkt2],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1784,6 +1911,7 @@ mod tests {
         // 4. Ack packet (3) --> `CongestionAvoidance`
         cc.on_packets_acked(
             &[pkt3],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1793,6 +1921,7 @@ mod tests {
         // 5. Ack packet (1) --> not all lost packets recovered yet
         cc.on_packets_acked(
             &[pkt1],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1803,6 +1932,7 @@ mod tests {
         //    because pref is turned off. Assert that nothing is reset.
         cc.on_packets_acked(
             &[pkt2],
+            &RateSample::default(),
             &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
             now,
             &mut cc_stats,
@@ -1834,7 +1964,13 @@ mod tests {
         // Send recovery packet
         let pkt_recovery = sent::make_packet(2, now, 1000);
         cc.on_packet_sent(&pkt_recovery, now);
-        cc.on_packets_acked(&[pkt_recovery], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt_recovery],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         // Grow cwnd back naturally.
         let mut next_pn_to_send = 3;
@@ -1847,7 +1983,13 @@ mod tests {
                 next_pn_to_send += 1;
             }
 
-            cc.on_packets_acked(&sent_packets, &rtt_estimate, now, &mut cc_stats);
+            cc.on_packets_acked(
+                &sent_packets,
+                &RateSample::default(),
+                &rtt_estimate,
+                now,
+                &mut cc_stats,
+            );
 
             if cc.cwnd() >= cc.cwnd_initial() {
                 break;
@@ -1862,7 +2004,13 @@ mod tests {
         );
 
         // Now detect spurious (late)
-        cc.on_packets_acked(&[pkt], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         // Detects the spurious congestion event but should NOT restore old params because cwnd has
         // recovered naturally.
@@ -1925,7 +2073,13 @@ mod tests {
         assert_eq!(cc.current.phase, Phase::Recovery);
 
         // Step 4: Ack packet 1 → spurious event #1 detected
-        cc.on_packets_acked(&[pkt1], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt1],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         assert_eq!(cc_stats.congestion_events.loss, 1);
         assert_eq!(cc_stats.congestion_events.spurious, 1);
@@ -1950,7 +2104,13 @@ mod tests {
 
         // 6. Ack packet 2 → should trigger spurious event #2 because we left recovery when
         //    recovering from spurious event #1
-        cc.on_packets_acked(&[pkt2], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt2],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         // Should now be 2 loss events and 2 spurious events, no double counting occured
         assert_eq!(cc_stats.congestion_events.loss, 2);
@@ -1985,7 +2145,13 @@ mod tests {
         // The cleanup is called when we ack packets, so we send and ack a new one.
         let pkt2 = sent::make_packet(2, now, 1000);
         cc.on_packet_sent(&pkt2, now);
-        cc.on_packets_acked(&[pkt2], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt2],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         // The packet is exactly the maximum age, so it shouldn't be removed yet. This assert makes
         // sure we don't clean up too early.
@@ -1997,7 +2163,13 @@ mod tests {
         // Send and ack another packet to trigger cleanup.
         let pkt3 = sent::make_packet(3, now, 1000);
         cc.on_packet_sent(&pkt3, now);
-        cc.on_packets_acked(&[pkt3], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt3],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         // Now the packet should be removed.
         assert!(cc.maybe_lost_packets.is_empty());
@@ -2019,7 +2191,7 @@ mod tests {
 
         match congestion_trigger {
             Ecn => {
-                cc.on_ecn_ce_received(&pkt1, now, &mut cc_stats);
+                cc.on_ecn_ce_received(&pkt1, 1, now, &mut cc_stats);
             }
             Loss(_) => {
                 cc.on_packets_lost(
@@ -2052,10 +2224,22 @@ mod tests {
             // Send recovery packet and ack it to exit recovery.
             let pkt2 = sent::make_packet(2, now, 1000);
             cc.on_packet_sent(&pkt2, now);
-            cc.on_packets_acked(&[pkt2], &rtt_estimate, now, &mut cc_stats);
+            cc.on_packets_acked(
+                &[pkt2],
+                &RateSample::default(),
+                &rtt_estimate,
+                now,
+                &mut cc_stats,
+            );
 
             // Late ack of pkt1 triggers spurious congestion detection - should reset to None.
-            cc.on_packets_acked(&[pkt1], &rtt_estimate, now, &mut cc_stats);
+            cc.on_packets_acked(
+                &[pkt1],
+                &RateSample::default(),
+                &rtt_estimate,
+                now,
+                &mut cc_stats,
+            );
 
             assert!(cc.current.phase.in_slow_start());
             assert_eq!(cc_stats.slow_start_exit, None);
@@ -2101,7 +2285,13 @@ mod tests {
             sent_packets.push(pkt);
             next_pn += 1;
         }
-        cc.on_packets_acked(&sent_packets, &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &sent_packets,
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
         let cwnd_after_growth = cc.cwnd();
         assert!(cwnd_after_growth > cwnd_initial);
 
@@ -2133,7 +2323,13 @@ mod tests {
         // Send and ack a single packet — not enough to fill cwnd, so app-limited.
         let pkt = sent::make_packet(0, now, cc.max_datagram_size());
         cc.on_packet_sent(&pkt, now);
-        cc.on_packets_acked(&[pkt], &rtt_estimate, now, &mut cc_stats);
+        cc.on_packets_acked(
+            &[pkt],
+            &RateSample::default(),
+            &rtt_estimate,
+            now,
+            &mut cc_stats,
+        );
 
         assert_eq!(cc.cwnd(), cwnd_initial);
     }
@@ -2194,7 +2390,7 @@ mod tests {
                 cc.max_datagram_size(),
             );
             cc.on_packet_sent(&p_ce, now);
-            cc.on_ecn_ce_received(&p_ce, now, stats);
+            cc.on_ecn_ce_received(&p_ce, 1, now, stats);
         });
     }
 
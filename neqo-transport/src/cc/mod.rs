@@ -13,15 +13,25 @@ use std::{
 
 use neqo_common::qlog::Qlog;
 
-use crate::{Pmtud, recovery::sent, rtt::RttEstimate, stats::CongestionControlStats};
+use crate::{
+    Pmtud,
+    recovery::{RateSample, sent},
+    rtt::RttEstimate,
+    stats::CongestionControlStats,
+};
 
+mod bbr;
+mod careful_resume;
 mod classic_cc;
 mod classic_slow_start;
 mod cubic;
 mod hystart;
 mod new_reno;
+mod prague;
 mod search;
 
+pub use bbr::Bbr;
+pub use careful_resume::CarefulResumeParams;
 pub use classic_cc::{
     CWND_INITIAL_PKTS, ClassicCongestionController, PERSISTENT_CONG_THRESH, Phase,
 };
@@ -29,6 +39,7 @@ pub use classic_slow_start::ClassicSlowStart;
 pub use cubic::Cubic;
 pub use hystart::{HyStart, HyStartCssBaseline};
 pub use new_reno::NewReno;
+pub use prague::Prague;
 #[cfg(test)]
 pub use search::Outcome;
 pub use search::Search;
@@ -42,6 +53,10 @@ pub enum CongestionTrigger {
     Ecn,
 }
 
+/// A congestion controller.
+///
+/// The built-in algorithms are selected with [`CongestionControl`].  Applications can supply
+/// their own implementation of this trait using a [`CongestionControllerFactory`].
 pub trait CongestionController: Display + Debug {
     fn set_qlog(&mut self, qlog: Qlog);
 
@@ -63,9 +78,12 @@ pub trait CongestionController: Display + Debug {
     #[must_use]
     fn pmtud_mut(&mut self) -> &mut Pmtud;
 
+    /// Called when packets are acknowledged.  `rate_sample` holds the
+    /// delivery rate sample that the acknowledgment produced.
     fn on_packets_acked(
         &mut self,
         acked_pkts: &[sent::Packet],
+        rate_sample: &RateSample,
         rtt_est: &RttEstimate,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
@@ -82,10 +100,12 @@ pub trait CongestionController: Display + Debug {
         cc_stats: &mut CongestionControlStats,
     ) -> bool;
 
+    /// Called when an ACK frame reports `ce_marks` new ECN CE marks.
     /// Returns true if the congestion window was reduced.
     fn on_ecn_ce_received(
         &mut self,
         largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) -> bool;
@@ -98,6 +118,31 @@ pub trait CongestionController: Display + Debug {
     fn on_packet_sent(&mut self, pkt: &sent::Packet, now: Instant);
 
     fn discard_in_flight(&mut self, now: Instant);
+
+    /// The rate in bytes per second at which the pacer should send, for
+    /// congestion controllers that maintain their own bandwidth estimate.
+    /// When this returns `None`, the pacer derives its rate from the
+    /// congestion window and the RTT.
+    #[must_use]
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Use congestion control parameters from an earlier connection to jump-start the
+    /// congestion window, following careful resume.  Controllers that do not support careful
+    /// resume ignore this.
+    fn careful_resume(&mut self, _params: CarefulResumeParams) {}
+}
+
+/// Creates congestion controllers for an application-supplied algorithm.
+///
+/// See [`crate::ConnectionParameters::congestion_controller_factory`].
+/// Factories are shared between connections, which might be on different threads.
+pub trait CongestionControllerFactory: Debug + Send + Sync {
+    /// Create a congestion controller for a new path.  The controller owns `pmtud`
+    /// and has to provide access to it through [`CongestionController::pmtud`] and
+    /// [`CongestionController::pmtud_mut`].
+    fn create(&self, pmtud: Pmtud, now: Instant) -> Box<dyn CongestionController>;
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, strum::EnumString, strum::VariantNames)]
@@ -108,6 +153,10 @@ pub enum CongestionControl {
     #[strum(serialize = "cubic")]
     #[default]
     Cubic,
+    /// BBRv3, which paces at its bandwidth estimate and uses its own startup
+    /// phase instead of the configured [`SlowStart`] algorithm.
+    #[strum(serialize = "bbr")]
+    Bbr,
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, strum::EnumString, strum::VariantNames)]
@@ -126,7 +175,7 @@ pub enum SlowStart {
 /// algorithm and slow-start strategy.
 ///
 /// This enum avoids the heap allocation and vtable indirection of `Box<dyn CongestionController>`
-/// on the per-packet hot path.
+/// on the per-packet hot path.  Only application-supplied controllers pay that cost.
 #[derive(Debug, strum::Display)]
 pub enum CongestionControlImplementation {
     #[strum(to_string = "{0}")]
@@ -141,12 +190,22 @@ pub enum CongestionControlImplementation {
     HyStartCubic(ClassicCongestionController<HyStart, Cubic>),
     #[strum(to_string = "{0}")]
     SearchCubic(ClassicCongestionController<Search, Cubic>),
+    #[strum(to_string = "{0}")]
+    Bbr(Bbr),
+    #[strum(to_string = "{0}")]
+    Prague(Prague),
+    /// A controller created by a [`CongestionControllerFactory`].
+    #[strum(to_string = "{0}")]
+    Custom(Box<dyn CongestionController>),
 }
 
 macro_rules! dispatch {
     ($self:ident . $method:ident $args:tt) => {
         neqo_common::dispatch!(
-            [ClassicNewReno, HyStartNewReno, SearchNewReno, ClassicCubic, HyStartCubic, SearchCubic]
+            [
+                ClassicNewReno, HyStartNewReno, SearchNewReno, ClassicCubic, HyStartCubic,
+                SearchCubic, Bbr, Prague, Custom
+            ]
             $self . $method $args
         )
     };
@@ -184,11 +243,12 @@ impl CongestionController for CongestionControlImplementation {
     fn on_packets_acked(
         &mut self,
         acked_pkts: &[sent::Packet],
+        rate_sample: &RateSample,
         rtt_est: &RttEstimate,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) {
-        dispatch!(self.on_packets_acked(acked_pkts, rtt_est, now, cc_stats));
+        dispatch!(self.on_packets_acked(acked_pkts, rate_sample, rtt_est, now, cc_stats));
     }
 
     fn on_packets_lost(
@@ -213,10 +273,11 @@ impl CongestionController for CongestionControlImplementation {
     fn on_ecn_ce_received(
         &mut self,
         largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) -> bool {
-        dispatch!(self.on_ecn_ce_received(largest_acked_pkt, now, cc_stats))
+        dispatch!(self.on_ecn_ce_received(largest_acked_pkt, ce_marks, now, cc_stats))
     }
 
     fn recovery_packet(&self) -> bool {
@@ -234,6 +295,14 @@ impl CongestionController for CongestionControlImplementation {
     fn discard_in_flight(&mut self, now: Instant) {
         dispatch!(self.discard_in_flight(now));
     }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        dispatch!(self.pacing_rate())
+    }
+
+    fn careful_resume(&mut self, params: CarefulResumeParams) {
+        dispatch!(self.careful_resume(params));
+    }
 }
 
 #[cfg(test)]
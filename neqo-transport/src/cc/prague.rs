@@ -0,0 +1,401 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Prague congestion control for L4S, following
+// https://datatracker.ietf.org/doc/draft-briscoe-iccrg-prague-congestion-control/
+
+use std::{
+    cmp::{max, min},
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, to_u64};
+
+use super::{
+    CongestionController,
+    classic_cc::{cwnd_initial, persistent_congestion},
+};
+use crate::{
+    Pmtud,
+    cc::CongestionTrigger,
+    packet, qlog,
+    recovery::{RateSample, sent},
+    rtt::RttEstimate,
+    sender::PACING_BURST_SIZE,
+    stats::{CongestionControlStats, SlowStartExitReason, SlowStartExitStats},
+};
+
+/// The fixed-point representation of an `alpha` of 1.
+const ALPHA_ONE: u64 = 1 << 20;
+/// The gain of the moving average of the CE fraction, as a shift, i.e., `g = 1/16`.
+const ALPHA_GAIN_SHIFT: u32 = 4;
+
+/// A Prague congestion controller.
+///
+/// Prague keeps `alpha`, a moving average of the fraction of packets that are CE marked per
+/// round trip. At most once per round trip, CE marks reduce the congestion window by
+/// `alpha / 2`. An L4S bottleneck marks early and at a shallow queue, so small reductions
+/// in proportion to the extent of marking keep the window close to the BDP and the queue
+/// short. Loss is handled like in Reno.
+#[derive(Debug)]
+pub struct Prague {
+    pmtud: Pmtud,
+    qlog: Qlog,
+    cwnd: usize,
+    ssthresh: Option<usize>,
+    bytes_in_flight: usize,
+    /// The bytes acknowledged towards the next increase in congestion avoidance.
+    acked_bytes: usize,
+    /// The moving average of the CE fraction, scaled by [`ALPHA_ONE`].
+    alpha: u64,
+    /// The number of the next packet that will be sent.
+    next_pn: packet::Number,
+    /// The current round ends when a packet with this number or higher is acknowledged.
+    round_end: packet::Number,
+    /// The number of packets acknowledged in the current round.
+    round_acked: u64,
+    /// The number of CE marks reported in the current round.
+    round_ce: u64,
+    /// Congestion signals for packets numbered below this belong to the last reduction.
+    recovery_start: Option<packet::Number>,
+}
+
+impl Display for Prague {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Prague [alpha {}%, cwnd {}, ssthresh {:?}, bif {}]",
+            self.alpha * 100 / ALPHA_ONE,
+            self.cwnd,
+            self.ssthresh,
+            self.bytes_in_flight
+        )
+    }
+}
+
+impl Prague {
+    #[must_use]
+    pub fn new(pmtud: Pmtud) -> Self {
+        let cwnd = cwnd_initial(pmtud.plpmtu());
+        Self {
+            pmtud,
+            qlog: Qlog::default(),
+            cwnd,
+            ssthresh: None,
+            bytes_in_flight: 0,
+            acked_bytes: 0,
+            // Like DCTCP, start from the assumption that all packets are marked, so that the
+            // first reduction is as large as in Reno.
+            alpha: ALPHA_ONE,
+            next_pn: 0,
+            round_end: 0,
+            round_acked: 0,
+            round_ce: 0,
+            recovery_start: None,
+        }
+    }
+
+    /// The moving average of the CE fraction, scaled by `ALPHA_ONE`.
+    #[cfg(test)]
+    pub const fn alpha(&self) -> u64 {
+        self.alpha
+    }
+
+    #[cfg(test)]
+    pub const fn alpha_one() -> u64 {
+        ALPHA_ONE
+    }
+
+    const fn mtu(&self) -> usize {
+        self.pmtud.plpmtu()
+    }
+
+    fn in_slow_start(&self) -> bool {
+        self.ssthresh.is_none_or(|ssthresh| self.cwnd < ssthresh)
+    }
+
+    fn after_recovery_start(&self, pkt: &sent::Packet) -> bool {
+        self.recovery_start.is_none_or(|pn| pkt.pn() >= pn)
+    }
+
+    fn app_limited(&self) -> bool {
+        if self.bytes_in_flight >= self.cwnd {
+            false
+        } else if self.in_slow_start() {
+            self.bytes_in_flight < self.cwnd / 2
+        } else {
+            self.bytes_in_flight + self.mtu() * PACING_BURST_SIZE < self.cwnd
+        }
+    }
+
+    /// Fold the CE fraction of the round that just ended into `alpha`.
+    fn end_round(&mut self) {
+        if let Some(fraction) = (self.round_ce * ALPHA_ONE).checked_div(self.round_acked) {
+            let fraction = min(fraction, ALPHA_ONE);
+            self.alpha =
+                self.alpha - (self.alpha >> ALPHA_GAIN_SHIFT) + (fraction >> ALPHA_GAIN_SHIFT);
+            qtrace!(
+                "[{self}] round ended with {} of {} packets marked",
+                self.round_ce,
+                self.round_acked
+            );
+        }
+        self.round_acked = 0;
+        self.round_ce = 0;
+        self.round_end = self.next_pn;
+    }
+
+    /// Take a packet that was acknowledged, declared lost, or discarded out of the bytes in
+    /// flight.  A packet is only taken out once: acknowledgments for packets that were
+    /// declared lost are ignored, as in [`super::ClassicCongestionController`].
+    const fn remove_in_flight(&mut self, pkt: &sent::Packet) {
+        // This is reset on a path change, but packets sent before a simple rebinding
+        // can still be acknowledged or declared lost.
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+    }
+
+    /// After persistent congestion, start again from the minimum window in slow start,
+    /// keeping the slow start threshold from the reduction that came before it.
+    fn on_persistent_congestion(&mut self, now: Instant) {
+        qinfo!("[{self}] persistent congestion");
+        self.cwnd = self.cwnd_min();
+        self.acked_bytes = 0;
+        self.recovery_start = Some(self.next_pn);
+        self.qlog_metrics(now);
+    }
+
+    /// Reduce the congestion window to `cwnd` and start a new recovery period.
+    fn reduce_cwnd(
+        &mut self,
+        cwnd: usize,
+        trigger: CongestionTrigger,
+        bytes_in_flight: usize,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        let detection_cwnd = self.cwnd;
+        let in_slow_start = self.in_slow_start();
+        self.cwnd = max(cwnd, self.cwnd_min());
+        self.ssthresh = Some(self.cwnd);
+        self.acked_bytes = 0;
+        self.recovery_start = Some(self.next_pn);
+        qinfo!("[{self}] Cong event {trigger:?} -> cwnd {}", self.cwnd);
+
+        match trigger {
+            CongestionTrigger::Loss(_) => cc_stats.congestion_events.loss += 1,
+            CongestionTrigger::Ecn => cc_stats.congestion_events.ecn += 1,
+        }
+        if in_slow_start {
+            cc_stats.slow_start_exit = Some(SlowStartExitStats {
+                reason: SlowStartExitReason::CongestionEvent(trigger),
+                detection_cwnd,
+                exit_cwnd: self.cwnd,
+                bytes_in_flight,
+            });
+        }
+        self.qlog_metrics(now);
+    }
+
+    fn qlog_metrics(&mut self, now: Instant) {
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [
+                Some(qlog::Metric::CongestionWindow(self.cwnd)),
+                Some(qlog::Metric::BytesInFlight(self.bytes_in_flight)),
+                self.ssthresh.map(qlog::Metric::SsThresh),
+            ]
+            .into_iter()
+            .flatten(),
+            now,
+        );
+    }
+}
+
+impl CongestionController for Prague {
+    fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    fn cwnd_avail(&self) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight)
+    }
+
+    fn cwnd_min(&self) -> usize {
+        self.mtu() * 2
+    }
+
+    fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        _rate_sample: &RateSample,
+        _rtt_est: &RttEstimate,
+        now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) {
+        let app_limited = self.app_limited();
+        let mut new_acked = 0;
+        for pkt in acked_pkts.iter().filter(|p| p.cc_outstanding()) {
+            self.remove_in_flight(pkt);
+            self.round_acked += 1;
+            if self.after_recovery_start(pkt) {
+                new_acked += pkt.len();
+            }
+        }
+        if acked_pkts.first().is_some_and(|p| p.pn() >= self.round_end) {
+            self.end_round();
+        }
+
+        if !app_limited && new_acked > 0 {
+            if self.in_slow_start() {
+                self.cwnd += new_acked;
+            } else {
+                self.acked_bytes += new_acked;
+                if self.acked_bytes >= self.cwnd {
+                    self.acked_bytes -= self.cwnd;
+                    self.cwnd += self.mtu();
+                }
+            }
+        }
+        self.qlog_metrics(now);
+    }
+
+    fn on_packets_lost(
+        &mut self,
+        first_rtt_sample_time: Option<Instant>,
+        prev_largest_acked_sent: Option<Instant>,
+        pto: Duration,
+        lost_packets: &[sent::Packet],
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let bytes_in_flight = self.bytes_in_flight;
+        let mut lost_count = 0;
+        let mut largest = None;
+        for pkt in lost_packets.iter().filter(|p| p.cc_in_flight()) {
+            self.remove_in_flight(pkt);
+            // Lost PMTUD probes say nothing about congestion.
+            if !pkt.is_pmtud_probe() {
+                lost_count += 1;
+                largest = Some(pkt);
+            }
+        }
+        let Some(largest) = largest else {
+            self.qlog_metrics(now);
+            return false;
+        };
+
+        let congestion = self.after_recovery_start(largest);
+        if congestion {
+            qdebug!(
+                "[{self}] {lost_count} packets lost, largest {}",
+                largest.pn()
+            );
+            self.reduce_cwnd(
+                self.cwnd / 2,
+                CongestionTrigger::Loss(lost_count),
+                bytes_in_flight,
+                now,
+                cc_stats,
+            );
+        }
+        // Persistent congestion checks still need to see lost packets that are not in flight.
+        let persistent = persistent_congestion(
+            first_rtt_sample_time,
+            prev_largest_acked_sent,
+            pto,
+            lost_packets.iter().filter(|p| !p.is_pmtud_probe()),
+        );
+        if persistent {
+            self.on_persistent_congestion(now);
+        } else if !congestion {
+            self.qlog_metrics(now);
+        }
+        congestion || persistent
+    }
+
+    fn on_ecn_ce_received(
+        &mut self,
+        largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        self.round_ce += ce_marks;
+        if !self.after_recovery_start(largest_acked_pkt) {
+            return false;
+        }
+        let reduction =
+            u128::from(self.alpha) * u128::from(to_u64(self.cwnd)) / u128::from(2 * ALPHA_ONE);
+        let cwnd = self
+            .cwnd
+            .saturating_sub(usize::try_from(reduction).unwrap_or(usize::MAX));
+        let prev_cwnd = self.cwnd;
+        self.reduce_cwnd(
+            cwnd,
+            CongestionTrigger::Ecn,
+            self.bytes_in_flight,
+            now,
+            cc_stats,
+        );
+        self.cwnd < prev_cwnd
+    }
+
+    fn recovery_packet(&self) -> bool {
+        false
+    }
+
+    fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
+        if !pkt.cc_outstanding() {
+            return;
+        }
+        self.remove_in_flight(pkt);
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn on_packet_sent(&mut self, pkt: &sent::Packet, now: Instant) {
+        if !pkt.cc_in_flight() {
+            return;
+        }
+        self.next_pn = max(self.next_pn, pkt.pn() + 1);
+        self.bytes_in_flight += pkt.len();
+        qtrace!("[{self}] packet_sent pn={} len={}", pkt.pn(), pkt.len());
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn discard_in_flight(&mut self, now: Instant) {
+        self.bytes_in_flight = 0;
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+}
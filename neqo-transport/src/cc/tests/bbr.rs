@@ -0,0 +1,303 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Tests for the BBRv3 congestion controller, driven over an emulated bottleneck link.
+
+use std::{
+    cmp::{max, min},
+    collections::VecDeque,
+    mem,
+    time::{Duration, Instant},
+};
+
+use neqo_common::to_u64;
+use test_fixture::now;
+
+use super::make_cc_bbr;
+use crate::{
+    cc::{Bbr, CongestionController as _, PERSISTENT_CONG_THRESH, bbr::State},
+    recovery::{RateSample, rate, sent},
+    rtt::RttEstimate,
+    stats::{CongestionControlStats, SlowStartExitReason},
+};
+
+/// 10 Mbit/s.
+const LINK_RATE: u64 = 1_250_000;
+const LINK_RTT: Duration = Duration::from_millis(40);
+
+/// A bottleneck link with an unlimited FIFO queue.
+struct Link {
+    /// The bottleneck rate in bytes per second.
+    rate: u64,
+    /// The base RTT of the path.
+    rtt: Duration,
+    /// If set, the link drops every `n`th packet.
+    drop_every: Option<u64>,
+    /// When the link finishes transmitting the last queued packet.
+    busy_until: Instant,
+    /// The packets in flight, with the time at which their ACK arrives.
+    in_flight: VecDeque<(Instant, sent::Packet)>,
+    /// The packets dropped by the link that have not been declared lost yet.
+    dropped: Vec<sent::Packet>,
+    next_pn: u64,
+    estimator: rate::Estimator,
+    stats: CongestionControlStats,
+}
+
+impl Link {
+    fn new(drop_every: Option<u64>, now: Instant) -> Self {
+        Self {
+            rate: LINK_RATE,
+            rtt: LINK_RTT,
+            drop_every,
+            busy_until: now,
+            in_flight: VecDeque::new(),
+            dropped: Vec::new(),
+            next_pn: 0,
+            estimator: rate::Estimator::new(now),
+            stats: CongestionControlStats::default(),
+        }
+    }
+
+    fn send(&mut self, cc: &mut Bbr, now: Instant) {
+        let mut pkt = sent::make_packet(self.next_pn, now, cc.pmtud().plpmtu());
+        self.estimator
+            .on_packet_sent(&mut pkt, cc.bytes_in_flight());
+        cc.on_packet_sent(&pkt, now);
+        if self.drop_every.is_some_and(|n| self.next_pn % n == n - 1) {
+            self.dropped.push(pkt);
+        } else {
+            let tx = Duration::from_nanos(to_u64(pkt.len()) * 1_000_000_000 / self.rate);
+            self.busy_until = max(self.busy_until, now) + tx;
+            self.in_flight.push_back((self.busy_until + self.rtt, pkt));
+        }
+        self.next_pn += 1;
+    }
+
+    /// Deliver the ACKs that have arrived by `now` and declare the packets
+    /// that they show to be missing lost.
+    fn deliver(&mut self, cc: &mut Bbr, now: Instant) {
+        let mut acked = Vec::new();
+        while self.in_flight.front().is_some_and(|(t, _)| *t <= now) {
+            acked.extend(self.in_flight.pop_front().map(|(_, pkt)| pkt));
+        }
+        // ACK frames list the largest packet first.
+        acked.reverse();
+        if let Some(largest) = acked.first().map(sent::Packet::pn) {
+            let rtt_est = RttEstimate::new(self.rtt);
+            let rs = self.estimator.on_packets_acked(&acked, &rtt_est, now);
+            cc.on_packets_acked(&acked, &rs, &rtt_est, now, &mut self.stats);
+            let lost: Vec<_> = self.dropped.extract_if(.., |p| p.pn() < largest).collect();
+            self.declare_lost(cc, &lost, now);
+        } else if self.in_flight.is_empty() {
+            // Nothing left to elicit an ACK, so a PTO would find these lost.
+            let lost = mem::take(&mut self.dropped);
+            self.declare_lost(cc, &lost, now);
+        }
+    }
+
+    fn declare_lost(&mut self, cc: &mut Bbr, lost: &[sent::Packet], now: Instant) {
+        if !lost.is_empty() {
+            self.estimator.on_packets_lost(lost);
+            cc.on_packets_lost(None, None, self.rtt, lost, now, &mut self.stats);
+        }
+    }
+
+    /// Send as fast as `cc` allows for `duration`, calling `observe` after every event.
+    fn run(
+        &mut self,
+        cc: &mut Bbr,
+        start: Instant,
+        duration: Duration,
+        mut observe: impl FnMut(&Bbr),
+    ) -> Instant {
+        let mtu = cc.pmtud().plpmtu();
+        let mut now = start;
+        let mut next_send = start;
+        while now < start + duration {
+            self.deliver(cc, now);
+            while next_send <= now && cc.cwnd_avail() >= mtu {
+                self.send(cc, now);
+                next_send = cc.pacing_rate().map_or(now, |rate| {
+                    now + Duration::from_nanos(to_u64(mtu) * 1_000_000_000 / rate)
+                });
+            }
+            observe(cc);
+            let next_ack = self.in_flight.front().map(|(t, _)| *t);
+            now = if next_send > now {
+                next_ack.map_or(next_send, |t| min(t, next_send))
+            } else {
+                next_ack.unwrap_or(now + self.rtt)
+            };
+        }
+        now
+    }
+}
+
+/// Run until `cc` has left Startup and Drain and return the states it went through.
+fn run_to_probe_bw(cc: &mut Bbr, link: &mut Link, now: Instant) -> (Instant, Vec<State>) {
+    let mut states = vec![cc.state()];
+    let now = link.run(cc, now, Duration::from_secs(3), |cc| {
+        if states.last() != Some(&cc.state()) {
+            states.push(cc.state());
+        }
+    });
+    (now, states)
+}
+
+#[test]
+fn startup_drain_probe_bw() {
+    let mut cc = make_cc_bbr();
+    let mut link = Link::new(None, now());
+    assert_eq!(cc.pacing_rate(), None);
+
+    let (_, states) = run_to_probe_bw(&mut cc, &mut link, now());
+    assert_eq!(states[..2], [State::Startup, State::Drain]);
+    assert!(states[2..].iter().all(|s| s.is_probe_bw()), "{states:?}");
+    assert_eq!(
+        link.stats
+            .slow_start_exit
+            .as_ref()
+            .map(|e| e.reason.clone()),
+        Some(SlowStartExitReason::Heuristic)
+    );
+    assert_eq!(link.stats.congestion_events.loss, 0);
+
+    // The model matches the path.
+    assert!(cc.bw() > LINK_RATE * 9 / 10, "bw {} too low", cc.bw());
+    assert!(cc.bw() < LINK_RATE * 11 / 10, "bw {} too high", cc.bw());
+    let min_rtt = cc.min_rtt().expect("have min RTT");
+    assert!(min_rtt >= LINK_RTT && min_rtt < LINK_RTT * 11 / 10);
+    assert!(cc.pacing_rate().is_some_and(|r| r < LINK_RATE * 13 / 10));
+}
+
+#[test]
+fn probe_rtt() {
+    let mut cc = make_cc_bbr();
+    let mut link = Link::new(None, now());
+    let (now, _) = run_to_probe_bw(&mut cc, &mut link, now());
+
+    // Without a lower RTT sample, BBR enters ProbeRTT and drains the queue.
+    let mut probe_rtt_cwnd = None;
+    let mut after_probe_rtt = None;
+    link.run(&mut cc, now, Duration::from_secs(8), |cc| {
+        if cc.state() == State::ProbeRtt {
+            probe_rtt_cwnd = Some(min(probe_rtt_cwnd.unwrap_or(usize::MAX), cc.cwnd()));
+        } else if probe_rtt_cwnd.is_some() && after_probe_rtt.is_none() {
+            after_probe_rtt = Some(cc.state());
+        }
+    });
+    let probe_rtt_cwnd = probe_rtt_cwnd.expect("entered ProbeRTT");
+    let min_rtt = cc.min_rtt().expect("have min RTT");
+    let bdp = usize::try_from(u128::from(cc.bw()) * min_rtt.as_nanos() / 1_000_000_000)
+        .expect("BDP fits in usize");
+    assert!(probe_rtt_cwnd <= max(bdp / 2, cc.cwnd_min()));
+    assert_eq!(after_probe_rtt, Some(State::ProbeBwCruise));
+    assert!(cc.cwnd() > probe_rtt_cwnd);
+}
+
+#[test]
+fn loss_sets_inflight_hi() {
+    let mut cc = make_cc_bbr();
+    // 5% loss is above the loss threshold of BBR.
+    let mut link = Link::new(Some(20), now());
+    let (now, _) = run_to_probe_bw(&mut cc, &mut link, now());
+    link.run(&mut cc, now, Duration::from_secs(10), |_| {});
+
+    assert!(link.stats.congestion_events.loss > 0);
+    let inflight_hi = cc.inflight_hi().expect("inflight_hi is set");
+    assert!(cc.cwnd() <= max(inflight_hi, cc.cwnd_min()));
+    assert!(cc.bw() > 0);
+}
+
+#[test]
+fn persistent_congestion() {
+    let mut cc = make_cc_bbr();
+    let mut link = Link::new(None, now());
+    let (start, _) = run_to_probe_bw(&mut cc, &mut link, now());
+    assert!(cc.cwnd() > cc.cwnd_min());
+
+    // Two contiguous packets that were sent more than `PERSISTENT_CONG_THRESH` PTOs apart are
+    // lost, which collapses the window and discards the model.
+    let pto = LINK_RTT;
+    let mtu = cc.pmtud().plpmtu();
+    let end = start + pto * PERSISTENT_CONG_THRESH + Duration::from_millis(1);
+    let lost: Vec<_> = [start, end]
+        .into_iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let pkt = sent::make_packet(link.next_pn + to_u64(i), t, mtu);
+            cc.on_packet_sent(&pkt, t);
+            pkt
+        })
+        .collect();
+    assert!(cc.on_packets_lost(Some(now()), None, pto, &lost, end + pto, &mut link.stats));
+    assert_eq!(cc.cwnd(), cc.cwnd_min());
+    assert_eq!(cc.state(), State::Startup);
+    assert_eq!(cc.bw(), 0);
+    assert_eq!(cc.inflight_hi(), None);
+}
+
+#[test]
+fn app_limited_keeps_bw() {
+    let mut cc = make_cc_bbr();
+    let mut link = Link::new(None, now());
+    let (mut now, _) = run_to_probe_bw(&mut cc, &mut link, now());
+    let bw = cc.bw();
+
+    // Send a single packet per RTT.
+    for _ in 0..10 {
+        link.estimator.on_app_limited(cc.bytes_in_flight());
+        link.send(&mut cc, now);
+        now += LINK_RTT * 2;
+        link.deliver(&mut cc, now);
+    }
+    assert_eq!(cc.bytes_in_flight(), 0);
+    assert!(cc.bw() >= bw, "app-limited samples lowered bw");
+}
+
+#[test]
+fn ecn_ce_once_per_round() {
+    let mut cc = make_cc_bbr();
+    let mut cc_stats = CongestionControlStats::default();
+    let now = now();
+    let pkt = sent::make_packet(0, now, cc.pmtud().plpmtu());
+    cc.on_packet_sent(&pkt, now);
+    let cwnd = cc.cwnd();
+    assert!(!cc.on_ecn_ce_received(&pkt, 1, now, &mut cc_stats));
+    assert!(!cc.on_ecn_ce_received(&pkt, 1, now, &mut cc_stats));
+    assert_eq!(cc_stats.congestion_events.ecn, 1);
+    assert_eq!(cc.cwnd(), cwnd);
+}
+
+#[test]
+fn discard_in_flight() {
+    let mut cc = make_cc_bbr();
+    let now = now();
+    let mtu = cc.pmtud().plpmtu();
+    let pkts: Vec<_> = (0..3)
+        .map(|pn| {
+            let pkt = sent::make_packet(pn, now, mtu);
+            cc.on_packet_sent(&pkt, now);
+            pkt
+        })
+        .collect();
+    assert_eq!(cc.bytes_in_flight(), 3 * mtu);
+    cc.discard(&pkts[0], now);
+    assert_eq!(cc.bytes_in_flight(), 2 * mtu);
+    cc.discard_in_flight(now);
+    assert_eq!(cc.bytes_in_flight(), 0);
+
+    // A late ACK for discarded packets doesn't underflow.
+    cc.on_packets_acked(
+        &pkts,
+        &RateSample::default(),
+        &RttEstimate::new(LINK_RTT),
+        now + LINK_RTT,
+        &mut CongestionControlStats::default(),
+    );
+    assert_eq!(cc.bytes_in_flight(), 0);
+}
@@ -24,7 +24,7 @@ use crate::{
         classic_cc::ClassicCongestionController,
         cubic::{Cubic, convert_to_f64},
     },
-    recovery::sent,
+    recovery::{RateSample, sent},
     rtt::RttEstimate,
     stats::CongestionControlStats,
 };
@@ -85,7 +85,13 @@ fn ack_packet(
     cc_stats: &mut CongestionControlStats,
 ) {
     let acked = sent::make_packet(pn, now, cc.max_datagram_size());
-    cc.on_packets_acked(&[acked], &RttEstimate::new(RTT), now, cc_stats);
+    cc.on_packets_acked(
+        &[acked],
+        &RateSample::default(),
+        &RttEstimate::new(RTT),
+        now,
+        cc_stats,
+    );
 }
 
 fn packet_lost(
@@ -106,7 +112,7 @@ fn ecn_ce(
     cc_stats: &mut CongestionControlStats,
 ) {
     let pkt = sent::make_packet(pn, now, cc.max_datagram_size());
-    cc.on_ecn_ce_received(&pkt, now, cc_stats);
+    cc.on_ecn_ce_received(&pkt, 1, now, cc_stats);
 }
 
 fn expected_tcp_acks(cwnd_rtt_start: usize, mtu: usize) -> u64 {
@@ -18,7 +18,7 @@ use crate::{
         hystart::HyStart, tests::INITIAL_CWND,
     },
     packet::MIN_INITIAL_PACKET_SIZE,
-    recovery::sent,
+    recovery::{RateSample, sent},
     rtt::RttEstimate,
     stats::{CongestionControlStats, SlowStartExitReason},
 };
@@ -714,7 +714,7 @@ fn integration_full_slow_start_to_css_to_ca() {
         );
         let cwnd_before = cc.cwnd();
         let ssthresh_before = cc.ssthresh();
-        cc.on_packets_acked(&[pkt], rtt_est, now, &mut stats);
+        cc.on_packets_acked(&[pkt], &RateSample::default(), rtt_est, now, &mut stats);
         let cwnd_after = cc.cwnd();
         let ssthresh_after = cc.ssthresh();
         let growth = cwnd_after - cwnd_before;
@@ -9,17 +9,21 @@ use std::{
     time::Duration,
 };
 
+use test_fixture::now;
+
 use crate::{
     MIN_INITIAL_PACKET_SIZE, Pmtud,
     cc::{
-        CWND_INITIAL_PKTS, ClassicSlowStart, classic_cc::ClassicCongestionController, cubic::Cubic,
-        hystart::HyStart, new_reno::NewReno,
+        Bbr, CWND_INITIAL_PKTS, ClassicSlowStart, Prague, classic_cc::ClassicCongestionController,
+        cubic::Cubic, hystart::HyStart, new_reno::NewReno,
     },
 };
 
+mod bbr;
 mod cubic;
 mod hystart;
 mod new_reno;
+mod prague;
 mod search;
 
 pub const IP_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
@@ -56,3 +60,13 @@ pub fn make_cc_hystart(paced: bool) -> ClassicCongestionController<HyStart, Cubi
         true,
     )
 }
+
+/// Helper to create `Bbr` for tests.
+pub fn make_cc_bbr() -> Bbr {
+    Bbr::new(Pmtud::new(IP_ADDR, MTU), now())
+}
+
+/// Helper to create `Prague` for tests.
+pub fn make_cc_prague() -> Prague {
+    Prague::new(Pmtud::new(IP_ADDR, MTU))
+}
@@ -22,7 +22,7 @@ use crate::{
         ClassicCongestionController, ClassicSlowStart, CongestionController as _, new_reno::NewReno,
     },
     packet,
-    recovery::{self, sent},
+    recovery::{self, RateSample, sent},
     rtt::RttEstimate,
     stats::CongestionControlStats,
 };
@@ -139,6 +139,7 @@ fn issue_876() {
     // and ack it. cwnd increases slightly
     cc.on_packets_acked(
         &sent_packets[6..],
+        &RateSample::default(),
         &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
         now,
         &mut cc_stats,
@@ -217,6 +218,7 @@ fn issue_1465() {
     // https://github.com/mozilla/neqo/pull/1465
     cc.on_packets_acked(
         &[p2],
+        &RateSample::default(),
         &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
         now,
         &mut cc_stats,
@@ -229,6 +231,7 @@ fn issue_1465() {
     now += RTT;
     cc.on_packets_acked(
         &[p4],
+        &RateSample::default(),
         &RttEstimate::new(crate::DEFAULT_INITIAL_RTT),
         now,
         &mut cc_stats,
@@ -284,7 +287,13 @@ fn congestion_avoidance_no_two_mss_cap() {
     }
 
     // ACK all packets in one call: new_acked = 3 * cwnd + mtu.
-    cc.on_packets_acked(&pkts, &RttEstimate::new(RTT), now + RTT, &mut cc_stats);
+    cc.on_packets_acked(
+        &pkts,
+        &RateSample::default(),
+        &RttEstimate::new(RTT),
+        now + RTT,
+        &mut cc_stats,
+    );
 
     // new_acked / bytes_for_increase = (3*cwnd0 + mtu) / cwnd0 = 3 increments,
     // remainder = mtu (one MTU of carry preserved for the next ACK).
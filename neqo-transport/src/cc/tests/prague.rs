@@ -0,0 +1,211 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Tests for the Prague congestion controller.
+
+use std::time::{Duration, Instant};
+
+use neqo_common::to_u64;
+use test_fixture::now;
+
+use super::{RTT, make_cc_prague};
+use crate::{
+    cc::{CongestionController as _, CongestionTrigger, PERSISTENT_CONG_THRESH, Prague},
+    packet,
+    recovery::{RateSample, sent},
+    rtt::RttEstimate,
+    stats::{CongestionControlStats, SlowStartExitReason},
+};
+
+/// Send a full congestion window of packets, then acknowledge them all at once, with `ce` of
+/// them reported as CE marked.
+fn round(
+    cc: &mut Prague,
+    next_pn: &mut packet::Number,
+    ce: u64,
+    now: Instant,
+    stats: &mut CongestionControlStats,
+) -> Instant {
+    let mtu = cc.pmtud().plpmtu();
+    let mut pkts = Vec::new();
+    while cc.cwnd_avail() >= mtu {
+        let pkt = sent::make_packet(*next_pn, now, mtu);
+        cc.on_packet_sent(&pkt, now);
+        pkts.push(pkt);
+        *next_pn += 1;
+    }
+    pkts.reverse();
+    let now = now + RTT;
+    if ce > 0 {
+        cc.on_ecn_ce_received(&pkts[0], ce, now, stats);
+    }
+    cc.on_packets_acked(
+        &pkts,
+        &RateSample::default(),
+        &RttEstimate::new(RTT),
+        now,
+        stats,
+    );
+    now
+}
+
+#[test]
+fn first_ce_halves_cwnd() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let mut pn = 0;
+    let cwnd = cc.cwnd();
+
+    // `alpha` starts at 1, so the first reduction is as large as in Reno.
+    round(&mut cc, &mut pn, 1, now(), &mut stats);
+    assert_eq!(cc.cwnd(), cwnd / 2);
+    assert_eq!(stats.congestion_events.ecn, 1);
+    assert_eq!(
+        stats.slow_start_exit.map(|e| e.reason),
+        Some(SlowStartExitReason::CongestionEvent(CongestionTrigger::Ecn))
+    );
+}
+
+#[test]
+fn ce_once_per_round() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let now = now();
+    let mtu = cc.pmtud().plpmtu();
+    let pkts: Vec<_> = (0..3)
+        .map(|pn| {
+            let pkt = sent::make_packet(pn, now, mtu);
+            cc.on_packet_sent(&pkt, now);
+            pkt
+        })
+        .collect();
+    assert!(cc.on_ecn_ce_received(&pkts[0], 1, now, &mut stats));
+    let cwnd = cc.cwnd();
+    // Marks on packets sent before the reduction don't reduce again.
+    assert!(!cc.on_ecn_ce_received(&pkts[2], 1, now, &mut stats));
+    assert_eq!(cc.cwnd(), cwnd);
+    assert_eq!(stats.congestion_events.ecn, 1);
+}
+
+#[test]
+fn alpha_tracks_ce_fraction() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let mut pn = 0;
+    let mut now = now();
+
+    now = round(&mut cc, &mut pn, 1, now, &mut stats);
+
+    // Without marks, `alpha` decays towards zero.
+    for _ in 0..100 {
+        now = round(&mut cc, &mut pn, 0, now, &mut stats);
+    }
+    assert!(cc.alpha() < Prague::alpha_one() / 100);
+
+    // With a fifth of the packets marked in every other round, `alpha` converges on a tenth.
+    for i in 0..200 {
+        let ce = if i % 2 == 0 {
+            to_u64(cc.cwnd() / cc.pmtud().plpmtu() / 5)
+        } else {
+            0
+        };
+        now = round(&mut cc, &mut pn, ce, now, &mut stats);
+    }
+    let alpha = cc.alpha() * 100 / Prague::alpha_one();
+    assert!(
+        (5..=20).contains(&alpha),
+        "alpha {alpha}% is not close to 10%"
+    );
+}
+
+#[test]
+fn reduction_proportional_to_alpha() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let mut pn = 0;
+    let mut now = round(&mut cc, &mut pn, 1, now(), &mut stats);
+    for _ in 0..100 {
+        now = round(&mut cc, &mut pn, 0, now, &mut stats);
+    }
+    let cwnd = cc.cwnd();
+    let alpha = cc.alpha();
+
+    // A single mark with a small `alpha` leads to a small reduction.
+    round(&mut cc, &mut pn, 1, now, &mut stats);
+    let reduction = to_u64(cwnd) * alpha / (2 * Prague::alpha_one());
+    assert_eq!(to_u64(cc.cwnd()), to_u64(cwnd) - reduction);
+    assert!(cc.cwnd() > cwnd * 9 / 10);
+}
+
+#[test]
+fn loss_halves_cwnd() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let mut pn = 0;
+    let now = round(&mut cc, &mut pn, 0, now(), &mut stats);
+    let cwnd = cc.cwnd();
+    let lost = sent::make_packet(pn, now, cc.pmtud().plpmtu());
+    cc.on_packet_sent(&lost, now);
+    assert!(cc.on_packets_lost(None, None, Duration::ZERO, &[lost], now + RTT, &mut stats));
+    assert_eq!(cc.cwnd(), cwnd / 2);
+    assert_eq!(cc.bytes_in_flight(), 0);
+    assert_eq!(stats.congestion_events.loss, 1);
+}
+
+#[test]
+fn persistent_congestion() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let mut pn = 0;
+    let start = round(&mut cc, &mut pn, 0, now(), &mut stats);
+    let mtu = cc.pmtud().plpmtu();
+
+    // Two contiguous packets that were sent more than `PERSISTENT_CONG_THRESH` PTOs apart
+    // are lost, which collapses the window.
+    let end = start + RTT * PERSISTENT_CONG_THRESH + Duration::from_millis(1);
+    let lost = [
+        sent::make_packet(pn, start, mtu),
+        sent::make_packet(pn + 1, end, mtu),
+    ];
+    for pkt in &lost {
+        cc.on_packet_sent(pkt, pkt.time_sent());
+    }
+    assert!(cc.on_packets_lost(Some(now()), None, RTT, &lost, end + RTT, &mut stats));
+    assert_eq!(cc.cwnd(), cc.cwnd_min());
+    assert_eq!(cc.bytes_in_flight(), 0);
+}
+
+#[test]
+fn late_ack_after_loss() {
+    let mut cc = make_cc_prague();
+    let mut stats = CongestionControlStats::default();
+    let now = now();
+    let mtu = cc.pmtud().plpmtu();
+    let mut pkts: Vec<_> = (0..3)
+        .map(|pn| {
+            let pkt = sent::make_packet(pn, now, mtu);
+            cc.on_packet_sent(&pkt, now);
+            pkt
+        })
+        .collect();
+
+    // Loss recovery marks packets as lost before passing them on.
+    assert!(pkts[0].declare_lost(now + RTT, sent::LossTrigger::TimeThreshold));
+    cc.on_packets_lost(None, None, RTT, &pkts[..1], now + RTT, &mut stats);
+    assert_eq!(cc.bytes_in_flight(), 2 * mtu);
+
+    // An acknowledgment for the lost packet doesn't take it out of the bytes in flight again.
+    pkts.truncate(2);
+    pkts.reverse();
+    cc.on_packets_acked(
+        &pkts,
+        &RateSample::default(),
+        &RttEstimate::new(RTT),
+        now + RTT,
+        &mut stats,
+    );
+    assert_eq!(cc.bytes_in_flight(), mtu);
+}
@@ -11,8 +11,10 @@ use std::{
     cell::{Ref, RefCell},
     cmp::{max, min},
     fmt::{self, Debug, Display, Formatter},
+    mem,
     ops::Deref,
     rc::Rc,
+    time::{Duration, Instant},
 };
 
 use neqo_common::{
@@ -27,7 +29,7 @@ use crate::{
     Error, Res,
     frame::{FrameEncoder as _, FrameType},
     packet, recovery,
-    stateless_reset::Token as Srt,
+    stateless_reset::{Key as StatelessResetKey, Token as Srt},
     stats::FrameStats,
 };
 
@@ -285,21 +287,27 @@ impl ConnectionIdEntry<Srt> {
         self.seqno
     }
 
-    /// Write the entry out in a `NEW_CONNECTION_ID` frame.
+    /// Write the entry out in a `NEW_CONNECTION_ID` frame, with the given Retire Prior To value.
     /// Returns `true` if the frame was written, `false` if there is insufficient space.
     pub fn write<B: Buffer>(
         &self,
+        retire_prior: u64,
         builder: &mut packet::Builder<B>,
         stats: &mut FrameStats,
     ) -> bool {
-        let len = 1 + Encoder::varint_len(self.seqno) + 1 + 1 + self.cid.len() + Srt::LEN;
+        let len = 1
+            + Encoder::varint_len(self.seqno)
+            + Encoder::varint_len(retire_prior)
+            + 1
+            + self.cid.len()
+            + Srt::LEN;
         if builder.remaining() < len {
             return false;
         }
 
         builder.encode_frame(FrameType::NewConnectionId, |b| {
             b.encode_varint(self.seqno);
-            b.encode_varint(0u64);
+            b.encode_varint(retire_prior);
             b.encode_vec(1, &self.cid);
             b.encode(&self.srt);
         });
@@ -310,6 +318,27 @@ impl ConnectionIdEntry<Srt> {
     pub fn is_empty(&self) -> bool {
         self.seqno == ConnectionIdManager::SEQNO_EMPTY || self.cid.is_empty()
     }
+
+    /// Write out the entry, so that it can be restored with [`Self::import`].
+    pub fn export(&self, enc: &mut Encoder) {
+        enc.encode_varint(self.seqno)
+            .encode_vec(1, &self.cid)
+            .encode(&self.srt);
+    }
+
+    /// Read an entry from the output of [`Self::export`].
+    pub fn import(dec: &mut Decoder) -> Res<Self> {
+        let seqno = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let cid = dec
+            .decode_vec(1)
+            .filter(|cid| cid.len() <= ConnectionId::MAX_LEN)
+            .ok_or(Error::InvalidInput)?;
+        let srt = dec
+            .decode(Srt::LEN)
+            .and_then(|srt| Srt::try_from(srt).ok())
+            .ok_or(Error::InvalidInput)?;
+        Ok(Self::new(seqno, ConnectionId::from(cid), srt))
+    }
 }
 
 impl<T: Clone + PartialEq> ConnectionIdEntry<T> {
@@ -401,6 +430,26 @@ impl ConnectionIdStore<Srt> {
         }
     }
 
+    /// Write out the stored connection IDs, so that they can be restored with [`Self::import`].
+    pub fn export(&self, enc: &mut Encoder) {
+        enc.encode_varint(to_u64(self.cids.len()));
+        for entry in &self.cids {
+            entry.export(enc);
+        }
+    }
+
+    /// Add connection IDs from the output of [`Self::export`].
+    pub fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        let count = dec
+            .decode_varint()
+            .filter(|&n| n <= to_u64(ConnectionIdManager::MAX_RETIRE_QUEUE))
+            .ok_or(Error::InvalidInput)?;
+        for _ in 0..count {
+            self.add_remote(ConnectionIdEntry::import(dec)?)?;
+        }
+        Ok(())
+    }
+
     // Retire connection IDs and return the sequence numbers of those that were retired.
     pub fn retire_prior_to(&mut self, retire_prior: u64) -> Vec<u64> {
         let mut retired = Vec::new();
@@ -434,6 +483,14 @@ impl<'a: 'b, 'b> ConnectionIdDecoderRef<'a> {
     }
 }
 
+/// A change to the set of local connection IDs.
+/// See [`ConnectionIdManager::track_changes`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum LocalConnectionIdChange {
+    Added(ConnectionId),
+    Retired(ConnectionId),
+}
+
 /// A connection ID manager looks after the generation of connection IDs,
 /// the set of connection IDs that are valid for the connection, and the
 /// generation of `NEW_CONNECTION_ID` frames.
@@ -450,8 +507,12 @@ pub struct ConnectionIdManager {
     limit: usize,
     /// The next sequence number that will be used for sending `NEW_CONNECTION_ID` frames.
     next_seqno: u64,
+    /// The Retire Prior To value that is sent in `NEW_CONNECTION_ID` frames.
+    retire_prior: u64,
     /// Outstanding, but lost `NEW_CONNECTION_ID` frames will be stored here.
     lost_new_connection_id: Vec<ConnectionIdEntry<Srt>>,
+    /// Changes to `connection_ids` that have not been taken yet, if these are tracked.
+    changes: Option<Vec<LocalConnectionIdChange>>,
 }
 
 impl ConnectionIdManager {
@@ -485,10 +546,52 @@ impl ConnectionIdManager {
             // value remains until the connection completes and transport parameters are handled.
             limit: 2,
             next_seqno: 1,
+            retire_prior: 0,
             lost_new_connection_id: Vec::new(),
+            changes: None,
+        }
+    }
+
+    /// Start tracking changes to the set of local connection IDs, so that
+    /// something that routes packets by connection ID can keep up.
+    /// The connection IDs that are already valid are reported as added.
+    pub fn track_changes(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(
+                self.connection_ids
+                    .cids
+                    .iter()
+                    .map(|e| LocalConnectionIdChange::Added(e.cid.clone()))
+                    .collect(),
+            );
         }
     }
 
+    /// Take the changes to the set of local connection IDs since the last call.
+    pub fn take_changes(&mut self) -> Vec<LocalConnectionIdChange> {
+        self.changes.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    fn add_local(&mut self, entry: ConnectionIdEntry<()>) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(LocalConnectionIdChange::Added(entry.cid.clone()));
+        }
+        self.connection_ids.add_local(entry);
+    }
+
+    fn retire_local(&mut self, seqno: u64) {
+        if let Some(changes) = &mut self.changes {
+            changes.extend(
+                self.connection_ids
+                    .cids
+                    .iter()
+                    .filter(|e| e.seqno == seqno)
+                    .map(|e| LocalConnectionIdChange::Retired(e.cid.clone())),
+            );
+        }
+        self.connection_ids.retire(seqno);
+    }
+
     pub fn generator(&self) -> Rc<RefCell<dyn ConnectionIdGenerator>> {
         Rc::clone(&self.generator)
     }
@@ -499,22 +602,29 @@ impl ConnectionIdManager {
         }
     }
 
+    /// Choose the stateless reset token for a connection ID.  This is derived from
+    /// `key` if one is provided, or chosen at random otherwise.
+    fn reset_token(key: Option<&StatelessResetKey>, cid: &ConnectionId) -> Res<Srt> {
+        key.map_or_else(|| Ok(Srt::random()), |key| key.token(cid))
+    }
+
     /// Generate a connection ID and stateless reset token for a preferred address.
-    pub fn preferred_address_cid(&mut self) -> Res<(ConnectionId, Srt)> {
+    pub fn preferred_address_cid(
+        &mut self,
+        reset_key: Option<&StatelessResetKey>,
+    ) -> Res<(ConnectionId, Srt)> {
         if self.generator.deref().borrow().generates_empty_cids() {
             return Err(Error::ConnectionIdsExhausted);
         }
-        match self.generator.borrow_mut().generate_cid() {
+        let maybe_cid = self.generator.borrow_mut().generate_cid();
+        match maybe_cid {
             Some(cid) => {
                 assert_ne!(cid.len(), 0);
                 debug_assert_eq!(self.next_seqno, Self::SEQNO_PREFERRED);
-                self.connection_ids.add_local(ConnectionIdEntry::new(
-                    self.next_seqno,
-                    cid.clone(),
-                    (),
-                ));
+                let srt = Self::reset_token(reset_key, &cid)?;
+                self.add_local(ConnectionIdEntry::new(self.next_seqno, cid.clone(), ()));
                 self.next_seqno += 1;
-                Ok((cid, Srt::random()))
+                Ok((cid, srt))
             }
             None => Err(Error::ConnectionIdsExhausted),
         }
@@ -524,6 +634,16 @@ impl ConnectionIdManager {
         self.connection_ids.contains(cid)
     }
 
+    /// The local connection IDs that are currently valid.
+    #[cfg(test)]
+    pub fn local_entries(&self) -> impl Iterator<Item = (u64, &ConnectionId)> {
+        self.connection_ids.cids.iter().map(|e| (e.seqno, &e.cid))
+    }
+
+    pub fn local_cids(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.connection_ids.cids.iter().map(|e| &e.cid)
+    }
+
     pub fn retire(&mut self, seqno: u64) -> Res<()> {
         // RFC 9000, Section 19.16: receipt of a RETIRE_CONNECTION_ID frame whose
         // sequence number is greater than any we have issued (i.e. not below the
@@ -543,7 +663,7 @@ impl ConnectionIdManager {
         if empty_cid {
             qdebug!("Connection ID {seqno} is zero-length, not retiring");
         } else {
-            self.connection_ids.retire(seqno);
+            self.retire_local(seqno);
             self.lost_new_connection_id.retain(|cid| cid.seqno != seqno);
         }
         Ok(())
@@ -555,12 +675,41 @@ impl ConnectionIdManager {
     /// successfully processed.
     pub fn add_odcid(&mut self, cid: ConnectionId) {
         let entry = ConnectionIdEntry::new(Self::SEQNO_ODCID, cid, ());
-        self.connection_ids.add_local(entry);
+        self.add_local(entry);
     }
 
     /// Stop treating the original destination connection ID as valid.
     pub fn remove_odcid(&mut self) {
-        self.connection_ids.retire(Self::SEQNO_ODCID);
+        self.retire_local(Self::SEQNO_ODCID);
+    }
+
+    /// The number of connection IDs that the peer can use, which excludes those
+    /// that it has been asked to retire.
+    fn active_len(&self) -> usize {
+        self.connection_ids
+            .cids
+            .iter()
+            .filter(|e| e.seqno >= self.retire_prior)
+            .count()
+    }
+
+    /// Replace all of the connection IDs that have been issued so far.  New connection IDs
+    /// are issued with a Retire Prior To value that asks the peer to retire the existing ones.
+    /// Returns `false` if connection IDs are empty or if the peer hasn't retired all of the
+    /// connection IDs from the last rotation yet.
+    pub fn rotate(&mut self) -> bool {
+        if self.generator.deref().borrow().generates_empty_cids()
+            || self
+                .connection_ids
+                .cids
+                .iter()
+                .any(|e| e.seqno < self.retire_prior)
+        {
+            return false;
+        }
+        qdebug!("Retiring connection IDs prior to {}", self.next_seqno);
+        self.retire_prior = self.next_seqno;
+        true
     }
 
     pub fn set_limit(&mut self, limit: u64) {
@@ -571,10 +720,11 @@ impl ConnectionIdManager {
 
     pub fn write_frames<B: Buffer>(
         &mut self,
+        reset_key: Option<&StatelessResetKey>,
         builder: &mut packet::Builder<B>,
         tokens: &mut recovery::Tokens,
         stats: &mut FrameStats,
-    ) {
+    ) -> Res<()> {
         if self.generator.deref().borrow().generates_empty_cids() {
             debug_assert_eq!(
                 self.generator
@@ -584,11 +734,12 @@ impl ConnectionIdManager {
                     .len(),
                 0
             );
-            return;
+            return Ok(());
         }
 
         while let Some(entry) = self.lost_new_connection_id.pop() {
-            if entry.write(builder, stats) {
+            // Retire Prior To can't be larger than the sequence number.
+            if entry.write(min(self.retire_prior, entry.seqno), builder, stats) {
                 tokens.push(recovery::Token::NewConnectionId(entry));
             } else {
                 // This shouldn't happen often.
@@ -599,26 +750,92 @@ impl ConnectionIdManager {
 
         // Keep writing while we have fewer than the limit of active connection IDs
         // and while there is room for more.  This uses the longest connection ID
-        // length to simplify (assuming Retire Prior To is just 1 byte).
-        while self.connection_ids.len() < self.limit && builder.remaining() >= 47 {
+        // and sequence number lengths to simplify.
+        let max_len = 46 + Encoder::varint_len(self.retire_prior);
+        while self.active_len() < self.limit && builder.remaining() >= max_len {
             let maybe_cid = self.generator.borrow_mut().generate_cid();
             if let Some(cid) = maybe_cid {
                 assert_ne!(cid.len(), 0);
+                let srt = Self::reset_token(reset_key, &cid)?;
                 let seqno = self.next_seqno;
                 self.next_seqno += 1;
-                self.connection_ids
-                    .add_local(ConnectionIdEntry::new(seqno, cid.clone(), ()));
+                self.add_local(ConnectionIdEntry::new(seqno, cid.clone(), ()));
 
-                // TODO: generate the stateless reset tokens from the connection ID and a key.
-                let entry = ConnectionIdEntry::new(seqno, cid, Srt::random());
-                entry.write(builder, stats);
+                let entry = ConnectionIdEntry::new(seqno, cid, srt);
+                entry.write(self.retire_prior, builder, stats);
                 tokens.push(recovery::Token::NewConnectionId(entry));
             }
         }
+        Ok(())
+    }
+
+    /// Write out the local connection IDs, so that they can be restored with [`Self::import`].
+    /// This fails if there are `NEW_CONNECTION_ID` frames that need to be sent again.
+    pub fn export(&self, enc: &mut Encoder) -> Res<()> {
+        if !self.lost_new_connection_id.is_empty() {
+            return Err(Error::ConnectionState);
+        }
+        enc.encode_varint(self.next_seqno)
+            .encode_varint(self.retire_prior)
+            .encode_varint(to_u64(self.connection_ids.len()));
+        for entry in &self.connection_ids.cids {
+            enc.encode_varint(entry.seqno).encode_vec(1, &entry.cid);
+        }
+        Ok(())
+    }
+
+    /// Replace the local connection IDs with those from the output of [`Self::export`].
+    pub fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        let next_seqno = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let retire_prior = dec
+            .decode_varint()
+            .filter(|&r| r <= next_seqno)
+            .ok_or(Error::InvalidInput)?;
+        let count = dec
+            .decode_varint()
+            .filter(|&n| n <= to_u64(Self::ACTIVE_LIMIT))
+            .ok_or(Error::InvalidInput)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let seqno = dec
+                .decode_varint()
+                .filter(|&s| s < next_seqno)
+                .ok_or(Error::InvalidInput)?;
+            let cid = dec
+                .decode_vec(1)
+                .filter(|cid| !cid.is_empty() && cid.len() <= ConnectionId::MAX_LEN)
+                .ok_or(Error::InvalidInput)?;
+            entries.push(ConnectionIdEntry::new(seqno, ConnectionId::from(cid), ()));
+        }
+
+        let current = self
+            .connection_ids
+            .cids
+            .iter()
+            .map(|e| e.seqno)
+            .collect::<Vec<_>>();
+        for seqno in current {
+            self.retire_local(seqno);
+        }
+        for entry in entries {
+            self.add_local(entry);
+        }
+        self.next_seqno = next_seqno;
+        self.retire_prior = retire_prior;
+        Ok(())
     }
 
     pub fn lost(&mut self, entry: &ConnectionIdEntry<Srt>) {
-        self.lost_new_connection_id.push(entry.clone());
+        if entry.seqno < self.retire_prior {
+            // The peer has already been asked to retire this connection ID.
+            qdebug!(
+                "Not resending NEW_CONNECTION_ID for retired {}",
+                entry.seqno
+            );
+            self.retire_local(entry.seqno);
+        } else {
+            self.lost_new_connection_id.push(entry.clone());
+        }
     }
 
     pub fn acked(&mut self, entry: &ConnectionIdEntry<Srt>) {
@@ -627,6 +844,115 @@ impl ConnectionIdManager {
     }
 }
 
+/// A policy for rotating connection IDs on an established connection, so that
+/// an observer can't easily link packets over the life of a long-lived connection.
+///
+/// Rotation happens when there is something to send, so an idle connection
+/// doesn't wake up just to change connection IDs.  All rotation is disabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionIdRotation {
+    /// How often to switch to a new connection ID from the peer.
+    remote_interval: Option<Duration>,
+    /// How many bytes to send before switching to a new connection ID from the peer.
+    remote_bytes: Option<u64>,
+    /// How often to issue new connection IDs and ask the peer to retire the old ones.
+    local_interval: Option<Duration>,
+}
+
+impl ConnectionIdRotation {
+    /// Switch to a new connection ID from the peer this often, retiring the old one.
+    /// A spare connection ID is always kept for migration, so rotation pauses
+    /// when the peer hasn't provided enough.
+    #[must_use]
+    pub const fn remote_interval(mut self, interval: Option<Duration>) -> Self {
+        self.remote_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_remote_interval(&self) -> Option<Duration> {
+        self.remote_interval
+    }
+
+    /// Switch to a new connection ID from the peer after sending this many bytes
+    /// on the primary path using the same connection ID.
+    #[must_use]
+    pub const fn remote_bytes(mut self, bytes: Option<u64>) -> Self {
+        self.remote_bytes = bytes;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_remote_bytes(&self) -> Option<u64> {
+        self.remote_bytes
+    }
+
+    /// Issue new connection IDs this often, using Retire Prior To to ask the peer
+    /// to retire all of those issued before.  A rotation is skipped if the peer
+    /// hasn't retired the connection IDs from the previous one yet.
+    #[must_use]
+    pub const fn local_interval(mut self, interval: Option<Duration>) -> Self {
+        self.local_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_local_interval(&self) -> Option<Duration> {
+        self.local_interval
+    }
+}
+
+/// Tracks when connection IDs were last rotated, following a [`ConnectionIdRotation`].
+#[derive(Debug, Default)]
+pub struct ConnectionIdRotator {
+    /// When the peer was last asked to switch to new connection IDs.
+    local: Option<Instant>,
+    /// The sequence number of the connection ID that is used for the peer on the
+    /// primary path, when it started being used, and how many bytes the path had sent then.
+    remote: Option<(u64, Instant, usize)>,
+}
+
+impl ConnectionIdRotator {
+    /// Whether it is time to issue new connection IDs.
+    pub fn local_due(&mut self, policy: &ConnectionIdRotation, now: Instant) -> bool {
+        let Some(interval) = policy.local_interval else {
+            return false;
+        };
+        let since = *self.local.get_or_insert(now);
+        now.saturating_duration_since(since) >= interval
+    }
+
+    /// Note that new connection IDs were issued.
+    pub const fn local_rotated(&mut self, now: Instant) {
+        self.local = Some(now);
+    }
+
+    /// Whether it is time to switch away from the connection ID with sequence number
+    /// `seqno`, given that the path that uses it has sent `sent` bytes in total.
+    /// A change in `seqno`, for whatever reason, restarts the clock.
+    pub fn remote_due(
+        &mut self,
+        policy: &ConnectionIdRotation,
+        seqno: u64,
+        sent: usize,
+        now: Instant,
+    ) -> bool {
+        if policy.remote_interval.is_none() && policy.remote_bytes.is_none() {
+            return false;
+        }
+        let (_, since, base) = match self.remote {
+            Some(remote) if remote.0 == seqno => remote,
+            _ => *self.remote.insert((seqno, now, sent)),
+        };
+        policy
+            .remote_interval
+            .is_some_and(|interval| now.saturating_duration_since(since) >= interval)
+            || policy
+                .remote_bytes
+                .is_some_and(|bytes| to_u64(sent.saturating_sub(base)) >= bytes)
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -671,7 +997,7 @@ mod tests {
         let mut builder = packet::Builder::short(enc, false, Some(&[]), len + 1);
         assert_eq!(builder.remaining(), len, "exactly `len` bytes remaining");
         assert!(
-            entry.write(&mut builder, &mut FrameStats::default()),
+            entry.write(0, &mut builder, &mut FrameStats::default()),
             "write must succeed when remaining == len"
         );
     }
@@ -689,7 +1015,7 @@ mod tests {
             "Builder::short consumed one byte"
         );
         assert!(
-            !entry.write(&mut builder, &mut FrameStats::default()),
+            !entry.write(0, &mut builder, &mut FrameStats::default()),
             "couldn't write frame into too-short builder",
         );
     }
@@ -772,4 +1098,114 @@ mod tests {
         assert_eq!(cid.to_string(), "010203");
         assert_eq!(cid_ref.to_string(), cid.to_string());
     }
+
+    #[test]
+    fn track_local_changes() {
+        use std::{cell::RefCell, rc::Rc};
+
+        use super::{LocalConnectionIdChange, RandomConnectionIdGenerator};
+
+        fixture_init();
+        let initial = ConnectionId::from(&[1; 8]);
+        let mut mgr = ConnectionIdManager::new(
+            Rc::new(RefCell::new(RandomConnectionIdGenerator::new(8))),
+            initial.clone(),
+        );
+        // Nothing is recorded until tracking starts.
+        let odcid = ConnectionId::from(&[2; 8]);
+        mgr.add_odcid(odcid.clone());
+        assert!(mgr.take_changes().is_empty());
+
+        mgr.track_changes();
+        assert_eq!(
+            mgr.take_changes(),
+            [
+                LocalConnectionIdChange::Added(initial),
+                LocalConnectionIdChange::Added(odcid.clone()),
+            ]
+        );
+        assert!(mgr.take_changes().is_empty());
+
+        mgr.remove_odcid();
+        assert_eq!(
+            mgr.take_changes(),
+            [LocalConnectionIdChange::Retired(odcid)]
+        );
+    }
+
+    #[test]
+    fn rotate_waits_for_retirement() {
+        use std::{cell::RefCell, rc::Rc};
+
+        use super::RandomConnectionIdGenerator;
+
+        fixture_init();
+        let mut mgr = ConnectionIdManager::new(
+            Rc::new(RefCell::new(RandomConnectionIdGenerator::new(8))),
+            ConnectionId::from(&[1; 8]),
+        );
+        assert!(mgr.rotate());
+        assert_eq!(mgr.active_len(), 0);
+        // The initial connection ID hasn't been retired by the peer yet.
+        assert!(!mgr.rotate());
+        mgr.retire(0).unwrap();
+        assert!(mgr.rotate());
+    }
+
+    #[test]
+    fn rotate_empty() {
+        use std::{cell::RefCell, rc::Rc};
+
+        use super::EmptyConnectionIdGenerator;
+
+        let mut mgr = ConnectionIdManager::new(
+            Rc::new(RefCell::new(EmptyConnectionIdGenerator::default())),
+            ConnectionId::from(&[]),
+        );
+        assert!(!mgr.rotate());
+    }
+
+    #[test]
+    fn rotator_remote() {
+        use std::time::Duration;
+
+        use test_fixture::now;
+
+        use super::{ConnectionIdRotation, ConnectionIdRotator};
+
+        let mut rotator = ConnectionIdRotator::default();
+        let disabled = ConnectionIdRotation::default();
+        assert!(!rotator.remote_due(&disabled, 0, 1_000, now()));
+
+        let bytes = ConnectionIdRotation::default().remote_bytes(Some(100));
+        assert!(!rotator.remote_due(&bytes, 0, 1_000, now()));
+        assert!(!rotator.remote_due(&bytes, 0, 1_099, now()));
+        assert!(rotator.remote_due(&bytes, 0, 1_100, now()));
+        // A new connection ID starts counting again.
+        assert!(!rotator.remote_due(&bytes, 1, 1_100, now()));
+
+        let interval = Duration::from_secs(10);
+        let timed = ConnectionIdRotation::default().remote_interval(Some(interval));
+        assert!(!rotator.remote_due(&timed, 1, 1_100, now() + interval / 2));
+        assert!(rotator.remote_due(&timed, 1, 1_100, now() + interval));
+    }
+
+    #[test]
+    fn rotator_local() {
+        use std::time::Duration;
+
+        use test_fixture::now;
+
+        use super::{ConnectionIdRotation, ConnectionIdRotator};
+
+        let interval = Duration::from_secs(10);
+        let policy = ConnectionIdRotation::default().local_interval(Some(interval));
+        let mut rotator = ConnectionIdRotator::default();
+        assert!(!rotator.local_due(&ConnectionIdRotation::default(), now() + interval));
+        assert!(!rotator.local_due(&policy, now()));
+        assert!(rotator.local_due(&policy, now() + interval));
+        rotator.local_rotated(now() + interval);
+        assert!(!rotator.local_due(&policy, now() + interval));
+        assert!(rotator.local_due(&policy, now() + interval * 2));
+    }
 }
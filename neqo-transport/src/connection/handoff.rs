@@ -0,0 +1,348 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Moving established connections between servers.
+
+use std::{
+    cell::RefCell,
+    cmp::min,
+    fmt::{self, Debug, Formatter},
+    net::{IpAddr, SocketAddr},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use neqo_common::{Decoder, Encoder, Role, qinfo, qwarn};
+use nss::{
+    Mode, RecordProtection as Aead, RecordProtectionOps as _, SymKey, TLS_AES_128_GCM_SHA256,
+    TLS_VERSION_1_3, hkdf, random,
+};
+
+use super::{Connection, PreferredAddressState, State, StateSignaling};
+use crate::{
+    CloseReason, Error, Res,
+    cc::CarefulResumeParams,
+    cid::{ConnectionId, ConnectionIdEntry, RemoteConnectionIdEntry},
+    path::Path,
+    tparams::{TransportParameterId::InitialSourceConnectionId, TransportParameters},
+    tracking::PacketNumberSpace,
+    version::{self, Version},
+};
+
+/// A key that protects the state of connections that are moved between servers.
+/// Every server that exports or imports a connection needs to use the same key.
+pub struct HandoffKey {
+    key: SymKey,
+    /// The wall-clock time that corresponds to an `Instant`, so that the age
+    /// of an export can be checked on another host.
+    wall_clock: (Instant, SystemTime),
+}
+
+impl HandoffKey {
+    /// The version of the export format.
+    const FORMAT: u8 = 2;
+    /// The length of the random salt that is included in each export.
+    pub const SALT_LEN: usize = 16;
+    /// How long an export can be imported for.
+    pub const MAX_AGE: Duration = Duration::from_secs(10);
+    /// The label used when deriving per-export keys.
+    const LABEL_PREFIX: &'static str = "neqo handoff ";
+
+    /// Create a key from a secret that is shared between servers.
+    /// `now` is used to relate the time that is passed to [`Connection::export`]
+    /// and [`crate::server::Server::import_connection`] to the wall clock.
+    ///
+    /// # Errors
+    ///
+    /// When the secret can't be imported.
+    pub fn new(secret: &[u8], now: Instant) -> Res<Self> {
+        Ok(Self {
+            key: hkdf::import_key(TLS_VERSION_1_3, secret)?,
+            wall_clock: (now, SystemTime::now()),
+        })
+    }
+
+    /// Convert `t` to milliseconds since the UNIX epoch.
+    fn unix_millis(&self, t: Instant) -> u64 {
+        let (base, wall) = self.wall_clock;
+        let wall = if t >= base {
+            wall.checked_add(t - base)
+        } else {
+            wall.checked_sub(base - t)
+        };
+        wall.and_then(|w| w.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// The random salt from the output of `seal`, which is unique to each export.
+    /// This doesn't authenticate `sealed`.
+    ///
+    /// # Errors
+    ///
+    /// When `sealed` isn't an export.
+    pub fn salt(sealed: &[u8]) -> Res<[u8; Self::SALT_LEN]> {
+        match sealed.split_first() {
+            Some((&Self::FORMAT, rest)) => rest
+                .get(..Self::SALT_LEN)
+                .and_then(|salt| <[u8; Self::SALT_LEN]>::try_from(salt).ok())
+                .ok_or(Error::InvalidInput),
+            _ => Err(Error::InvalidInput),
+        }
+    }
+
+    /// Create an AEAD for a single export, keyed with the salt from that export.
+    fn aead(&self, salt: &[u8], mode: Mode) -> Res<Aead> {
+        let salt = hkdf::import_key(TLS_VERSION_1_3, salt)?;
+        let secret = hkdf::extract(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            Some(&salt),
+            &self.key,
+        )?;
+        Ok(Aead::new(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            &secret,
+            Self::LABEL_PREFIX,
+            mode,
+        )?)
+    }
+
+    /// Protect `plaintext`, producing the format version, a salt, and the ciphertext.
+    fn seal(&self, plaintext: &[u8]) -> Res<Vec<u8>> {
+        let salt = random::<{ Self::SALT_LEN }>();
+        let aead = self.aead(&salt, Mode::Encrypt)?;
+        let offset = 1 + Self::SALT_LEN;
+        let mut sealed = vec![0; offset + plaintext.len() + aead.expansion()];
+        sealed[0] = Self::FORMAT;
+        sealed[1..offset].copy_from_slice(&salt);
+        let len = aead
+            .encrypt(0, &[Self::FORMAT], plaintext, &mut sealed[offset..])?
+            .len();
+        sealed.truncate(offset + len);
+        Ok(sealed)
+    }
+
+    /// Remove protection from the output of `seal`.
+    fn open(&self, sealed: &[u8]) -> Res<Vec<u8>> {
+        let salt = Self::salt(sealed)?;
+        let ciphertext = &sealed[1 + Self::SALT_LEN..];
+        let aead = self.aead(&salt, Mode::Decrypt)?;
+        let mut plaintext = vec![0; ciphertext.len()];
+        let len = aead
+            .decrypt(0, &[Self::FORMAT], ciphertext, &mut plaintext)?
+            .len();
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+impl Debug for HandoffKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "HandoffKey")
+    }
+}
+
+fn encode_addr(enc: &mut Encoder, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => enc.encode_byte(4).encode(ip.octets()),
+        IpAddr::V6(ip) => enc.encode_byte(6).encode(ip.octets()),
+    };
+    enc.encode_uint(2, addr.port());
+}
+
+fn decode_addr(dec: &mut Decoder) -> Res<SocketAddr> {
+    let ip = match dec.decode_uint::<u8>() {
+        Some(4) => dec
+            .decode(4)
+            .and_then(|b| <[u8; 4]>::try_from(b).ok())
+            .map(IpAddr::from),
+        Some(6) => dec
+            .decode(16)
+            .and_then(|b| <[u8; 16]>::try_from(b).ok())
+            .map(IpAddr::from),
+        _ => None,
+    };
+    let port = dec.decode_uint::<u16>();
+    ip.zip(port)
+        .map(|(ip, port)| SocketAddr::new(ip, port))
+        .ok_or(Error::InvalidInput)
+}
+
+fn encode_duration(enc: &mut Encoder, d: Duration) {
+    enc.encode_varint(u64::try_from(d.as_micros()).unwrap_or(u64::MAX));
+}
+
+fn decode_duration(dec: &mut Decoder) -> Res<Duration> {
+    dec.decode_varint()
+        .map(Duration::from_micros)
+        .ok_or(Error::InvalidInput)
+}
+
+impl Connection {
+    /// Whether the connection is idle enough to be exported.
+    fn can_export(&mut self) -> bool {
+        self.role == Role::Server
+            && self.state == State::Confirmed
+            && self.paths.is_single()
+            && !self.loss_recovery.has_outstanding()
+            && self.quic_datagrams.is_empty()
+            && matches!(self.state_signaling, StateSignaling::Idle)
+            && self
+                .crypto
+                .streams_mut()
+                .is_empty(PacketNumberSpace::ApplicationData)
+    }
+
+    /// Export the state of an established connection so that it can be restored with
+    /// [`crate::server::Server::import_connection`], perhaps in another process.
+    /// The output is versioned and protected with `key`.  It records when it was
+    /// created and can only be imported for a short time afterwards.
+    ///
+    /// Only idle server connections can be exported: the handshake has to be confirmed,
+    /// there can't be any streams, queued datagrams, or unacknowledged packets, and
+    /// the connection has to use a single path.  Once exported, this connection
+    /// is closed without notifying the peer.
+    ///
+    /// The restored connection has no TLS state, so it can't send session tickets or
+    /// export keying material, and [`Self::tls_info`] returns `None`.  It needs to be
+    /// restored by a server with the same [`crate::ConnectionParameters`].
+    ///
+    /// # Errors
+    ///
+    /// [`Error::ConnectionState`] if the connection isn't idle,
+    /// [`Error::KeyUpdateBlocked`] if a key update is in progress,
+    /// or an error if the state can't be protected.
+    pub fn export(&mut self, key: &HandoffKey, now: Instant) -> Res<Vec<u8>> {
+        if !self.can_export() {
+            return Err(Error::ConnectionState);
+        }
+        let path = self.paths.primary().ok_or(Error::NoAvailablePath)?;
+        let path = path.borrow();
+        let remote_cid = path.remote_cid_entry().ok_or(Error::NoAvailablePath)?;
+
+        let mut enc = Encoder::default();
+        enc.encode_uint(8, key.unix_millis(now));
+        encode_duration(&mut enc, HandoffKey::MAX_AGE);
+        enc.encode_uint(4, self.version.wire_version());
+        encode_addr(&mut enc, path.local_address());
+        encode_addr(&mut enc, path.remote_address());
+        self.cid_manager.export(&mut enc)?;
+        enc.encode_vec(1, path.local_cid().map(AsRef::as_ref).unwrap_or_default());
+        remote_cid.export(&mut enc);
+        self.cids.export(&mut enc);
+        enc.encode_vvec_with(|enc| self.tps.borrow().remote().encode(enc));
+        self.crypto.states().export(&mut enc)?;
+        self.streams.export(&mut enc)?;
+        encode_duration(&mut enc, path.rtt().estimate());
+        drop(path);
+        if let Some(params) = self.careful_resume_params() {
+            enc.encode_byte(1).encode_varint(params.bandwidth());
+            encode_duration(&mut enc, params.rtt());
+        } else {
+            enc.encode_byte(0);
+        }
+
+        let blob = key.seal(enc.as_ref())?;
+        qinfo!("[{self}] Exported connection");
+        self.set_state(State::Closed(CloseReason::Transport(Error::None)), now);
+        Ok(blob)
+    }
+
+    /// Restore the state of a connection from the output of [`Self::export`].
+    /// This is only used on a server connection that hasn't been used yet.
+    /// Exports that are older than [`HandoffKey::MAX_AGE`] are rejected.  The caller
+    /// needs to reject exports that it has already imported, using [`HandoffKey::salt`].
+    pub(crate) fn import(&mut self, key: &HandoffKey, blob: &[u8], now: Instant) -> Res<()> {
+        debug_assert_eq!(self.role, Role::Server);
+        debug_assert_eq!(self.state, State::Init);
+        let plaintext = key.open(blob)?;
+        let mut dec = Decoder::from(&plaintext[..]);
+
+        let created = dec.decode_uint::<u64>().ok_or(Error::InvalidInput)?;
+        let max_age = decode_duration(&mut dec)?;
+        let age = Duration::from_millis(key.unix_millis(now).saturating_sub(created));
+        if age > min(max_age, HandoffKey::MAX_AGE) {
+            qwarn!("[{self}] Export expired {age:?} after creation");
+            return Err(Error::InvalidInput);
+        }
+
+        let version = dec
+            .decode_uint::<version::Wire>()
+            .and_then(|v| Version::try_from(v).ok())
+            .filter(|v| self.conn_params.get_versions().all().contains(v))
+            .ok_or(Error::InvalidInput)?;
+        let local = decode_addr(&mut dec)?;
+        let remote = decode_addr(&mut dec)?;
+        self.cid_manager.import(&mut dec)?;
+        let local_cid = dec
+            .decode_vec(1)
+            .filter(|cid| cid.len() <= ConnectionId::MAX_LEN)
+            .ok_or(Error::InvalidInput)?;
+        let local_cid = (!local_cid.is_empty()).then(|| ConnectionId::from(local_cid));
+        let remote_cid = RemoteConnectionIdEntry::import(&mut dec)?;
+        self.cids.import(&mut dec)?;
+        let remote_tps = dec.decode_vvec().ok_or(Error::InvalidInput)?;
+        let remote_tps = TransportParameters::decode(Role::Server, &mut Decoder::from(remote_tps))?;
+        self.remote_initial_source_cid = remote_tps
+            .get_bytes(InitialSourceConnectionId)
+            .map(ConnectionId::from);
+        self.version = version;
+        self.tps.borrow_mut().import(version, remote_tps);
+        self.crypto.states_mut().import(version, &mut dec)?;
+
+        let path = Path::temporary(
+            local,
+            remote,
+            &self.conn_params,
+            self.qlog.clone(),
+            now,
+            &mut self.stats.borrow_mut(),
+        );
+        let path = Rc::new(RefCell::new(path));
+        // Processing transport parameters sets the stateless reset token for the
+        // first connection ID from the peer, so the connection ID that was in use
+        // only replaces that afterwards.
+        self.paths.make_permanent(
+            &path,
+            local_cid,
+            ConnectionIdEntry::initial_remote(remote_cid.connection_id().clone()),
+            now,
+        );
+        path.borrow_mut().set_valid(now);
+        self.process_tps(now)?;
+        path.borrow_mut().make_permanent(None, remote_cid);
+        self.streams.import(&mut dec)?;
+
+        let rtt = decode_duration(&mut dec)?;
+        path.borrow_mut().rtt_mut().set_initial(rtt);
+        match dec.decode_uint::<u8>() {
+            Some(0) => {}
+            Some(1) => {
+                let bandwidth = dec.decode_varint().ok_or(Error::InvalidInput)?;
+                let min_rtt = decode_duration(&mut dec)?;
+                path.borrow_mut()
+                    .careful_resume(CarefulResumeParams::new(bandwidth, min_rtt));
+            }
+            _ => return Err(Error::InvalidInput),
+        }
+        if dec.remaining() > 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        for space in [PacketNumberSpace::Initial, PacketNumberSpace::Handshake] {
+            self.crypto.discard(space);
+            self.loss_recovery.discard(&path, space, now);
+            self.acks.drop_space(space);
+        }
+        self.idle_timeout.on_packet_received(now);
+        // Any preferred address was dealt with by the server that exported this.
+        self.preferred_address = PreferredAddressState::Inactive;
+        self.set_confirmed(now)?;
+        qinfo!("[{self}] Imported connection");
+        Ok(())
+    }
+}
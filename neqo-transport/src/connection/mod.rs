@@ -11,7 +11,7 @@ use std::{
     fmt::{self, Debug, Display, Formatter, Write as _},
     iter, mem,
     net::{IpAddr, SocketAddr},
-    num::NonZeroUsize,
+    num::{NonZeroU64, NonZeroUsize},
     ops::RangeInclusive,
     rc::{Rc, Weak},
     time::{Duration, Instant},
@@ -37,10 +37,12 @@ use strum::IntoEnumIterator as _;
 use crate::{
     AppError, CloseReason, Error, Res, StreamId,
     addr_valid::{AddressValidation, NewTokenState},
-    cc::Phase,
+    budget::SendBudget,
+    cc::{CarefulResumeParams, Phase},
     cid::{
         ConnectionId, ConnectionIdEntry, ConnectionIdGenerator, ConnectionIdManager,
-        ConnectionIdRef, ConnectionIdStore,
+        ConnectionIdRef, ConnectionIdRotation, ConnectionIdRotator, ConnectionIdStore,
+        LocalConnectionIdChange,
     },
     crypto::{Crypto, CryptoDxState, Epoch},
     ecn,
@@ -54,7 +56,7 @@ use crate::{
     recv_stream,
     rtt::{GRANULARITY, RttEstimate},
     saved::SavedDatagrams,
-    send_stream::{self, SendStream},
+    send_stream::{self, SendChunk, SendStream},
     stateless_reset::Token as Srt,
     stats::{Stats, StatsCell},
     stream_id::StreamType,
@@ -73,6 +75,7 @@ use crate::{
     version::{self, Version},
 };
 
+mod handoff;
 mod idle;
 pub mod params;
 mod state;
@@ -80,6 +83,7 @@ mod state;
 #[cfg_attr(coverage_nightly, coverage(off))]
 pub mod test_internal;
 
+pub use handoff::HandoffKey;
 use idle::IdleTimeout;
 pub use params::ConnectionParameters;
 use params::PreferredAddressConfig;
@@ -97,6 +101,30 @@ pub enum ZeroRttState {
     Rejected,
 }
 
+/// Whether a client migrated to the preferred address that a server advertised.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PreferredAddressOutcome {
+    /// The client migrated to the preferred address; this is the new path.
+    Migrated {
+        local: SocketAddr,
+        remote: SocketAddr,
+    },
+    /// The client did not migrate to the preferred address in time.
+    NotMigrated,
+}
+
+/// Server-side tracking of whether the client uses the preferred address.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum PreferredAddressState {
+    /// No preferred address was advertised, or the outcome was already taken.
+    Inactive,
+    /// A preferred address was advertised.  Once the handshake is confirmed,
+    /// this holds the time by which the client has to migrate.
+    Advertised(Option<Instant>),
+    /// The outcome is known, but has not been taken yet.
+    Outcome(PreferredAddressOutcome),
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// Type returned from `process()` and `process_output()`. Users are required to
 /// call these repeatedly until `Callback` or `None` is returned.
@@ -290,6 +318,8 @@ pub struct Connection {
     address_validation: AddressValidationInfo,
     /// The connection IDs that were provided by the peer.
     cids: ConnectionIdStore<Srt>,
+    /// When connection IDs were last rotated.
+    cid_rotator: ConnectionIdRotator,
 
     /// The source connection ID that this endpoint uses for the handshake.
     /// Since we need to communicate this to our peer in tparams, setting this
@@ -327,8 +357,16 @@ pub struct Connection {
     /// A session ticket was received without `NEW_TOKEN`,
     /// this is when that turns into an event without `NEW_TOKEN`.
     release_resumption_token_timer: Option<Instant>,
+    /// Whether the client migrated to our preferred address.
+    preferred_address: PreferredAddressState,
     conn_params: ConnectionParameters,
     hrtime: hrtime::Handle,
+    /// The space in the congestion window that produces a
+    /// [`ConnectionEvent::SendBudgetAvailable`] event, if any.
+    send_budget_watermark: Option<NonZeroUsize>,
+    /// Whether the space in the congestion window was at or above the watermark
+    /// when it was last checked.
+    send_budget_open: bool,
 
     /// For testing purposes it is sometimes necessary to inject frames that wouldn't
     /// otherwise be sent, just to see how a connection handles them.  Inserting them
@@ -430,6 +468,14 @@ impl Connection {
         let mut tps = conn_params.create_transport_parameter(role, &mut cid_manager)?;
         tps.local_mut()
             .set_bytes(InitialSourceConnectionId, local_initial_source_cid.to_vec());
+        if role == Role::Server
+            && !local_initial_source_cid.is_empty()
+            && let Some(key) = conn_params.get_stateless_reset_key()
+        {
+            let srt = key.token(&local_initial_source_cid)?;
+            tps.local_mut()
+                .set_bytes(StatelessResetToken, srt.as_bytes().to_vec());
+        }
 
         let tphandler = Rc::new(RefCell::new(tps));
         let crypto = Crypto::new(
@@ -445,9 +491,19 @@ impl Connection {
         let quic_datagrams = QuicDatagrams::new(
             conn_params.get_datagram_size(),
             conn_params.get_outgoing_datagram_queue(),
+            conn_params.get_outgoing_datagram_drop_policy(),
             events.clone(),
         );
 
+        let preferred_address = if role == Role::Server
+            && matches!(
+                conn_params.get_preferred_address(),
+                PreferredAddressConfig::Address(_)
+            ) {
+            PreferredAddressState::Advertised(None)
+        } else {
+            PreferredAddressState::Inactive
+        };
         let c = Self {
             role,
             version: conn_params.get_versions().initial(),
@@ -467,15 +523,23 @@ impl Connection {
             idle_timeout: IdleTimeout::new(conn_params.get_idle_timeout()),
             streams: Streams::new(tphandler, role, events.clone()),
             cids: ConnectionIdStore::default(),
+            cid_rotator: ConnectionIdRotator::default(),
             state_signaling: StateSignaling::Idle,
-            loss_recovery: recovery::Loss::new(stats.clone(), conn_params.get_fast_pto()),
+            loss_recovery: recovery::Loss::new(
+                stats.clone(),
+                conn_params.get_fast_pto(),
+                conn_params.adaptive_reordering_enabled(),
+            ),
             events,
             new_token: NewTokenState::new(role),
             stats,
             qlog: Qlog::disabled(),
             release_resumption_token_timer: None,
+            preferred_address,
             conn_params,
             hrtime: hrtime::Time::get(Self::LOOSE_TIMER_RESOLUTION),
+            send_budget_watermark: None,
+            send_budget_open: true,
             quic_datagrams,
             #[cfg(any(test, feature = "build-fuzzing-corpus"))]
             test_frame_writer: None,
@@ -851,6 +915,20 @@ impl Connection {
         self.address_validation = AddressValidationInfo::Server(Rc::downgrade(validation));
     }
 
+    /// Set a policy that chooses the version when the client offers compatible versions.
+    /// `validated` is whether the client's address was validated with a Retry.
+    pub(crate) fn set_version_policy(
+        &mut self,
+        policy: Rc<RefCell<dyn version::Policy>>,
+        peer: SocketAddr,
+        validated: bool,
+    ) {
+        assert_eq!(self.role, Role::Server);
+        self.tps
+            .borrow_mut()
+            .set_version_policy(version::PolicyState::new(policy, peer, validated));
+    }
+
     /// Send a TLS session ticket AND a `NEW_TOKEN` frame (if possible).
     /// # Errors
     /// When the operation fails, which is usually due to bad inputs or bad connection state.
@@ -979,6 +1057,89 @@ impl Connection {
         v
     }
 
+    /// The congestion control parameters observed on the primary path.  An application can
+    /// save these and pass them to [`Self::set_careful_resume`] on a later connection to the
+    /// same peer.  This is `None` until the RTT has been measured.
+    #[must_use]
+    pub fn careful_resume_params(&self) -> Option<CarefulResumeParams> {
+        let path = self.paths.primary()?;
+        let path = path.borrow();
+        if path.rtt().is_guesstimate() {
+            return None;
+        }
+        let min_rtt = path.rtt().minimum();
+        let bandwidth = self.stats.borrow().max_delivery_rate.or_else(|| {
+            // Without a delivery rate sample, use the rate that the congestion window allows.
+            (u128::from(to_u64(path.sender().cwnd())) * 1_000_000)
+                .checked_div(min_rtt.as_micros())
+                .and_then(|bw| u64::try_from(bw).ok())
+        })?;
+        Some(CarefulResumeParams::new(bandwidth, min_rtt))
+    }
+
+    /// Use congestion control parameters from [`Self::careful_resume_params`] on an earlier
+    /// connection to the same peer to jump-start the congestion window, following careful
+    /// resume.  The jump only happens once the RTT is confirmed to be similar and there is
+    /// enough data to send.  Congestion before the larger window is validated causes a
+    /// retreat.  Only the Cubic and NewReno congestion controllers support careful resume.
+    ///
+    /// # Errors
+    ///
+    /// When the connection is already confirmed.
+    pub fn set_careful_resume(&mut self, params: CarefulResumeParams) -> Res<()> {
+        if self.state >= State::Confirmed {
+            return Err(Error::ConnectionState);
+        }
+        let path = self.paths.primary().ok_or(Error::NoAvailablePath)?;
+        path.borrow_mut().careful_resume(params);
+        Ok(())
+    }
+
+    /// Limit the rate at which this connection sends to `rate` bits per second,
+    /// or remove the limit with `None`.  This overrides the value from
+    /// [`ConnectionParameters::max_send_rate`] and applies to all paths.
+    /// If the network provides SCONE advice for a path, the lower rate applies.
+    /// The pacer enforces the limit, even if pacing is disabled.
+    pub fn set_max_send_rate(&mut self, rate: Option<NonZeroU64>) {
+        qdebug!("[{self}] Maximum send rate set to {rate:?} bit/s");
+        self.conn_params.set_max_send_rate(rate);
+        self.paths.set_max_send_rate(rate);
+    }
+
+    /// Get what the connection can send now on the primary path, along with
+    /// the estimates of the path conditions that limit that.
+    ///
+    /// # Errors
+    /// When there is no primary path.
+    pub fn send_budget(&self) -> Res<SendBudget> {
+        let path = self.paths.primary().ok_or(Error::NoAvailablePath)?;
+        let budget = path.borrow().send_budget();
+        Ok(budget)
+    }
+
+    /// Generate a [`ConnectionEvent::SendBudgetAvailable`] event each time the space
+    /// in the congestion window rises to at least `watermark` bytes after being below it.
+    /// `None` disables the event.
+    pub fn set_send_budget_watermark(&mut self, watermark: Option<NonZeroUsize>) {
+        self.send_budget_watermark = watermark;
+        self.send_budget_open = true;
+        self.check_send_budget();
+    }
+
+    fn check_send_budget(&mut self) {
+        let Some(watermark) = self.send_budget_watermark else {
+            return;
+        };
+        let Some(path) = self.paths.primary() else {
+            return;
+        };
+        let open = path.borrow().send_budget().cwnd_avail >= watermark.get();
+        if open && !self.send_budget_open {
+            self.events.send_budget_available();
+        }
+        self.send_budget_open = open;
+    }
+
     // This function wraps a call to another function and sets the connection state
     // properly if that call fails.
     fn capture_error<T>(
@@ -1081,6 +1242,9 @@ impl Connection {
         }
 
         self.streams.cleanup_closed_streams();
+        self.streams.expire_send(now);
+        self.quic_datagrams
+            .expire(now, &mut self.stats.borrow_mut());
 
         let res = self.crypto.states_mut().check_key_update(now);
         self.absorb_error(now, res);
@@ -1093,6 +1257,8 @@ impl Connection {
             qlog::packets_lost(&mut self.qlog, &lost, now);
         }
 
+        self.maybe_failover(now);
+
         // Declare the connection broken if too many consecutive PTOs have gone
         // unacknowledged, i.e. the path is a black hole. This closes the connection
         // sooner than, and with a distinct reason from, the idle timeout.
@@ -1118,6 +1284,14 @@ impl Connection {
             self.create_resumption_token(now);
         }
 
+        if let PreferredAddressState::Advertised(Some(deadline)) = self.preferred_address
+            && deadline <= now
+        {
+            qinfo!("[{self}] Client did not migrate to the preferred address");
+            self.preferred_address =
+                PreferredAddressState::Outcome(PreferredAddressOutcome::NotMigrated);
+        }
+
         if !self
             .paths
             .process_timeout(now, pto, &mut self.stats.borrow_mut())
@@ -1133,6 +1307,22 @@ impl Connection {
         self.cid_manager.is_valid(cid)
     }
 
+    /// Start recording changes to the set of local connection IDs, for a server
+    /// that routes packets by connection ID.
+    pub(crate) fn track_local_cids(&mut self) {
+        self.cid_manager.track_changes();
+    }
+
+    /// Take the changes to the set of local connection IDs since the last call.
+    pub(crate) fn take_local_cid_changes(&mut self) -> Vec<LocalConnectionIdChange> {
+        self.cid_manager.take_changes()
+    }
+
+    /// The local connection IDs that are currently valid.
+    pub(crate) fn local_cids(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.cid_manager.local_cids()
+    }
+
     /// Process a new input datagram on the connection.
     pub fn process_input<A: AsRef<[u8]> + AsMut<[u8]>>(&mut self, d: Datagram<A>, now: Instant) {
         self.process_multiple_input(iter::once(d), now);
@@ -1161,9 +1351,12 @@ impl Connection {
         }
         self.process_saved(now);
         self.streams.cleanup_closed_streams();
+        self.check_send_budget();
     }
 
     /// Get the time that we next need to be called back, relative to `now`.
+    /// `paced` is true if output was held back by the pacer, which also enforces any
+    /// maximum send rate, whether or not pacing is enabled.
     fn next_delay(&mut self, now: Instant, paced: bool) -> Duration {
         qtrace!("[{self}] Get callback delay {now:?}");
 
@@ -1173,7 +1366,7 @@ impl Connection {
             return timeout.duration_since(now);
         }
 
-        let mut delays = SmallVec::<[_; 7]>::new();
+        let mut delays = SmallVec::<[_; 8]>::new();
         if let Some(ack_time) = self.acks.ack_time(now) {
             qtrace!("[{self}] Delayed ACK timer {ack_time:?}");
             delays.push(ack_time);
@@ -1211,11 +1404,26 @@ impl Connection {
             }
         }
 
+        if let PreferredAddressState::Advertised(Some(deadline)) = self.preferred_address {
+            qtrace!("[{self}] Preferred address timer {deadline:?}");
+            delays.push(deadline);
+        }
+
         if let Some(key_update_time) = self.crypto.states().update_time() {
             qtrace!("[{self}] Key update timer {key_update_time:?}");
             delays.push(key_update_time);
         }
 
+        if let Some(stream_deadline) = self.streams.next_send_deadline() {
+            qtrace!("[{self}] Stream deadline timer {stream_deadline:?}");
+            delays.push(stream_deadline);
+        }
+
+        if let Some(datagram_expiry) = self.quic_datagrams.next_expiry() {
+            qtrace!("[{self}] Datagram expiry timer {datagram_expiry:?}");
+            delays.push(datagram_expiry);
+        }
+
         // `release_resumption_token_timer` is not considered here, because
         // it is not important enough to force the application to set a
         // timeout for it  It is expected that other activities will
@@ -1266,7 +1474,9 @@ impl Connection {
             }
         }
 
-        match self.output(now, max_datagrams) {
+        let output = self.output(now, max_datagrams);
+        self.check_send_budget();
+        match output {
             SendOptionBatch::Yes(dgram) => OutputBatch::DatagramBatch(dgram),
             SendOptionBatch::No(paced) => match self.state {
                 State::Init | State::Closed(_) => OutputBatch::None,
@@ -2124,6 +2334,64 @@ impl Connection {
         force: bool,
         now: Instant,
     ) -> Res<()> {
+        let path = self.migration_path(local, remote, now)?;
+        qinfo!(
+            "[{self}] Migrate to {} probe {}",
+            path.borrow(),
+            if force { "now" } else { "after" }
+        );
+        if self
+            .paths
+            .migrate(&path, force, now, &mut self.stats.borrow_mut())
+        {
+            self.loss_recovery.migrate();
+            self.path_migrated(&path);
+        }
+        Ok(())
+    }
+
+    /// Use the provided path as a standby path, following the [`crate::FailoverPolicy`]
+    /// from [`ConnectionParameters::failover`].
+    /// The addresses are treated as they are for [`Self::migrate`].
+    /// The standby path is probed now and periodically after that, to keep it valid.
+    /// If the primary path fails, the connection migrates to the standby path
+    /// and reports [`ConnectionEvent::PathFailover`].
+    /// Setting a new standby path replaces the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no failover policy is configured, or for the same reasons as [`Self::migrate`].
+    pub fn set_standby_path(
+        &mut self,
+        local: Option<SocketAddr>,
+        remote: Option<SocketAddr>,
+        now: Instant,
+    ) -> Res<()> {
+        let policy = self
+            .conn_params
+            .get_failover()
+            .ok_or(Error::InvalidMigration)?;
+        let path = self.migration_path(local, remote, now)?;
+        if path.borrow().is_primary() {
+            return Err(Error::InvalidMigration);
+        }
+        qinfo!("[{self}] Standby path {}", path.borrow());
+        self.paths.set_standby(
+            &path,
+            policy.get_probe_interval(),
+            &mut self.stats.borrow_mut(),
+        );
+        Ok(())
+    }
+
+    /// Find or create a permanent path for migration, using the current primary
+    /// path to fill in a missing local or remote address.
+    fn migration_path(
+        &mut self,
+        local: Option<SocketAddr>,
+        remote: Option<SocketAddr>,
+        now: Instant,
+    ) -> Res<PathRef> {
         if self.role != Role::Client {
             return Err(Error::InvalidMigration);
         }
@@ -2166,19 +2434,66 @@ impl Connection {
             &mut self.stats.borrow_mut(),
         );
         self.ensure_permanent(&path, now)?;
-        qinfo!(
-            "[{self}] Migrate to {} probe {}",
-            path.borrow(),
-            if force { "now" } else { "after" }
-        );
-        if self
-            .paths
-            .migrate(&path, force, now, &mut self.stats.borrow_mut())
+        Ok(path)
+    }
+
+    /// Rotate connection IDs if the policy from [`ConnectionParameters::cid_rotation`]
+    /// says that it is time.
+    fn maybe_rotate_cids(&mut self, now: Instant) {
+        let policy = *self.conn_params.get_cid_rotation();
+        if policy == ConnectionIdRotation::default() {
+            return;
+        }
+        if self.cid_rotator.local_due(&policy, now) && self.cid_manager.rotate() {
+            qinfo!("[{self}] Issuing new connection IDs");
+            self.cid_rotator.local_rotated(now);
+        }
+        let Some(primary) = self.paths.primary() else {
+            return;
+        };
+        let (seqno, sent) = {
+            let path = primary.borrow();
+            let Some(cid) = path.remote_cid_entry() else {
+                return;
+            };
+            (cid.sequence_number(), path.sent_bytes())
+        };
+        if self.cid_rotator.remote_due(&policy, seqno, sent, now)
+            && self.paths.rotate_remote_cid(&mut self.cids)
         {
-            self.loss_recovery.migrate();
-            self.path_migrated(&path);
+            qinfo!("[{self}] Rotated to a new connection ID for the peer");
         }
-        Ok(())
+    }
+
+    /// Fail over to the standby path if the primary path appears to have failed.
+    fn maybe_failover(&mut self, now: Instant) {
+        let Some(policy) = self.conn_params.get_failover() else {
+            return;
+        };
+        if !matches!(self.state, State::Confirmed) {
+            return;
+        }
+        let Some(primary) = self.paths.primary() else {
+            return;
+        };
+        let Some(reason) = policy.check(self.loss_recovery.pto_count(), primary.borrow().rtt())
+        else {
+            return;
+        };
+        let Some(path) = self.paths.failover(now, &mut self.stats.borrow_mut()) else {
+            return;
+        };
+        qinfo!(
+            "[{self}] Primary path failed ({reason:?}), failing over to {}",
+            path.borrow()
+        );
+        self.loss_recovery.migrate();
+        let (local, remote) = {
+            let p = path.borrow();
+            (p.local_address(), p.remote_address())
+        };
+        self.events.path_failover(local, remote, reason);
+        self.path_migrated(&path);
     }
 
     fn path_migrated(&self, path: &PathRef) {
@@ -2187,6 +2502,42 @@ impl Connection {
             .path_migrated(p.local_address(), p.remote_address());
     }
 
+    /// At a server, note when the client migrates to a path that uses the
+    /// preferred address.
+    fn check_preferred_address_migration(&mut self, path: &PathRef) {
+        if !matches!(self.preferred_address, PreferredAddressState::Advertised(_)) {
+            return;
+        }
+        let PreferredAddressConfig::Address(pa) = self.conn_params.get_preferred_address() else {
+            return;
+        };
+        let p = path.borrow();
+        let local = p.local_address();
+        let preferred = match local {
+            SocketAddr::V4(v4) => pa.ipv4() == Some(v4),
+            SocketAddr::V6(v6) => pa.ipv6() == Some(v6),
+        };
+        if preferred {
+            qinfo!("[{self}] Client migrated to the preferred address {local}");
+            self.preferred_address =
+                PreferredAddressState::Outcome(PreferredAddressOutcome::Migrated {
+                    local,
+                    remote: p.remote_address(),
+                });
+        }
+    }
+
+    /// Take the outcome of advertising a preferred address, if it is known.
+    /// This only returns a value once.
+    pub(crate) fn take_preferred_address_outcome(&mut self) -> Option<PreferredAddressOutcome> {
+        if let PreferredAddressState::Outcome(outcome) = self.preferred_address {
+            self.preferred_address = PreferredAddressState::Inactive;
+            Some(outcome)
+        } else {
+            None
+        }
+    }
+
     fn migrate_to_preferred_address(&mut self, now: Instant) -> Res<()> {
         let spa: Option<(tparams::PreferredAddress, ConnectionIdEntry<Srt>)> = if matches!(
             self.conn_params.get_preferred_address(),
@@ -2256,6 +2607,7 @@ impl Connection {
                 .handle_migration(path, remote, now, &mut self.stats.borrow_mut());
             if !was_primary {
                 self.path_migrated(path);
+                self.check_preferred_address_migration(path);
             }
         } else {
             qinfo!(
@@ -2267,6 +2619,9 @@ impl Connection {
 
     fn output(&mut self, now: Instant, max_datagrams: NonZeroUsize) -> SendOptionBatch {
         qtrace!("[{self}] output {now:?}");
+        if self.state == State::Confirmed {
+            self.maybe_rotate_cids(now);
+        }
         let res = match &self.state {
             State::Init
             | State::WaitInitial
@@ -2379,68 +2734,81 @@ impl Connection {
         builder: &mut packet::Builder<&mut Vec<u8>>,
         tokens: &mut recovery::Tokens,
         now: Instant,
-    ) {
+    ) -> Res<()> {
         let rtt = self.paths.primary().map_or_else(
             || RttEstimate::new(self.conn_params.get_initial_rtt()).estimate(),
             |p| p.borrow().rtt().estimate(),
         );
 
         let stats = &mut self.stats.borrow_mut();
-        let frame_stats = &mut stats.frame_tx;
         if self.role == Role::Server
             && let Some(t) = self.state_signaling.write_done(builder)
         {
             tokens.push(t);
-            frame_stats.handshake_done += 1;
+            stats.frame_tx.handshake_done += 1;
         }
 
-        self.streams
-            .write_frames(TransmissionPriority::Critical, builder, tokens, frame_stats);
+        // Drop expired datagrams once, rather than for each priority.
+        self.quic_datagrams.expire(now, stats);
+
+        // Datagrams are best-effort and unreliable.  Streams at the same priority starve them.
+        self.streams.write_frames(
+            TransmissionPriority::Critical,
+            builder,
+            tokens,
+            &mut stats.frame_tx,
+        );
+        self.quic_datagrams
+            .write_frames(TransmissionPriority::Critical, builder, tokens, stats);
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         self.streams
-            .write_maintenance_frames(builder, tokens, frame_stats, now, rtt);
+            .write_maintenance_frames(builder, tokens, &mut stats.frame_tx, now, rtt);
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         self.streams.write_frames(
             TransmissionPriority::Important,
             builder,
             tokens,
-            frame_stats,
+            &mut stats.frame_tx,
         );
+        self.quic_datagrams
+            .write_frames(TransmissionPriority::Important, builder, tokens, stats);
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         // NEW_CONNECTION_ID, RETIRE_CONNECTION_ID, and ACK_FREQUENCY.
-        self.cid_manager.write_frames(builder, tokens, frame_stats);
+        self.cid_manager.write_frames(
+            self.conn_params.get_stateless_reset_key(),
+            builder,
+            tokens,
+            &mut stats.frame_tx,
+        )?;
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
-        self.paths.write_frames(builder, tokens, frame_stats);
+        self.paths
+            .write_frames(builder, tokens, &mut stats.frame_tx);
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         for prio in [TransmissionPriority::High, TransmissionPriority::Normal] {
             self.streams
                 .write_frames(prio, builder, tokens, &mut stats.frame_tx);
+            self.quic_datagrams
+                .write_frames(prio, builder, tokens, stats);
             if builder.is_full() {
-                return;
+                return Ok(());
             }
         }
 
-        // Datagrams are best-effort and unreliable.  Let streams starve them for now.
-        self.quic_datagrams.write_frames(builder, tokens, stats);
-        if builder.is_full() {
-            return;
-        }
-
         // CRYPTO here only includes NewSessionTicket, plus NEW_TOKEN.
         // Both of these are only used for resumption and so can be relatively low priority.
         let frame_stats = &mut stats.frame_tx;
@@ -2452,16 +2820,19 @@ impl Connection {
             frame_stats,
         );
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         self.new_token.write_frames(builder, tokens, frame_stats);
         if builder.is_full() {
-            return;
+            return Ok(());
         }
 
         self.streams
             .write_frames(TransmissionPriority::Low, builder, tokens, frame_stats);
+        self.quic_datagrams
+            .write_frames(TransmissionPriority::Low, builder, tokens, stats);
+        Ok(())
     }
 
     // Maybe send a probe.  Return true if the packet was ack-eliciting.
@@ -2524,7 +2895,7 @@ impl Connection {
         builder: &mut packet::Builder<&mut Vec<u8>>,
         coalesced: bool, // Whether this packet is coalesced behind another one.
         now: Instant,
-    ) -> (recovery::Tokens, bool, bool) {
+    ) -> Res<(recovery::Tokens, bool, bool)> {
         let mut tokens = recovery::Tokens::new();
         let primary = path.borrow().is_primary();
         let mut ack_eliciting = false;
@@ -2560,7 +2931,7 @@ impl Connection {
 
         if profile.ack_only() {
             // If we are CC limited we can only send ACKs!
-            return (tokens, false, false);
+            return Ok((tokens, false, false));
         }
 
         if primary {
@@ -2577,7 +2948,7 @@ impl Connection {
                     );
                     ack_eliciting = true;
                 }
-                self.write_appdata_frames(builder, &mut tokens, now);
+                self.write_appdata_frames(builder, &mut tokens, now)?;
             } else {
                 let stats = &mut self.stats.borrow_mut().frame_tx;
                 self.crypto.write_frame(
@@ -2614,7 +2985,7 @@ impl Connection {
             false
         };
 
-        (tokens, ack_eliciting, padded)
+        Ok((tokens, ack_eliciting, padded))
     }
 
     fn write_closing_frames<B: Buffer>(
@@ -2829,7 +3200,7 @@ impl Connection {
                 self.write_closing_frames(close, &mut builder, space, now, path, &mut tokens);
             } else {
                 (tokens, ack_eliciting, padded) =
-                    self.write_frames(path, space, &profile, &mut builder, header_start != 0, now);
+                    self.write_frames(path, space, &profile, &mut builder, header_start != 0, now)?;
             }
             if builder.packet_empty() {
                 // Nothing to include in this packet.
@@ -2838,8 +3209,10 @@ impl Connection {
                 continue;
             }
 
-            if packet_tos.is_ecn_marked() {
-                tokens.push(recovery::Token::EcnEct0);
+            match Ecn::from(packet_tos) {
+                Ecn::Ect0 => tokens.push(recovery::Token::EcnEct0),
+                Ecn::Ect1 => tokens.push(recovery::Token::EcnEct1),
+                Ecn::NotEct | Ecn::Ce => {}
             }
 
             self.log_packet(
@@ -2923,6 +3296,11 @@ impl Connection {
 
         if encoder.is_empty() {
             qdebug!("TX blocked, profile={profile:?}");
+            if !profile.paced() && !profile.ack_only() {
+                // Nothing to send, even though there is room, so the
+                // delivery rate samples that follow are app-limited.
+                path.borrow_mut().on_app_limited();
+            }
             Ok(SendOption::No(profile.paced()))
         } else {
             // Perform additional padding for Initial packets as necessary.
@@ -3034,7 +3412,16 @@ impl Connection {
 
     /// Close the connection.
     pub fn close<A: AsRef<str>>(&mut self, now: Instant, app_error: AppError, msg: A) {
-        let error = CloseReason::Application(app_error);
+        self.close_with_reason(now, CloseReason::Application(app_error), msg);
+    }
+
+    /// Close the connection with a transport error.  A server uses this to refuse
+    /// a connection after receiving its first Initial.
+    pub(crate) fn close_with_error(&mut self, now: Instant, error: Error) {
+        self.close_with_reason(now, CloseReason::Transport(error), "");
+    }
+
+    fn close_with_reason<A: AsRef<str>>(&mut self, now: Instant, error: CloseReason, msg: A) {
         let timeout = self.get_closing_period_time(now);
         match self.paths.primary() {
             Some(path) => {
@@ -3259,6 +3646,22 @@ impl Connection {
             version
         };
 
+        if self.stats.borrow().version_negotiation.is_none() {
+            // A client that received Version Negotiation keeps its original
+            // version in its configuration, so that it can authenticate that.
+            let negotiation = if self.role == Role::Client
+                && self.version != self.conn_params.get_versions().initial()
+            {
+                version::Negotiation::Incompatible
+            } else if self.version == v {
+                version::Negotiation::None
+            } else {
+                version::Negotiation::Compatible
+            };
+            self.stats.borrow_mut().version_negotiation = Some(negotiation);
+            self.events.version_negotiated(negotiation);
+        }
+
         // OK, it's all confirmed.
         if self.version != v {
             qdebug!("[{self}] Compatible upgrade {:?} ==> {v:?}", self.version);
@@ -3324,6 +3727,13 @@ impl Connection {
 
     fn set_confirmed(&mut self, now: Instant) -> Res<()> {
         self.set_state(State::Confirmed, now);
+        if self.preferred_address == PreferredAddressState::Advertised(None) {
+            // Allow the client enough time to validate the new path,
+            // including any retransmissions of its probes.
+            let pto = self.pto();
+            let probes = u32::try_from(2 * Path::MAX_PROBES + 1).unwrap_or(u32::MAX);
+            self.preferred_address = PreferredAddressState::Advertised(Some(now + pto * probes));
+        }
         if self.conn_params.pmtud_enabled() {
             self.paths
                 .primary()
@@ -3411,6 +3821,9 @@ impl Connection {
                 if self.crypto.streams().data_ready(space)
                     && self.crypto.streams_mut().read_to_end(space, &mut buf)? > 0
                 {
+                    if self.role == Role::Server && space == PacketNumberSpace::Initial {
+                        self.tps.borrow_mut().client_hello(&buf);
+                    }
                     self.handshake(now, packet_version, space, Some(&buf))?;
                     self.create_resumption_token(now);
                 } else {
@@ -3587,7 +4000,9 @@ impl Connection {
                             .datagram_outcome(dgram_tracker, OutgoingDatagramOutcome::Lost);
                         self.stats.borrow_mut().datagram_tx.lost += 1;
                     }
-                    recovery::Token::EcnEct0 => self.paths.lost_ecn(&mut self.stats.borrow_mut()),
+                    recovery::Token::EcnEct0 | recovery::Token::EcnEct1 => {
+                        self.paths.lost_ecn(&mut self.stats.borrow_mut());
+                    }
                     // PMTUD probe loss is handled by the PMTUD state machine.
                     recovery::Token::PmtudProbe => (),
                 }
@@ -3659,7 +4074,9 @@ impl Connection {
                     recovery::Token::Datagram(dgram_tracker) => self
                         .events
                         .datagram_outcome(dgram_tracker, OutgoingDatagramOutcome::Acked),
-                    recovery::Token::EcnEct0 => self.paths.acked_ecn(),
+                    recovery::Token::EcnEct0 | recovery::Token::EcnEct1 => {
+                        self.paths.acked_ecn();
+                    }
                     // We don't care about these being ACK'ed
                     recovery::Token::HandshakeDone | recovery::Token::PmtudProbe => (),
                 }
@@ -3892,6 +4309,23 @@ impl Connection {
         self.streams.get_send_stream_mut(stream_id)?.send(data)
     }
 
+    /// Send data on a stream without copying it.  The stream keeps a reference
+    /// to the accepted part of `chunk` until the peer acknowledges all of it.
+    /// Accepted bytes are removed from the front of `chunk`, so a chunk that is
+    /// only partly accepted can be passed again once there is space.
+    /// Returns how many bytes were accepted.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` the stream does not exist,
+    /// `InvalidInput` if `chunk` is empty,
+    /// `FinalSizeError` if the stream has already been closed.
+    pub fn stream_send_chunk(&mut self, stream_id: StreamId, chunk: &mut SendChunk) -> Res<usize> {
+        self.streams
+            .get_send_stream_mut(stream_id)?
+            .send_chunk(chunk)
+    }
+
     /// Send all data or nothing on a stream. May cause `DATA_BLOCKED` or
     /// `STREAM_DATA_BLOCKED` frames to be sent.
     /// Returns true if data was successfully sent, otherwise false.
@@ -3977,6 +4411,29 @@ impl Connection {
         stream.commit()
     }
 
+    /// Set a deadline for stream data that is written after this, or `None` for no deadline.
+    /// If any of that data is not acknowledged by `deadline`, it is no longer retransmitted
+    /// and the stream is reset with error `err`, using `RESET_STREAM_AT` so that the data
+    /// before it is still delivered.  A [`ConnectionEvent::SendStreamExpired`] event is
+    /// generated when this happens.
+    ///
+    /// Call this before each write that has a different deadline.
+    /// # Errors
+    /// When the stream ID is invalid, the peer did not enable reliable reset
+    /// ([`Error::NotAvailable`]), or the stream is already closed ([`Error::FinalSize`]).
+    pub fn stream_set_deadline(
+        &mut self,
+        stream_id: StreamId,
+        deadline: Option<Instant>,
+        err: AppError,
+    ) -> Res<()> {
+        self.streams.get_send_stream(stream_id)?;
+        if !self.tps.borrow().remote().get_empty(ResetStreamAt) {
+            return Err(Error::NotAvailable);
+        }
+        self.streams.set_send_deadline(stream_id, deadline, err)
+    }
+
     /// Abandon transmission of in-flight and future stream data.
     ///
     /// If a reliable prefix was committed via [`Self::stream_commit`] and the peer advertised
@@ -4000,6 +4457,31 @@ impl Connection {
         self.streams.recv(stream_id, data)
     }
 
+    /// Get buffered data from a stream without copying it.  This returns the contiguous
+    /// bytes that can be read next, which is empty if none are available.  Calling
+    /// [`Self::stream_recv_consume`] marks bytes as read.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist.
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn stream_recv_peek(&self, stream_id: StreamId) -> Res<&[u8]> {
+        self.streams.recv_peek(stream_id)
+    }
+
+    /// Mark up to `n` bytes of buffered data from a stream as read, such as the bytes from
+    /// [`Self::stream_recv_peek`].  This returns the number of bytes consumed and whether
+    /// the final data on the stream was consumed.  Flow control credit is released for
+    /// consumed bytes.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist.
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn stream_recv_consume(&mut self, stream_id: StreamId, n: usize) -> Res<(usize, bool)> {
+        self.streams.recv_consume(stream_id, n)
+    }
+
     /// Application is no longer interested in this stream.
     /// # Errors
     /// When the stream ID is invalid.
@@ -4083,7 +4565,8 @@ impl Connection {
         Ok(min(data_len_possible, max_dgram_size))
     }
 
-    /// Queue a datagram for sending.
+    /// Queue a datagram for sending.  Queued datagrams whose time-to-live has run out by
+    /// `now` are dropped first.
     ///
     /// # Errors
     ///
@@ -4095,9 +4578,41 @@ impl Connection {
     /// to check the estimated max datagram size and to use smaller datagrams.
     /// `max_datagram_size` is just a current estimate and will change over
     /// time depending on the encoded size of the packet number, ack frames, etc.
-    pub fn send_datagram<I: Into<DatagramTracking>>(&mut self, buf: Vec<u8>, id: I) -> Res<()> {
+    pub fn send_datagram<I: Into<DatagramTracking>>(
+        &mut self,
+        buf: Vec<u8>,
+        id: I,
+        now: Instant,
+    ) -> Res<()> {
+        self.send_datagram_with_priority(buf, id, TransmissionPriority::default(), None, now)
+    }
+
+    /// Queue a datagram for sending, like [`Self::send_datagram`], with a priority
+    /// and a time-to-live.
+    ///
+    /// Datagrams are sent in order of `priority`, after any stream data with the same
+    /// priority.  A datagram that is not sent within `ttl` of `now` is dropped, which
+    /// is reported with [`OutgoingDatagramOutcome::Expired`].  If the queue is full,
+    /// [`ConnectionParameters::outgoing_datagram_drop_policy`] determines which datagram
+    /// is dropped.
+    ///
+    /// # Errors
+    ///
+    /// The function returns `TooMuchData` if the supply buffer is bigger than
+    /// the allowed remote datagram size.
+    pub fn send_datagram_with_priority<I: Into<DatagramTracking>>(
+        &mut self,
+        buf: Vec<u8>,
+        id: I,
+        priority: TransmissionPriority,
+        ttl: Option<Duration>,
+        now: Instant,
+    ) -> Res<()> {
+        let stats = &mut self.stats.borrow_mut();
+        // Make room for the new datagram, if any have expired.
+        self.quic_datagrams.expire(now, stats);
         self.quic_datagrams
-            .add_datagram(buf, id.into(), &mut self.stats.borrow_mut())
+            .add_datagram(buf, id.into(), priority, ttl.map(|ttl| now + ttl), stats)
     }
 
     /// Return the PLMTU of the primary path.
@@ -4,13 +4,19 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cmp::max, num::NonZeroUsize, time::Duration};
+use std::{
+    cmp::max,
+    num::{NonZeroU64, NonZeroUsize},
+    sync::Arc,
+    time::Duration,
+};
 
 use neqo_common::to_u64;
 
 pub use crate::recovery::FAST_PTO_SCALE;
 use crate::{
-    CongestionControl, DEFAULT_INITIAL_RTT, HyStartCssBaseline, Res, SlowStart,
+    CongestionControl, CongestionControllerFactory, ConnectionIdRotation, DEFAULT_INITIAL_RTT,
+    DatagramDropPolicy, FailoverPolicy, HyStartCssBaseline, Res, SlowStart, StatelessResetKey,
     connection::{ConnectionIdManager, Role},
     rtt::GRANULARITY,
     stream_id::StreamType,
@@ -109,6 +115,9 @@ pub enum PreferredAddressConfig {
 pub struct ConnectionParameters {
     versions: version::Config,
     congestion_control: CongestionControl,
+    /// Creates application-supplied congestion controllers.  When set, this is used
+    /// instead of `congestion_control` and `slow_start`.
+    congestion_controller_factory: Option<Arc<dyn CongestionControllerFactory>>,
     slow_start: SlowStart,
     hystart_css_baseline: HyStartCssBaseline,
     /// Initial connection-level flow control limit.
@@ -140,9 +149,20 @@ pub struct ConnectionParameters {
     /// locally. `None` (the default) disables the check, leaving the idle timeout
     /// as the only backstop.
     max_pto: Option<NonZeroUsize>,
+    /// The maximum rate at which the connection sends, in bits per second.
+    /// `None` (the default) means that only congestion control limits the rate.
+    max_send_rate: Option<NonZeroU64>,
+    /// The policy for failing over to a standby path.  `None` disables failover.
+    failover: Option<FailoverPolicy>,
+    /// The policy for rotating connection IDs.
+    cid_rotation: ConnectionIdRotation,
+    /// The static key for deriving stateless reset tokens.  When this is not
+    /// set, random tokens are used.
+    stateless_reset_key: Option<Arc<StatelessResetKey>>,
     preferred_address: PreferredAddressConfig,
     datagram_size: u64,
     outgoing_datagram_queue: usize,
+    outgoing_datagram_drop_policy: DatagramDropPolicy,
     initial_rtt: Duration,
     fast_pto: u8,
     grease: bool,
@@ -166,6 +186,11 @@ pub struct ConnectionParameters {
     /// Whether to recover from spurious congestion events by restoring prior Congestion Controller
     /// state. Detection and metrics are always active regardless of this setting.
     spurious_recovery: bool,
+    /// Whether the thresholds for loss detection adapt to reordering.
+    adaptive_reordering: bool,
+    /// Whether to use L4S, i.e., mark packets with ECT(1) and use the Prague congestion
+    /// controller.
+    l4s: bool,
 }
 
 impl Default for ConnectionParameters {
@@ -173,6 +198,7 @@ impl Default for ConnectionParameters {
         Self {
             versions: version::Config::default(),
             congestion_control: CongestionControl::Cubic,
+            congestion_controller_factory: None,
             slow_start: SlowStart::Classic,
             hystart_css_baseline: HyStartCssBaseline::CurrentRoundMinRtt,
             max_data: INITIAL_LOCAL_MAX_DATA,
@@ -184,9 +210,14 @@ impl Default for ConnectionParameters {
             ack_ratio: Self::DEFAULT_ACK_RATIO,
             idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
             max_pto: None,
+            max_send_rate: None,
+            failover: None,
+            cid_rotation: ConnectionIdRotation::default(),
+            stateless_reset_key: None,
             preferred_address: PreferredAddressConfig::Default,
             datagram_size: MAX_DATAGRAM_FRAME_SIZE,
             outgoing_datagram_queue: MAX_QUEUED_DATAGRAMS_DEFAULT,
+            outgoing_datagram_drop_policy: DatagramDropPolicy::Oldest,
             initial_rtt: DEFAULT_INITIAL_RTT,
             fast_pto: FAST_PTO_SCALE,
             grease: true,
@@ -200,6 +231,8 @@ impl Default for ConnectionParameters {
             scone: false,
             reliable_stream_reset: true,
             spurious_recovery: true,
+            adaptive_reordering: false,
+            l4s: false,
         }
     }
 }
@@ -243,6 +276,23 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub fn get_congestion_controller_factory(&self) -> Option<&dyn CongestionControllerFactory> {
+        self.congestion_controller_factory.as_deref()
+    }
+
+    /// Use congestion controllers created by `factory` instead of a built-in algorithm.
+    /// This overrides [`Self::congestion_control`] and [`Self::slow_start`].  If [`Self::l4s`]
+    /// is enabled, the controller needs to respond to ECN marks as L4S requires.
+    #[must_use]
+    pub fn congestion_controller_factory(
+        mut self,
+        factory: Box<dyn CongestionControllerFactory>,
+    ) -> Self {
+        self.congestion_controller_factory = Some(Arc::from(factory));
+        self
+    }
+
     #[must_use]
     pub const fn get_slow_start(&self) -> SlowStart {
         self.slow_start
@@ -391,6 +441,66 @@ impl ConnectionParameters {
         self.max_pto
     }
 
+    /// Limit the rate at which the connection sends to `rate` bits per second,
+    /// on every path.  The pacer enforces the limit, even if pacing is disabled.
+    /// When the network provides SCONE advice, the lower of the two rates applies.
+    /// `None` removes the limit.  See also [`crate::Connection::set_max_send_rate`].
+    #[must_use]
+    pub const fn max_send_rate(mut self, rate: Option<NonZeroU64>) -> Self {
+        self.max_send_rate = rate;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_max_send_rate(&self) -> Option<NonZeroU64> {
+        self.max_send_rate
+    }
+
+    pub(crate) const fn set_max_send_rate(&mut self, rate: Option<NonZeroU64>) {
+        self.max_send_rate = rate;
+    }
+
+    /// Fail over to a standby path when the primary path fails, following `policy`.
+    /// `None` (the default) disables failover.
+    #[must_use]
+    pub const fn failover(mut self, policy: Option<FailoverPolicy>) -> Self {
+        self.failover = policy;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_failover(&self) -> Option<FailoverPolicy> {
+        self.failover
+    }
+
+    /// Rotate connection IDs on an established connection, following `policy`.
+    /// By default, connection IDs only change when a path changes.
+    #[must_use]
+    pub const fn cid_rotation(mut self, policy: ConnectionIdRotation) -> Self {
+        self.cid_rotation = policy;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_cid_rotation(&self) -> &ConnectionIdRotation {
+        &self.cid_rotation
+    }
+
+    /// Derive stateless reset tokens from `key` and the connection ID, rather than
+    /// choosing them at random.  A server that keeps the same key across restarts
+    /// can then reset connections that it has lost state for.
+    /// See [`crate::server::Server`].
+    #[must_use]
+    pub fn stateless_reset_key(mut self, key: StatelessResetKey) -> Self {
+        self.stateless_reset_key = Some(Arc::new(key));
+        self
+    }
+
+    #[must_use]
+    pub fn get_stateless_reset_key(&self) -> Option<&StatelessResetKey> {
+        self.stateless_reset_key.as_deref()
+    }
+
     #[must_use]
     pub const fn get_initial_rtt(&self) -> Duration {
         self.initial_rtt
@@ -425,6 +535,19 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn get_outgoing_datagram_drop_policy(&self) -> DatagramDropPolicy {
+        self.outgoing_datagram_drop_policy
+    }
+
+    /// Choose which datagram is dropped when a datagram is sent while the
+    /// outgoing queue is full.  The default is [`DatagramDropPolicy::Oldest`].
+    #[must_use]
+    pub const fn outgoing_datagram_drop_policy(mut self, policy: DatagramDropPolicy) -> Self {
+        self.outgoing_datagram_drop_policy = policy;
+        self
+    }
+
     #[must_use]
     pub const fn get_fast_pto(&self) -> u8 {
         self.fast_pto
@@ -568,6 +691,36 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn adaptive_reordering_enabled(&self) -> bool {
+        self.adaptive_reordering
+    }
+
+    /// Adapt the thresholds for loss detection to reordering on the path, like RACK-TLP
+    /// (RFC 8985).  When a packet that was declared lost is acknowledged, the packet and
+    /// time thresholds are widened to cover the reordering that was seen.  They are reset
+    /// after a run of losses that were not spurious.
+    #[must_use]
+    pub const fn adaptive_reordering(mut self, adaptive_reordering: bool) -> Self {
+        self.adaptive_reordering = adaptive_reordering;
+        self
+    }
+
+    #[must_use]
+    pub const fn l4s_enabled(&self) -> bool {
+        self.l4s
+    }
+
+    /// Enable L4S (RFC 9330). Packets are marked with ECT(1) instead of ECT(0) and the
+    /// congestion window is managed by a Prague controller, which reduces it in proportion
+    /// to the fraction of CE-marked packets. This overrides the configured congestion
+    /// control and slow start algorithms.
+    #[must_use]
+    pub const fn l4s(mut self, l4s: bool) -> Self {
+        self.l4s = l4s;
+        self
+    }
+
     /// # Errors
     /// When a connection ID cannot be obtained.
     /// # Panics
@@ -625,7 +778,7 @@ impl ConnectionParameters {
         if let PreferredAddressConfig::Address(preferred) = &self.preferred_address
             && role == Role::Server
         {
-            let (cid, srt) = cid_manager.preferred_address_cid()?;
+            let (cid, srt) = cid_manager.preferred_address_cid(self.get_stateless_reset_key())?;
             tps.local_mut().set(
                 PreferredAddressTp,
                 TransportParameter::PreferredAddress {
@@ -674,6 +827,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn max_send_rate() {
+        // Default is unlimited; verify builder can set a limit.
+        assert_eq!(ConnectionParameters::default().get_max_send_rate(), None);
+        let rate = NonZeroU64::new(1_000_000);
+        assert_eq!(
+            ConnectionParameters::default()
+                .max_send_rate(rate)
+                .get_max_send_rate(),
+            rate
+        );
+    }
+
+    #[test]
+    fn failover() {
+        // Default is disabled; verify builder can enable it.
+        assert_eq!(ConnectionParameters::default().get_failover(), None);
+        let policy = FailoverPolicy::default().probe_interval(Duration::from_secs(1));
+        assert_eq!(
+            ConnectionParameters::default()
+                .failover(Some(policy))
+                .get_failover(),
+            Some(policy)
+        );
+    }
+
+    #[test]
+    fn cid_rotation() {
+        assert_eq!(
+            ConnectionParameters::default().get_cid_rotation(),
+            &ConnectionIdRotation::default()
+        );
+        let policy = ConnectionIdRotation::default()
+            .remote_bytes(Some(1_000_000))
+            .local_interval(Some(Duration::from_secs(60)));
+        let params = ConnectionParameters::default().cid_rotation(policy);
+        assert_eq!(params.get_cid_rotation(), &policy);
+        assert_eq!(params.get_cid_rotation().get_remote_interval(), None);
+    }
+
+    #[test]
+    fn stateless_reset_key() {
+        // Default is random tokens; verify builder can set a key.
+        assert!(
+            ConnectionParameters::default()
+                .get_stateless_reset_key()
+                .is_none()
+        );
+        test_fixture::fixture_init();
+        let key = StatelessResetKey::new(&[1; 32]).unwrap();
+        assert!(
+            ConnectionParameters::default()
+                .stateless_reset_key(key)
+                .get_stateless_reset_key()
+                .is_some()
+        );
+    }
+
     #[test]
     fn scone_enabled() {
         // Default is false; verify builder can toggle it.
@@ -681,6 +892,13 @@ mod tests {
         assert!(ConnectionParameters::default().scone(true).scone_enabled());
     }
 
+    #[test]
+    fn l4s_enabled() {
+        // Default is false; verify builder can toggle it.
+        assert!(!ConnectionParameters::default().l4s_enabled());
+        assert!(ConnectionParameters::default().l4s(true).l4s_enabled());
+    }
+
     #[test]
     fn reliable_stream_reset_enabled() {
         // Default is true; verify builder can toggle it.
@@ -692,6 +910,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn adaptive_reordering_enabled() {
+        // Default is false; verify builder can toggle it.
+        assert!(!ConnectionParameters::default().adaptive_reordering_enabled());
+        assert!(
+            ConnectionParameters::default()
+                .adaptive_reordering(true)
+                .adaptive_reordering_enabled()
+        );
+    }
+
     #[test]
     fn spurious_recovery_enabled() {
         // Default is true; verify builder can toggle it.
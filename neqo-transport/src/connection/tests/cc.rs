@@ -4,17 +4,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::time::Duration;
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    time::Duration,
+};
 
 use neqo_common::{Datagram, Ecn, qdebug, qinfo, to_u64};
 
 use super::{
     super::Output, CLIENT_HANDSHAKE_1RTT_PACKETS, DEFAULT_RTT, POST_HANDSHAKE_CWND, ack_bytes,
     assert_full_cwnd, connect_rtt_idle, cwnd, cwnd_avail, cwnd_packets, default_client,
-    default_server, fill_cwnd, induce_persistent_congestion, send_something,
+    default_server, fill_cwnd, fill_stream, induce_persistent_congestion, send_something,
 };
 use crate::{
-    CongestionControl, ConnectionParameters,
+    CarefulResumeParams, CongestionControl, ConnectionEvent, ConnectionParameters, Error,
     connection::tests::{connect_with_rtt, new_client, new_server, now},
     packet,
     recovery::{ACK_ONLY_SIZE_LIMIT, PACKET_THRESHOLD},
@@ -460,3 +463,193 @@ fn pace() {
     assert_ne!(fin, Duration::new(0, 0));
     assert_ne!(fin, gap);
 }
+
+/// A maximum send rate slows the pacer, and removing it restores the pace
+/// from the congestion window.
+#[test]
+fn pace_max_send_rate() {
+    // 400 kbit/s is 50 kB/s, well below the rate that the pacer derives from
+    // the initial congestion window and the RTT.
+    const MAX_RATE: NonZeroU64 = NonZeroU64::new(400_000).unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    let mut now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+    client.set_max_send_rate(Some(MAX_RATE));
+    assert_eq!(client.stats().max_send_rate, Some(MAX_RATE));
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    fill_stream(&mut client, stream);
+    let mtu = client.plpmtu();
+    let start = now;
+    let mut sent = 0;
+    while sent < cwnd_packets(POST_HANDSHAKE_CWND, mtu) / 2 {
+        match client.process_output(now) {
+            Output::Datagram(_) => sent += 1,
+            Output::Callback(t) => now += t,
+            Output::None => panic!(),
+        }
+    }
+    // The pacer allows a burst, after which every packet waits for its share
+    // of the maximum rate.
+    let paced = to_u64((sent - 1 - PACING_BURST_SIZE) * mtu);
+    assert!(now - start >= Duration::from_micros(paced * 1_000_000 / (MAX_RATE.get() / 8)));
+
+    client.set_max_send_rate(None);
+    assert_eq!(client.stats().max_send_rate, None);
+    let gap = client.process_output(now).callback();
+    assert!(gap < Duration::from_micros(to_u64(mtu) * 1_000_000 / (MAX_RATE.get() / 8)));
+}
+
+/// Saved parameters that support a window of 200,000 bytes at `DEFAULT_RTT`.
+const CAREFUL_RESUME: CarefulResumeParams = CarefulResumeParams::new(2_000_000, DEFAULT_RTT);
+
+/// Careful resume jumps to half of the saved window once the sender fills its window.
+#[test]
+fn cc_careful_resume() {
+    let mut client = default_client();
+    let mut server = default_server();
+    client.set_careful_resume(CAREFUL_RESUME).unwrap();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+    assert_eq!(cwnd(&client), POST_HANDSHAKE_CWND);
+    assert_eq!(
+        client.set_careful_resume(CAREFUL_RESUME),
+        Err(Error::ConnectionState)
+    );
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    assert_full_cwnd(&c_tx_dgrams, POST_HANDSHAKE_CWND, client.plpmtu());
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert_eq!(cwnd(&client), CAREFUL_RESUME.cwnd() / 2);
+    assert_eq!(
+        client.stats().cc.careful_resume_jump,
+        Some(CAREFUL_RESUME.cwnd() / 2)
+    );
+
+    // The larger window is used and validated.
+    let (c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    let sent = c_tx_dgrams.iter().map(Datagram::len).sum::<usize>();
+    assert!(sent > CAREFUL_RESUME.cwnd() / 2 - client.plpmtu());
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert!(!client.stats().cc.careful_resume_retreat);
+    assert!(cwnd(&client) > CAREFUL_RESUME.cwnd() / 2 - 2 * client.plpmtu());
+
+    let params = client.careful_resume_params().unwrap();
+    assert_eq!(params.rtt(), client.stats().min_rtt);
+    assert!(params.bandwidth() > 0);
+}
+
+/// Loss before the jump is validated causes a retreat to half of what was delivered.
+#[test]
+fn cc_careful_resume_retreat() {
+    let mut client = default_client();
+    let mut server = default_server();
+    client.set_careful_resume(CAREFUL_RESUME).unwrap();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert_eq!(cwnd(&client), CAREFUL_RESUME.cwnd() / 2);
+
+    // Lose the first packet sent with the larger window.
+    let (mut c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    c_tx_dgrams.remove(0);
+    let delivered = c_tx_dgrams.iter().map(Datagram::len).sum::<usize>();
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert!(client.stats().cc.careful_resume_retreat);
+    assert_eq!(client.stats().cc.congestion_events.loss, 1);
+    // The packet sizes on the wire are a little larger than the packets that were acknowledged.
+    assert!(cwnd(&client) <= delivered / 2);
+    assert!(cwnd(&client) + client.plpmtu() > delivered / 2);
+}
+
+/// Careful resume is not used if the RTT is very different.
+#[test]
+fn cc_careful_resume_rtt_mismatch() {
+    let mut client = default_client();
+    let mut server = default_server();
+    client
+        .set_careful_resume(CarefulResumeParams::new(2_000_000, DEFAULT_RTT * 4))
+        .unwrap();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert!(cwnd(&client) <= POST_HANDSHAKE_CWND * 2);
+    assert_eq!(client.stats().cc.careful_resume_jump, None);
+}
+
+#[test]
+fn send_budget() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let budget = client.send_budget().unwrap();
+    assert_eq!(budget.cwnd, cwnd(&client));
+    assert_eq!(budget.cwnd_avail, cwnd_avail(&client));
+    assert_eq!(budget.rtt, DEFAULT_RTT);
+    assert!(budget.pacing_rate.is_some());
+    assert!(budget.loss_rate.abs() < f64::EPSILON);
+    assert!(budget.ecn_ce_rate.abs() < f64::EPSILON);
+
+    // Lose the first packet of a flight and mark the last with ECN-CE.
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (mut c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    let sent = c_tx_dgrams.len();
+    c_tx_dgrams.remove(0);
+    c_tx_dgrams.last_mut().unwrap().set_tos(Ecn::Ce.into());
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+
+    let budget = client.send_budget().unwrap();
+    assert!(
+        budget.loss_rate > 0.0 && budget.loss_rate <= 1.0 / f64::from(u32::try_from(sent).unwrap())
+    );
+    assert!(budget.ecn_ce_rate > 0.0);
+}
+
+#[test]
+fn send_budget_available() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+    let is_budget_event = |e: ConnectionEvent| matches!(e, ConnectionEvent::SendBudgetAvailable);
+
+    let watermark = NonZeroUsize::new(client.plpmtu()).unwrap();
+    client.set_send_budget_watermark(Some(watermark));
+    assert!(!client.events().any(is_budget_event));
+
+    // Filling the congestion window takes the budget below the watermark.
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (c_tx_dgrams, mut now) = fill_cwnd(&mut client, stream_id, now);
+    assert!(cwnd_avail(&client) < watermark.get());
+    assert!(!client.events().any(is_budget_event));
+
+    // Acknowledgments open it up again.
+    now += DEFAULT_RTT / 2;
+    let s_ack = ack_bytes(&mut server, stream_id, c_tx_dgrams, now);
+    now += DEFAULT_RTT / 2;
+    client.process_input(s_ack, now);
+    assert!(cwnd_avail(&client) >= watermark.get());
+    assert!(client.events().any(is_budget_event));
+}
@@ -0,0 +1,229 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::time::Duration;
+
+use test_fixture::now;
+
+use super::{
+    AT_LEAST_PTO, Connection, ConnectionId, State, connect_force_idle, default_client,
+    default_server, migration::get_cid, new_client, send_something,
+};
+use crate::{ConnectionIdRotation, ConnectionParameters, Token as Srt, cid::ConnectionIdEntry};
+
+const INTERVAL: Duration = Duration::from_secs(10);
+
+fn rotating_client(policy: ConnectionIdRotation) -> Connection {
+    new_client(ConnectionParameters::default().cid_rotation(policy))
+}
+
+fn local_cids(c: &Connection) -> Vec<ConnectionId> {
+    c.cid_manager.local_cids().cloned().collect()
+}
+
+/// The client switches to a new connection ID for the server once the interval passes.
+#[test]
+fn remote_interval() {
+    let mut client =
+        rotating_client(ConnectionIdRotation::default().remote_interval(Some(INTERVAL)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    let before = send_something(&mut client, now());
+    let before_cid = ConnectionId::from(get_cid(&before));
+    server.process_input(before, now());
+    assert_eq!(client.stats().frame_tx.retire_connection_id, 0);
+
+    let after = send_something(&mut client, now() + INTERVAL);
+    assert_ne!(ConnectionId::from(get_cid(&after)), before_cid);
+    assert_eq!(client.stats().frame_tx.retire_connection_id, 1);
+
+    // The server retires the old connection ID and replaces it.
+    let ncid_before = server.stats().frame_tx.new_connection_id;
+    server.process_input(after, now() + INTERVAL);
+    assert_eq!(server.stats().frame_rx.retire_connection_id, 1);
+    assert!(!local_cids(&server).contains(&before_cid));
+    _ = send_something(&mut server, now() + INTERVAL);
+    assert!(server.stats().frame_tx.new_connection_id > ncid_before);
+}
+
+/// The client switches to a new connection ID for the server after sending enough bytes.
+#[test]
+fn remote_bytes() {
+    let mut client = rotating_client(ConnectionIdRotation::default().remote_bytes(Some(1)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    let first = send_something(&mut client, now());
+    let retired = client.stats().frame_tx.retire_connection_id;
+    let second = send_something(&mut client, now());
+    assert_ne!(get_cid(&first), get_cid(&second));
+    assert_eq!(client.stats().frame_tx.retire_connection_id, retired + 1);
+    server.process_input(first, now());
+    server.process_input(second, now());
+    assert_eq!(server.stats().frame_rx.retire_connection_id, retired + 1);
+    assert_eq!(*server.state(), State::Confirmed);
+}
+
+/// Rotation pauses rather than using the last spare connection ID.
+#[test]
+fn remote_keeps_spare() {
+    let mut client = rotating_client(ConnectionIdRotation::default().remote_bytes(Some(1)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    // Without delivering anything to the server, there are no replacements.
+    let mut cids = Vec::new();
+    for _ in 0..10 {
+        cids.push(ConnectionId::from(get_cid(&send_something(
+            &mut client,
+            now(),
+        ))));
+    }
+    // Rotation stopped when there was only one spare connection ID left.
+    assert_eq!(client.cids.len(), 1);
+    assert_eq!(cids[8], cids[9]);
+    assert!(client.stats().frame_tx.retire_connection_id > 0);
+}
+
+/// The client issues new connection IDs and has the server retire the old ones.
+#[test]
+fn local_interval() {
+    let mut client =
+        rotating_client(ConnectionIdRotation::default().local_interval(Some(INTERVAL)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let old = local_cids(&client);
+    let old_dcid = ConnectionId::from(get_cid(&send_something(&mut server, now())));
+    assert!(old.contains(&old_dcid));
+
+    let ncid_before = client.stats().frame_tx.new_connection_id;
+    let dgram = send_something(&mut client, now() + INTERVAL);
+    assert!(client.stats().frame_tx.new_connection_id > ncid_before);
+    server.process_input(dgram, now() + INTERVAL);
+
+    // The server switches to a new connection ID and retires all of the old ones.
+    let response = send_something(&mut server, now() + INTERVAL);
+    let new_dcid = ConnectionId::from(get_cid(&response));
+    assert!(!old.contains(&new_dcid));
+    assert_eq!(server.stats().frame_tx.retire_connection_id, old.len());
+    client.process_input(response, now() + INTERVAL);
+    let current = local_cids(&client);
+    assert!(current.contains(&new_dcid));
+    assert!(current.iter().all(|cid| !old.contains(cid)));
+}
+
+/// When the `NEW_CONNECTION_ID` frames that rotate connection IDs are lost,
+/// they are sent again and the peer still retires the old connection IDs.
+#[test]
+fn local_interval_lost() {
+    let mut client =
+        rotating_client(ConnectionIdRotation::default().local_interval(Some(INTERVAL)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let old = local_cids(&client);
+
+    let mut now = now() + INTERVAL;
+    let ncid_before = client.stats().frame_tx.new_connection_id;
+    _ = send_something(&mut client, now); // Lost.
+    let sent = client.stats().frame_tx.new_connection_id - ncid_before;
+    assert!(sent > 0);
+
+    // The PTO causes the frames to be sent again.
+    now += AT_LEAST_PTO;
+    while let Some(dgram) = client.process_output(now).dgram() {
+        server.process_input(dgram, now);
+    }
+    assert!(client.stats().frame_tx.new_connection_id >= ncid_before + 2 * sent);
+
+    let response = send_something(&mut server, now);
+    assert!(!old.contains(&ConnectionId::from(get_cid(&response))));
+    assert_eq!(server.stats().frame_tx.retire_connection_id, old.len());
+    client.process_input(response, now);
+    assert!(local_cids(&client).iter().all(|cid| !old.contains(cid)));
+}
+
+/// When the loss of a `NEW_CONNECTION_ID` frame is only detected after the next
+/// rotation, the connection ID is retired rather than sent again with a Retire Prior To
+/// value that is larger than its sequence number.
+#[test]
+fn local_interval_lost_after_rotation() {
+    let mut client =
+        rotating_client(ConnectionIdRotation::default().local_interval(Some(INTERVAL)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    // The first rotation completes.
+    let mut now = now() + INTERVAL;
+    let dgram = send_something(&mut client, now);
+    server.process_input(dgram, now);
+    let response = send_something(&mut server, now);
+    client.process_input(response, now);
+    let (seqno, cid) = client
+        .cid_manager
+        .local_entries()
+        .max_by_key(|&(seqno, _)| seqno)
+        .map(|(seqno, cid)| (seqno, cid.clone()))
+        .unwrap();
+
+    // The second rotation starts.
+    now += INTERVAL;
+    let dgram = send_something(&mut client, now);
+    server.process_input(dgram, now);
+
+    // Then a frame from the first rotation is declared lost.
+    client
+        .cid_manager
+        .lost(&ConnectionIdEntry::new(seqno, cid.clone(), Srt::random()));
+    assert!(!local_cids(&client).contains(&cid));
+    let dgram = send_something(&mut client, now);
+    server.process_input(dgram, now);
+    assert_eq!(*server.state(), State::Confirmed);
+}
+
+/// When the `RETIRE_CONNECTION_ID` frames from the peer are lost, the next rotation
+/// waits until they arrive.
+#[test]
+fn local_interval_retire_lost() {
+    let mut client =
+        rotating_client(ConnectionIdRotation::default().local_interval(Some(INTERVAL)));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let old = local_cids(&client);
+
+    let mut now = now() + INTERVAL;
+    let dgram = send_something(&mut client, now);
+    server.process_input(dgram, now);
+    _ = send_something(&mut server, now); // Lost, with the RETIRE_CONNECTION_ID frames.
+    assert_eq!(server.stats().frame_tx.retire_connection_id, old.len());
+
+    // Another interval passes, but the old connection IDs are still there,
+    // so no new connection IDs are issued.
+    now += INTERVAL;
+    let rotated = local_cids(&client);
+    let dgram = send_something(&mut client, now);
+    assert_eq!(local_cids(&client), rotated);
+    server.process_input(dgram, now);
+
+    // The server sends the lost frames again.
+    let response = send_something(&mut server, now);
+    assert_eq!(server.stats().frame_tx.retire_connection_id, 2 * old.len());
+    client.process_input(response, now);
+    assert!(local_cids(&client).iter().all(|cid| !old.contains(cid)));
+}
+
+/// Without a policy, connection IDs don't change.
+#[test]
+fn no_rotation() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let cids = local_cids(&client);
+    let before = send_something(&mut client, now());
+    let after = send_something(&mut client, now() + INTERVAL * 2);
+    assert_eq!(get_cid(&before), get_cid(&after));
+    assert_eq!(local_cids(&client), cids);
+}
@@ -4,7 +4,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
 use neqo_common::{event::Provider as _, to_u64};
 use static_assertions::const_assert;
@@ -13,8 +13,8 @@ use super::{
     AT_LEAST_PTO, assert_error, connect_force_idle, default_server, new_client, new_server, now,
 };
 use crate::{
-    CloseReason, Connection, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, Pmtud,
-    StreamType,
+    CloseReason, Connection, ConnectionParameters, DatagramDropPolicy, Error,
+    MIN_INITIAL_PACKET_SIZE, Pmtud, StreamType,
     connection::tests::DEFAULT_ADDR,
     events::{ConnectionEvent, OutgoingDatagramOutcome},
     frame::FrameType,
@@ -72,12 +72,12 @@ fn datagram_disabled_both() {
     assert_eq!(client.max_datagram_size(), Err(Error::NotAvailable));
     assert_eq!(server.max_datagram_size(), Err(Error::NotAvailable));
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), None),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), None, now()),
         Err(Error::TooMuchData)
     );
     assert_eq!(server.stats().frame_tx.datagram, 0);
     assert_eq!(
-        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), None),
+        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), None, now()),
         Err(Error::TooMuchData)
     );
     assert_eq!(server.stats().frame_tx.datagram, 0);
@@ -96,12 +96,12 @@ fn datagram_enabled_on_client() {
         Ok(DATAGRAM_LEN_SMALLER_THAN_MTU)
     );
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Err(Error::TooMuchData)
     );
     let dgram_sent = server.stats().frame_tx.datagram;
     assert_eq!(
-        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let out = server.process_output(now()).dgram().unwrap();
@@ -127,12 +127,12 @@ fn datagram_enabled_on_server() {
     );
     assert_eq!(server.max_datagram_size(), Err(Error::NotAvailable));
     assert_eq!(
-        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        server.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Err(Error::TooMuchData)
     );
     let dgram_sent = client.stats().frame_tx.datagram;
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let out = client.process_output(now()).dgram().unwrap();
@@ -178,7 +178,7 @@ fn limit_data_size() {
     // Datagram can be queued because they are smaller than allowed by the peer,
     // but they cannot be sent.
     assert_eq!(
-        server.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1)),
+        server.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
 
@@ -197,7 +197,7 @@ fn limit_data_size() {
 
     // The same test for the client side.
     assert_eq!(
-        client.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let dgram_sent_c = client.stats().frame_tx.datagram;
@@ -216,11 +216,11 @@ fn after_dgram_dropped_continue_writing_frames() {
     // Datagram can be queued because they are smaller than allowed by the peer,
     // but they cannot be sent.
     assert_eq!(
-        client.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_BIGGER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(2)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(2), now()),
         Ok(())
     );
 
@@ -248,7 +248,7 @@ fn datagram_acked() {
 
     let dgram_sent = client.stats().frame_tx.datagram;
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let out = client.process_output(now()).dgram();
@@ -303,7 +303,10 @@ fn datagram_after_stream_data() {
 
     // Write a datagram first.
     let dgram_sent = client.stats().frame_tx.datagram;
-    assert_eq!(client.send_datagram(DATA_MTU.to_vec(), Some(1)), Ok(()));
+    assert_eq!(
+        client.send_datagram(DATA_MTU.to_vec(), Some(1), now()),
+        Ok(())
+    );
 
     // Create a stream with normal priority and send some data.
     let stream_id = client.stream_create(StreamType::BiDi).unwrap();
@@ -345,7 +348,10 @@ fn datagram_before_stream_data() {
 
     // Write a datagram.
     let dgram_sent = client.stats().frame_tx.datagram;
-    assert_eq!(client.send_datagram(DATA_MTU.to_vec(), Some(1)), Ok(()));
+    assert_eq!(
+        client.send_datagram(DATA_MTU.to_vec(), Some(1), now()),
+        Ok(())
+    );
 
     if let ConnectionEvent::Datagram(data) =
         &send_packet_and_get_server_event(&mut client, &mut server)
@@ -368,7 +374,7 @@ fn datagram_lost() {
 
     let dgram_sent = client.stats().frame_tx.datagram;
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let _out = client.process_output(now()).dgram(); // This packet will be lost.
@@ -398,7 +404,7 @@ fn datagram_sent_once() {
 
     let dgram_sent = client.stats().frame_tx.datagram;
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     let _out = client.process_output(now()).dgram();
@@ -448,18 +454,21 @@ fn outgoing_datagram_queue_full() {
 
     let dgram_sent = client.stats().frame_tx.datagram;
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), now()),
         Ok(())
     );
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(2)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(2), now()),
         Ok(())
     );
 
     // The outgoing datagram queue limit is 2, therefore the datagram with id 1
     // will be dropped after adding one more datagram.
     let dgram_dropped = client.stats().datagram_tx.dropped_queue_full;
-    assert_eq!(client.send_datagram(DATA_MTU.to_vec(), Some(3)), Ok(()));
+    assert_eq!(
+        client.send_datagram(DATA_MTU.to_vec(), Some(3), now()),
+        Ok(())
+    );
     assert!(matches!(
         client.next_event().unwrap(),
         ConnectionEvent::OutgoingDatagramOutcome { id, outcome } if id == 1 && outcome == OutgoingDatagramOutcome::DroppedQueueFull
@@ -489,9 +498,142 @@ fn outgoing_datagram_queue_full() {
     ));
 }
 
+fn connect_datagram_with_policy(policy: DatagramDropPolicy) -> (Connection, Connection) {
+    let mut client = new_client(
+        ConnectionParameters::default()
+            .datagram_size(QuicDatagram::MAX_SIZE)
+            .outgoing_datagram_queue(OUTGOING_QUEUE)
+            .outgoing_datagram_drop_policy(policy),
+    );
+    let mut server =
+        new_server(ConnectionParameters::default().datagram_size(QuicDatagram::MAX_SIZE));
+    connect_force_idle(&mut client, &mut server);
+    (client, server)
+}
+
+fn send_prioritized(client: &mut Connection, id: u64, priority: TransmissionPriority) {
+    client
+        .send_datagram_with_priority(
+            vec![u8::try_from(id).unwrap(); DATAGRAM_LEN_MTU],
+            Some(id),
+            priority,
+            None,
+            now(),
+        )
+        .unwrap();
+}
+
+fn assert_dropped(client: &mut Connection, dropped: u64) {
+    assert!(client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome }
+            if id == dropped && outcome == OutgoingDatagramOutcome::DroppedQueueFull
+    )));
+}
+
+/// Datagrams with a higher priority are sent first.
+#[test]
+fn outgoing_datagram_priority() {
+    let (mut client, mut server) = connect_datagram();
+    send_prioritized(&mut client, 1, TransmissionPriority::Normal);
+    send_prioritized(&mut client, 2, TransmissionPriority::High);
+
+    for id in [2, 1] {
+        let out = client.process_output(now()).dgram();
+        server.process_input(out.unwrap(), now());
+        assert!(matches!(
+            server.next_event().unwrap(),
+            ConnectionEvent::Datagram(data) if data[0] == id
+        ));
+    }
+}
+
+/// A datagram that isn't sent before its time-to-live runs out is dropped.
+#[test]
+fn outgoing_datagram_expired() {
+    const TTL: Duration = Duration::from_millis(10);
+    let (mut client, _server) = connect_datagram();
+    client
+        .send_datagram_with_priority(
+            DATA_MTU.to_vec(),
+            Some(1),
+            TransmissionPriority::Normal,
+            Some(TTL),
+            now(),
+        )
+        .unwrap();
+    assert!(client.process_output(now() + TTL).dgram().is_none());
+    assert!(client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome }
+            if id == 1 && outcome == OutgoingDatagramOutcome::Expired
+    )));
+    assert_eq!(client.stats().datagram_tx.expired, 1);
+    assert_eq!(client.stats().datagram_tx.dropped_queue_full, 0);
+}
+
+/// Queuing a datagram without a time-to-live first drops expired datagrams, so a queue
+/// full of expired datagrams doesn't cause anything to be dropped for lack of space.
+#[test]
+fn outgoing_datagram_expired_before_queue_full() {
+    const TTL: Duration = Duration::from_millis(10);
+    let (mut client, _server) = connect_datagram();
+    for id in 1..=2 {
+        client
+            .send_datagram_with_priority(
+                DATA_MTU.to_vec(),
+                Some(id),
+                TransmissionPriority::Normal,
+                Some(TTL),
+                now(),
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        client.send_datagram(DATA_MTU.to_vec(), Some(3), now() + TTL),
+        Ok(())
+    );
+    assert!(!client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::OutgoingDatagramOutcome {
+            outcome: OutgoingDatagramOutcome::DroppedQueueFull,
+            ..
+        }
+    )));
+    assert_eq!(client.stats().datagram_tx.expired, 2);
+    assert_eq!(client.stats().datagram_tx.dropped_queue_full, 0);
+}
+
+/// With `DatagramDropPolicy::Newest`, a full queue drops the new datagram.
+#[test]
+fn outgoing_datagram_drop_newest() {
+    let (mut client, _server) = connect_datagram_with_policy(DatagramDropPolicy::Newest);
+    send_prioritized(&mut client, 1, TransmissionPriority::Normal);
+    send_prioritized(&mut client, 2, TransmissionPriority::Normal);
+    send_prioritized(&mut client, 3, TransmissionPriority::Normal);
+    assert_dropped(&mut client, 3);
+}
+
+/// With `DatagramDropPolicy::LowestPriority`, a full queue drops the oldest datagram with
+/// the lowest priority, or the new datagram if its priority is lower than all of them.
+#[test]
+fn outgoing_datagram_drop_lowest_priority() {
+    let (mut client, _server) = connect_datagram_with_policy(DatagramDropPolicy::LowestPriority);
+    send_prioritized(&mut client, 1, TransmissionPriority::High);
+    send_prioritized(&mut client, 2, TransmissionPriority::Normal);
+    send_prioritized(&mut client, 3, TransmissionPriority::High);
+    assert_dropped(&mut client, 2);
+
+    send_prioritized(&mut client, 4, TransmissionPriority::Low);
+    assert_dropped(&mut client, 4);
+
+    send_prioritized(&mut client, 5, TransmissionPriority::High);
+    assert_dropped(&mut client, 1);
+}
+
 fn send_datagram(sender: &mut Connection, receiver: &mut Connection, data: Vec<u8>) {
     let dgram_sent = sender.stats().frame_tx.datagram;
-    assert_eq!(sender.send_datagram(data, Some(1)), Ok(()));
+    assert_eq!(sender.send_datagram(data, Some(1), now()), Ok(()));
     let out = sender.process_output(now()).dgram().unwrap();
     assert_eq!(sender.stats().frame_tx.datagram, dgram_sent + 1);
 
@@ -548,11 +690,11 @@ fn multiple_quic_datagrams_in_one_packet() {
     let dgram_sent = client.stats().frame_tx.datagram;
     // Enqueue 2 datagrams that can fit in a single packet.
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(1)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(1), now()),
         Ok(())
     );
     assert_eq!(
-        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(2)),
+        client.send_datagram(DATA_SMALLER_THAN_MTU_2.to_vec(), Some(2), now()),
         Ok(())
     );
 
@@ -51,6 +51,14 @@ fn remark(d: Datagram) -> Datagram {
     }
 }
 
+fn remark_ect0(d: Datagram) -> Datagram {
+    if d.tos().is_ecn_marked() {
+        set_tos(d, Ecn::Ect0)
+    } else {
+        d
+    }
+}
+
 fn ce(d: Datagram) -> Datagram {
     if d.tos().is_ecn_marked() {
         set_tos(d, Ecn::Ce)
@@ -290,6 +298,61 @@ fn disables_on_remark() {
     assert_ecn_disabled(client_pkt.tos());
 }
 
+#[test]
+fn l4s_marks_ect1() {
+    let now = now();
+    let mut client = new_client(ConnectionParameters::default().l4s(true));
+    let mut server = new_server(ConnectionParameters::default().l4s(true));
+    connect_force_idle(&mut client, &mut server);
+
+    for _ in 0..ecn::TEST_COUNT {
+        let ack = send_and_receive(&mut client, &mut server, now);
+        client.process_input(ack.unwrap(), now);
+    }
+
+    let stats = client.stats();
+    assert_eq!(
+        stats.ecn_path_validation[ecn::ValidationOutcome::Capable],
+        1
+    );
+    for packet_type in packet::Type::iter() {
+        assert_eq!(stats.ecn_tx[packet_type][Ecn::Ect0], 0);
+    }
+    assert!(stats.ecn_tx[packet::Type::Short][Ecn::Ect1] > 0);
+    assert_eq!(
+        server.stats().ecn_rx[packet::Type::Short][Ecn::Ect1],
+        stats.ecn_tx[packet::Type::Short][Ecn::Ect1]
+    );
+
+    let client_pkt = send_something(&mut client, now);
+    assert_eq!(Ecn::from(client_pkt.tos()), Ecn::Ect1);
+}
+
+#[test]
+fn l4s_disables_on_ect0_remark() {
+    let now = now();
+    let mut client = new_client(ConnectionParameters::default().l4s(true));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    for _ in 0..ecn::TEST_COUNT {
+        if let Some(ack) =
+            send_with_modifier_and_receive(&mut client, &mut server, now, remark_ect0)
+        {
+            client.process_input(ack, now);
+        }
+    }
+
+    // ECN should now be disabled.
+    assert_eq!(
+        client.stats().ecn_path_validation
+            [ecn::ValidationOutcome::NotCapable(ecn::ValidationError::ReceivedUnsentECT0)],
+        1
+    );
+    let client_pkt = send_something(&mut client, now);
+    assert_ecn_disabled(client_pkt.tos());
+}
+
 /// This function performs a handshake over a path that modifies packets via `orig_path_modifier`.
 /// It then sends `burst` packets on that path, and then migrates to a new path that
 /// modifies packets via `new_path_modifier`.  It sends `burst` packets on the new path.
@@ -13,6 +13,7 @@
 use std::{
     cell::RefCell,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    num::NonZeroUsize,
     rc::Rc,
     time::{Duration, Instant},
 };
@@ -32,8 +33,8 @@ use super::{
 };
 use crate::{
     CloseReason, ConnectionEvent, ConnectionId, ConnectionIdDecoder as _, ConnectionIdGenerator,
-    ConnectionIdRef, ConnectionParameters, EmptyConnectionIdGenerator, Error,
-    MIN_INITIAL_PACKET_SIZE,
+    ConnectionIdRef, ConnectionParameters, EmptyConnectionIdGenerator, Error, FailoverPolicy,
+    FailoverReason, MIN_INITIAL_PACKET_SIZE,
     cid::ConnectionIdManager,
     connection::tests::{
         assert_path_challenge_min_len, connect, send_something_paced, send_with_extra,
@@ -1372,3 +1373,105 @@ fn error_on_new_path_with_no_connection_id() {
         State::Closed(CloseReason::Transport(Error::UnknownFrameType))
     ));
 }
+
+/// Create a client with a failover policy and connect it, then set up a validated
+/// standby path on the IPv4 address.
+fn failover_client(policy: FailoverPolicy, server: &mut Connection, now: Instant) -> Connection {
+    let mut client = new_client(ConnectionParameters::default().failover(Some(policy)));
+    connect_force_idle(&mut client, server);
+
+    client
+        .set_standby_path(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), now)
+        .unwrap();
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_v4_path(&probe, true);
+    let resp = server.process(Some(probe), now).dgram().unwrap();
+    assert_v4_path(&resp, true);
+    client.process_input(resp, now);
+    // The standby path doesn't change the primary path.
+    let data = send_something(&mut client, now);
+    assert_v6_path(&data, false);
+    server.process_input(data, now);
+    client
+}
+
+#[test]
+fn standby_path_requires_failover_policy() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    assert_eq!(
+        client.set_standby_path(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), now()),
+        Err(Error::InvalidMigration)
+    );
+}
+
+/// A standby path is probed periodically so that it stays valid.
+#[test]
+fn standby_path_probed() {
+    const INTERVAL: Duration = Duration::from_secs(5);
+    let mut server = default_server();
+    let mut now = now();
+    let mut client = failover_client(
+        FailoverPolicy::default().probe_interval(INTERVAL),
+        &mut server,
+        now,
+    );
+    while let Some(d) = client.process_output(now).dgram() {
+        server.process_input(d, now);
+    }
+
+    let before = client.stats().frame_tx.path_challenge;
+    now += INTERVAL;
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_v4_path(&probe, true);
+    assert_eq!(client.stats().frame_tx.path_challenge, before + 1);
+}
+
+/// When the primary path blackholes, the client fails over to the standby path.
+#[test]
+fn failover_after_pto() {
+    let mut server = default_server();
+    let mut now = now();
+    let mut client = failover_client(
+        FailoverPolicy::default().pto_threshold(NonZeroUsize::new(2).unwrap()),
+        &mut server,
+        now,
+    );
+    drop(client.events().count());
+
+    // Send data that is never delivered.
+    drop(send_something(&mut client, now));
+    let failover = loop {
+        match client.process_output(now) {
+            Output::Datagram(d) => assert_v6_path(&d, false),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("connection should not close"),
+        }
+        if let Some(e) = client
+            .events()
+            .find(|e| matches!(e, ConnectionEvent::PathFailover { .. }))
+        {
+            break e;
+        }
+    };
+    assert_eq!(
+        failover,
+        ConnectionEvent::PathFailover {
+            local: DEFAULT_ADDR_V4,
+            remote: DEFAULT_ADDR_V4,
+            reason: FailoverReason::Pto(2),
+        }
+    );
+
+    // The client now sends on the standby path, and the server follows.
+    let client1 = send_something(&mut client, now);
+    assert_v4_path(&client1, true);
+    server.process_input(client1, now);
+    assert!(
+        server
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::PathMigrated { .. }))
+    );
+}
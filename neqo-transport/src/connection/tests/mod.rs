@@ -38,6 +38,7 @@ use crate::{
 // All the tests.
 mod ackrate;
 mod cc;
+mod cid_rotation;
 mod close;
 mod datagram;
 mod ecn;
@@ -6,6 +6,8 @@
 
 // Tests for RESET_STREAM_AT (draft-ietf-quic-reliable-stream-reset).
 
+use std::time::Duration;
+
 use neqo_common::event::Provider as _;
 use test_fixture::now;
 
@@ -135,6 +137,62 @@ fn reliable_reset_emits_no_send_complete() {
     assert_eq!(completes, 0);
 }
 
+/// Data that isn't acknowledged by its deadline is not retransmitted. The stream is reset
+/// with `RESET_STREAM_AT`, so the receiver only gets the data before it.
+#[test]
+fn deadline_expires() {
+    const DEADLINE: Duration = Duration::from_millis(10);
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+    let mut now = now();
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &DATA[..RELIABLE]).unwrap();
+    let dgram = client.process_output(now).dgram();
+    server.process_input(dgram.unwrap(), now);
+
+    // The rest of the data has a deadline, and it is lost.
+    client
+        .stream_set_deadline(stream_id, Some(now + DEADLINE), 3)
+        .unwrap();
+    client.stream_send(stream_id, &DATA[RELIABLE..]).unwrap();
+    let lost = client.process_output(now).dgram();
+    assert!(lost.is_some());
+
+    now += DEADLINE;
+    let dgram = client.process_output(now).dgram();
+    assert!(client.events().any(
+        |e| matches!(e, ConnectionEvent::SendStreamExpired { stream_id: id } if id == stream_id)
+    ));
+    assert_eq!(client.stats().frame_tx.reset_stream_at, 1);
+    server.process_input(dgram.unwrap(), now);
+
+    let mut buf = [0; 64];
+    let (n, fin) = server.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!(&buf[..n], &DATA[..RELIABLE]);
+    assert!(!fin);
+    assert!(server.events().any(
+        |e| matches!(e, ConnectionEvent::RecvStreamReset { stream_id: id, app_error: 3 } if id == stream_id)
+    ));
+}
+
+/// Against a peer that did not advertise reliable resets, deadlines are not available.
+#[test]
+fn deadline_unavailable_without_peer_support() {
+    let mut client = default_client();
+    let mut server = new_server(ConnectionParameters::default().reliable_stream_reset(false));
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    assert_eq!(
+        client
+            .stream_set_deadline(stream_id, Some(now()), 0)
+            .unwrap_err(),
+        Error::NotAvailable
+    );
+}
+
 /// Writes a raw `RESET_STREAM_AT` frame for `stream_id` with zero error/final/reliable sizes.
 struct ResetStreamAtWriter(u64);
 
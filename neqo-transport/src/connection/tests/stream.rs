@@ -4,7 +4,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cmp::max, collections::HashMap, fmt::Debug};
+use std::{cmp::max, collections::HashMap, fmt::Debug, rc::Rc};
 
 use neqo_common::{Role, event::Provider as _, qdebug, to_u64};
 use test_fixture::now;
@@ -19,7 +19,7 @@ use crate::{
     events::ConnectionEvent,
     frame::FrameType,
     packet,
-    send_stream::{self, OrderGroup},
+    send_stream::{self, OrderGroup, SendChunk},
     streams::SendOrder,
     tparams::{TransportParameter, TransportParameterId::*},
 };
@@ -116,6 +116,87 @@ fn transfer() {
     assert!(fin3);
 }
 
+/// Shared buffers are sent without a copy and released once they are acknowledged.
+#[test]
+fn transfer_chunks() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    let data: Rc<[u8]> = Rc::from(vec![9; 4000]);
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[6; 100]).unwrap();
+    let mut chunk = SendChunk::new(Rc::clone(&data));
+    assert_eq!(
+        client.stream_send_chunk(stream_id, &mut chunk).unwrap(),
+        4000
+    );
+    assert!(chunk.is_empty());
+    client.stream_send(stream_id, &[7; 40]).unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    assert_eq!(Rc::strong_count(&data), 3);
+
+    let mut out = client.process_output(now());
+    while let Some(d) = out.dgram() {
+        let ack = server.process(Some(d), now()).dgram();
+        client.process_input(ack.unwrap(), now());
+        out = client.process_output(now());
+    }
+    drop(chunk);
+    assert_eq!(Rc::strong_count(&data), 1);
+
+    let mut buf = vec![0; 5000];
+    let (received, fin) = server.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!(received, 4140);
+    assert!(fin);
+    assert!(buf[..100].iter().all(|b| *b == 6));
+    assert_eq!(&buf[100..4100], &data[..]);
+    assert!(buf[4100..4140].iter().all(|b| *b == 7));
+}
+
+#[test]
+fn recv_without_copy() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    let data: Vec<u8> = (0..10_000)
+        .map(|i| u8::try_from(i % 251).unwrap())
+        .collect();
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &data).unwrap();
+    client.stream_close_send(stream_id).unwrap();
+
+    let mut out = client.process_output(now());
+    while let Some(d) = out.dgram() {
+        let ack = server.process(Some(d), now()).dgram();
+        client.process_input(ack.unwrap(), now());
+        out = client.process_output(now());
+    }
+
+    let mut received = Vec::new();
+    loop {
+        let chunk = server.stream_recv_peek(stream_id).unwrap();
+        let n = chunk.len();
+        received.extend_from_slice(chunk);
+        // Consume in two steps, to check partial consumption.
+        let (half, fin) = server.stream_recv_consume(stream_id, n / 2).unwrap();
+        assert_eq!(half, n / 2);
+        assert!(!fin);
+        let (rest, fin) = server.stream_recv_consume(stream_id, n - half).unwrap();
+        assert_eq!(rest, n - half);
+        if fin {
+            break;
+        }
+        assert_ne!(n, 0);
+    }
+    assert_eq!(received, data);
+    assert_eq!(
+        server.stream_recv_peek(stream_id).unwrap_err(),
+        Error::NoMoreData
+    );
+}
+
 // tests stream sendorder prioritization
 fn sendorder_test(order_of_sendorder: &[Option<SendOrder>]) {
     let mut client = default_client();
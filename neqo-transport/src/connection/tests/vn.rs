@@ -26,6 +26,7 @@ use crate::{
     frame::FrameType,
     packet::{self},
     tparams::{TransportParameter, TransportParameterId::*},
+    version,
 };
 
 // The expected PTO duration after the first Initial is sent.
@@ -252,6 +253,14 @@ fn compatible_upgrade() {
     connect(&mut client, &mut server);
     assert_eq!(client.version(), Version::Version2);
     assert_eq!(server.version(), Version::Version2);
+    assert_eq!(
+        client.stats().version_negotiation,
+        Some(version::Negotiation::Compatible)
+    );
+    assert_eq!(
+        server.stats().version_negotiation,
+        Some(version::Negotiation::Compatible)
+    );
     assert_dscp(&client.stats());
     assert_dscp(&server.stats());
 }
@@ -312,6 +321,10 @@ fn compatible_no_upgrade() {
     connect(&mut client, &mut server);
     assert_eq!(client.version(), Version::Version1);
     assert_eq!(server.version(), Version::Version1);
+    assert_eq!(
+        client.stats().version_negotiation,
+        Some(version::Negotiation::None)
+    );
 }
 
 /// A server that supports versions 1 and 2 might prefer version 1 and that's OK.
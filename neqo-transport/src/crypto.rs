@@ -26,7 +26,7 @@ use std::{
 
 use enum_map::EnumMap;
 use neqo_common::{
-    Buffer, Encoder, Role,
+    Buffer, Decoder, Encoder, MAX_VARINT, Role,
     hex::{Hex, HexSnipMiddle},
     qdebug, qinfo, qtrace, to_u64,
 };
@@ -815,6 +815,10 @@ pub struct CryptoState {
 pub struct CryptoDxAppData {
     dx: CryptoDxState,
     cipher: Cipher,
+    // The secret used to create `self.dx`, which is needed to export the state.
+    secret: SymKey,
+    // The secret for the first iteration, from which the header protection key is derived.
+    hp_secret: SymKey,
     // Not the secret used to create `self.dx`, but the one needed for the next iteration.
     next_secret: SymKey,
 }
@@ -829,10 +833,82 @@ impl CryptoDxAppData {
         Ok(Self {
             dx: CryptoDxState::new(version, dir, Epoch::ApplicationData, secret, cipher, 0)?,
             cipher,
+            secret: secret.clone(),
+            hp_secret: secret.clone(),
             next_secret: Self::update_secret(cipher, secret)?,
         })
     }
 
+    /// The number of packet numbers to skip after an import.  This is at least
+    /// 2^24 and less than 2^30, so that packet numbers stay small enough to encode.
+    fn import_pn_skip() -> packet::Number {
+        u64::from(u32::from_be_bytes(random::<4>()) >> 2) | (1 << 24)
+    }
+
+    /// Write out the state needed to recreate this with [`Self::import`].
+    fn export(&self, enc: &mut Encoder) -> Res<()> {
+        enc.encode_varint(to_u64(self.dx.epoch))
+            .encode_varint(self.dx.next_pn())
+            .encode_varint(self.dx.invocations)
+            .encode_vec(1, self.secret.as_bytes()?)
+            .encode_vec(1, self.hp_secret.as_bytes()?);
+        Ok(())
+    }
+
+    /// Recreate the state from the output of [`Self::export`].
+    /// Packet numbers from before the export are not accepted.
+    /// A random number of packet numbers are skipped when sending, so that
+    /// an export that is somehow imported twice is unlikely to reuse a nonce.
+    fn import(
+        version: Version,
+        dir: CryptoDxDirection,
+        cipher: Cipher,
+        dec: &mut Decoder,
+    ) -> Res<Self> {
+        let epoch = dec
+            .decode_varint()
+            .and_then(|e| usize::try_from(e).ok())
+            .filter(|&e| e >= usize::from(Epoch::ApplicationData))
+            .ok_or(Error::InvalidInput)?;
+        let mut next_pn = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        if dir == CryptoDxDirection::Write {
+            next_pn = next_pn
+                .checked_add(Self::import_pn_skip())
+                .filter(|&pn| pn <= MAX_VARINT)
+                .ok_or(Error::InvalidInput)?;
+        }
+        let invocations = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let secret = dec.decode_vec(1).ok_or(Error::InvalidInput)?;
+        let secret = hkdf::import_key(TLS_VERSION_1_3, secret)?;
+        let hp_secret = dec.decode_vec(1).ok_or(Error::InvalidInput)?;
+        let hp_secret = hkdf::import_key(TLS_VERSION_1_3, hp_secret)?;
+
+        let mut dx = CryptoDxState::new(
+            version,
+            dir,
+            Epoch::ApplicationData,
+            &hp_secret,
+            cipher,
+            next_pn,
+        )?;
+        dx.aead = Aead::new(
+            TLS_VERSION_1_3,
+            cipher,
+            &secret,
+            version.label_prefix(),
+            Mode::from(dir),
+        )?;
+        dx.epoch = epoch;
+        dx.invocations = min(invocations, dx.invocations);
+        Ok(Self {
+            dx,
+            cipher,
+            next_secret: Self::update_secret(cipher, &secret)?,
+            secret,
+            hp_secret,
+        })
+    }
+
     fn update_secret(cipher: Cipher, secret: &SymKey) -> Res<SymKey> {
         let next = hkdf::expand_label(TLS_VERSION_1_3, cipher, secret, &[], "quic ku")?;
         Ok(next)
@@ -847,6 +923,8 @@ impl CryptoDxAppData {
         Ok(Self {
             dx: self.dx.next(&self.next_secret, self.cipher)?,
             cipher: self.cipher,
+            secret: self.next_secret.clone(),
+            hp_secret: self.hp_secret.clone(),
             next_secret,
         })
     }
@@ -1393,6 +1471,42 @@ impl CryptoStates {
         Ok(())
     }
 
+    /// Write out the application data keys, so that they can be restored with
+    /// [`Self::import`].  This fails while a key update is in progress.
+    pub fn export(&self, enc: &mut Encoder) -> Res<()> {
+        let (Some(write), Some(read)) = (&self.app_write, &self.app_read) else {
+            return Err(Error::KeysPending(Epoch::ApplicationData));
+        };
+        if self.read_update_time.is_some() || write.epoch() != read.epoch() {
+            return Err(Error::KeyUpdateBlocked);
+        }
+        enc.encode_uint(2, self.cipher);
+        write.export(enc)?;
+        read.export(enc)
+    }
+
+    /// Install application data keys from the output of [`Self::export`].
+    pub fn import(&mut self, version: Version, dec: &mut Decoder) -> Res<()> {
+        let cipher = dec
+            .decode_uint::<Cipher>()
+            .filter(|c| {
+                [
+                    TLS_AES_128_GCM_SHA256,
+                    TLS_AES_256_GCM_SHA384,
+                    TLS_CHACHA20_POLY1305_SHA256,
+                ]
+                .contains(c)
+            })
+            .ok_or(Error::InvalidInput)?;
+        let write = CryptoDxAppData::import(version, CryptoDxDirection::Write, cipher, dec)?;
+        let read = CryptoDxAppData::import(version, CryptoDxDirection::Read, cipher, dec)?;
+        self.cipher = cipher;
+        self.app_write = Some(write);
+        self.app_read_next = Some(read.next()?);
+        self.app_read = Some(read);
+        Ok(())
+    }
+
     /// Make some state for removing protection in tests.
     #[cfg(all(not(feature = "disable-encryption"), any(test, feature = "bench")))]
     pub fn test_default() -> Self {
@@ -1404,6 +1518,8 @@ impl CryptoStates {
         let app_read = |epoch| CryptoDxAppData {
             dx: read(epoch),
             cipher: TLS_AES_128_GCM_SHA256,
+            secret: hkdf::import_key(TLS_VERSION_1_3, &[0xaa; 32]).expect("key is valid"),
+            hp_secret: hkdf::import_key(TLS_VERSION_1_3, &[0xaa; 32]).expect("key is valid"),
             next_secret: hkdf::import_key(TLS_VERSION_1_3, &[0xaa; 32]).expect("key is valid"),
         };
         let initials = EnumMap::from_fn(|v| {
@@ -1461,6 +1577,8 @@ impl CryptoStates {
                 largest_packet_len: INITIAL_LARGEST_PACKET_LEN,
             },
             cipher: TLS_CHACHA20_POLY1305_SHA256,
+            secret: secret.clone(),
+            hp_secret: secret.clone(),
             next_secret: secret.clone(),
         };
         Self {
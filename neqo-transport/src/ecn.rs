@@ -148,6 +148,7 @@ impl DerefMut for ValidationCount {
 pub enum ValidationError {
     BlackHole,
     Bleaching,
+    ReceivedUnsentECT0,
     ReceivedUnsentECT1,
 }
 
@@ -157,11 +158,15 @@ pub enum ValidationOutcome {
     NotCapable(ValidationError),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct Info {
     /// The current state of ECN validation on this path.
     state: ValidationState,
 
+    /// The ECT codepoint to mark packets with, [`Ecn::Ect1`] for L4S and [`Ecn::Ect0`]
+    /// otherwise.
+    ect: Ecn,
+
     /// The largest ACK seen so far.
     largest_acked: packet::Number,
 
@@ -170,6 +175,15 @@ pub(crate) struct Info {
 }
 
 impl Info {
+    pub(crate) const fn new(l4s: bool) -> Self {
+        Self {
+            state: ValidationState::NotStarted,
+            ect: if l4s { Ecn::Ect1 } else { Ecn::Ect0 },
+            largest_acked: 0,
+            baseline: Count::new(0, 0, 0, 0),
+        }
+    }
+
     pub(crate) fn start(&mut self, stats: &mut Stats) {
         if !matches!(self.state, ValidationState::NotStarted) {
             return;
@@ -217,22 +231,25 @@ impl Info {
 
     /// Process ECN counts from an ACK frame.
     ///
-    /// Returns whether ECN counts contain new valid ECN CE marks.
+    /// Returns the number of new valid ECN CE marks in the ECN counts.
     pub(crate) fn on_packets_acked(
         &mut self,
         acked_packets: &[sent::Packet],
         ack_ecn: Option<&Count>,
         stats: &mut Stats,
-    ) -> bool {
+    ) -> u64 {
         let prev_baseline = self.baseline;
 
         self.validate_ack_ecn_and_update(acked_packets, ack_ecn, stats);
 
-        matches!(self.state, ValidationState::Capable)
-            && (self.baseline - prev_baseline)[Ecn::Ce] > 0
+        if matches!(self.state, ValidationState::Capable) {
+            (self.baseline - prev_baseline)[Ecn::Ce]
+        } else {
+            0
+        }
     }
 
-    /// An [`Ecn::Ect0`] marked packet has been acked.
+    /// An ECT marked packet has been acked.
     pub(crate) const fn acked_ecn(&mut self) {
         if let ValidationState::Testing {
             initial_probes_acked: probes_acked,
@@ -243,7 +260,7 @@ impl Info {
         }
     }
 
-    /// An [`Ecn::Ect0`] marked packet has been declared lost.
+    /// An ECT marked packet has been declared lost.
     pub(crate) fn lost_ecn(&mut self, stats: &mut Stats) {
         if let ValidationState::Testing {
             initial_probes_acked: probes_acked,
@@ -312,22 +329,30 @@ impl Info {
         // > ECN validation also fails if the sum of the increase in ECT(0) and ECN-CE counts is
         // > less than the number of newly acknowledged packets that were originally sent with an
         // > ECT(0) marking.
-        let newly_acked_sent_with_ect0: u64 = acked_packets
+        //
+        // The same applies to ECT(1) when marking for L4S.
+        let newly_acked_sent_with_ect: u64 = acked_packets
             .iter()
-            .filter(|p| p.ecn_marked_ect0())
+            .filter(|p| p.ecn_marked(self.ect))
             .count()
             .try_into()
             .expect("usize fits into u64");
         let ecn_diff = ack_ecn - self.baseline;
-        let sum_inc = ecn_diff[Ecn::Ect0] + ecn_diff[Ecn::Ce];
-        if sum_inc < newly_acked_sent_with_ect0 {
+        let sum_inc = ecn_diff[self.ect] + ecn_diff[Ecn::Ce];
+        let (unsent, unsent_error) = if self.ect == Ecn::Ect1 {
+            (Ecn::Ect0, ValidationError::ReceivedUnsentECT0)
+        } else {
+            (Ecn::Ect1, ValidationError::ReceivedUnsentECT1)
+        };
+        if sum_inc < newly_acked_sent_with_ect {
             qinfo!(
-                "ECN validation failed, ACK counted {sum_inc} new marks, but {newly_acked_sent_with_ect0} of newly acked packets were sent with ECT(0)"
+                "ECN validation failed, ACK counted {sum_inc} new marks, but {newly_acked_sent_with_ect} of newly acked packets were sent with {:?}",
+                self.ect
             );
             self.disable_ecn(stats, ValidationError::Bleaching);
-        } else if ecn_diff[Ecn::Ect1] > 0 {
-            qinfo!("ECN validation failed, ACK counted ECT(1) marks that were never sent");
-            self.disable_ecn(stats, ValidationError::ReceivedUnsentECT1);
+        } else if ecn_diff[unsent] > 0 {
+            qinfo!("ECN validation failed, ACK counted {unsent:?} marks that were never sent");
+            self.disable_ecn(stats, unsent_error);
         } else if self.state != ValidationState::Capable {
             qinfo!("ECN validation succeeded, path is capable");
             self.state.set(ValidationState::Capable, stats);
@@ -348,7 +373,7 @@ impl Info {
     /// The ECN mark to use for an outgoing UDP datagram.
     pub(crate) const fn ecn_mark(&self) -> Ecn {
         if self.is_marking() {
-            Ecn::Ect0
+            self.ect
         } else {
             Ecn::NotEct
         }
@@ -14,9 +14,11 @@ use nss::ResumptionToken;
 use crate::{
     AppError,
     connection::State,
+    failover::FailoverReason,
     quic_datagrams::DatagramTracking,
     scone::Bitrate,
     stream_id::{StreamId, StreamType},
+    version,
 };
 
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
@@ -25,6 +27,8 @@ pub enum OutgoingDatagramOutcome {
     DroppedQueueFull,
     Lost,
     Acked,
+    /// The datagram was not sent before its time-to-live ran out.
+    Expired,
 }
 
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
@@ -58,6 +62,11 @@ pub enum ConnectionEvent {
         stream_id: StreamId,
         app_error: AppError,
     },
+    /// Data on the stream was not acknowledged by its deadline, so the stream was reset.
+    /// See [`crate::Connection::stream_set_deadline`].
+    SendStreamExpired {
+        stream_id: StreamId,
+    },
     /// Peer has acked everything sent on the stream.
     SendStreamComplete {
         stream_id: StreamId,
@@ -86,6 +95,20 @@ pub enum ConnectionEvent {
         local: SocketAddr,
         remote: SocketAddr,
     },
+    /// The primary path failed and the connection failed over to the standby path,
+    /// following the configured [`crate::FailoverPolicy`].  A `PathMigrated` event
+    /// for the same path follows.
+    PathFailover {
+        local: SocketAddr,
+        remote: SocketAddr,
+        reason: FailoverReason,
+    },
+    /// The handshake settled on a version, in the way described.
+    /// [`crate::Connection::version`] reports the version.
+    VersionNegotiated(version::Negotiation),
+    /// The space in the congestion window reached the watermark that was set with
+    /// [`crate::Connection::set_send_budget_watermark`].
+    SendBudgetAvailable,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -134,6 +157,13 @@ impl ConnectionEvents {
         });
     }
 
+    pub fn send_stream_expired(&self, stream_id: StreamId) {
+        // If reset, no longer writable.
+        self.remove(|evt| matches!(evt, ConnectionEvent::SendStreamWritable { stream_id: x } if *x == stream_id));
+
+        self.insert(ConnectionEvent::SendStreamExpired { stream_id });
+    }
+
     pub fn send_stream_complete(&self, stream_id: StreamId) {
         self.remove(|evt| {
             matches!(evt,
@@ -145,6 +175,10 @@ impl ConnectionEvents {
         self.insert(ConnectionEvent::SendStreamComplete { stream_id });
     }
 
+    pub fn send_budget_available(&self) {
+        self.insert(ConnectionEvent::SendBudgetAvailable);
+    }
+
     pub fn send_stream_creatable(&self, stream_type: StreamType) {
         self.insert(ConnectionEvent::SendStreamCreatable { stream_type });
     }
@@ -201,6 +235,18 @@ impl ConnectionEvents {
         self.insert(ConnectionEvent::PathMigrated { local, remote });
     }
 
+    pub fn path_failover(&self, local: SocketAddr, remote: SocketAddr, reason: FailoverReason) {
+        self.insert(ConnectionEvent::PathFailover {
+            local,
+            remote,
+            reason,
+        });
+    }
+
+    pub fn version_negotiated(&self, negotiation: version::Negotiation) {
+        self.insert(ConnectionEvent::VersionNegotiated(negotiation));
+    }
+
     fn insert(&self, event: ConnectionEvent) {
         let mut q = self.events.borrow_mut();
 
@@ -0,0 +1,172 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Automatic failover from a failing primary path to a standby path.
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+
+use crate::rtt::RttEstimate;
+
+/// The reason that the primary path was considered to have failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailoverReason {
+    /// The given number of consecutive PTOs fired without an acknowledgment.
+    Pto(usize),
+    /// The smoothed RTT grew beyond the configured multiple of the minimum RTT.
+    RttInflation,
+}
+
+/// A policy for failing over from the primary path to a standby path.
+///
+/// A client sets a standby path with [`crate::Connection::set_standby_path`].
+/// The standby path is probed with `PATH_CHALLENGE` so that it stays validated.
+/// When the primary path appears to fail, the connection migrates to the
+/// standby path and reports [`crate::ConnectionEvent::PathFailover`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailoverPolicy {
+    /// How often an idle standby path is probed.
+    probe_interval: Duration,
+    /// The number of consecutive PTOs on the primary path that indicate failure.
+    pto_threshold: NonZeroUsize,
+    /// The multiple of the minimum RTT that the smoothed RTT on the primary path
+    /// has to exceed to indicate failure.  `None` disables this check.
+    rtt_inflation: Option<NonZeroU32>,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self {
+            probe_interval: Self::DEFAULT_PROBE_INTERVAL,
+            pto_threshold: Self::DEFAULT_PTO_THRESHOLD,
+            rtt_inflation: Some(Self::DEFAULT_RTT_INFLATION),
+        }
+    }
+}
+
+impl FailoverPolicy {
+    /// By default, probe the standby path this often.
+    pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+    /// By default, fail over after this many consecutive PTOs.
+    pub const DEFAULT_PTO_THRESHOLD: NonZeroUsize = NonZeroUsize::new(3).expect("3 > 0");
+    /// By default, fail over when the smoothed RTT is this many times the minimum RTT.
+    pub const DEFAULT_RTT_INFLATION: NonZeroU32 = NonZeroU32::new(10).expect("10 > 0");
+
+    /// Set how often an idle standby path is probed.  Any packet received on
+    /// the standby path postpones the next probe.
+    #[must_use]
+    pub const fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_probe_interval(&self) -> Duration {
+        self.probe_interval
+    }
+
+    /// Set the number of consecutive PTOs after which the primary path is
+    /// considered to have failed.  This needs to be lower than
+    /// [`crate::ConnectionParameters::max_pto`], if that is set, or the
+    /// connection closes instead.
+    #[must_use]
+    pub const fn pto_threshold(mut self, count: NonZeroUsize) -> Self {
+        self.pto_threshold = count;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_pto_threshold(&self) -> NonZeroUsize {
+        self.pto_threshold
+    }
+
+    /// Set the multiple of the minimum RTT that the smoothed RTT on the primary
+    /// path has to exceed for the path to be considered to have failed.
+    /// `None` disables this check.
+    #[must_use]
+    pub const fn rtt_inflation(mut self, factor: Option<NonZeroU32>) -> Self {
+        self.rtt_inflation = factor;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_rtt_inflation(&self) -> Option<NonZeroU32> {
+        self.rtt_inflation
+    }
+
+    /// Determine whether the primary path failed, given the number of
+    /// consecutive PTOs and the RTT estimate for the path.
+    pub(crate) fn check(&self, pto_count: usize, rtt: &RttEstimate) -> Option<FailoverReason> {
+        if pto_count >= self.pto_threshold.get() {
+            return Some(FailoverReason::Pto(pto_count));
+        }
+        let factor = self.rtt_inflation?;
+        (!rtt.is_guesstimate() && rtt.estimate() > rtt.minimum() * factor.get())
+            .then_some(FailoverReason::RttInflation)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    use neqo_common::qlog::Qlog;
+    use test_fixture::now;
+
+    use super::{FailoverPolicy, FailoverReason};
+    use crate::rtt::{RttEstimate, RttSource};
+
+    const RTT: Duration = Duration::from_millis(50);
+
+    fn rtt(samples: &[Duration]) -> RttEstimate {
+        let mut rtt = RttEstimate::new(RTT);
+        for &sample in samples {
+            rtt.update(
+                &mut Qlog::disabled(),
+                sample,
+                Duration::ZERO,
+                RttSource::Ack,
+                now(),
+            );
+        }
+        rtt
+    }
+
+    #[test]
+    fn pto() {
+        let policy = FailoverPolicy::default().pto_threshold(NonZeroUsize::new(2).unwrap());
+        let rtt = rtt(&[RTT]);
+        assert_eq!(policy.check(0, &rtt), None);
+        assert_eq!(policy.check(1, &rtt), None);
+        assert_eq!(policy.check(2, &rtt), Some(FailoverReason::Pto(2)));
+    }
+
+    #[test]
+    fn rtt_inflation() {
+        let policy = FailoverPolicy::default();
+        assert_eq!(policy.check(0, &rtt(&[RTT, RTT * 5])), None);
+        assert_eq!(
+            policy.check(0, &rtt(&[RTT, RTT * 100, RTT * 100, RTT * 100])),
+            Some(FailoverReason::RttInflation)
+        );
+        assert_eq!(
+            policy
+                .rtt_inflation(None)
+                .check(0, &rtt(&[RTT, RTT * 100, RTT * 100, RTT * 100])),
+            None
+        );
+    }
+
+    /// Without an RTT sample, the initial RTT cannot be inflated.
+    #[test]
+    fn rtt_guesstimate() {
+        let policy = FailoverPolicy::default();
+        assert_eq!(policy.check(0, &rtt(&[])), None);
+    }
+}
@@ -16,7 +16,9 @@ use std::{
 };
 
 use enum_map::EnumMap;
-use neqo_common::{Buffer, Length, MAX_VARINT, Role, const_min_u64, qdebug, qtrace, to_u64};
+use neqo_common::{
+    Buffer, Decoder, Encoder, Length, MAX_VARINT, Role, const_min_u64, qdebug, qtrace, to_u64,
+};
 
 use crate::{
     Error, Res,
@@ -165,6 +167,25 @@ where
             self.blocked_frame = true;
         }
     }
+
+    /// Write out the limit and how much of it is used.
+    pub fn export(&self, enc: &mut Encoder) {
+        enc.encode_varint(self.limit).encode_varint(self.used);
+    }
+
+    /// Restore the limit and how much of it is used from the output of [`Self::export`].
+    pub fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        let limit = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let used = dec
+            .decode_varint()
+            .filter(|&u| u <= limit)
+            .ok_or(Error::InvalidInput)?;
+        self.limit = limit;
+        self.used = used;
+        self.blocked_at = None;
+        self.blocked_frame = false;
+        Ok(())
+    }
 }
 
 impl SenderFlowControl<()> {
@@ -351,6 +372,40 @@ where
         self.consumed
     }
 
+    /// Write out the window, the limit that was last sent, and what has been consumed and retired.
+    /// This fails if there is an update to the limit that hasn't been sent.
+    pub fn export(&self, enc: &mut Encoder) -> Res<()> {
+        if self.frame_pending {
+            return Err(Error::ConnectionState);
+        }
+        enc.encode_varint(self.max_active)
+            .encode_varint(self.max_allowed)
+            .encode_varint(self.consumed)
+            .encode_varint(self.retired);
+        Ok(())
+    }
+
+    /// Restore the state from the output of [`Self::export`].
+    pub fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        let max_active = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let max_allowed = dec.decode_varint().ok_or(Error::InvalidInput)?;
+        let consumed = dec
+            .decode_varint()
+            .filter(|&c| c <= max_allowed)
+            .ok_or(Error::InvalidInput)?;
+        let retired = dec
+            .decode_varint()
+            .filter(|&r| r <= consumed)
+            .ok_or(Error::InvalidInput)?;
+        self.max_active = max_active;
+        self.max_allowed = max_allowed;
+        self.consumed = consumed;
+        self.retired = retired;
+        self.last_update = None;
+        self.frame_pending = false;
+        Ok(())
+    }
+
     /// Core auto-tuning logic for adjusting the maximum flow control window.
     ///
     /// This method is called by both connection-level and stream-level
@@ -656,6 +711,28 @@ impl RemoteStreamLimit {
         assert!(self.is_allowed(new_stream));
         new_stream
     }
+
+    /// Write out the stream limit and the next stream that the peer can open.
+    pub fn export(&self, enc: &mut Encoder) -> Res<()> {
+        self.streams_fc.export(enc)?;
+        enc.encode_varint(self.next_stream.as_u64());
+        Ok(())
+    }
+
+    /// Restore the state from the output of [`Self::export`].
+    pub fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        self.streams_fc.import(dec)?;
+        let next_stream = dec
+            .decode_varint()
+            .map(StreamId::from)
+            .filter(|id| {
+                id.stream_type() == self.next_stream.stream_type()
+                    && id.role() == self.next_stream.role()
+            })
+            .ok_or(Error::InvalidInput)?;
+        self.next_stream = next_stream;
+        Ok(())
+    }
 }
 
 impl Deref for RemoteStreamLimit {
@@ -15,12 +15,15 @@ mod ackrate;
 pub mod addr_valid;
 #[cfg(not(fuzzing))]
 mod addr_valid;
+mod admission;
+mod budget;
 mod cc;
 mod cid;
 mod connection;
 mod crypto;
 pub mod ecn;
 mod events;
+mod failover;
 mod fc;
 #[cfg(any(fuzzing, feature = "bench"))]
 pub mod frame;
@@ -35,6 +38,7 @@ mod path;
 mod pmtud;
 mod qlog;
 mod quic_datagrams;
+mod quic_lb;
 #[cfg(feature = "bench")]
 pub mod recovery;
 #[cfg(not(feature = "bench"))]
@@ -54,29 +58,39 @@ pub mod streams;
 pub mod tparams;
 mod tracking;
 pub mod version;
+mod vhost;
 
 pub use self::{
-    cc::{CongestionControl, CongestionTrigger, HyStartCssBaseline, SlowStart},
+    budget::SendBudget,
+    cc::{
+        CarefulResumeParams, CongestionControl, CongestionController, CongestionControllerFactory,
+        CongestionTrigger, HyStartCssBaseline, SlowStart,
+    },
     cid::{
         ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef,
-        EmptyConnectionIdGenerator, RandomConnectionIdGenerator,
+        ConnectionIdRotation, EmptyConnectionIdGenerator, RandomConnectionIdGenerator,
     },
     connection::{
-        Connection, Output, OutputBatch, State, ZeroRttState,
+        Connection, HandoffKey, Output, OutputBatch, PreferredAddressOutcome, State, ZeroRttState,
         params::{
             ConnectionParameters, INITIAL_LOCAL_MAX_DATA, INITIAL_LOCAL_MAX_STREAM_DATA,
             MAX_DATAGRAM_FRAME_SIZE, MAX_LOCAL_MAX_STREAM_DATA,
         },
     },
     events::{ConnectionEvent, ConnectionEvents},
+    failover::{FailoverPolicy, FailoverReason},
     frame::CloseError,
     packet::MIN_INITIAL_PACKET_SIZE,
     pmtud::Pmtud,
-    quic_datagrams::DatagramTracking,
-    rtt::DEFAULT_INITIAL_RTT,
+    quic_datagrams::{DatagramDropPolicy, DatagramTracking},
+    quic_lb::{
+        Config as QuicLbConfig, Generator as QuicLbConnectionIdGenerator, Router as QuicLbRouter,
+    },
+    recovery::{RateSample, rate::DeliveryState, sent::Packet as SentPacket},
+    rtt::{DEFAULT_INITIAL_RTT, RttEstimate},
     sni::find_sni,
-    stateless_reset::Token,
-    stats::{SlowStartExitReason, Stats},
+    stateless_reset::{Key as StatelessResetKey, Token},
+    stats::{CongestionControlStats, DropReason, ServerStats, SlowStartExitReason, Stats},
     stream_id::{StreamId, StreamType},
     version::Version,
 };
@@ -38,6 +38,9 @@ pub struct Pacer {
     c: isize,
     /// The packet size or minimum capacity for sending, in bytes.
     p: usize,
+    /// The maximum pacing rate, in bytes per second.  This applies even when
+    /// pacing is otherwise disabled.
+    max_rate: Option<u64>,
 }
 
 impl Pacer {
@@ -51,6 +54,8 @@ impl Pacer {
     /// our current congestion controller, which double the window every RTT.
     const SPEEDUP: u64 = 2;
 
+    const NANOS_PER_SEC: u64 = 1_000_000_000;
+
     /// Create a new `Pacer`.  This takes the current time, the maximum burst size,
     /// and the packet size.
     ///
@@ -70,6 +75,7 @@ impl Pacer {
             m,
             c: isize::try_from(m).expect("maximum capacity fits into isize"),
             p,
+            max_rate: None,
         }
     }
 
@@ -81,11 +87,35 @@ impl Pacer {
         self.p = mtu;
     }
 
+    pub const fn max_rate(&self) -> Option<u64> {
+        self.max_rate
+    }
+
+    /// Limit the pacing rate to `max_rate` bytes per second, or remove the
+    /// limit if `max_rate` is `None`.  A limit of zero is treated as one byte
+    /// per second.
+    pub fn set_max_rate(&mut self, max_rate: Option<u64>) {
+        self.max_rate = max_rate.map(|r| r.max(1));
+    }
+
+    /// The rate to pace at, when a maximum rate applies, given the rate that
+    /// the pacer would otherwise use.  Without pacing, this is the maximum rate.
+    fn limit(&self, rate: Option<u64>) -> Option<u64> {
+        let max_rate = self.max_rate?;
+        Some(match rate {
+            Some(rate) if self.enabled && rate > 0 => min(rate, max_rate),
+            _ => max_rate,
+        })
+    }
+
     /// Determine when the next packet will be available based on the provided
     /// RTT, provided congestion window and accumulated credit or debt.  This
     /// doesn't update state.  This returns a time, which could be in the past
     /// (this object doesn't know what the current time is).
     pub fn next(&self, rtt: Duration, cwnd: usize) -> Instant {
+        if let Some(rate) = self.limit(Self::rate(cwnd, rtt)) {
+            return self.next_at_limited_rate(rate);
+        }
         let packet = isize::try_from(self.p).expect("packet size fits into isize");
 
         if self.c >= packet {
@@ -106,20 +136,51 @@ impl Pacer {
         let rtt_ns = u64::try_from(rtt.as_nanos()).unwrap_or(u64::MAX);
         let divisor = to_u64(cwnd).saturating_mul(Self::SPEEDUP);
         let w_ns = rtt_ns.saturating_mul(deficit) / divisor;
+        let nxt = self.wait(w_ns);
+        qtrace!("[{self}] next {cwnd}/{rtt:?} wait {w_ns}ns = {nxt:?}");
+        nxt
+    }
 
-        // If the increment is below the timer granularity, send immediately.
+    /// Determine when the next packet will be available when pacing at an
+    /// explicit `rate` in bytes per second, rather than at a rate derived from
+    /// the congestion window.  This is for congestion controllers that maintain
+    /// their own bandwidth estimate.  A `rate` of zero disables pacing.
+    pub fn next_at_rate(&self, rate: u64) -> Instant {
+        self.next_at_limited_rate(self.limit(Some(rate)).unwrap_or(rate))
+    }
+
+    fn next_at_limited_rate(&self, rate: u64) -> Instant {
+        let packet = isize::try_from(self.p).expect("packet size fits into isize");
+        if self.c >= packet || rate == 0 {
+            qtrace!("[{self}] next at {rate}B/s no wait = {:?}", self.t);
+            return self.t;
+        }
+
+        // This is the inverse of the function in `spend_at_rate`:
+        // self.t + (self.p - self.c) / rate
+        let Ok(deficit) = u64::try_from(packet - self.c) else {
+            qtrace!("[{self}] next at {rate}B/s deficit overflow");
+            return self.t;
+        };
+        let w_ns =
+            u64::try_from(u128::from(deficit) * u128::from(Self::NANOS_PER_SEC) / u128::from(rate))
+                .unwrap_or(u64::MAX);
+        let nxt = self.wait(w_ns);
+        qtrace!("[{self}] next at {rate}B/s wait {w_ns}ns = {nxt:?}");
+        nxt
+    }
+
+    /// The time at which a wait of `w_ns` nanoseconds from the last update
+    /// ends.  Waits below the timer granularity end immediately.
+    fn wait(&self, w_ns: u64) -> Instant {
         #[expect(
             clippy::cast_possible_truncation,
             reason = "GRANULARITY is 1ms, fits in u64"
         )]
         if w_ns < GRANULARITY.as_nanos() as u64 {
-            qtrace!("[{self}] next {cwnd}/{rtt:?} below granularity ({w_ns}ns)");
             return self.t;
         }
-
-        let nxt = self.t + Duration::from_nanos(w_ns);
-        qtrace!("[{self}] next {cwnd}/{rtt:?} wait {w_ns}ns = {nxt:?}");
-        nxt
+        self.t + Duration::from_nanos(w_ns)
     }
 
     /// Bytes sendable at `SPEEDUP * cwnd / rtt` pace over `elapsed`.
@@ -153,6 +214,10 @@ impl Pacer {
     /// trip time (`rtt`), the estimated congestion window (`cwnd`), and the
     /// number of bytes that were sent (`count`).
     pub fn spend(&mut self, now: Instant, rtt: Duration, cwnd: usize, count: usize) {
+        if let Some(rate) = self.limit(Self::rate(cwnd, rtt)) {
+            self.spend_at_limited_rate(now, rate, count);
+            return;
+        }
         if !self.enabled {
             self.t = now;
             return;
@@ -164,8 +229,34 @@ impl Pacer {
         let incr = Self::bytes_for(cwnd, rtt, now.saturating_duration_since(self.t))
             .and_then(|b| usize::try_from(b).ok())
             .unwrap_or(self.m);
+        self.update(now, incr, count);
+    }
+
+    /// Spend credit when pacing at an explicit `rate` in bytes per second.
+    /// This is the counterpart of [`Pacer::next_at_rate`], in the same way that
+    /// [`Pacer::spend`] is the counterpart of [`Pacer::next`].
+    pub fn spend_at_rate(&mut self, now: Instant, rate: u64, count: usize) {
+        if self.enabled || self.max_rate.is_some() {
+            let rate = self.limit(Some(rate)).unwrap_or(rate);
+            self.spend_at_limited_rate(now, rate, count);
+        } else {
+            self.t = now;
+        }
+    }
+
+    fn spend_at_limited_rate(&mut self, now: Instant, rate: u64, count: usize) {
+        qtrace!("[{self}] spend {count} at {rate}B/s");
+        // Increase the capacity by the elapsed time times the pacing rate.
+        let elapsed = now.saturating_duration_since(self.t);
+        let incr = usize::try_from(
+            elapsed.as_nanos().saturating_mul(u128::from(rate)) / u128::from(Self::NANOS_PER_SEC),
+        )
+        .unwrap_or(self.m);
+        self.update(now, incr, count);
+    }
 
-        // Add the capacity up to a limit of `self.m`, then subtract `count`.
+    /// Add `incr` to the capacity up to a limit of `self.m`, then subtract `count`.
+    fn update(&mut self, now: Instant, incr: usize, count: usize) {
         self.c = min(
             isize::try_from(self.m).unwrap_or(isize::MAX),
             self.c
@@ -298,6 +389,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn even_at_rate() {
+        // 20 KB/s is the same rate as CWND over RTT with SPEEDUP.
+        const RATE: u64 = 20_000;
+        let n = now();
+        let mut p = Pacer::new(true, n, PACKET, PACKET);
+        assert_eq!(p.next_at_rate(RATE), n);
+        p.spend_at_rate(n, RATE, PACKET);
+        assert_eq!(p.next_at_rate(RATE), n + (RTT / 20));
+        // Spending at the scheduled time leaves the pacer in the same state.
+        let t = p.next_at_rate(RATE);
+        p.spend_at_rate(t, RATE, PACKET);
+        assert_eq!(p.next_at_rate(RATE), t + (RTT / 20));
+    }
+
+    #[test]
+    fn max_rate() {
+        // 10 KB/s is half the rate that CWND over RTT with SPEEDUP gives.
+        const MAX_RATE: u64 = 10_000;
+        let n = now();
+        let mut p = Pacer::new(true, n, PACKET, PACKET);
+        p.set_max_rate(Some(MAX_RATE));
+        p.spend(n, RTT, CWND, PACKET);
+        assert_eq!(p.next(RTT, CWND), n + (RTT / 10));
+        // A higher explicit rate is also limited.
+        assert_eq!(p.next_at_rate(20_000), n + (RTT / 10));
+        // A lower rate is not affected.
+        assert_eq!(p.next(RTT, CWND / 4), n + (RTT / 5));
+
+        p.set_max_rate(None);
+        assert_eq!(p.next(RTT, CWND), n + (RTT / 20));
+    }
+
+    #[test]
+    fn max_rate_pacing_disabled() {
+        const MAX_RATE: u64 = 10_000;
+        let n = now();
+        let mut p = Pacer::new(false, n, PACKET, PACKET);
+        p.set_max_rate(Some(MAX_RATE));
+        p.spend(n, RTT, CWND, PACKET);
+        assert_eq!(p.next(RTT, CWND), n + (RTT / 10));
+        p.spend_at_rate(n, 0, PACKET);
+        assert_eq!(p.next_at_rate(0), n + (RTT / 5));
+    }
+
+    #[test]
+    fn zero_rate_does_not_pace() {
+        let n = now();
+        let mut p = Pacer::new(true, n, PACKET, PACKET);
+        p.spend_at_rate(n, 0, PACKET);
+        assert_eq!(p.next_at_rate(0), n);
+    }
+
+    #[test]
+    fn at_rate_below_granularity() {
+        // At 10 MB/s, a 1000 byte packet takes 100us, well below granularity.
+        const RATE: u64 = 10_000_000;
+        let n = now();
+        let mut p = Pacer::new(true, n, PACKET, PACKET);
+        p.spend_at_rate(n, RATE, PACKET);
+        assert_eq!(p.next_at_rate(RATE), n);
+    }
+
+    #[test]
+    fn pacing_disabled_at_rate() {
+        let n = now();
+        let mut p = Pacer::new(false, n, PACKET, PACKET);
+        p.spend_at_rate(n, 1, PACKET);
+        assert_eq!(p.next_at_rate(1), n);
+    }
+
     #[test]
     fn pacer_display_and_debug() {
         let mut p = Pacer::new(true, now(), PACKET, PACKET);
@@ -38,7 +38,7 @@ pub const MIN_INITIAL_PACKET_SIZE: usize = 1200;
 
 pub const BIT_LONG: u8 = 0x80;
 const BIT_SHORT: u8 = 0x00;
-const BIT_FIXED_QUIC: u8 = 0x40;
+pub(crate) const BIT_FIXED_QUIC: u8 = 0x40;
 const BIT_SPIN: u8 = 0x20;
 const BIT_KEY_PHASE: u8 = 0x04;
 
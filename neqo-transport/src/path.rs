@@ -6,9 +6,10 @@
 
 use std::{
     cell::RefCell,
+    cmp::min,
     fmt::{self, Display},
     net::SocketAddr,
-    num::NonZeroUsize,
+    num::{NonZeroU64, NonZeroUsize},
     rc::Rc,
     time::{Duration, Instant},
 };
@@ -21,6 +22,8 @@ use nss::random;
 use crate::{
     ConnectionParameters, Stats,
     ackrate::{AckRate, PeerAckDelay},
+    budget::{RecentLoss, SendBudget},
+    cc::CarefulResumeParams,
     cid::{ConnectionId, ConnectionIdRef, ConnectionIdStore, RemoteConnectionIdEntry},
     ecn,
     frame::{FrameEncoder as _, FrameType},
@@ -57,6 +60,8 @@ pub struct Paths {
 
     /// The path that we would prefer to migrate to.
     migration_target: Option<PathRef>,
+    /// The path that we fail over to if the primary path fails.
+    standby: Option<PathRef>,
 
     /// Connection IDs that need to be retired.
     to_retire: Vec<u64>,
@@ -75,6 +80,7 @@ impl Paths {
             paths: Vec::new(),
             primary: None,
             migration_target: None,
+            standby: None,
             to_retire: Vec::new(),
             qlog: Qlog::disabled(),
             pmtud,
@@ -157,6 +163,14 @@ impl Paths {
                 );
                 self.migration_target = None;
             }
+            if self
+                .standby
+                .as_ref()
+                .is_some_and(|standby| Rc::ptr_eq(standby, &removed))
+            {
+                qinfo!("[{}] The standby path had to be removed", path.borrow());
+                self.standby = None;
+            }
             debug_assert_eq!(Rc::strong_count(&removed), 1);
         }
 
@@ -174,6 +188,14 @@ impl Paths {
     #[must_use]
     fn select_primary(&mut self, path: &PathRef, now: Instant) -> Option<PathRef> {
         qdebug!("[{}] set as primary path", path.borrow());
+        if self
+            .standby
+            .as_ref()
+            .is_some_and(|standby| Rc::ptr_eq(standby, path))
+        {
+            path.borrow_mut().set_standby(None);
+            self.standby = None;
+        }
         let old_path = self.primary.replace(Rc::clone(path)).inspect(|old| {
             old.borrow_mut().set_primary(false, now);
         });
@@ -203,10 +225,9 @@ impl Paths {
         stats: &mut Stats,
     ) -> bool {
         debug_assert!(!self.is_temporary(path));
-        let baseline = self.primary().map_or_else(
-            || ecn::Info::default().baseline(),
-            |p| p.borrow().ecn_info.baseline(),
-        );
+        let baseline = self
+            .primary()
+            .map_or_else(ecn::Count::default, |p| p.borrow().ecn_info.baseline());
         path.borrow_mut().set_ecn_baseline(baseline);
         path.borrow_mut().start_ecn(stats);
         if force || path.borrow().is_valid() {
@@ -220,6 +241,39 @@ impl Paths {
         self.migration_target.is_none()
     }
 
+    /// Use the identified path as the standby path, replacing any previous
+    /// standby path.  The path is probed now if it is not yet valid, and every
+    /// `probe_interval` after that.
+    pub fn set_standby(&mut self, path: &PathRef, probe_interval: Duration, stats: &mut Stats) {
+        debug_assert!(!self.is_temporary(path));
+        if let Some(old) = self.standby.replace(Rc::clone(path)) {
+            old.borrow_mut().set_standby(None);
+        }
+        qdebug!("[{}] Standby path", path.borrow());
+        let mut p = path.borrow_mut();
+        p.set_standby(Some(probe_interval));
+        if !p.is_valid() {
+            p.probe(stats);
+        }
+    }
+
+    /// Migrate to the standby path after the primary path failed.
+    /// Returns the standby path if it was valid and is now primary.
+    /// A standby path that is not valid is kept, so that it can be used later.
+    pub fn failover(&mut self, now: Instant, stats: &mut Stats) -> Option<PathRef> {
+        let standby = self.standby.as_ref()?;
+        if !standby.borrow().is_valid() {
+            qinfo!(
+                "[{}] Standby path is not valid, not failing over",
+                standby.borrow()
+            );
+            return None;
+        }
+        let path = self.standby.take()?;
+        path.borrow_mut().set_standby(None);
+        self.migrate(&path, false, now, stats).then_some(path)
+    }
+
     /// Process elapsed time for active paths.
     /// Returns an true if there are viable paths remaining after tidying up.
     ///
@@ -227,6 +281,7 @@ impl Paths {
     /// for themselves.
     pub fn process_timeout(&mut self, now: Instant, pto: Duration, stats: &mut Stats) -> bool {
         let to_retire = &mut self.to_retire;
+        let standby = &mut self.standby;
         let mut primary_failed = false;
         self.paths.retain(|p| {
             if p.borrow_mut().process_timeout(now, pto, stats) {
@@ -236,6 +291,9 @@ impl Paths {
                 if p.borrow().is_primary() {
                     primary_failed = true;
                 }
+                if standby.as_ref().is_some_and(|s| Rc::ptr_eq(s, p)) {
+                    *standby = None;
+                }
                 Self::retire(to_retire, p);
                 false
             }
@@ -362,6 +420,7 @@ impl Paths {
     pub fn retire_cids(&mut self, retire_prior: u64, store: &mut ConnectionIdStore<Srt>) {
         let to_retire = &mut self.to_retire;
         let migration_target = &mut self.migration_target;
+        let standby = &mut self.standby;
 
         // First, tell the store to release any connection IDs that are too old.
         let mut retired = store.retire_prior_to(retire_prior);
@@ -390,6 +449,10 @@ impl Paths {
                     );
                     *migration_target = None;
                 }
+                if !has_replacement && standby.as_ref().is_some_and(|s| Rc::ptr_eq(s, p)) {
+                    qinfo!("[{path}] NEW_CONNECTION_ID with Retire Prior To removed standby path");
+                    *standby = None;
+                }
                 has_replacement
             } else {
                 true
@@ -397,12 +460,53 @@ impl Paths {
         });
     }
 
+    /// Switch the primary path to the next connection ID from `store` and retire the
+    /// one that it was using.  This leaves at least one connection ID in `store`, so
+    /// that migration remains possible, and does nothing while migrating.
+    /// Returns `true` if the connection ID was changed.
+    pub fn rotate_remote_cid(&mut self, store: &mut ConnectionIdStore<Srt>) -> bool {
+        if store.len() < 2 || self.migration_target.is_some() {
+            return false;
+        }
+        let Some(primary) = &self.primary else {
+            return false;
+        };
+        let mut path = primary.borrow_mut();
+        if path
+            .remote_cid
+            .as_ref()
+            .is_none_or(|cid| cid.connection_id().is_empty())
+        {
+            return false;
+        }
+        let Some(next) = store.next() else {
+            return false;
+        };
+        qdebug!(
+            "[{path}] Rotating to connection ID {}",
+            next.sequence_number()
+        );
+        if let Some(old) = path.remote_cid.replace(next) {
+            self.to_retire.push(old.sequence_number());
+        }
+        true
+    }
+
     /// The number of connection IDs that have been retired locally but whose
     /// `RETIRE_CONNECTION_ID` frames have not yet been ACK'ed.
     pub(crate) const fn retire_queue_len(&self) -> usize {
         self.to_retire.len()
     }
 
+    /// Whether the primary path is the only path, with no migration, standby path,
+    /// or frames for retiring connection IDs outstanding.
+    pub fn is_single(&self) -> bool {
+        self.paths.len() == 1
+            && self.migration_target.is_none()
+            && self.standby.is_none()
+            && self.to_retire.is_empty()
+    }
+
     /// Write out any `RETIRE_CONNECTION_ID` frames that are outstanding.
     pub fn write_frames<B: Buffer>(
         &mut self,
@@ -485,6 +589,13 @@ impl Paths {
         }
         self.qlog = qlog;
     }
+
+    /// Limit the send rate on all paths to `rate` bits per second.
+    pub fn set_max_send_rate(&self, rate: Option<NonZeroU64>) {
+        for p in &self.paths {
+            p.borrow_mut().set_max_send_rate(rate);
+        }
+    }
 }
 
 /// The state of a path with respect to address validation.
@@ -560,8 +671,14 @@ pub struct Path {
     sent_bytes: usize,
     /// The ECN-related state for this path (see RFC9000, Section 13.4 and Appendix A.4)
     ecn_info: ecn::Info,
+    /// Recent loss and ECN-CE marks on this path.
+    recent_loss: RecentLoss,
     /// SCONE info for this path.
     scone: Option<Scone>,
+    /// The maximum send rate that the application set, in bits per second.
+    max_send_rate: Option<NonZeroU64>,
+    /// For a standby path, how often the path is probed to keep it valid.
+    standby: Option<Duration>,
     /// For logging of events.
     qlog: Qlog,
 }
@@ -619,8 +736,11 @@ impl Path {
             sender,
             received_bytes: 0,
             sent_bytes: 0,
-            ecn_info: ecn::Info::default(),
+            ecn_info: ecn::Info::new(conn_params.l4s_enabled()),
+            recent_loss: RecentLoss::default(),
             scone: None,
+            max_send_rate: conn_params.get_max_send_rate(),
+            standby: None,
             qlog,
         }
     }
@@ -634,11 +754,39 @@ impl Path {
         self.ecn_info.ecn_mark().into()
     }
 
+    /// Make this a standby path that is probed every `probe_interval`, or
+    /// stop probing it if `probe_interval` is `None`.
+    pub const fn set_standby(&mut self, probe_interval: Option<Duration>) {
+        self.standby = probe_interval;
+    }
+
+    /// The time at which a standby path is due to be probed again.
+    fn standby_probe_time(&self) -> Option<Instant> {
+        if matches!(self.state, ProbeState::Valid) {
+            self.validated.zip(self.standby).map(|(v, i)| v + i)
+        } else {
+            None
+        }
+    }
+
     /// Whether this path is the primary or current path for the connection.
     pub const fn is_primary(&self) -> bool {
         self.primary
     }
 
+    /// What this path can send now, and the estimates that limit that.
+    pub fn send_budget(&self) -> SendBudget {
+        let rtt = self.rtt.estimate();
+        SendBudget {
+            cwnd_avail: self.sender.cwnd_avail(),
+            cwnd: self.sender.cwnd(),
+            pacing_rate: self.sender.effective_pacing_rate(rtt),
+            rtt,
+            loss_rate: self.recent_loss.loss_rate(),
+            ecn_ce_rate: self.recent_loss.ecn_ce_rate(),
+        }
+    }
+
     /// Whether this path is a temporary one.
     pub const fn is_temporary(&self) -> bool {
         self.remote_cid.is_none()
@@ -698,6 +846,9 @@ impl Path {
         } else {
             false
         };
+        if updated {
+            self.sender.set_max_send_rate(self.max_send_rate());
+        }
         if updated && self.is_primary() {
             self.scone.as_ref().map(Scone::rate)
         } else {
@@ -705,6 +856,26 @@ impl Path {
         }
     }
 
+    /// Limit the send rate on this path to `rate` bits per second.
+    /// SCONE advice can limit the rate further.
+    pub fn set_max_send_rate(&mut self, rate: Option<NonZeroU64>) {
+        self.max_send_rate = rate;
+        self.sender.set_max_send_rate(self.max_send_rate());
+    }
+
+    /// The maximum send rate on this path in bits per second.  This is the
+    /// lower of the rate that the application set and the SCONE advice.
+    pub fn max_send_rate(&self) -> Option<NonZeroU64> {
+        let scone = self
+            .scone
+            .as_ref()
+            .and_then(|s| Option::<NonZeroU64>::from(s.rate()));
+        match (self.max_send_rate, scone) {
+            (Some(app), Some(scone)) => Some(min(app, scone)),
+            (app, scone) => app.or(scone),
+        }
+    }
+
     /// Update the last use of this path, if it is valid.
     /// This will keep the path active slightly longer.
     pub const fn update(&mut self, now: Instant) {
@@ -744,6 +915,11 @@ impl Path {
             .map(super::cid::ConnectionIdEntry::connection_id)
     }
 
+    /// Access the entry for the remote connection ID, including the stateless reset token.
+    pub const fn remote_cid_entry(&self) -> Option<&RemoteConnectionIdEntry> {
+        self.remote_cid.as_ref()
+    }
+
     /// Set the stateless reset token for the connection ID that is currently in use.
     pub fn set_reset_token(&mut self, token: Srt) {
         if let Some(remote_cid) = self.remote_cid.as_mut() {
@@ -942,11 +1118,15 @@ impl Path {
         {
             self.probe(stats);
         }
+        if self.standby_probe_time().is_some_and(|t| t <= now) {
+            qdebug!("[{self}] Probing standby path");
+            self.probe(stats);
+        }
         if matches!(self.state, ProbeState::Failed) {
             // Retire failed paths immediately.
             false
-        } else if self.primary {
-            // Keep valid primary paths otherwise.
+        } else if self.primary || self.standby.is_some() {
+            // Keep valid primary and standby paths otherwise.
             true
         } else if matches!(self.state, ProbeState::Valid) {
             // Retire validated, non-primary paths.
@@ -969,7 +1149,7 @@ impl Path {
         if let ProbeState::Probing { sent, .. } = &self.state {
             Some(*sent + pto)
         } else {
-            None
+            self.standby_probe_time()
         }
     }
 
@@ -993,6 +1173,11 @@ impl Path {
         &self.sender
     }
 
+    /// Start careful resume with parameters from an earlier connection.
+    pub fn careful_resume(&mut self, params: CarefulResumeParams) {
+        self.sender.careful_resume(params);
+    }
+
     /// Take a snapshot of this path's RTT and congestion-control stats into `stats`.
     pub fn update_stats(&self, stats: &mut Stats) {
         stats.rtt = self.rtt.estimate();
@@ -1000,6 +1185,7 @@ impl Path {
         stats.min_rtt = self.rtt.minimum();
         stats.cc.cwnd = self.sender.cwnd();
         stats.cc.bytes_in_flight = self.sender.bytes_in_flight();
+        stats.max_send_rate = self.max_send_rate();
     }
 
     /// Pass on RTT configuration: the maximum acknowledgment delay of the peer,
@@ -1041,6 +1227,11 @@ impl Path {
         self.sent_bytes = self.sent_bytes.saturating_add(count);
     }
 
+    /// The number of bytes sent on this path.
+    pub const fn sent_bytes(&self) -> usize {
+        self.sent_bytes
+    }
+
     /// Record a packet as having been sent on this path.
     pub fn packet_sent(&mut self, sent: &mut sent::Packet, now: Instant) {
         if !self.is_primary() {
@@ -1049,6 +1240,12 @@ impl Path {
         self.sender.on_packet_sent(sent, self.rtt.estimate(), now);
     }
 
+    /// Record that there was nothing to send on this path, even though the
+    /// congestion controller and pacer would have allowed it.
+    pub fn on_app_limited(&mut self) {
+        self.sender.on_app_limited();
+    }
+
     /// Discard a packet that previously might have been in-flight.
     pub fn discard_packet(&mut self, sent: &sent::Packet, now: Instant, stats: &mut Stats) {
         if self.rtt.first_sample_time().is_none() {
@@ -1084,10 +1281,13 @@ impl Path {
     ) {
         debug_assert!(self.is_primary());
 
-        let ecn_ce_received = self.ecn_info.on_packets_acked(acked_pkts, ack_ecn, stats);
-        if ecn_ce_received {
+        let ce_marks = self.ecn_info.on_packets_acked(acked_pkts, ack_ecn, stats);
+        self.recent_loss
+            .on_packets_acked(acked_pkts.len(), ce_marks);
+        if ce_marks > 0 {
             let cwnd_reduced = self.sender.on_ecn_ce_received(
                 acked_pkts.first().expect("must be there"),
+                ce_marks,
                 now,
                 &mut stats.cc,
             );
@@ -1110,6 +1310,7 @@ impl Path {
         now: Instant,
     ) {
         debug_assert!(self.is_primary());
+        self.recent_loss.on_packets_lost(lost_packets.len());
         let cwnd_reduced = self.sender.on_packets_lost(
             self.rtt.first_sample_time(),
             prev_largest_acked_sent,
@@ -225,6 +225,35 @@ pub fn server_version_information_failed(
     );
 }
 
+/// Log a change to the keys that protect address validation tokens.
+/// These keys are shared by all connections and qlog has no event for them,
+/// so the change is logged as a message.  Only the key identifier is logged.
+pub fn token_key_updated(qlog: &mut Qlog, id: u8, now: Instant) {
+    qlog.add_event_at(
+        || {
+            Some(EventData::Message {
+                message: format!("token_key_updated: {id}"),
+            })
+        },
+        now,
+    );
+}
+
+/// Log a change to the maximum send rate, in bytes per second.
+/// qlog has no event for this, so it is logged as a message.
+pub fn max_send_rate_updated(qlog: &mut Qlog, rate: Option<u64>, now: Instant) {
+    qlog.add_event_at(
+        || {
+            let message = rate.map_or_else(
+                || "max_send_rate: none".to_string(),
+                |rate| format!("max_send_rate: {rate} B/s"),
+            );
+            Some(EventData::Message { message })
+        },
+        now,
+    );
+}
+
 pub fn packet_io(qlog: &mut Qlog, meta: packet::MetaData, now: Instant) {
     qlog.add_event_at(
         || {
@@ -320,6 +349,7 @@ pub fn recovery_parameters_set(
         || {
             let loss_reduction_factor = match cc {
                 CongestionControl::NewReno => 0.5,
+                CongestionControl::Bbr => 0.7,
                 CongestionControl::Cubic => {
                     f32::from(u8::try_from(Cubic::BETA_USIZE_DIVIDEND).expect("fits"))
                         / f32::from(u8::try_from(Cubic::BETA_USIZE_DIVISOR).expect("fits"))
@@ -329,7 +359,9 @@ pub fn recovery_parameters_set(
                 reordering_threshold: Some(
                     u16::try_from(crate::recovery::PACKET_THRESHOLD).expect("fits"),
                 ),
-                time_threshold: Some(9.0 / 8.0),
+                time_threshold: Some(
+                    f32::from(u16::try_from(crate::recovery::TIME_THRESHOLD).expect("fits")) / 8.0,
+                ),
                 timer_granularity: Some(u16::try_from(GRANULARITY.as_millis()).expect("fits")),
                 initial_rtt: Some(DEFAULT_INITIAL_RTT.as_secs_f32() * 1000.0),
                 max_datagram_size: Some(u32::try_from(plpmtu).expect("MTU fits in u32")),
@@ -347,6 +379,31 @@ pub fn recovery_parameters_set(
     );
 }
 
+/// Log the reordering thresholds for loss detection after they adapted to reordering.
+pub fn reordering_thresholds_updated(
+    qlog: &mut Qlog,
+    packet_threshold: u64,
+    time_threshold: u32,
+    now: Instant,
+) {
+    qlog.add_event_at(
+        || {
+            Some(EventData::RecoveryParametersSet(RecoveryParametersSet {
+                reordering_threshold: Some(u16::try_from(packet_threshold).expect("fits")),
+                time_threshold: Some(f32::from(u16::try_from(time_threshold).expect("fits")) / 8.0),
+                timer_granularity: None,
+                initial_rtt: None,
+                max_datagram_size: None,
+                initial_congestion_window: None,
+                minimum_congestion_window: None,
+                loss_reduction_factor: None,
+                persistent_congestion_threshold: None,
+            }))
+        },
+        now,
+    );
+}
+
 pub fn connection_closed(qlog: &mut Qlog, close_reason: &CloseReason, now: Instant) {
     qlog.add_event_at(
         || Some(EventData::ConnectionClosed(close_reason.into())),
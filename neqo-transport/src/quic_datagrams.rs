@@ -6,12 +6,13 @@
 
 // https://datatracker.ietf.org/doc/html/draft-ietf-quic-datagram
 
-use std::{cmp::min, collections::VecDeque};
+use std::{cmp::min, collections::VecDeque, time::Instant};
 
 use neqo_common::{Buffer, Encoder, qdebug, to_u64};
 
 use crate::{
     ConnectionEvents, Error, Res, Stats,
+    connection::TransmissionPriority,
     events::OutgoingDatagramOutcome,
     frame::{FrameEncoder as _, FrameType},
     packet, recovery,
@@ -41,9 +42,25 @@ impl From<Option<u64>> for DatagramTracking {
     }
 }
 
+/// Which datagram to drop when a datagram is added to a full send queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatagramDropPolicy {
+    /// Drop the datagram that was queued first.
+    #[default]
+    Oldest,
+    /// Drop the oldest of the datagrams with the lowest priority.  The new datagram
+    /// is dropped if it has a lower priority than any queued datagram.
+    LowestPriority,
+    /// Drop the new datagram.
+    Newest,
+}
+
 pub struct QuicDatagram {
     data: Vec<u8>,
     tracking: DatagramTracking,
+    priority: TransmissionPriority,
+    /// When the datagram is dropped if it hasn't been sent.
+    expiry: Option<Instant>,
 }
 
 impl QuicDatagram {
@@ -66,6 +83,7 @@ pub struct QuicDatagrams {
     /// The max size of a datagram that would be acceptable by the peer.
     remote_datagram_size: u64,
     max_queued_outgoing_datagrams: usize,
+    drop_policy: DatagramDropPolicy,
     /// Datagram queued for sending.
     datagrams: VecDeque<QuicDatagram>,
     conn_events: ConnectionEvents,
@@ -75,12 +93,14 @@ impl QuicDatagrams {
     pub fn new(
         local_datagram_size: u64,
         max_queued_outgoing_datagrams: usize,
+        drop_policy: DatagramDropPolicy,
         conn_events: ConnectionEvents,
     ) -> Self {
         Self {
             local_datagram_size,
             remote_datagram_size: 0,
             max_queued_outgoing_datagrams,
+            drop_policy,
             datagrams: VecDeque::with_capacity(max_queued_outgoing_datagrams),
             conn_events,
         }
@@ -94,16 +114,48 @@ impl QuicDatagrams {
         self.remote_datagram_size = min(v, QuicDatagram::MAX_SIZE);
     }
 
-    /// This function tries to write a datagram frame into a packet. If the
-    /// frame does not fit into the packet, the datagram will be dropped and a
+    /// Whether there are no datagrams queued for sending.
+    pub fn is_empty(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+
+    /// When the next queued datagram expires.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.datagrams.iter().filter_map(|d| d.expiry).min()
+    }
+
+    /// Drop datagrams that were not sent before they expired, posting an
+    /// [`OutgoingDatagramOutcome::Expired`] event for each.
+    pub fn expire(&mut self, now: Instant, stats: &mut Stats) {
+        let conn_events = &self.conn_events;
+        self.datagrams.retain(|dgram| {
+            let expired = dgram.expiry.is_some_and(|e| e <= now);
+            if expired {
+                qdebug!("QUIC datagram ({}) expired.", dgram.data.len());
+                conn_events.datagram_outcome(dgram.tracking(), OutgoingDatagramOutcome::Expired);
+                stats.datagram_tx.expired += 1;
+            }
+            !expired
+        });
+    }
+
+    /// This function tries to write the datagram frames with the given priority into a
+    /// packet, in the order they were queued. If a frame does not fit into an empty
+    /// packet, the datagram will be dropped and a
     /// [`OutgoingDatagramOutcome::DroppedTooBig`] event will be posted.
     pub fn write_frames<B: Buffer>(
         &mut self,
+        priority: TransmissionPriority,
         builder: &mut packet::Builder<B>,
         tokens: &mut recovery::Tokens,
         stats: &mut Stats,
     ) {
-        while let Some(dgram) = self.datagrams.pop_front() {
+        let mut i = 0;
+        while let Some(dgram) = self.datagrams.get(i) {
+            if dgram.priority != priority {
+                i += 1;
+                continue;
+            }
             let len = dgram.as_ref().len();
             if len + DATAGRAM_FRAME_TYPE_VARINT_LEN <= builder.remaining() {
                 // The datagram fits into the packet.
@@ -136,10 +188,10 @@ impl QuicDatagrams {
                     .datagram_outcome(dgram.tracking(), OutgoingDatagramOutcome::DroppedTooBig);
                 stats.datagram_tx.dropped_too_big += 1;
             } else {
-                self.datagrams.push_front(dgram);
                 // Try later on an empty packet.
                 return;
             }
+            self.datagrams.remove(i);
         }
     }
 
@@ -152,10 +204,14 @@ impl QuicDatagrams {
     /// datagram can fit into a packet (i.e. MTU limit). This is checked during
     /// creation of an actual packet and the datagram will be dropped if it does
     /// not fit into the packet.
+    ///
+    /// If the queue is full, the drop policy chooses which datagram is dropped.
     pub fn add_datagram(
         &mut self,
         data: Vec<u8>,
         tracking: DatagramTracking,
+        priority: TransmissionPriority,
+        expiry: Option<Instant>,
         stats: &mut Stats,
     ) -> Res<()> {
         if to_u64(data.len()) > self.remote_datagram_size {
@@ -166,18 +222,44 @@ impl QuicDatagrams {
             );
             return Err(Error::TooMuchData);
         }
-        if self.datagrams.len() == self.max_queued_outgoing_datagrams {
-            qdebug!("QUIC datagram queue full, dropping first datagram in queue (head-drop).");
-            self.conn_events.datagram_outcome(
-                self.datagrams
-                    .pop_front()
-                    .ok_or(Error::Internal)?
-                    .tracking(),
-                OutgoingDatagramOutcome::DroppedQueueFull,
-            );
-            stats.datagram_tx.dropped_queue_full += 1;
+        let dgram = QuicDatagram {
+            data,
+            tracking,
+            priority,
+            expiry,
+        };
+        if self.datagrams.len() < self.max_queued_outgoing_datagrams {
+            self.datagrams.push_back(dgram);
+            return Ok(());
         }
-        self.datagrams.push_back(QuicDatagram { data, tracking });
+
+        let drop_index = match self.drop_policy {
+            DatagramDropPolicy::Oldest => Some(0),
+            DatagramDropPolicy::LowestPriority => self
+                .datagrams
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by_key(|(_, d)| d.priority)
+                .and_then(|(i, d)| (d.priority >= priority).then_some(i)),
+            DatagramDropPolicy::Newest => None,
+        };
+        qdebug!(
+            "QUIC datagram queue full, dropping {:?} datagram.",
+            self.drop_policy
+        );
+        let dropped = if let Some(i) = drop_index {
+            let dropped = self.datagrams.remove(i).ok_or(Error::Internal)?;
+            self.datagrams.push_back(dgram);
+            dropped
+        } else {
+            dgram
+        };
+        self.conn_events.datagram_outcome(
+            dropped.tracking(),
+            OutgoingDatagramOutcome::DroppedQueueFull,
+        );
+        stats.datagram_tx.dropped_queue_full += 1;
         Ok(())
     }
 
@@ -0,0 +1,581 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Routable connection IDs, following QUIC-LB (draft-ietf-quic-load-balancers).
+//!
+//! A connection ID starts with a byte that holds the configuration rotation bits,
+//! followed by the server ID and a nonce.  The server ID and nonce are either
+//! sent in the clear or encrypted so that a load balancer that has the
+//! configuration can recover the server ID, but nobody else can.
+//!
+//! Encrypted configurations use the single-pass algorithm when the server ID
+//! and nonce are 16 bytes long, and the four-pass algorithm otherwise.
+
+mod aes;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    rc::Rc,
+};
+
+use aes::{Aes128, BLOCK_LEN};
+use neqo_common::{Decoder, qtrace};
+use nss::random;
+
+use crate::{
+    Error, Res,
+    cid::{ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef},
+    packet,
+};
+
+/// The rotation bits that mark a connection ID as unroutable.
+const UNROUTABLE: u8 = 0b111;
+/// The number of bits used for configuration rotation.
+const ROTATION_BITS: u8 = 3;
+/// The largest server ID.
+const MAX_SERVER_ID_LEN: usize = 15;
+/// The smallest nonce.
+const MIN_NONCE_LEN: usize = 4;
+/// The largest nonce.
+const MAX_NONCE_LEN: usize = 18;
+/// The largest combined server ID and nonce, so that connection IDs are at most 20 bytes.
+const MAX_COMBINED_LEN: usize = 19;
+/// The largest half of the server ID and nonce in the four-pass algorithm.
+const MAX_HALF_LEN: usize = MAX_COMBINED_LEN.div_ceil(2);
+
+/// A QUIC-LB configuration, which is shared between a load balancer and the servers behind it.
+#[derive(Clone)]
+pub struct Config {
+    /// The configuration rotation bits, which identify this configuration.
+    rotation: u8,
+    /// The length of server IDs.
+    server_id_len: usize,
+    /// The length of nonces.
+    nonce_len: usize,
+    /// Whether the first byte encodes the length of the connection ID.
+    encode_length: bool,
+    /// The key, if the server ID and nonce are encrypted.
+    key: Option<Rc<Aes128>>,
+}
+
+impl Config {
+    /// Create a configuration where the server ID is sent in the clear.
+    ///
+    /// # Errors
+    ///
+    /// When the rotation bits are `0b111`, which is reserved for unroutable connection IDs,
+    /// or when the lengths are out of range.
+    pub fn plaintext(rotation: u8, server_id_len: usize, nonce_len: usize) -> Res<Self> {
+        if rotation >= UNROUTABLE
+            || !(1..=MAX_SERVER_ID_LEN).contains(&server_id_len)
+            || !(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce_len)
+            || server_id_len + nonce_len > MAX_COMBINED_LEN
+        {
+            return Err(Error::InvalidInput);
+        }
+        Ok(Self {
+            rotation,
+            server_id_len,
+            nonce_len,
+            encode_length: false,
+            key: None,
+        })
+    }
+
+    /// Create a configuration where the server ID and nonce are encrypted with `key`,
+    /// which is used as an AES-128 key.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Config::plaintext`].
+    pub fn encrypted(
+        rotation: u8,
+        server_id_len: usize,
+        nonce_len: usize,
+        key: &[u8; BLOCK_LEN],
+    ) -> Res<Self> {
+        let mut config = Self::plaintext(rotation, server_id_len, nonce_len)?;
+        config.key = Some(Rc::new(Aes128::new(key)));
+        Ok(config)
+    }
+
+    /// Set whether the first byte of each connection ID encodes its length,
+    /// rather than carrying random bits.
+    #[must_use]
+    pub const fn encode_length(mut self, encode_length: bool) -> Self {
+        self.encode_length = encode_length;
+        self
+    }
+
+    /// The configuration rotation bits.
+    #[must_use]
+    pub const fn rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    /// The length of server IDs.
+    #[must_use]
+    pub const fn server_id_len(&self) -> usize {
+        self.server_id_len
+    }
+
+    /// The length of connection IDs that use this configuration.
+    #[must_use]
+    pub const fn cid_len(&self) -> usize {
+        1 + self.server_id_len + self.nonce_len
+    }
+
+    /// Build a connection ID from a server ID and nonce.
+    fn encode(&self, server_id: &[u8], nonce: &[u8]) -> Res<ConnectionId> {
+        debug_assert_eq!(server_id.len(), self.server_id_len);
+        debug_assert_eq!(nonce.len(), self.nonce_len);
+        let low_bits = if self.encode_length {
+            u8::try_from(self.cid_len() - 1)?
+        } else {
+            random::<1>()[0]
+        };
+        let mut cid = Vec::with_capacity(self.cid_len());
+        cid.push((self.rotation << (8 - ROTATION_BITS)) | (low_bits & 0x1f));
+        cid.extend_from_slice(server_id);
+        cid.extend_from_slice(nonce);
+        if let Some(key) = &self.key {
+            encrypt(key, &mut cid[1..], Direction::Encrypt)?;
+        }
+        Ok(ConnectionId::from(&cid))
+    }
+
+    /// Recover the server ID from a connection ID that uses this configuration.
+    fn server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        let mut block = cid.get(1..self.cid_len())?.to_vec();
+        if let Some(key) = &self.key {
+            encrypt(key, &mut block, Direction::Decrypt).ok()?;
+        }
+        block.truncate(self.server_id_len);
+        Some(block)
+    }
+}
+
+impl Debug for Config {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "QUIC-LB config {} server_id_len={} nonce_len={}{}",
+            self.rotation,
+            self.server_id_len,
+            self.nonce_len,
+            if self.key.is_some() { " encrypted" } else { "" }
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+/// Encrypt or decrypt the server ID and nonce in place.  When they fill
+/// an AES block, they are encrypted directly; otherwise, the four-pass
+/// algorithm is used.
+fn encrypt(key: &Aes128, block: &mut [u8], direction: Direction) -> Res<()> {
+    let Ok(block) = <&mut [u8; BLOCK_LEN]>::try_from(&mut *block) else {
+        return four_pass(key, block, direction);
+    };
+    match direction {
+        Direction::Encrypt => key.encrypt(block),
+        Direction::Decrypt => key.decrypt(block),
+    }
+    Ok(())
+}
+
+/// Run one pass of the Feistel network, which modifies `dst` based on `src`.
+fn feistel_round(
+    key: &Aes128,
+    src: &[u8],
+    dst: &mut [u8],
+    len: usize,
+    pass: u8,
+    nibble_mask: (usize, u8),
+) -> Res<()> {
+    let mut input = [0; BLOCK_LEN];
+    input[..src.len()].copy_from_slice(src);
+    input[BLOCK_LEN - 2] = u8::try_from(len)?;
+    input[BLOCK_LEN - 1] = pass;
+    key.encrypt(&mut input);
+    for (d, o) in dst.iter_mut().zip(input) {
+        *d ^= o;
+    }
+    dst[nibble_mask.0] &= nibble_mask.1;
+    Ok(())
+}
+
+/// The four-pass encryption algorithm, which works on the server ID and nonce in place.
+fn four_pass(key: &Aes128, block: &mut [u8], direction: Direction) -> Res<()> {
+    let len = block.len();
+    let half = len.div_ceil(2);
+    let odd = len % 2 == 1;
+    // For odd lengths, the halves overlap in the middle byte, with the left half
+    // taking the high bits and the right half taking the low bits.
+    let left_mask = (half - 1, if odd { 0xf0 } else { 0xff });
+    let right_mask = (0, if odd { 0x0f } else { 0xff });
+
+    let mut left = [0; MAX_HALF_LEN];
+    let mut right = [0; MAX_HALF_LEN];
+    let (left, right) = (&mut left[..half], &mut right[..half]);
+    left.copy_from_slice(&block[..half]);
+    right.copy_from_slice(&block[len - half..]);
+    left[left_mask.0] &= left_mask.1;
+    right[right_mask.0] &= right_mask.1;
+
+    let passes: [u8; 4] = if direction == Direction::Encrypt {
+        [1, 2, 3, 4]
+    } else {
+        [4, 3, 2, 1]
+    };
+    for pass in passes {
+        if pass % 2 == 1 {
+            feistel_round(key, left, right, len, pass, right_mask)?;
+        } else {
+            feistel_round(key, right, left, len, pass, left_mask)?;
+        }
+    }
+
+    block[len - half..].copy_from_slice(right);
+    if odd {
+        block[half - 1] |= left[half - 1];
+        block[..half - 1].copy_from_slice(&left[..half - 1]);
+    } else {
+        block[..half].copy_from_slice(left);
+    }
+    Ok(())
+}
+
+/// Recovers server IDs from connection IDs, for use in a load balancer.
+/// This holds a configuration for each value of the configuration rotation bits,
+/// so that a pool of servers can change configuration without breaking
+/// existing connections.
+#[derive(Debug, Default, Clone)]
+pub struct Router {
+    configs: [Option<Config>; UNROUTABLE as usize],
+}
+
+impl Router {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        let mut router = Self::default();
+        router.add_config(config);
+        router
+    }
+
+    /// Add a configuration, replacing any with the same rotation bits.
+    pub fn add_config(&mut self, config: Config) {
+        let i = usize::from(config.rotation);
+        self.configs[i] = Some(config);
+    }
+
+    /// Remove the configuration with the given rotation bits.
+    pub fn remove_config(&mut self, rotation: u8) {
+        if let Some(c) = self.configs.get_mut(usize::from(rotation)) {
+            *c = None;
+        }
+    }
+
+    /// Find the configuration that applies to a connection ID, based on its first byte.
+    fn config(&self, first: u8) -> Option<&Config> {
+        self.configs
+            .get(usize::from(first >> (8 - ROTATION_BITS)))?
+            .as_ref()
+    }
+
+    /// Recover the server ID from a connection ID.  This returns `None` if the
+    /// connection ID doesn't use a known configuration or is too short.
+    #[must_use]
+    pub fn server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        self.config(*cid.first()?)?.server_id(cid)
+    }
+
+    /// Recover the server ID from the destination connection ID of the first
+    /// packet in a datagram.  This returns `None` for datagrams that need to be
+    /// routed some other way, such as Initial packets with a connection ID
+    /// that the client chose.
+    #[must_use]
+    pub fn route(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let mut dec = Decoder::new(datagram);
+        let first = dec.decode_uint::<u8>()?;
+        let cid = if first & packet::BIT_LONG == 0 {
+            let len = self.config(dec.peek_byte()?)?.cid_len();
+            dec.decode(len)?
+        } else {
+            dec.decode(4)?; // Version.
+            dec.decode_vec(1)?
+        };
+        let server_id = self.server_id(cid);
+        qtrace!(
+            "QUIC-LB: route {} to {server_id:?}",
+            ConnectionIdRef::from(cid)
+        );
+        server_id
+    }
+}
+
+impl ConnectionIdDecoder for Router {
+    fn decode_cid<'a>(&self, dec: &mut Decoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        let len = self.config(dec.peek_byte()?)?.cid_len();
+        dec.decode(len).map(ConnectionIdRef::from)
+    }
+}
+
+/// Generates connection IDs that a QUIC-LB load balancer can route to this server.
+#[derive(Debug)]
+pub struct Generator {
+    /// The configurations that are accepted when decoding connection IDs.
+    router: Router,
+    /// The configuration used for new connection IDs.
+    config: Config,
+    /// The server ID for the current configuration.
+    server_id: Vec<u8>,
+    /// The next value of the counter that makes encrypted nonces unique.
+    counter: u64,
+    /// The number of nonces that can still be generated.
+    remaining: u64,
+}
+
+impl Generator {
+    /// Create a generator for the given configuration and server ID.
+    ///
+    /// # Errors
+    ///
+    /// When the server ID doesn't have the length that the configuration requires.
+    pub fn new(config: Config, server_id: &[u8]) -> Res<Self> {
+        let mut generator = Self {
+            router: Router::default(),
+            config: config.clone(),
+            server_id: Vec::new(),
+            counter: 0,
+            remaining: 0,
+        };
+        generator.rotate(config, server_id)?;
+        Ok(generator)
+    }
+
+    /// Start using a new configuration, which might come with a new server ID.
+    /// Connection IDs that use earlier configurations continue to be accepted
+    /// until those configurations are retired.
+    ///
+    /// # Errors
+    ///
+    /// When the server ID doesn't have the length that the configuration requires.
+    pub fn rotate(&mut self, config: Config, server_id: &[u8]) -> Res<()> {
+        if server_id.len() != config.server_id_len {
+            return Err(Error::InvalidInput);
+        }
+        // Counters for encrypted nonces start at a random value, so that a restarted
+        // server is unlikely to repeat connection IDs.
+        let counter_len = config.nonce_len.min(size_of::<u64>());
+        let capacity = 1_u64
+            .checked_shl(u32::try_from(counter_len * 8)?)
+            .unwrap_or(0)
+            .wrapping_sub(1);
+        self.counter = u64::from_be_bytes(random::<8>()) & capacity;
+        self.remaining = capacity;
+        self.server_id = server_id.to_vec();
+        self.router.add_config(config.clone());
+        self.config = config;
+        Ok(())
+    }
+
+    /// Stop accepting connection IDs that use the given configuration.
+    /// The current configuration can't be retired.
+    pub fn retire(&mut self, rotation: u8) {
+        if rotation != self.config.rotation {
+            self.router.remove_config(rotation);
+        }
+    }
+
+    fn nonce(&mut self) -> Option<Vec<u8>> {
+        if self.config.key.is_none() {
+            return Some(random::<MAX_NONCE_LEN>()[..self.config.nonce_len].to_vec());
+        }
+        self.remaining = self.remaining.checked_sub(1)?;
+        let counter = self.counter.to_be_bytes();
+        self.counter = self.counter.wrapping_add(1);
+        let mut nonce = random::<MAX_NONCE_LEN>()[..self.config.nonce_len].to_vec();
+        let counter_len = self.config.nonce_len.min(counter.len());
+        let offset = self.config.nonce_len - counter_len;
+        nonce[offset..].copy_from_slice(&counter[counter.len() - counter_len..]);
+        Some(nonce)
+    }
+}
+
+impl ConnectionIdDecoder for Generator {
+    fn decode_cid<'a>(&self, dec: &mut Decoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        self.router.decode_cid(dec)
+    }
+}
+
+impl ConnectionIdGenerator for Generator {
+    fn generate_cid(&mut self) -> Option<ConnectionId> {
+        let nonce = self.nonce()?;
+        self.config.encode(&self.server_id, &nonce).ok()
+    }
+
+    fn as_decoder(&self) -> &dyn ConnectionIdDecoder {
+        self
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use neqo_common::{Decoder, Encoder};
+    use test_fixture::fixture_init;
+
+    use super::{Config, Generator, Router};
+    use crate::{ConnectionIdDecoder as _, ConnectionIdGenerator as _, Error};
+
+    const SERVER_ID: &[u8] = &[0x31, 0x44, 0x1a];
+    const KEY: &[u8; 16] = &[
+        0x8f, 0x95, 0xf0, 0x92, 0x45, 0x76, 0x5f, 0x80, 0x25, 0x69, 0x34, 0xe5, 0x0c, 0x66, 0x20,
+        0x7f,
+    ];
+
+    #[test]
+    fn invalid_config() {
+        assert_eq!(Config::plaintext(7, 3, 4).unwrap_err(), Error::InvalidInput);
+        assert_eq!(Config::plaintext(0, 0, 4).unwrap_err(), Error::InvalidInput);
+        assert_eq!(Config::plaintext(0, 3, 3).unwrap_err(), Error::InvalidInput);
+        assert_eq!(
+            Config::plaintext(0, 15, 5).unwrap_err(),
+            Error::InvalidInput
+        );
+        assert!(Config::plaintext(0, 15, 4).is_ok());
+        let config = Config::plaintext(0, 3, 4).unwrap();
+        assert_eq!(
+            Generator::new(config, &[1]).unwrap_err(),
+            Error::InvalidInput
+        );
+    }
+
+    #[test]
+    fn plaintext() {
+        let config = Config::plaintext(1, SERVER_ID.len(), 5)
+            .unwrap()
+            .encode_length(true);
+        let mut generator = Generator::new(config.clone(), SERVER_ID).unwrap();
+        let cid = generator.generate_cid().unwrap();
+        assert_eq!(cid.len(), 9);
+        assert_eq!(cid[0], 0b0010_1000);
+        assert_eq!(&cid[1..4], SERVER_ID);
+        assert_eq!(Router::new(config).server_id(&cid).unwrap(), SERVER_ID);
+    }
+
+    /// Encrypted connection IDs decode to the server ID, for odd and even lengths.
+    #[test]
+    fn encrypted() {
+        fixture_init();
+        for nonce_len in 4..=16 {
+            let config = Config::encrypted(2, SERVER_ID.len(), nonce_len, KEY).unwrap();
+            let router = Router::new(config.clone());
+            let mut generator = Generator::new(config, SERVER_ID).unwrap();
+            let a = generator.generate_cid().unwrap();
+            let b = generator.generate_cid().unwrap();
+            assert_eq!(a.len(), 1 + SERVER_ID.len() + nonce_len);
+            assert_eq!(a[0] >> 5, 2);
+            assert_ne!(a, b);
+            assert_ne!(&a[1..4], SERVER_ID);
+            assert_eq!(router.server_id(&a).unwrap(), SERVER_ID);
+            assert_eq!(router.server_id(&b).unwrap(), SERVER_ID);
+        }
+    }
+
+    /// The examples from Appendix B.2 of draft-ietf-quic-load-balancers, which
+    /// cover the four-pass algorithm with odd lengths and the single-pass algorithm.
+    #[test]
+    fn draft_vectors() {
+        const VECTORS: &[(u8, &str, &str, &str)] = &[
+            (0, "ed793a", "ee080dbf", "0720b1d07b359d3c"),
+            (
+                1,
+                "ed793a51d49b8f5fab65",
+                "ee080dbf48",
+                "2fcc381bc74cb4fbad2823a3d1f8fed2",
+            ),
+            (
+                2,
+                "ed793a51d49b8f5f",
+                "ee080dbf48c0d1e5",
+                "504dd2d05a7b0de9b2b9907afb5ecf8cc3",
+            ),
+        ];
+        for &(rotation, server_id, nonce, cid) in VECTORS {
+            let hex = |s: &str| Vec::from(Encoder::from_hex(s));
+            let (server_id, nonce, cid) = (hex(server_id), hex(nonce), hex(cid));
+            let config = Config::encrypted(rotation, server_id.len(), nonce.len(), KEY)
+                .unwrap()
+                .encode_length(true);
+            assert_eq!(&config.encode(&server_id, &nonce).unwrap()[..], &cid[..]);
+            assert_eq!(Router::new(config).server_id(&cid).unwrap(), server_id);
+        }
+    }
+
+    #[test]
+    fn wrong_key() {
+        fixture_init();
+        let config = Config::encrypted(0, SERVER_ID.len(), 6, KEY).unwrap();
+        let cid = Generator::new(config, SERVER_ID)
+            .unwrap()
+            .generate_cid()
+            .unwrap();
+        let other = Config::encrypted(0, SERVER_ID.len(), 6, &[0; 16]).unwrap();
+        assert_ne!(Router::new(other).server_id(&cid).unwrap(), SERVER_ID);
+    }
+
+    /// Connection IDs from a retired configuration are no longer decoded.
+    #[test]
+    fn rotation() {
+        fixture_init();
+        let old = Config::plaintext(0, SERVER_ID.len(), 4).unwrap();
+        let new = Config::encrypted(1, 2, 8, KEY).unwrap();
+        let mut generator = Generator::new(old, SERVER_ID).unwrap();
+        let old_cid = generator.generate_cid().unwrap();
+        generator.rotate(new, &[9, 9]).unwrap();
+        let new_cid = generator.generate_cid().unwrap();
+        assert_eq!(new_cid.len(), 11);
+
+        let decode =
+            |g: &Generator, cid: &[u8]| g.decode_cid(&mut Decoder::new(cid)).map(|c| c.len());
+        assert_eq!(decode(&generator, &old_cid), Some(old_cid.len()));
+        assert_eq!(decode(&generator, &new_cid), Some(new_cid.len()));
+        generator.retire(0);
+        assert_eq!(decode(&generator, &old_cid), None);
+        generator.retire(1);
+        assert_eq!(decode(&generator, &new_cid), Some(new_cid.len()));
+    }
+
+    #[test]
+    fn route_datagram() {
+        let config = Config::plaintext(0, SERVER_ID.len(), 4).unwrap();
+        let router = Router::new(config.clone());
+        let cid = Generator::new(config, SERVER_ID)
+            .unwrap()
+            .generate_cid()
+            .unwrap();
+
+        let mut short = vec![0x40];
+        short.extend_from_slice(&cid);
+        short.extend_from_slice(&[0; 20]);
+        assert_eq!(router.route(&short).unwrap(), SERVER_ID);
+
+        let mut long = vec![0xc0, 0, 0, 0, 1, u8::try_from(cid.len()).unwrap()];
+        long.extend_from_slice(&cid);
+        long.extend_from_slice(&[0; 20]);
+        assert_eq!(router.route(&long).unwrap(), SERVER_ID);
+
+        // Unroutable connection IDs.
+        short[1] |= 0xe0;
+        assert!(router.route(&short).is_none());
+    }
+}
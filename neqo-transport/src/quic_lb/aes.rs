@@ -0,0 +1,168 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// AES-128 for QUIC-LB, which uses the configured key directly.  NSS only
+// provides AES with keys that are derived using HKDF, so this is a small
+// software implementation.  It computes the S-box rather than using a table,
+// so that memory accesses don't depend on the key or the data.
+
+/// The size of an AES block.
+pub const BLOCK_LEN: usize = 16;
+/// The number of rounds for a 128-bit key.
+const ROUNDS: usize = 10;
+
+/// Multiply by `x` in GF(2^8).
+const fn xtime(a: u8) -> u8 {
+    (a << 1) ^ (0x1b & 0_u8.wrapping_sub(a >> 7))
+}
+
+/// Multiply in GF(2^8), without branching on either input.
+const fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    let mut i = 0;
+    while i < 8 {
+        p ^= a & 0_u8.wrapping_sub(b & 1);
+        a = xtime(a);
+        b >>= 1;
+        i += 1;
+    }
+    p
+}
+
+/// The multiplicative inverse in GF(2^8), with 0 mapping to 0.  This is `a^254`.
+const fn inverse(a: u8) -> u8 {
+    let a2 = mul(a, a);
+    let a3 = mul(a2, a);
+    let a6 = mul(a3, a3);
+    let a12 = mul(a6, a6);
+    let a15 = mul(a12, a3);
+    let a30 = mul(a15, a15);
+    let a60 = mul(a30, a30);
+    let a120 = mul(a60, a60);
+    let a126 = mul(a120, a6);
+    let a127 = mul(a126, a);
+    mul(a127, a127)
+}
+
+const fn sub_byte(a: u8) -> u8 {
+    let b = inverse(a);
+    b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63
+}
+
+const fn inv_sub_byte(a: u8) -> u8 {
+    inverse(a.rotate_left(1) ^ a.rotate_left(3) ^ a.rotate_left(6) ^ 0x05)
+}
+
+/// An AES-128 key, expanded into round keys.
+pub struct Aes128 {
+    round_keys: [[u8; BLOCK_LEN]; ROUNDS + 1],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; BLOCK_LEN]) -> Self {
+        let mut round_keys = [[0; BLOCK_LEN]; ROUNDS + 1];
+        round_keys[0] = *key;
+        let mut rcon = 1;
+        for r in 1..=ROUNDS {
+            let prev = round_keys[r - 1];
+            let mut t = [prev[13], prev[14], prev[15], prev[12]];
+            for b in &mut t {
+                *b = sub_byte(*b);
+            }
+            t[0] ^= rcon;
+            rcon = xtime(rcon);
+            let rk = &mut round_keys[r];
+            for i in 0..BLOCK_LEN {
+                let w = if i < 4 { t[i] } else { rk[i - 4] };
+                rk[i] = prev[i] ^ w;
+            }
+        }
+        Self { round_keys }
+    }
+
+    fn add_round_key(&self, state: &mut [u8; BLOCK_LEN], round: usize) {
+        for (s, k) in state.iter_mut().zip(self.round_keys[round]) {
+            *s ^= k;
+        }
+    }
+
+    /// Rotate row `r` of the state left by `r` columns, or right when inverting.
+    fn shift_rows(state: &mut [u8; BLOCK_LEN], inverse: bool) {
+        let old = *state;
+        for r in 1..4 {
+            for c in 0..4 {
+                let from = if inverse { c + 4 - r } else { c + r };
+                state[r + 4 * c] = old[r + 4 * (from % 4)];
+            }
+        }
+    }
+
+    fn mix_columns(state: &mut [u8; BLOCK_LEN], m: [u8; 4]) {
+        for col in state.as_chunks_mut::<4>().0 {
+            let a = *col;
+            for (r, out) in col.iter_mut().enumerate() {
+                *out = (0..4).fold(0, |acc, i| acc ^ mul(m[(4 + i - r) % 4], a[i]));
+            }
+        }
+    }
+
+    pub fn encrypt(&self, block: &mut [u8; BLOCK_LEN]) {
+        self.add_round_key(block, 0);
+        for round in 1..=ROUNDS {
+            for b in block.iter_mut() {
+                *b = sub_byte(*b);
+            }
+            Self::shift_rows(block, false);
+            if round < ROUNDS {
+                Self::mix_columns(block, [2, 3, 1, 1]);
+            }
+            self.add_round_key(block, round);
+        }
+    }
+
+    pub fn decrypt(&self, block: &mut [u8; BLOCK_LEN]) {
+        self.add_round_key(block, ROUNDS);
+        for round in (0..ROUNDS).rev() {
+            Self::shift_rows(block, true);
+            for b in block.iter_mut() {
+                *b = inv_sub_byte(*b);
+            }
+            self.add_round_key(block, round);
+            if round > 0 {
+                Self::mix_columns(block, [14, 11, 13, 9]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::Aes128;
+
+    /// The example from Appendix C.1 of FIPS 197.
+    #[test]
+    fn fips_197() {
+        let key = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let plaintext = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        let ciphertext = [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ];
+        let aes = Aes128::new(&key);
+        let mut block = plaintext;
+        aes.encrypt(&mut block);
+        assert_eq!(block, ciphertext);
+        aes.decrypt(&mut block);
+        assert_eq!(block, plaintext);
+    }
+}
@@ -6,6 +6,8 @@
 
 // Tracking of sent packets and detecting their loss.
 
+pub mod rate;
+mod reorder;
 pub mod sent;
 mod token;
 
@@ -19,6 +21,8 @@ use std::{
 use enum_map::EnumMap;
 use enumset::enum_set;
 use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, qwarn};
+pub use rate::RateSample;
+use reorder::Reordering;
 use strum::IntoEnumIterator as _;
 pub use token::{StreamRecoveryToken, Token, Tokens};
 
@@ -32,6 +36,8 @@ use crate::{
 };
 
 pub const PACKET_THRESHOLD: u64 = 3;
+/// The time threshold for loss detection, in eighths of an RTT.
+pub const TIME_THRESHOLD: u32 = 9;
 /// `ACK_ONLY_SIZE_LIMIT` is the minimum size of the congestion window.
 /// If the congestion window is this small, we will only send ACK frames.
 pub const ACK_ONLY_SIZE_LIMIT: usize = 256;
@@ -138,6 +144,8 @@ pub struct LossRecoverySpace {
     /// This is `None` if there were no out-of-order packets detected.
     /// When set to `Some(T)`, time-based loss detection should be enabled.
     first_ooo_time: Option<Instant>,
+    /// The thresholds for declaring packets lost.
+    reordering: Reordering,
 }
 
 impl LossRecoverySpace {
@@ -151,6 +159,21 @@ impl LossRecoverySpace {
             in_flight_outstanding: 0,
             sent_packets: sent::Packets::default(),
             first_ooo_time: None,
+            reordering: Reordering::default(),
+        }
+    }
+
+    /// The time to wait before declaring a packet in this space lost.
+    #[must_use]
+    pub fn loss_delay(&self, rtt: &RttEstimate) -> Duration {
+        self.reordering.loss_delay(rtt)
+    }
+
+    /// Record any change to the reordering thresholds in stats and qlog.
+    fn report_reordering(&mut self, qlog: &mut Qlog, stats: &StatsCell, now: Instant) {
+        if let Some((packet_threshold, time_threshold)) = self.reordering.take_update() {
+            stats.borrow_mut().reordering_adaptations += 1;
+            qlog::reordering_thresholds_updated(qlog, packet_threshold, time_threshold, now);
         }
     }
 
@@ -271,6 +294,7 @@ impl LossRecoverySpace {
                 // A packet declared lost that is subsequently acknowledged was never
                 // really lost, so take it back out of the loss counters. Every packet
                 // marked lost was counted by `count_lost`, so this cannot underflow.
+                self.reordering.on_spurious_loss(p, self.largest_acked);
                 stats.late_ack += 1;
                 stats.lost -= 1;
                 stats.bytes_lost -= p.len();
@@ -326,6 +350,8 @@ impl LossRecoverySpace {
         self.first_ooo_time = None;
 
         let largest_acked = self.largest_acked;
+        let packet_threshold = self.reordering.packet_threshold();
+        let first = lost_packets.len();
 
         for packet in self
             .sent_packets
@@ -340,9 +366,9 @@ impl LossRecoverySpace {
                     packet.time_sent()
                 );
                 sent::LossTrigger::TimeThreshold
-            } else if largest_acked >= Some(packet.pn() + PACKET_THRESHOLD) {
+            } else if largest_acked >= Some(packet.pn() + packet_threshold) {
                 qtrace!(
-                    "lost={}, is >= {PACKET_THRESHOLD} from largest acked {largest_acked:?}",
+                    "lost={}, is >= {packet_threshold} from largest acked {largest_acked:?}",
                     packet.pn()
                 );
                 sent::LossTrigger::ReorderingThreshold
@@ -358,6 +384,9 @@ impl LossRecoverySpace {
                 lost_packets.push(packet.clone());
             }
         }
+        if lost_packets.len() > first {
+            self.reordering.on_loss();
+        }
     }
 }
 
@@ -494,11 +523,17 @@ pub struct Loss {
 
 impl Loss {
     #[must_use]
-    pub fn new(stats: StatsCell, fast_pto: u8) -> Self {
+    pub fn new(stats: StatsCell, fast_pto: u8, adaptive_reordering: bool) -> Self {
+        let mut spaces = LossRecoverySpaces::default();
+        if adaptive_reordering {
+            for sp in spaces.iter_mut() {
+                sp.reordering.enable();
+            }
+        }
         Self {
             confirmed_time: None,
             pto_state: None,
-            spaces: LossRecoverySpaces::default(),
+            spaces,
             qlog: Qlog::default(),
             stats,
             fast_pto,
@@ -506,6 +541,14 @@ impl Loss {
         }
     }
 
+    /// Whether any ack-eliciting packets are outstanding.
+    #[must_use]
+    pub fn has_outstanding(&self) -> bool {
+        self.spaces
+            .iter()
+            .any(LossRecoverySpace::in_flight_outstanding)
+    }
+
     #[must_use]
     pub fn largest_acknowledged_pn(&self, pn_space: PacketNumberSpace) -> Option<packet::Number> {
         self.spaces.get(pn_space)?.largest_acked
@@ -681,9 +724,10 @@ impl Loss {
         let Some(sp) = self.spaces.get_mut(pn_space) else {
             return (Vec::new(), Vec::new());
         };
-        let loss_delay = primary_path.borrow().rtt().loss_delay();
+        let loss_delay = sp.loss_delay(primary_path.borrow().rtt());
         let mut lost = Vec::new();
         sp.detect_lost_packets(now, loss_delay, cleanup_delay, &mut lost);
+        sp.report_reordering(&mut self.qlog, &self.stats, now);
         self.count_lost(&lost);
 
         // Tell the congestion controller about any lost packets.
@@ -821,9 +865,11 @@ impl Loss {
     fn earliest_loss_time(&self, rtt: &RttEstimate) -> Option<Instant> {
         self.spaces
             .iter()
-            .filter_map(LossRecoverySpace::loss_recovery_timer_start)
+            .filter_map(|sp| {
+                sp.loss_recovery_timer_start()
+                    .map(|val| val + sp.loss_delay(rtt))
+            })
             .min()
-            .map(|val| val + rtt.loss_delay())
     }
 
     /// Simple wrapper for the PTO calculation that avoids borrow check rules.
@@ -983,11 +1029,11 @@ impl Loss {
             qlog::loss_timer_expired(&mut self.qlog, timer_type, now);
         }
 
-        let loss_delay = primary_path.borrow().rtt().loss_delay();
         let confirmed = self.confirmed();
 
         let mut lost_packets = Vec::new();
         for space in self.spaces.iter_mut() {
+            let loss_delay = space.loss_delay(primary_path.borrow().rtt());
             let first = lost_packets.len(); // The first packet lost in this space.
             let pto = Self::pto_period_inner(
                 primary_path.borrow().rtt(),
@@ -996,6 +1042,7 @@ impl Loss {
                 self.fast_pto,
             );
             space.detect_lost_packets(now, loss_delay, pto, &mut lost_packets);
+            space.report_reordering(&mut self.qlog, &self.stats, now);
 
             primary_path.borrow_mut().on_packets_lost(
                 space.largest_acked_sent_time,
@@ -1074,7 +1121,7 @@ mod tests {
 
     use super::{
         ACK_ONLY_SIZE_LIMIT, FAST_PTO_SCALE, LossRecoverySpace, MIN_OUTSTANDING_UNACK,
-        PacketNumberSpace, PtoState, SendProfile,
+        PacketNumberSpace, PtoState, SendProfile, TIME_THRESHOLD,
     };
     use crate::{
         ConnectionParameters, Token as Srt,
@@ -1082,6 +1129,7 @@ mod tests {
         ecn, packet,
         path::{Path, PathRef},
         recovery::{self, MAX_PTO_PACKET_COUNT, sent},
+        rtt::RttEstimate,
         stats::{Stats, StatsCell},
         tracking::PacketNumberSpaceSet,
     };
@@ -1163,7 +1211,7 @@ mod tests {
             path.set_primary(true, now());
             path.rtt_mut().set_initial(TEST_RTT);
             Self {
-                lr: recovery::Loss::new(stats, FAST_PTO_SCALE),
+                lr: recovery::Loss::new(stats, FAST_PTO_SCALE, false),
                 path: Rc::new(RefCell::new(path)),
             }
         }
@@ -1298,6 +1346,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn adaptive_reordering() {
+        let mut lrs = LossRecoverySpace::new(PacketNumberSpace::ApplicationData);
+        lrs.reordering.enable();
+        let mut stats = Stats::default();
+        add_sent(&mut lrs, 10);
+
+        // Acknowledging 10 means that 0..=7 are lost by the packet threshold.
+        let (acked, _) = lrs.remove_acked(vec![10..=10], &mut stats);
+        match_acked(&acked, &[10]);
+        lrs.largest_acked = Some(10);
+        let mut lost = Vec::new();
+        lrs.detect_lost_packets(pn_time(10), ms(1_000), ms(1_000), &mut lost);
+        assert_eq!(lost.len(), 8);
+        stats.lost = lost.len();
+        stats.bytes_lost = lost.iter().map(sent::Packet::len).sum();
+
+        // Then 5 arrives, so the loss was spurious and the thresholds are widened.
+        let (acked, _) = lrs.remove_acked(vec![5..=5], &mut stats);
+        match_acked(&acked, &[5]);
+        assert_eq!(stats.late_ack, 1);
+        assert_eq!(lrs.reordering.packet_threshold(), 6);
+        assert_eq!(lrs.reordering.time_threshold(), TIME_THRESHOLD + 2);
+        let rtt = RttEstimate::new(TEST_RTT);
+        assert!(lrs.loss_delay(&rtt) > rtt.loss_delay());
+
+        let stats = StatsCell::default();
+        lrs.report_reordering(&mut Qlog::default(), &stats, pn_time(10));
+        assert_eq!(stats.borrow().reordering_adaptations, 1);
+    }
+
     #[test]
     fn remove_acked() {
         let mut lrs = LossRecoverySpace::new(PacketNumberSpace::ApplicationData);
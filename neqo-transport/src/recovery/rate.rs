@@ -0,0 +1,353 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Delivery rate estimation, as specified in draft-cheng-iccrg-delivery-rate-estimation.
+
+use std::{
+    cmp::max,
+    time::{Duration, Instant},
+};
+
+use neqo_common::to_u64;
+
+use crate::{recovery::sent, rtt::RttEstimate};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// The delivery state of the connection when a packet was sent.
+#[derive(Debug, Clone, Copy)]
+pub struct DeliveryState {
+    /// The bytes delivered when the packet was sent.
+    delivered: usize,
+    /// The time of the most recent delivery when the packet was sent.
+    delivered_time: Instant,
+    /// The send time of the packet that was most recently acknowledged when the packet was sent.
+    first_sent_time: Instant,
+    /// Whether the sender was app-limited when the packet was sent.
+    is_app_limited: bool,
+    /// The bytes in flight when the packet was sent, including the packet itself.
+    tx_in_flight: usize,
+    /// The bytes lost when the packet was sent.
+    lost: usize,
+}
+
+impl DeliveryState {
+    #[must_use]
+    pub const fn is_app_limited(&self) -> bool {
+        self.is_app_limited
+    }
+
+    #[must_use]
+    pub const fn tx_in_flight(&self) -> usize {
+        self.tx_in_flight
+    }
+
+    #[must_use]
+    pub const fn lost(&self) -> usize {
+        self.lost
+    }
+}
+
+/// A sample of the delivery rate, taken from the most recently sent packet
+/// that an ACK acknowledges.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateSample {
+    delivery_rate: Option<u64>,
+    is_app_limited: bool,
+    delivered: usize,
+    prior_delivered: usize,
+    tx_in_flight: usize,
+    lost: usize,
+    newly_acked: usize,
+    rtt: Option<Duration>,
+}
+
+impl RateSample {
+    /// The delivery rate in bytes per second.  This is `None` if the ACK did
+    /// not acknowledge a packet that was in flight, or if the sampling interval
+    /// was too short to produce a reliable sample.
+    #[must_use]
+    pub const fn delivery_rate(&self) -> Option<u64> {
+        self.delivery_rate
+    }
+
+    /// Whether the application did not have enough data to fill the pipe
+    /// when the sampled packet was sent.  App-limited samples can
+    /// underestimate the capacity of the path.
+    #[must_use]
+    pub const fn is_app_limited(&self) -> bool {
+        self.is_app_limited
+    }
+
+    /// The bytes delivered over the sampling interval.
+    #[must_use]
+    pub const fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// The bytes delivered when the sampled packet was sent.
+    #[must_use]
+    pub const fn prior_delivered(&self) -> usize {
+        self.prior_delivered
+    }
+
+    /// The bytes in flight when the sampled packet was sent, including that packet.
+    #[must_use]
+    pub const fn tx_in_flight(&self) -> usize {
+        self.tx_in_flight
+    }
+
+    /// The bytes lost over the sampling interval.
+    #[must_use]
+    pub const fn lost(&self) -> usize {
+        self.lost
+    }
+
+    /// The bytes newly acknowledged by this ACK.
+    #[must_use]
+    pub const fn newly_acked(&self) -> usize {
+        self.newly_acked
+    }
+
+    /// The RTT of the sampled packet, if there is one.
+    #[must_use]
+    pub const fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
+
+/// Tracks the delivery state of a path and produces [`RateSample`]s from ACKs.
+#[derive(Debug)]
+pub struct Estimator {
+    /// The total bytes delivered.
+    delivered: usize,
+    /// The time of the most recent delivery.
+    delivered_time: Instant,
+    /// The send time of the packet that was most recently acknowledged.
+    first_sent_time: Instant,
+    /// The total bytes lost.
+    lost: usize,
+    /// If set, the sender is app-limited until more than this many bytes are delivered.
+    app_limited: Option<usize>,
+}
+
+impl Estimator {
+    #[must_use]
+    pub const fn new(now: Instant) -> Self {
+        Self {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            lost: 0,
+            app_limited: None,
+        }
+    }
+
+    /// The total bytes delivered.
+    #[cfg(test)]
+    #[must_use]
+    pub const fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// Whether the sender is currently app-limited.
+    #[must_use]
+    pub const fn is_app_limited(&self) -> bool {
+        self.app_limited.is_some()
+    }
+
+    /// Record that the sender has no data to send even though the congestion
+    /// controller and pacer would allow it.  Packets sent until the bytes
+    /// currently in flight are delivered produce app-limited samples.
+    pub fn on_app_limited(&mut self, bytes_in_flight: usize) {
+        self.app_limited = Some(max(self.delivered + bytes_in_flight, 1));
+    }
+
+    /// Record the delivery state in `pkt`, which is about to be sent with
+    /// `bytes_in_flight` bytes already in flight.
+    pub const fn on_packet_sent(&mut self, pkt: &mut sent::Packet, bytes_in_flight: usize) {
+        if !pkt.cc_in_flight() {
+            return;
+        }
+        if bytes_in_flight == 0 {
+            // Don't count an idle period as part of the sampling interval.
+            self.first_sent_time = pkt.time_sent();
+            self.delivered_time = pkt.time_sent();
+        }
+        pkt.set_delivery_state(DeliveryState {
+            delivered: self.delivered,
+            delivered_time: self.delivered_time,
+            first_sent_time: self.first_sent_time,
+            is_app_limited: self.is_app_limited(),
+            tx_in_flight: bytes_in_flight + pkt.len(),
+            lost: self.lost,
+        });
+    }
+
+    /// Account for newly acknowledged packets and generate a rate sample from
+    /// the most recently sent of them.
+    pub fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rtt_est: &RttEstimate,
+        now: Instant,
+    ) -> RateSample {
+        let mut rs = RateSample::default();
+        let mut newest: Option<(&sent::Packet, DeliveryState)> = None;
+        for pkt in acked_pkts {
+            let Some(state) = pkt.delivery_state() else {
+                continue;
+            };
+            self.delivered += pkt.len();
+            self.delivered_time = now;
+            rs.newly_acked += pkt.len();
+            if newest.is_none_or(|(p, s)| {
+                (pkt.time_sent(), state.delivered) > (p.time_sent(), s.delivered)
+            }) {
+                newest = Some((pkt, state));
+            }
+        }
+        if self.app_limited.is_some_and(|limit| self.delivered > limit) {
+            self.app_limited = None;
+        }
+
+        let Some((pkt, state)) = newest else {
+            return rs;
+        };
+        self.first_sent_time = pkt.time_sent();
+        // Use the longer of the send and ACK intervals, to avoid overestimating
+        // the delivery rate because of ACK compression.
+        let send_elapsed = pkt
+            .time_sent()
+            .saturating_duration_since(state.first_sent_time);
+        let ack_elapsed = self
+            .delivered_time
+            .saturating_duration_since(state.delivered_time);
+        let interval = max(send_elapsed, ack_elapsed);
+        rs.delivered = self.delivered - state.delivered;
+        rs.prior_delivered = state.delivered;
+        rs.is_app_limited = state.is_app_limited;
+        rs.tx_in_flight = state.tx_in_flight;
+        rs.lost = self.lost.saturating_sub(state.lost);
+        rs.rtt = Some(now.saturating_duration_since(pkt.time_sent()));
+        // Intervals shorter than the minimum RTT produce unreliable samples.
+        let ns = interval.as_nanos();
+        rs.delivery_rate = (ns > 0 && interval >= rtt_est.minimum()).then(|| {
+            u64::try_from(u128::from(to_u64(rs.delivered)) * NANOS_PER_SEC / ns).unwrap_or(u64::MAX)
+        });
+        rs
+    }
+
+    /// Account for lost packets.
+    pub fn on_packets_lost(&mut self, lost_packets: &[sent::Packet]) {
+        // Lost PMTUD probes say nothing about the capacity of the path.
+        self.lost += lost_packets
+            .iter()
+            .filter(|p| p.delivery_state().is_some() && !p.is_pmtud_probe())
+            .map(sent::Packet::len)
+            .sum::<usize>();
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{slice, time::Duration};
+
+    use test_fixture::now;
+
+    use super::Estimator;
+    use crate::{recovery::sent, rtt::RttEstimate};
+
+    const RTT: Duration = Duration::from_millis(100);
+    const LEN: usize = 1000;
+
+    fn send(rate: &mut Estimator, pn: u64, bytes_in_flight: usize, t: Duration) -> sent::Packet {
+        let mut pkt = sent::make_packet(pn, now() + t, LEN);
+        rate.on_packet_sent(&mut pkt, bytes_in_flight);
+        pkt
+    }
+
+    #[test]
+    fn delivery_rate() {
+        let mut rate = Estimator::new(now());
+        let rtt = RttEstimate::new(RTT);
+        // Send ten packets spaced 10ms apart, so that each is acknowledged one RTT later.
+        let pkts: Vec<_> = (0..10)
+            .map(|i| {
+                let t = RTT / 10 * u32::try_from(i).unwrap();
+                send(&mut rate, i, usize::try_from(i).unwrap() * LEN, t)
+            })
+            .collect();
+        for (i, pkt) in pkts.iter().enumerate() {
+            let rs = rate.on_packets_acked(slice::from_ref(pkt), &rtt, pkt.time_sent() + RTT);
+            assert_eq!(rs.newly_acked(), LEN);
+            assert_eq!(rs.prior_delivered(), 0);
+            assert_eq!(rs.delivered(), (i + 1) * LEN);
+            assert_eq!(rs.tx_in_flight(), (i + 1) * LEN);
+            assert_eq!(rs.rtt(), Some(RTT));
+            assert!(!rs.is_app_limited());
+            assert!(rs.delivery_rate().is_some());
+        }
+        assert_eq!(rate.delivered(), 10 * LEN);
+
+        // The next packet samples the delivery rate over a full round trip.
+        let pkt = send(&mut rate, 10, 0, RTT * 2);
+        let rs = rate.on_packets_acked(&[pkt], &rtt, now() + RTT * 3);
+        assert_eq!(rs.prior_delivered(), 10 * LEN);
+        assert_eq!(rs.delivered(), LEN);
+        assert_eq!(rs.delivery_rate(), Some(10_000));
+    }
+
+    #[test]
+    fn no_sample_without_delivery_state() {
+        let mut rate = Estimator::new(now());
+        let pkt = sent::make_packet(0, now(), LEN);
+        let rs = rate.on_packets_acked(&[pkt], &RttEstimate::new(RTT), now() + RTT);
+        assert_eq!(rs.newly_acked(), 0);
+        assert_eq!(rs.rtt(), None);
+        assert_eq!(rs.delivery_rate(), None);
+        assert_eq!(rate.delivered(), 0);
+    }
+
+    #[test]
+    fn app_limited() {
+        let mut rate = Estimator::new(now());
+        let rtt = RttEstimate::new(RTT);
+        let first = send(&mut rate, 0, 0, Duration::ZERO);
+        rate.on_app_limited(LEN);
+        assert!(rate.is_app_limited());
+        let second = send(&mut rate, 1, LEN, Duration::ZERO);
+        assert!(!first.delivery_state().unwrap().is_app_limited());
+        assert!(second.delivery_state().unwrap().is_app_limited());
+
+        let rs = rate.on_packets_acked(&[first], &rtt, now() + RTT);
+        assert!(!rs.is_app_limited());
+        // The pipe has not drained yet.
+        assert!(rate.is_app_limited());
+        let rs = rate.on_packets_acked(&[second], &rtt, now() + RTT);
+        assert!(rs.is_app_limited());
+        assert!(!rate.is_app_limited());
+    }
+
+    #[test]
+    fn lost_bytes() {
+        let mut rate = Estimator::new(now());
+        let rtt = RttEstimate::new(RTT);
+        let lost = send(&mut rate, 0, 0, Duration::ZERO);
+        let acked = send(&mut rate, 1, LEN, Duration::ZERO);
+        rate.on_packets_lost(&[lost]);
+        let rs = rate.on_packets_acked(&[acked], &rtt, now() + RTT);
+        assert_eq!(rs.lost(), LEN);
+        assert_eq!(rs.tx_in_flight(), 2 * LEN);
+
+        // Packets sent after the loss don't see it.
+        let pkt = send(&mut rate, 2, 0, RTT);
+        assert_eq!(pkt.delivery_state().unwrap().lost(), LEN);
+        let rs = rate.on_packets_acked(&[pkt], &rtt, now() + RTT * 2);
+        assert_eq!(rs.lost(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Adaptive reordering thresholds for loss detection, following the approach that
+// RACK-TLP (RFC 8985) takes.  When a packet that was declared lost is later
+// acknowledged, the loss was spurious and caused by reordering, so the thresholds
+// are widened.  If enough losses happen without any being spurious, the thresholds
+// are reset, so that a burst of reordering doesn't slow loss detection forever.
+
+use std::{cmp::min, time::Duration};
+
+use neqo_common::qdebug;
+
+use crate::{
+    packet,
+    recovery::{PACKET_THRESHOLD, TIME_THRESHOLD, sent},
+    rtt::RttEstimate,
+};
+
+/// The largest time threshold, in eighths of an RTT.
+/// RACK limits the reordering window to one smoothed RTT; this allows one more than that
+/// as the QUIC time threshold already includes an RTT.
+const MAX_TIME_THRESHOLD: u32 = 16;
+/// How much the time threshold grows on each spurious loss, in eighths of an RTT.
+/// RACK increases the reordering window in units of a quarter of the minimum RTT.
+const TIME_THRESHOLD_STEP: u32 = 2;
+/// The largest packet reordering threshold.
+const MAX_PACKET_THRESHOLD: u64 = 32;
+/// The number of loss events without a spurious loss before the thresholds are reset.
+const RESET_AFTER: usize = 16;
+
+#[derive(Debug)]
+pub struct Reordering {
+    /// Whether the thresholds adapt to reordering.
+    adaptive: bool,
+    /// The number of packets that must be acknowledged after a packet before it is lost.
+    packet_threshold: u64,
+    /// The time after which a packet is lost, in eighths of an RTT.
+    time_threshold: u32,
+    /// The largest packet number acknowledged when the time threshold was last widened.
+    /// The time threshold is widened at most once per round trip.
+    widened_at: Option<packet::Number>,
+    /// The number of loss events since the thresholds were last widened.
+    losses: usize,
+    /// Whether the thresholds changed since they were last reported.
+    updated: bool,
+}
+
+impl Default for Reordering {
+    fn default() -> Self {
+        Self {
+            adaptive: false,
+            packet_threshold: PACKET_THRESHOLD,
+            time_threshold: TIME_THRESHOLD,
+            widened_at: None,
+            losses: 0,
+            updated: false,
+        }
+    }
+}
+
+impl Reordering {
+    pub const fn enable(&mut self) {
+        self.adaptive = true;
+    }
+
+    #[must_use]
+    pub const fn packet_threshold(&self) -> u64 {
+        self.packet_threshold
+    }
+
+    /// The time threshold, in eighths of an RTT.
+    #[must_use]
+    pub const fn time_threshold(&self) -> u32 {
+        self.time_threshold
+    }
+
+    #[must_use]
+    const fn widened(&self) -> bool {
+        self.packet_threshold != PACKET_THRESHOLD || self.time_threshold != TIME_THRESHOLD
+    }
+
+    /// The time to wait before declaring a packet lost.
+    #[must_use]
+    pub fn loss_delay(&self, rtt: &RttEstimate) -> Duration {
+        rtt.loss_delay_scaled(self.time_threshold)
+    }
+
+    /// A packet that was declared lost has been acknowledged.  `largest_acked` is the
+    /// largest packet number that was acknowledged before this.
+    pub fn on_spurious_loss(&mut self, p: &sent::Packet, largest_acked: Option<packet::Number>) {
+        if !self.adaptive {
+            return;
+        }
+        let Some(largest_acked) = largest_acked.filter(|&la| la > p.pn()) else {
+            return;
+        };
+
+        // Enough to cover the reordering that was seen.
+        let mut changed = false;
+        let distance = min(largest_acked - p.pn() + 1, MAX_PACKET_THRESHOLD);
+        if distance > self.packet_threshold {
+            self.packet_threshold = distance;
+            changed = true;
+        }
+        if self.widened_at.is_none_or(|w| p.pn() > w) && self.time_threshold < MAX_TIME_THRESHOLD {
+            self.time_threshold = min(
+                self.time_threshold + TIME_THRESHOLD_STEP,
+                MAX_TIME_THRESHOLD,
+            );
+            self.widened_at = Some(largest_acked);
+            changed = true;
+        }
+        self.losses = 0;
+        if changed {
+            self.updated = true;
+            qdebug!(
+                "Reordering thresholds widened to {} packets, {}/8 RTT",
+                self.packet_threshold,
+                self.time_threshold
+            );
+        }
+    }
+
+    /// Packets were declared lost.
+    pub fn on_loss(&mut self) {
+        if !self.adaptive || !self.widened() {
+            return;
+        }
+        self.losses += 1;
+        if self.losses >= RESET_AFTER {
+            qdebug!("Reordering thresholds reset after {} losses", self.losses);
+            *self = Self {
+                adaptive: true,
+                updated: true,
+                ..Self::default()
+            };
+        }
+    }
+
+    /// If the thresholds changed since the last call, return the new packet and time
+    /// thresholds.
+    pub const fn take_update(&mut self) -> Option<(u64, u32)> {
+        if self.updated {
+            self.updated = false;
+            Some((self.packet_threshold, self.time_threshold))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::Duration;
+
+    use test_fixture::now;
+
+    use super::{MAX_PACKET_THRESHOLD, MAX_TIME_THRESHOLD, RESET_AFTER, Reordering};
+    use crate::{
+        packet,
+        recovery::{self, PACKET_THRESHOLD, TIME_THRESHOLD, sent},
+        rtt::RttEstimate,
+    };
+
+    fn pkt(pn: packet::Number) -> sent::Packet {
+        sent::Packet::new(
+            packet::Type::Short,
+            pn,
+            now(),
+            true,
+            recovery::Tokens::new(),
+            100,
+        )
+    }
+
+    fn adaptive() -> Reordering {
+        let mut r = Reordering::default();
+        r.enable();
+        r
+    }
+
+    #[test]
+    fn disabled() {
+        let mut r = Reordering::default();
+        r.on_spurious_loss(&pkt(1), Some(10));
+        assert_eq!(r.packet_threshold(), PACKET_THRESHOLD);
+        assert_eq!(r.time_threshold(), TIME_THRESHOLD);
+        assert_eq!(r.take_update(), None);
+    }
+
+    #[test]
+    fn widen() {
+        let mut r = adaptive();
+        r.on_spurious_loss(&pkt(1), Some(10));
+        assert_eq!(r.take_update(), Some((10, TIME_THRESHOLD + 2)));
+        assert_eq!(r.take_update(), None);
+
+        // The time threshold only grows once per round trip.
+        r.on_spurious_loss(&pkt(5), Some(12));
+        assert_eq!(r.time_threshold(), TIME_THRESHOLD + 2);
+        assert_eq!(r.take_update(), None);
+        r.on_spurious_loss(&pkt(11), Some(20));
+        assert_eq!(r.take_update(), Some((10, TIME_THRESHOLD + 4)));
+
+        // Both thresholds are capped.
+        for pn in 0..100 {
+            r.on_spurious_loss(&pkt(pn * 100), Some(pn * 100 + 1_000));
+        }
+        assert_eq!(r.packet_threshold(), MAX_PACKET_THRESHOLD);
+        assert_eq!(r.time_threshold(), MAX_TIME_THRESHOLD);
+    }
+
+    #[test]
+    fn reset() {
+        let mut r = adaptive();
+        // Losses don't count until the thresholds are widened.
+        r.on_loss();
+        assert_eq!(r.take_update(), None);
+
+        r.on_spurious_loss(&pkt(1), Some(10));
+        assert!(r.take_update().is_some());
+        for _ in 1..RESET_AFTER {
+            r.on_loss();
+        }
+        assert_eq!(r.take_update(), None);
+        r.on_loss();
+        assert_eq!(r.take_update(), Some((PACKET_THRESHOLD, TIME_THRESHOLD)));
+
+        // Still adaptive after a reset.
+        r.on_spurious_loss(&pkt(1), Some(10));
+        assert!(r.take_update().is_some());
+    }
+
+    #[test]
+    fn loss_delay() {
+        let r = adaptive();
+        let rtt = RttEstimate::new(Duration::from_millis(80));
+        assert_eq!(r.loss_delay(&rtt), rtt.loss_delay());
+    }
+}
@@ -12,7 +12,12 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{packet, recovery};
+use neqo_common::Ecn;
+
+use crate::{
+    packet,
+    recovery::{self, rate::DeliveryState},
+};
 
 /// The reason a packet was declared lost.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,6 +45,8 @@ pub struct Packet {
     loss_info: Option<LossInfo>,
     /// After a PTO, this is true when the packet has been released.
     pto: bool,
+    /// The delivery state of the path when the packet was sent, if it was in flight.
+    delivery: Option<DeliveryState>,
 
     len: usize,
 }
@@ -63,6 +70,7 @@ impl Packet {
             tokens,
             loss_info: None,
             pto: false,
+            delivery: None,
             len,
         }
     }
@@ -79,12 +87,14 @@ impl Packet {
         self.pn
     }
 
-    /// The ECN mark of the packet.
+    /// Whether the packet was sent with the ECN mark `ecn`.
     #[must_use]
-    pub fn ecn_marked_ect0(&self) -> bool {
-        self.tokens
-            .iter()
-            .any(|t| matches!(t, recovery::Token::EcnEct0))
+    pub fn ecn_marked(&self, ecn: Ecn) -> bool {
+        self.tokens.iter().any(|t| match t {
+            recovery::Token::EcnEct0 => ecn == Ecn::Ect0,
+            recovery::Token::EcnEct1 => ecn == Ecn::Ect1,
+            _ => false,
+        })
     }
 
     /// Returns `true` if this packet is a PMTUD probe.
@@ -130,6 +140,18 @@ impl Packet {
         &self.tokens
     }
 
+    /// The delivery state of the path when this packet was sent, used for
+    /// delivery rate estimation.
+    #[must_use]
+    pub const fn delivery_state(&self) -> Option<DeliveryState> {
+        self.delivery
+    }
+
+    /// Record the delivery state of the path as this packet is sent.
+    pub const fn set_delivery_state(&mut self, delivery: DeliveryState) {
+        self.delivery = Some(delivery);
+    }
+
     /// Clears the flag that had this packet on the primary path.
     /// Used when migrating to clear out state.
     pub const fn clear_primary_path(&mut self) {
@@ -322,7 +344,10 @@ mod tests {
     };
 
     use super::{LossTrigger, Packet, Packets};
-    use crate::{packet, recovery};
+    use crate::{
+        packet,
+        recovery::{self, rate::DeliveryState},
+    };
 
     const PACKET_GAP: Duration = Duration::from_secs(1);
     fn start_time() -> Instant {
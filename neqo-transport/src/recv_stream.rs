@@ -99,10 +99,23 @@ impl RecvStreams {
         self.has_ended = false;
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
     pub(crate) const fn set_ended(&mut self, ended: bool) {
         self.has_ended |= ended;
     }
 
+    #[allow(
+        clippy::allow_attributes,
+        clippy::missing_errors_doc,
+        reason = "OK here."
+    )]
+    pub fn get(&self, id: StreamId) -> Res<&RecvStream> {
+        self.streams.get(&id).ok_or(Error::InvalidStreamId)
+    }
+
     /// Read from a stream, noting when it ends.
     ///
     /// # Errors
@@ -117,6 +130,18 @@ impl RecvStreams {
         Ok((n, fin))
     }
 
+    /// Consume data from a stream without copying it, noting when it ends.
+    ///
+    /// # Errors
+    /// When the stream does not exist or has no more data.
+    pub fn consume(&mut self, stream_id: StreamId, n: usize) -> Res<(usize, bool)> {
+        let s = self.get_mut(stream_id)?;
+        let (n, fin) = s.consume(n)?;
+        let ended = s.is_ended();
+        self.set_ended(ended);
+        Ok((n, fin))
+    }
+
     /// Stop sending on a stream, noting when it ends.
     ///
     /// # Errors
@@ -491,6 +516,42 @@ impl RxStreamOrderer {
         copied
     }
 
+    /// The contiguous bytes at the start of the first buffered range, if they can be read.
+    /// Data that spans several ranges needs to be consumed before the next range is returned.
+    #[must_use]
+    pub fn peek(&self) -> &[u8] {
+        match self.data_ranges.first_key_value() {
+            Some((&start, data)) if start <= self.retired => {
+                // Conversion OK because this is what is held in this buffer.
+                &data[expect_usize(self.retired - start)..]
+            }
+            _ => &[],
+        }
+    }
+
+    /// Retire up to `n` readable bytes without copying them. Returns bytes retired.
+    pub fn consume(&mut self, n: usize) -> usize {
+        let mut consumed = 0;
+        while consumed < n
+            && let Some(e) = self.data_ranges.first_entry()
+            && *e.key() <= self.retired
+        {
+            // Conversion OK because this is what is held in this buffer.
+            let available = e.get().len() - expect_usize(self.retired - *e.key());
+            let retire = min(available, n - consumed);
+            consumed += retire;
+            self.retired += to_u64(retire);
+            if retire < available {
+                break;
+            }
+            e.remove();
+        }
+        if self.data_ranges.is_empty() {
+            self.end = self.retired;
+        }
+        consumed
+    }
+
     /// Extend the given Vector with any available data.
     pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> usize {
         let orig_len = buf.len();
@@ -1066,6 +1127,36 @@ impl RecvStream {
     /// # Errors
     /// `NoMoreData` if data and fin bit were previously read by the application.
     pub fn read(&mut self, buf: &mut [u8]) -> Res<(usize, bool)> {
+        self.retire_buffered(|recv_buf| recv_buf.read(buf))
+    }
+
+    /// The contiguous data at the start of the receive buffer, which is empty if nothing
+    /// can be read yet.  This might not be all of the data that [`Self::read`] would return.
+    ///
+    /// # Errors
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn peek(&self) -> Res<&[u8]> {
+        self.state
+            .recv_buf()
+            .map(RxStreamOrderer::peek)
+            .ok_or(Error::NoMoreData)
+    }
+
+    /// Mark up to `n` bytes as read, without copying them.  Flow control credit is released
+    /// for the bytes that are consumed, as with [`Self::read`].
+    ///
+    /// # Errors
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn consume(&mut self, n: usize) -> Res<(usize, bool)> {
+        self.retire_buffered(|recv_buf| recv_buf.consume(n))
+    }
+
+    /// Take data from the receive buffer using `f`, which returns the number of bytes taken,
+    /// then update flow control and the stream state to match.
+    fn retire_buffered(
+        &mut self,
+        f: impl FnOnce(&mut RxStreamOrderer) -> usize,
+    ) -> Res<(usize, bool)> {
         let data_recvd_state = matches!(self.state, RecvStreamState::DataRecvd { .. });
         match &mut self.state {
             RecvStreamState::Recv {
@@ -1084,7 +1175,7 @@ impl RecvStream {
                 fc,
                 session_fc,
             } => {
-                let bytes_read = recv_buf.read(buf);
+                let bytes_read = f(recv_buf);
                 Self::flow_control_retire_data(u64::try_from(bytes_read)?, fc, session_fc);
                 let fin_read = if data_recvd_state {
                     if recv_buf.buffered() == 0 {
@@ -1109,7 +1200,7 @@ impl RecvStream {
                 session_fc,
                 ..
             } => {
-                let bytes_read = recv_buf.read(buf);
+                let bytes_read = f(recv_buf);
                 Self::flow_control_retire_data(u64::try_from(bytes_read)?, fc, session_fc);
                 // Once the whole reliable prefix has been read, surface the reset. A reliable
                 // reset never delivers a FIN, so `fin_read` is always `false`.
@@ -1803,6 +1894,78 @@ mod tests {
         check_chunks(&s, &[(13, 5)]);
     }
 
+    #[test]
+    fn peek_consume() {
+        let mut s = RxStreamOrderer::new();
+        assert!(s.peek().is_empty());
+
+        s.inbound_frame(0, &[1; 5]);
+        s.inbound_frame(10, &[3; 5]);
+        assert_eq!(s.peek(), &[1; 5]);
+
+        // Partially consumed ranges are retained.
+        assert_eq!(s.consume(2), 2);
+        assert_eq!(s.peek(), &[1; 3]);
+        check_chunks(&s, &[(0, 5), (10, 5)]);
+
+        // Consuming stops at a gap.
+        assert_eq!(s.consume(100), 3);
+        assert!(s.peek().is_empty());
+        check_chunks(&s, &[(10, 5)]);
+
+        // Filling the gap makes the next range available, one range at a time.
+        s.inbound_frame(5, &[2; 5]);
+        assert_eq!(s.peek(), &[2; 5]);
+        assert_eq!(s.consume(7), 7);
+        assert_eq!(s.peek(), &[3; 3]);
+        assert_eq!(s.consume(3), 3);
+        assert!(s.data_ranges.is_empty());
+        assert_eq!(s.retired(), 15);
+
+        // New data is appended after the consumed data.
+        s.inbound_frame(15, &[4; 2]);
+        check_chunks(&s, &[(15, 2)]);
+        assert_eq!(s.peek(), &[4; 2]);
+    }
+
+    #[test]
+    fn stream_consume_to_end() {
+        let mut s = create_stream(1024 * to_u64(INITIAL_LOCAL_MAX_STREAM_DATA));
+        let big_buf = vec![7; INITIAL_LOCAL_MAX_STREAM_DATA];
+        let (first, rest) = big_buf.split_at(RxStreamOrderer::RANGE_TARGET);
+        s.inbound_stream_frame(false, 0, first).unwrap();
+        s.inbound_stream_frame(true, to_u64(first.len()), rest)
+            .unwrap();
+        assert_eq!(s.peek().unwrap(), first);
+
+        let mut consumed = 0;
+        let mut fin = false;
+        while !fin {
+            let n = s.peek().unwrap().len();
+            let (c, f) = s.consume(n).unwrap();
+            assert_eq!(c, n);
+            consumed += c;
+            fin = f;
+        }
+        assert_eq!(consumed, INITIAL_LOCAL_MAX_STREAM_DATA);
+        assert!(matches!(s.state, RecvStreamState::DataRead { .. }));
+        assert_eq!(s.peek(), Err(Error::NoMoreData));
+        assert_eq!(s.consume(1), Err(Error::NoMoreData));
+    }
+
+    #[test]
+    fn stream_consume_releases_credit() {
+        let mut s = create_stream(1024 * to_u64(INITIAL_LOCAL_MAX_STREAM_DATA));
+        let big_buf = vec![0; INITIAL_LOCAL_MAX_STREAM_DATA];
+        s.inbound_stream_frame(false, 0, &big_buf).unwrap();
+        assert_eq!(
+            s.consume(INITIAL_LOCAL_MAX_STREAM_DATA).unwrap(),
+            (INITIAL_LOCAL_MAX_STREAM_DATA, false)
+        );
+        assert!(!s.data_ready());
+        assert!(s.has_frames_to_write());
+    }
+
     #[test]
     fn stream_flowc_update() {
         let mut s = create_stream(1024 * to_u64(INITIAL_LOCAL_MAX_STREAM_DATA));
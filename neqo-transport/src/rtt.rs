@@ -167,10 +167,15 @@ impl RttEstimate {
     /// RTT measurement received.
     pub fn loss_delay(&self) -> Duration {
         // kTimeThreshold = 9/8
+        self.loss_delay_scaled(recovery::TIME_THRESHOLD)
+    }
+
+    /// Calculate the loss delay using a time threshold of `threshold` eighths of an RTT.
+    pub fn loss_delay_scaled(&self, threshold: u32) -> Duration {
         // loss_delay = kTimeThreshold * max(latest_rtt, smoothed_rtt)
         // loss_delay = max(loss_delay, kGranularity)
         let rtt = max(self.latest_rtt, self.smoothed_rtt);
-        max(rtt * 9 / 8, GRANULARITY)
+        max(rtt * threshold / 8, GRANULARITY)
     }
 
     pub const fn first_sample_time(&self) -> Option<Instant> {
@@ -9,12 +9,13 @@
 use std::{
     cell::RefCell,
     cmp::{Ordering, max, min},
-    collections::{BTreeMap, VecDeque, btree_map::Entry},
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map::Entry},
     fmt::{self, Display, Formatter},
-    mem,
+    iter, mem,
     num::NonZeroUsize,
-    ops::Add,
+    ops::{Add, Deref, Range},
     rc::Rc,
+    time::Instant,
 };
 
 use indexmap::IndexMap;
@@ -461,11 +462,95 @@ impl RangeTracker {
     }
 }
 
+/// A reference-counted buffer of stream data, for sending without a copy.
+///
+/// This holds part of a shared buffer.  As a stream accepts data from the
+/// front of the chunk, what remains shrinks, so a chunk that a stream only
+/// accepts part of can be offered again later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendChunk {
+    data: Rc<[u8]>,
+    range: Range<usize>,
+}
+
+impl SendChunk {
+    #[must_use]
+    pub fn new(data: Rc<[u8]>) -> Self {
+        let range = 0..data.len();
+        Self { data, range }
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.range.end - self.range.start
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take `n` bytes from the front of the chunk, sharing the underlying buffer.
+    fn split_to(&mut self, n: usize) -> Self {
+        debug_assert!(n <= self.len());
+        let front = Self {
+            data: Rc::clone(&self.data),
+            range: self.range.start..self.range.start + n,
+        };
+        self.range.start += n;
+        front
+    }
+}
+
+impl Deref for SendChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data[self.range.clone()]
+    }
+}
+
+impl From<Rc<[u8]>> for SendChunk {
+    fn from(data: Rc<[u8]>) -> Self {
+        Self::new(data)
+    }
+}
+
+impl From<Vec<u8>> for SendChunk {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(Rc::from(data))
+    }
+}
+
+/// The data in a [`TxBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+enum TxChunk {
+    /// Bytes that were copied into the buffer.
+    Copied(Vec<u8>),
+    /// Bytes that are shared with the application.
+    Shared(SendChunk),
+}
+
+impl Deref for TxChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Copied(v) => v,
+            Self::Shared(c) => c,
+        }
+    }
+}
+
 /// Buffer to contain queued bytes and track their state.
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct TxBuffer {
-    send_buf: VecDeque<u8>, // buffer of not-acked bytes
-    ranges: RangeTracker,   // ranges in buffer that have been sent or acked
+    /// Chunks of bytes that are not acked, each with the stream offset of
+    /// its first byte.  A chunk is only dropped once all of it is acked.
+    chunks: VecDeque<(u64, TxChunk)>,
+    /// The number of bytes that are not retired.
+    buffered: usize,
+    ranges: RangeTracker, // ranges in buffer that have been sent or acked
 }
 
 const_assert!(MAX_LOCAL_MAX_STREAM_DATA <= to_u64(usize::MAX));
@@ -481,6 +566,11 @@ impl TxBuffer {
     )]
     pub const MAX_SIZE: usize = MAX_LOCAL_MAX_STREAM_DATA as usize;
 
+    /// Copied bytes are added to the last chunk until it reaches this size.  Chunks
+    /// are only freed once they are acked in full, so this limits how many acked
+    /// bytes are kept when there are many small writes.
+    const COPY_CHUNK_SIZE: usize = 16_384;
+
     #[must_use]
     pub fn new() -> Self {
         Self::default()
@@ -488,18 +578,40 @@ impl TxBuffer {
 
     /// Attempt to add some or all of the passed-in buffer to the `TxBuffer`.
     pub fn send(&mut self, buf: &[u8]) -> usize {
-        let can_buffer = min(Self::MAX_SIZE - self.buffered(), buf.len());
+        let can_buffer = min(self.avail(), buf.len());
+        let mut buf = &buf[..can_buffer];
+        if let Some((_, TxChunk::Copied(v))) = self.chunks.back_mut() {
+            let room = Self::COPY_CHUNK_SIZE.saturating_sub(v.len());
+            let (head, tail) = buf.split_at(min(room, buf.len()));
+            v.extend_from_slice(head);
+            self.buffered += head.len();
+            buf = tail;
+        }
+        for piece in buf.chunks(Self::COPY_CHUNK_SIZE) {
+            self.chunks
+                .push_back((self.used(), TxChunk::Copied(piece.to_vec())));
+            self.buffered += piece.len();
+        }
+        debug_assert!(self.buffered() <= Self::MAX_SIZE);
+        can_buffer
+    }
+
+    /// Attempt to add some or all of `chunk` to the `TxBuffer`, without copying.
+    /// The bytes that are added are removed from the front of `chunk`.
+    pub fn send_chunk(&mut self, chunk: &mut SendChunk) -> usize {
+        let can_buffer = min(self.avail(), chunk.len());
         if can_buffer > 0 {
-            self.send_buf.extend(&buf[..can_buffer]);
-            debug_assert!(self.send_buf.len() <= Self::MAX_SIZE);
+            self.chunks
+                .push_back((self.used(), TxChunk::Shared(chunk.split_to(can_buffer))));
+            self.buffered += can_buffer;
+            debug_assert!(self.buffered() <= Self::MAX_SIZE);
         }
         can_buffer
     }
 
     fn first_unmarked_range(&mut self) -> Option<(u64, Option<u64>)> {
         let (start, maybe_len) = self.ranges.first_unmarked_range();
-        let buffered = to_u64(self.buffered());
-        (start != self.retired() + buffered).then_some((start, maybe_len))
+        (start != self.used()).then_some((start, maybe_len))
     }
 
     pub fn is_empty(&mut self) -> bool {
@@ -519,18 +631,13 @@ impl TxBuffer {
     pub fn next_bytes(&mut self) -> Option<(u64, &[u8])> {
         let (start, maybe_len) = self.first_unmarked_range()?;
 
-        // Convert from ranges-relative-to-zero to
-        // ranges-relative-to-buffer-start
-        // Conversion is safe because the delta is bounded by send_buf size.
-        let buff_off = expect_usize(start - self.retired());
-
-        // Deque returns two slices. Create a subslice from whichever
-        // one contains the first unmarked data.
-        let slc = if buff_off < self.send_buf.as_slices().0.len() {
-            &self.send_buf.as_slices().0[buff_off..]
-        } else {
-            &self.send_buf.as_slices().1[buff_off - self.send_buf.as_slices().0.len()..]
-        };
+        // Find the chunk that contains the first unmarked byte and
+        // create a subslice from there to the end of that chunk.
+        let i = self.chunks.partition_point(|(off, _)| *off <= start);
+        debug_assert!(i > 0);
+        let (chunk_off, chunk) = &self.chunks[i - 1];
+        // Conversion is safe because the delta is bounded by the chunk size.
+        let slc = &chunk[expect_usize(start - chunk_off)..];
 
         let len = maybe_len.map_or(slc.len(), |range_len| {
             // Safe conversion because of a min over a usize value.
@@ -556,11 +663,20 @@ impl TxBuffer {
         let prev_retired = self.retired();
         self.ranges.mark_acked(offset, len);
 
-        // Any newly-retired bytes can be dropped from the buffer.
         // No way this can fail because we have to hold this range in our buffer.
         let new_retirable = expect_usize(self.retired() - prev_retired);
         debug_assert!(new_retirable <= self.buffered());
-        self.send_buf.drain(..new_retirable);
+        self.buffered -= new_retirable;
+
+        // Any chunks that are now entirely retired can be dropped from the buffer.
+        let retired = self.retired();
+        while self
+            .chunks
+            .front()
+            .is_some_and(|(off, chunk)| off + to_u64(chunk.len()) <= retired)
+        {
+            self.chunks.pop_front();
+        }
     }
 
     pub fn mark_as_lost(&mut self, offset: u64, len: usize) {
@@ -577,8 +693,8 @@ impl TxBuffer {
         self.ranges.acked_from_zero()
     }
 
-    fn buffered(&self) -> usize {
-        self.send_buf.len()
+    const fn buffered(&self) -> usize {
+        self.buffered
     }
 
     fn avail(&self) -> usize {
@@ -738,6 +854,11 @@ pub struct SendStream {
     fair: bool,
     send_group: Option<SendGroupId>,
     writable_event_low_watermark: NonZeroUsize,
+    /// Deadlines for written data, in order of the offset from which each applies.
+    /// See [`Self::set_deadline`].
+    deadlines: VecDeque<(u64, Option<Instant>)>,
+    /// The error code for a reset when a deadline passes.
+    expiry_error: AppError,
 }
 
 impl SendStream {
@@ -762,6 +883,8 @@ impl SendStream {
             fair: false,
             send_group: None,
             writable_event_low_watermark: NonZeroUsize::MIN,
+            deadlines: VecDeque::new(),
+            expiry_error: 0,
         };
         if ss.avail() > 0 {
             ss.conn_events.send_stream_writable(stream_id);
@@ -1371,6 +1494,7 @@ impl SendStream {
             }
             _ => qtrace!("[{self}] mark_as_acked called from state {:?}", self.state),
         }
+        self.prune_deadlines();
     }
 
     #[allow(
@@ -1463,8 +1587,26 @@ impl SendStream {
         }
     }
 
+    /// Take ownership of some or all of `chunk`, without copying.  The bytes that
+    /// are accepted are removed from the front of `chunk`.
+    ///
+    /// # Errors
+    /// When `chunk` is empty or when the stream is already closed.
+    pub fn send_chunk(&mut self, chunk: &mut SendChunk) -> Res<usize> {
+        let len = self.send_space(chunk.len(), false)?;
+        self.buffer(len, |send_buf| {
+            send_buf.send_chunk(&mut chunk.split_to(len))
+        })
+    }
+
     fn send_internal(&mut self, buf: &[u8], atomic: bool) -> Res<usize> {
-        if buf.is_empty() {
+        let len = self.send_space(buf.len(), atomic)?;
+        self.buffer(len, |send_buf| send_buf.send(&buf[..len]))
+    }
+
+    /// Determine how many of `len` bytes can be sent now.
+    fn send_space(&mut self, len: usize, atomic: bool) -> Res<usize> {
+        if len == 0 {
             qerror!("[{self}] zero-length send on stream");
             return Err(Error::InvalidInput);
         }
@@ -1484,28 +1626,33 @@ impl SendStream {
             return Err(Error::FinalSize);
         }
 
-        let buf = if self.avail() == 0 {
-            return Ok(0);
-        } else if self.avail() < buf.len() {
+        let avail = self.avail();
+        if avail == 0 {
+            Ok(0)
+        } else if avail < len {
             if atomic {
-                self.send_blocked_if_space_needed(buf.len());
+                self.send_blocked_if_space_needed(len);
                 return Ok(0);
             }
-
-            &buf[..self.avail()]
+            Ok(avail)
         } else {
-            buf
-        };
+            Ok(len)
+        }
+    }
 
+    /// Add `len` bytes to the send buffer using `f`, which returns how many it added.
+    fn buffer<F: FnOnce(&mut TxBuffer) -> usize>(&mut self, len: usize, f: F) -> Res<usize> {
+        if len == 0 {
+            return Ok(0);
+        }
         match &mut self.state {
-            State::Ready { .. } => unreachable!(),
             State::Send {
                 fc,
                 conn_fc,
                 send_buf,
                 ..
             } => {
-                let sent = send_buf.send(buf);
+                let sent = f(send_buf);
                 fc.consume(sent);
                 conn_fc.borrow_mut().consume(sent);
                 Ok(sent)
@@ -1579,6 +1726,104 @@ impl SendStream {
         }
     }
 
+    /// Set a deadline for data that is written to the stream after this, or `None` for no
+    /// deadline.  If any of that data is not acknowledged by `deadline`, it is no longer
+    /// retransmitted and the stream is reset with error `err`.  The reset is sent as a
+    /// `RESET_STREAM_AT` that still delivers the data before the expired data.
+    /// The caller is responsible for ensuring that their peer supports this feature.
+    ///
+    /// # Errors
+    /// [`Error::FinalSize`] when the stream is already closed or reset.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>, err: AppError) -> Res<()> {
+        let offset = match &self.state {
+            State::Ready { .. } => 0,
+            State::Send { send_buf, .. } => send_buf.used(),
+            _ => return Err(Error::FinalSize),
+        };
+        // A deadline that was set without writing any data is replaced.
+        if self.deadlines.back().is_some_and(|&(o, _)| o == offset) {
+            self.deadlines.pop_back();
+        }
+        if self
+            .deadlines
+            .back()
+            .map_or(deadline.is_some(), |&(_, d)| d != deadline)
+        {
+            self.deadlines.push_back((offset, deadline));
+        }
+        self.expiry_error = err;
+        Ok(())
+    }
+
+    /// The offset of the first unacknowledged byte and the deadline of each range of data
+    /// that has a deadline and that isn't acknowledged yet.
+    fn pending_deadlines(&self) -> impl Iterator<Item = (u64, Instant)> + '_ {
+        let (retired, used) = match &self.state {
+            State::Send { send_buf, .. } | State::DataSent { send_buf, .. } => {
+                (send_buf.retired(), send_buf.used())
+            }
+            _ => (0, 0),
+        };
+        let ends = self
+            .deadlines
+            .iter()
+            .skip(1)
+            .map(|&(o, _)| o)
+            .chain(iter::once(used));
+        self.deadlines
+            .iter()
+            .zip(ends)
+            .filter_map(move |(&(start, deadline), end)| {
+                let start = max(start, retired);
+                deadline.filter(|_| end > start).map(|d| (start, d))
+            })
+    }
+
+    /// Drop deadlines for data that has been acknowledged.
+    fn prune_deadlines(&mut self) {
+        let retired = match &self.state {
+            State::Send { send_buf, .. } | State::DataSent { send_buf, .. } => send_buf.retired(),
+            _ => {
+                self.deadlines.clear();
+                return;
+            }
+        };
+        while self.deadlines.get(1).is_some_and(|&(o, _)| o <= retired) {
+            self.deadlines.pop_front();
+        }
+    }
+
+    /// Whether any data, written or yet to be written, has a deadline.
+    fn has_deadlines(&self) -> bool {
+        matches!(
+            self.state,
+            State::Ready { .. } | State::Send { .. } | State::DataSent { .. }
+        ) && self.deadlines.iter().any(|&(_, d)| d.is_some())
+    }
+
+    /// When data next expires, if it has a deadline.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending_deadlines().map(|(_, d)| d).min()
+    }
+
+    /// Reset the stream if data was not acknowledged by its deadline.  The data before
+    /// the first range of expired data is still delivered.  Returns `true` if the stream
+    /// was reset.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let Some((offset, _)) = self.pending_deadlines().find(|&(_, d)| d <= now) else {
+            return false;
+        };
+        qdebug!("[{self}] data from offset {offset} expired");
+        if let State::Send { committed, .. } | State::DataSent { committed, .. } = &mut self.state {
+            *committed = max(*committed, offset);
+        }
+        self.reset(self.expiry_error);
+        self.deadlines.clear();
+        self.conn_events.send_stream_expired(self.stream_id);
+        true
+    }
+
     /// Reset the stream. When a non-zero commitment exists (set via [`Self::commit`], which is
     /// only reachable when the peer supports the feature), a `RESET_STREAM_AT` is emitted
     /// (reliably delivering `[0, reliable_size)`); otherwise a plain `RESET_STREAM` is sent.
@@ -1907,6 +2152,9 @@ pub struct SendStreams {
     // Groups are served round-robin; within a group sendOrder determines priority.
     /// Set when any stream has ended; cleared by `remove_ended`.
     has_ended: bool,
+    /// Streams that have had a deadline set, so that finding and expiring deadlines
+    /// doesn't need to visit every stream.  Pruned by `expire`.
+    with_deadlines: BTreeSet<StreamId>,
 
     per_group: IndexMap<SendGroupId, PerGroupQueues>,
     per_group_next: usize, // round-robin cursor over per_group entries
@@ -1947,6 +2195,10 @@ impl SendStreams {
         self.map.contains_key(&id)
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     pub fn insert(&mut self, id: StreamId, stream: SendStream) {
         self.map.insert(id, stream);
     }
@@ -2148,9 +2400,49 @@ impl SendStreams {
         }
     }
 
+    /// Set a deadline on a stream.  See [`SendStream::set_deadline`].
+    ///
+    /// # Errors
+    /// [`Error::InvalidStreamId`] when the stream doesn't exist, or [`Error::FinalSize`] when
+    /// it is already closed or reset.
+    pub fn set_deadline(
+        &mut self,
+        id: StreamId,
+        deadline: Option<Instant>,
+        err: AppError,
+    ) -> Res<()> {
+        let ss = self.map.get_mut(&id).ok_or(Error::InvalidStreamId)?;
+        ss.set_deadline(deadline, err)?;
+        if ss.has_deadlines() {
+            self.with_deadlines.insert(id);
+        }
+        Ok(())
+    }
+
+    /// When data on any stream next expires.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.with_deadlines
+            .iter()
+            .filter_map(|id| self.map.get(id))
+            .filter_map(SendStream::next_deadline)
+            .min()
+    }
+
+    /// Reset streams with data that was not acknowledged by its deadline.
+    pub fn expire(&mut self, now: Instant) {
+        self.with_deadlines.retain(|id| {
+            self.map.get_mut(id).is_some_and(|ss| {
+                ss.expire(now);
+                ss.has_deadlines()
+            })
+        });
+    }
+
     pub fn clear(&mut self) {
         self.map.clear();
         self.has_ended = false;
+        self.with_deadlines.clear();
         self.per_group.clear();
         self.per_group_next = 0;
         self.fair_rr_next = 0;
@@ -2166,6 +2458,7 @@ impl SendStreams {
         let mut removed = false;
         for (stream_id, stream) in self.map.extract_if(.., |_, s| s.is_ended()) {
             removed = true;
+            self.with_deadlines.remove(&stream_id);
             if stream.is_fair() {
                 let group_id = stream.send_group().unwrap_or(NULL_GROUP_ID);
                 if let Some(grp_queues) = self.per_group.get_mut(&group_id) {
@@ -2423,11 +2716,12 @@ pub struct RecoveryToken {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use std::{cell::RefCell, collections::VecDeque, num::NonZeroUsize, rc::Rc};
+    use std::{cell::RefCell, collections::VecDeque, num::NonZeroUsize, rc::Rc, time::Duration};
 
     use neqo_common::{
         Encoder, MAX_VARINT, event::Provider as _, expect_usize, hex::HexWithLen, qtrace, to_u64,
     };
+    use test_fixture::now;
 
     use super::RecoveryToken;
     use crate::{
@@ -2438,7 +2732,8 @@ mod tests {
         packet,
         recovery::{self, StreamRecoveryToken},
         send_stream::{
-            NULL_GROUP_ID, RangeState, RangeTracker, SendStream, SendStreams, State, TxBuffer,
+            NULL_GROUP_ID, RangeState, RangeTracker, SendChunk, SendStream, SendStreams, State,
+            TxBuffer,
         },
         stats::FrameStats,
         streams::SendGroupId,
@@ -4244,6 +4539,220 @@ mod tests {
         assert_eq!(txb.send(&[0x01]), 0);
     }
 
+    /// Copied and shared bytes are kept in separate chunks, in order.
+    #[test]
+    fn tx_buffer_chunks() {
+        let mut txb = TxBuffer::new();
+        assert_eq!(txb.send(&[1; 10]), 10);
+        let mut chunk = SendChunk::from(vec![2; 10]);
+        assert_eq!(txb.send_chunk(&mut chunk), 10);
+        assert!(chunk.is_empty());
+        assert_eq!(txb.send(&[3; 10]), 10);
+        assert_eq!(txb.buffered(), 30);
+
+        for (i, b) in [1, 2, 3].into_iter().enumerate() {
+            let (start, x) = txb.next_bytes().unwrap();
+            assert_eq!(start, to_u64(i * 10));
+            assert_eq!(x, &[b; 10]);
+            txb.mark_as_sent(start, x.len());
+        }
+        assert!(txb.next_bytes().is_none());
+
+        // Losing bytes from the middle of a chunk resends the rest of that chunk.
+        txb.mark_as_lost(15, 10);
+        let (start, x) = txb.next_bytes().unwrap();
+        assert_eq!((start, x), (15, &[2; 5][..]));
+    }
+
+    /// Copied bytes are split into chunks of no more than `COPY_CHUNK_SIZE` bytes.
+    #[test]
+    fn tx_buffer_copy_chunk_size() {
+        let mut txb = TxBuffer::new();
+        assert_eq!(txb.send(&[1; 10]), 10);
+        let len = TxBuffer::COPY_CHUNK_SIZE * 2;
+        assert_eq!(txb.send(&vec![2; len]), len);
+        assert_eq!(txb.buffered(), len + 10);
+        assert_eq!(txb.chunks.len(), 3);
+        assert!(
+            txb.chunks
+                .iter()
+                .all(|(_, c)| c.len() <= TxBuffer::COPY_CHUNK_SIZE)
+        );
+        assert_eq!(
+            txb.chunks.back().unwrap().0,
+            to_u64(TxBuffer::COPY_CHUNK_SIZE * 2)
+        );
+    }
+
+    /// A stream only holds on to a shared buffer until all of it is acked.
+    #[test]
+    fn send_chunk_released_when_acked() {
+        let data: Rc<[u8]> = Rc::from(vec![7; 150]);
+        let mut chunk = SendChunk::new(Rc::clone(&data));
+        let mut s = SendStream::new(
+            StreamId::new(0),
+            100,
+            connection_fc(4096),
+            ConnectionEvents::default(),
+        );
+
+        // Flow control limits what is taken.
+        assert_eq!(s.send_chunk(&mut chunk).unwrap(), 100);
+        assert_eq!(chunk.len(), 50);
+        assert_eq!(&chunk[..], &[7; 50]);
+        assert_eq!(s.send_chunk(&mut chunk).unwrap(), 0);
+        assert_eq!(Rc::strong_count(&data), 3);
+
+        s.mark_as_sent(0, 100, false);
+        s.mark_as_acked(0, 60, false);
+        assert_eq!(Rc::strong_count(&data), 3);
+        s.mark_as_acked(60, 40, false);
+        assert_eq!(Rc::strong_count(&data), 2);
+
+        drop(chunk);
+        assert_eq!(Rc::strong_count(&data), 1);
+    }
+
+    /// An empty chunk is rejected, like an empty slice.
+    #[test]
+    fn send_chunk_empty() {
+        let mut s = SendStream::new(
+            StreamId::new(0),
+            100,
+            connection_fc(4096),
+            ConnectionEvents::default(),
+        );
+        let mut chunk = SendChunk::from(Vec::new());
+        assert_eq!(s.send_chunk(&mut chunk).unwrap_err(), Error::InvalidInput);
+    }
+
+    const DEADLINE: Duration = Duration::from_millis(30);
+
+    /// Data that isn't acknowledged by its deadline resets the stream, and only the data
+    /// before it is delivered.
+    #[test]
+    fn deadline_expires() {
+        let conn_events = ConnectionEvents::default();
+        let mut s = SendStream::new(
+            StreamId::new(0),
+            100,
+            connection_fc(4096),
+            conn_events.clone(),
+        );
+        let start = now();
+        s.send(&[1; 10]).unwrap();
+        s.set_deadline(Some(start + DEADLINE), 7).unwrap();
+        s.send(&[2; 10]).unwrap();
+        s.set_deadline(None, 7).unwrap();
+        s.send(&[3; 10]).unwrap();
+        assert_eq!(s.next_deadline(), Some(start + DEADLINE));
+
+        s.mark_as_sent(0, 30, false);
+        assert!(!s.expire(start + DEADLINE - Duration::from_millis(1)));
+        assert!(s.expire(start + DEADLINE));
+        assert!(matches!(
+            s.state(),
+            State::ResetSentReliable {
+                reliable_size: 10,
+                err: 7,
+                ..
+            }
+        ));
+        assert_eq!(s.next_deadline(), None);
+        assert!(conn_events.events().any(|e| matches!(
+            e,
+            ConnectionEvent::SendStreamExpired { stream_id } if stream_id == StreamId::new(0)
+        )));
+        let stats = send_reset_frame(&mut s);
+        assert_eq!(stats.reset_stream_at, 1);
+    }
+
+    /// Data that is acknowledged before its deadline doesn't expire.
+    #[test]
+    fn deadline_acked() {
+        let mut s = SendStream::new(
+            StreamId::new(0),
+            100,
+            connection_fc(4096),
+            ConnectionEvents::default(),
+        );
+        let start = now();
+        s.set_deadline(Some(start + DEADLINE), 7).unwrap();
+        s.send(&[1; 10]).unwrap();
+        s.mark_as_sent(0, 10, false);
+        s.mark_as_acked(0, 10, false);
+        assert_eq!(s.next_deadline(), None);
+        assert!(!s.expire(start + DEADLINE));
+
+        // Data that is written later has the same deadline.
+        s.send(&[2; 10]).unwrap();
+        assert_eq!(s.next_deadline(), Some(start + DEADLINE));
+        assert!(s.expire(start + DEADLINE));
+        assert!(matches!(
+            s.state(),
+            State::ResetSent {
+                reliable_size: 10,
+                ..
+            }
+        ));
+    }
+
+    /// A deadline can't be set once the stream is closed.
+    #[test]
+    fn deadline_after_close() {
+        let mut s = SendStream::new(
+            StreamId::new(0),
+            100,
+            connection_fc(4096),
+            ConnectionEvents::default(),
+        );
+        s.close();
+        assert_eq!(
+            s.set_deadline(Some(now()), 0).unwrap_err(),
+            Error::FinalSize
+        );
+    }
+
+    /// Only streams with deadlines are tracked, and they stop being tracked once expired.
+    #[test]
+    fn deadline_tracked_streams() {
+        let conn_events = ConnectionEvents::default();
+        let mut ss = SendStreams::default();
+        for i in 0..3 {
+            let id = StreamId::from(i * 4);
+            ss.insert(
+                id,
+                SendStream::new(id, 100, connection_fc(4096), conn_events.clone()),
+            );
+        }
+        let start = now();
+        assert_eq!(
+            ss.set_deadline(StreamId::from(12), Some(start), 7)
+                .unwrap_err(),
+            Error::InvalidStreamId
+        );
+        ss.set_deadline(StreamId::from(4), Some(start + DEADLINE), 7)
+            .unwrap();
+        ss.get_mut(StreamId::from(4))
+            .unwrap()
+            .send(&[1; 10])
+            .unwrap();
+        assert_eq!(ss.with_deadlines.len(), 1);
+        assert_eq!(ss.next_deadline(), Some(start + DEADLINE));
+
+        ss.expire(start + DEADLINE);
+        assert!(ss.with_deadlines.is_empty());
+        assert_eq!(ss.next_deadline(), None);
+        assert!(matches!(
+            ss.get(StreamId::from(4)).unwrap().state(),
+            State::ResetSent { .. }
+        ));
+        assert!(matches!(
+            ss.get(StreamId::from(0)).unwrap().state(),
+            State::Ready { .. }
+        ));
+    }
+
     fn make_send_stream(data: &[u8]) -> (SendStream, u64) {
         let len = to_u64(data.len());
         let mut s = SendStream::new(
@@ -6,21 +6,25 @@
 
 // Congestion control
 
-use std::time::{Duration, Instant};
+use std::{
+    cmp::{max, min},
+    num::NonZeroU64,
+    time::{Duration, Instant},
+};
 
 use neqo_common::{qdebug, qlog::Qlog};
 
 use crate::{
     ConnectionParameters, SlowStart, Stats,
     cc::{
-        ClassicCongestionController, ClassicSlowStart, CongestionControl,
+        Bbr, CarefulResumeParams, ClassicCongestionController, ClassicSlowStart, CongestionControl,
         CongestionControlImplementation, CongestionController as _, Cubic, HyStart, NewReno,
-        Search,
+        Prague, Search,
     },
     pace::Pacer,
     pmtud::Pmtud,
     qlog,
-    recovery::sent,
+    recovery::{rate, sent},
     rtt::RttEstimate,
     stats::CongestionControlStats,
 };
@@ -32,7 +36,10 @@ pub const PACING_BURST_SIZE: usize = 2;
 pub struct PacketSender {
     cc: CongestionControlImplementation,
     pacer: Pacer,
+    rate: rate::Estimator,
     qlog: Qlog,
+    /// Whether the maximum send rate changed since it was last logged.
+    max_rate_changed: bool,
 }
 
 impl PacketSender {
@@ -40,11 +47,21 @@ impl PacketSender {
     pub fn new(conn_params: &ConnectionParameters, pmtud: Pmtud, now: Instant) -> Self {
         let mtu = pmtud.plpmtu();
         let spurious_recovery = conn_params.spurious_recovery_enabled();
-        Self {
-            cc: match (
+        let cc = if let Some(factory) = conn_params.get_congestion_controller_factory() {
+            CongestionControlImplementation::Custom(factory.create(pmtud, now))
+        } else {
+            match (
                 conn_params.get_congestion_control(),
                 conn_params.get_slow_start(),
             ) {
+                // L4S requires a scalable congestion controller.
+                _ if conn_params.l4s_enabled() => {
+                    CongestionControlImplementation::Prague(Prague::new(pmtud))
+                }
+                // BBR has its own startup phase and ignores the slow start setting.
+                (CongestionControl::Bbr, _) => {
+                    CongestionControlImplementation::Bbr(Bbr::new(pmtud, now))
+                }
                 (CongestionControl::NewReno, SlowStart::Classic) => {
                     CongestionControlImplementation::ClassicNewReno(
                         ClassicCongestionController::new(
@@ -105,14 +122,21 @@ impl PacketSender {
                         spurious_recovery,
                     ))
                 }
-            },
-            pacer: Pacer::new(
-                conn_params.pacing_enabled(),
-                now,
-                mtu * PACING_BURST_SIZE,
-                mtu,
-            ),
+            }
+        };
+        let mut pacer = Pacer::new(
+            conn_params.pacing_enabled(),
+            now,
+            mtu * PACING_BURST_SIZE,
+            mtu,
+        );
+        pacer.set_max_rate(conn_params.get_max_send_rate().map(bytes_per_sec));
+        Self {
+            cc,
+            pacer,
+            rate: rate::Estimator::new(now),
             qlog: Qlog::default(),
+            max_rate_changed: conn_params.get_max_send_rate().is_some(),
         }
     }
 
@@ -150,9 +174,50 @@ impl PacketSender {
         self.cc.cwnd_min()
     }
 
-    /// Emit a `PacingRate` qlog metric.
+    /// The pacing rate in bytes per second, if the congestion controller
+    /// provides one.  Otherwise, the pacer derives its rate from the congestion
+    /// window and the RTT.
+    #[must_use]
+    pub fn pacing_rate(&self) -> Option<u64> {
+        self.cc.pacing_rate()
+    }
+
+    /// The maximum send rate in bits per second, if there is one.
+    #[cfg(test)]
+    #[must_use]
+    pub fn max_send_rate(&self) -> Option<NonZeroU64> {
+        self.pacer
+            .max_rate()
+            .and_then(|r| NonZeroU64::new(r.saturating_mul(8)))
+    }
+
+    /// Limit the send rate to `rate` bits per second, or remove the limit.
+    pub fn set_max_send_rate(&mut self, rate: Option<NonZeroU64>) {
+        qdebug!("Maximum send rate set to {rate:?} bit/s");
+        self.pacer.set_max_rate(rate.map(bytes_per_sec));
+        self.max_rate_changed = true;
+    }
+
+    /// The rate that packets are paced at, in bytes per second, taking any
+    /// maximum send rate into account.
+    #[must_use]
+    pub fn effective_pacing_rate(&self, rtt: Duration) -> Option<u64> {
+        let rate = self
+            .pacing_rate()
+            .or_else(|| Pacer::rate(self.cc.cwnd(), rtt));
+        match (rate, self.pacer.max_rate()) {
+            (Some(rate), Some(max_rate)) => Some(min(rate, max_rate)),
+            (rate, max_rate) => rate.or(max_rate),
+        }
+    }
+
+    /// Emit a `PacingRate` qlog metric, after logging any change to the maximum send rate.
     fn maybe_qlog_pacing_rate(&mut self, rtt: Duration, now: Instant) {
-        if let Some(rate) = Pacer::rate(self.cc.cwnd(), rtt) {
+        if self.max_rate_changed {
+            self.max_rate_changed = false;
+            qlog::max_send_rate_updated(&mut self.qlog, self.pacer.max_rate(), now);
+        }
+        if let Some(rate) = self.effective_pacing_rate(rtt) {
             qlog::metrics_updated(&mut self.qlog, [qlog::Metric::PacingRate(rate)], now);
         }
     }
@@ -175,8 +240,15 @@ impl PacketSender {
         now: Instant,
         stats: &mut Stats,
     ) {
+        let rs = self.rate.on_packets_acked(acked_pkts, rtt_est, now);
+        if let Some(rate) = rs.delivery_rate() {
+            stats.delivery_rate = Some(rate);
+            if !rs.is_app_limited() {
+                stats.max_delivery_rate = max(stats.max_delivery_rate, Some(rate));
+            }
+        }
         self.cc
-            .on_packets_acked(acked_pkts, rtt_est, now, &mut stats.cc);
+            .on_packets_acked(acked_pkts, &rs, rtt_est, now, &mut stats.cc);
         self.maybe_qlog_pacing_rate(rtt_est.estimate(), now);
         self.pmtud_mut().on_packets_acked(acked_pkts, now, stats);
         self.maybe_update_pacer_mtu();
@@ -192,6 +264,7 @@ impl PacketSender {
         stats: &mut Stats,
         now: Instant,
     ) -> bool {
+        self.rate.on_packets_lost(lost_packets);
         let ret = self.cc.on_packets_lost(
             first_rtt_sample_time,
             prev_largest_acked_sent,
@@ -207,14 +280,16 @@ impl PacketSender {
         ret
     }
 
-    /// Called when ECN CE mark received.  Returns true if the congestion window was reduced.
+    /// Called when ECN CE marks are received.  Returns true if the congestion window was reduced.
     pub fn on_ecn_ce_received(
         &mut self,
         largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) -> bool {
-        self.cc.on_ecn_ce_received(largest_acked_pkt, now, cc_stats)
+        self.cc
+            .on_ecn_ce_received(largest_acked_pkt, ce_marks, now, cc_stats)
     }
 
     pub fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
@@ -227,29 +302,56 @@ impl PacketSender {
         self.cc.discard_in_flight(now);
     }
 
-    pub fn on_packet_sent(&mut self, pkt: &sent::Packet, rtt: Duration, now: Instant) {
-        self.pacer
-            .spend(pkt.time_sent(), rtt, self.cc.cwnd(), pkt.len());
+    /// Called when the sender has nothing to send, even though the congestion
+    /// window and the pacer would allow it.
+    pub fn on_app_limited(&mut self) {
+        self.rate.on_app_limited(self.cc.bytes_in_flight());
+    }
+
+    pub fn on_packet_sent(&mut self, pkt: &mut sent::Packet, rtt: Duration, now: Instant) {
+        self.rate.on_packet_sent(pkt, self.cc.bytes_in_flight());
+        if let Some(rate) = self.pacing_rate() {
+            self.pacer.spend_at_rate(pkt.time_sent(), rate, pkt.len());
+        } else {
+            self.pacer
+                .spend(pkt.time_sent(), rtt, self.cc.cwnd(), pkt.len());
+        }
         self.cc.on_packet_sent(pkt, now);
     }
 
     #[must_use]
     pub fn next_paced(&self, rtt: Duration) -> Option<Instant> {
-        // Only pace if there are bytes in flight.
-        (self.cc.bytes_in_flight() > 0).then(|| self.pacer.next(rtt, self.cc.cwnd()))
+        // Only pace if there are bytes in flight, unless there is a maximum send
+        // rate, which has to hold even across idle periods.
+        (self.cc.bytes_in_flight() > 0 || self.pacer.max_rate().is_some()).then(|| {
+            self.pacing_rate().map_or_else(
+                || self.pacer.next(rtt, self.cc.cwnd()),
+                |rate| self.pacer.next_at_rate(rate),
+            )
+        })
     }
 
     #[must_use]
     pub fn recovery_packet(&self) -> bool {
         self.cc.recovery_packet()
     }
+
+    pub fn careful_resume(&mut self, params: CarefulResumeParams) {
+        self.cc.careful_resume(params);
+    }
+}
+
+/// Convert a rate in bits per second into bytes per second.
+fn bytes_per_sec(rate: NonZeroU64) -> u64 {
+    rate.get() / 8
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
         net::{IpAddr, Ipv4Addr},
-        time::Duration,
+        num::NonZeroU64,
+        time::{Duration, Instant},
     };
 
     use neqo_common::to_u64;
@@ -257,8 +359,16 @@ mod tests {
 
     use super::PacketSender;
     use crate::{
-        ConnectionParameters, SlowStart, cc::CongestionControl, pmtud::Pmtud, recovery::sent,
-        rtt::RttEstimate, stats::Stats,
+        ConnectionParameters, SlowStart,
+        cc::{
+            ClassicCongestionController, ClassicSlowStart, CongestionControl,
+            CongestionControlImplementation, CongestionController, CongestionControllerFactory,
+            NewReno,
+        },
+        pmtud::Pmtud,
+        recovery::sent,
+        rtt::RttEstimate,
+        stats::Stats,
     };
 
     #[test]
@@ -291,6 +401,8 @@ mod tests {
                 "HyStart++/Cubic",
             ),
             (CongestionControl::Cubic, SlowStart::Search, "SEARCH/Cubic"),
+            (CongestionControl::Bbr, SlowStart::Classic, "BBRv3"),
+            (CongestionControl::Bbr, SlowStart::HyStart, "BBRv3"),
         ];
         for (cc, ss, expected_prefix) in cases {
             let params = ConnectionParameters::default()
@@ -309,6 +421,43 @@ mod tests {
         }
     }
 
+    #[derive(Debug)]
+    struct NewRenoFactory;
+
+    impl CongestionControllerFactory for NewRenoFactory {
+        fn create(&self, pmtud: Pmtud, _now: Instant) -> Box<dyn CongestionController> {
+            Box::new(ClassicCongestionController::new(
+                ClassicSlowStart::default(),
+                NewReno::default(),
+                pmtud,
+                true,
+            ))
+        }
+    }
+
+    /// A congestion controller factory overrides the configured algorithm.
+    #[test]
+    fn packet_sender_custom() {
+        let params = ConnectionParameters::default()
+            .congestion_control(CongestionControl::Bbr)
+            .congestion_controller_factory(Box::new(NewRenoFactory));
+        let sender = PacketSender::new(
+            &params,
+            Pmtud::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), Some(1500)),
+            now(),
+        );
+        assert!(matches!(
+            sender.cc,
+            CongestionControlImplementation::Custom(_)
+        ));
+        assert!(
+            sender
+                .cc
+                .to_string()
+                .starts_with("ClassicSlowStart/NewReno")
+        );
+    }
+
     const RTT: Duration = Duration::from_millis(100);
 
     fn make_sender(pacing: bool) -> PacketSender {
@@ -329,8 +478,8 @@ mod tests {
 
         let pkts: Vec<_> = (0..n)
             .map(|pn| {
-                let p = sent::make_packet(to_u64(pn), now, mtu);
-                sender.on_packet_sent(&p, RTT, now);
+                let mut p = sent::make_packet(to_u64(pn), now, mtu);
+                sender.on_packet_sent(&mut p, RTT, now);
                 p
             })
             .collect();
@@ -360,4 +509,89 @@ mod tests {
         let (before, after) = send_and_ack(true, super::PACING_BURST_SIZE + 1);
         assert_eq!(after, before, "cwnd should not grow when app limited");
     }
+
+    #[test]
+    fn max_send_rate() {
+        // 800 kbit/s is 100 kB/s.
+        const MAX_RATE: NonZeroU64 = NonZeroU64::new(800_000).unwrap();
+        let params = ConnectionParameters::default().max_send_rate(Some(MAX_RATE));
+        let mut sender = PacketSender::new(
+            &params,
+            Pmtud::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), Some(1500)),
+            now(),
+        );
+        assert_eq!(sender.max_send_rate(), Some(MAX_RATE));
+        let now = now();
+        let mtu = sender.pmtud().plpmtu();
+
+        // Exceed the burst by one packet, leaving a deficit of two packets.
+        for pn in 0..=super::PACING_BURST_SIZE {
+            let mut p = sent::make_packet(to_u64(pn), now, mtu);
+            sender.on_packet_sent(&mut p, RTT, now);
+        }
+        let limited = now + Duration::from_micros(to_u64(2 * mtu * 10));
+        assert_eq!(sender.next_paced(RTT), Some(limited));
+
+        sender.set_max_send_rate(None);
+        assert_eq!(sender.max_send_rate(), None);
+        assert!(sender.next_paced(RTT).unwrap() < limited);
+    }
+
+    /// The maximum send rate holds without pacing, and when nothing is in flight.
+    #[test]
+    fn max_send_rate_without_pacing() {
+        const MAX_RATE: NonZeroU64 = NonZeroU64::new(800_000).unwrap();
+        let params = ConnectionParameters::default()
+            .pacing(false)
+            .max_send_rate(Some(MAX_RATE));
+        let mut sender = PacketSender::new(
+            &params,
+            Pmtud::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), Some(1500)),
+            now(),
+        );
+        let now = now();
+        let mtu = sender.pmtud().plpmtu();
+
+        let pkts: Vec<_> = (0..=super::PACING_BURST_SIZE)
+            .map(|pn| {
+                let mut p = sent::make_packet(to_u64(pn), now, mtu);
+                sender.on_packet_sent(&mut p, RTT, now);
+                p
+            })
+            .collect();
+        sender.on_packets_acked(&pkts, &RttEstimate::new(RTT), now, &mut Stats::default());
+        assert_eq!(sender.bytes_in_flight(), 0);
+        let limited = now + Duration::from_micros(to_u64(2 * mtu * 10));
+        assert_eq!(sender.next_paced(RTT), Some(limited));
+    }
+
+    #[test]
+    fn delivery_rate_stats() {
+        let mut sender = make_sender(true);
+        let mut stats = Stats::default();
+        let now = now();
+        let mtu = sender.pmtud().plpmtu();
+        let rtt_est = RttEstimate::new(RTT);
+
+        // Ten packets delivered over one RTT.
+        let pkts: Vec<_> = (0..10)
+            .map(|pn| {
+                let mut p = sent::make_packet(pn, now, mtu);
+                sender.on_packet_sent(&mut p, RTT, now);
+                p
+            })
+            .collect();
+        sender.on_packets_acked(&pkts, &rtt_est, now + RTT, &mut stats);
+        let rate = to_u64(10 * mtu * 10);
+        assert_eq!(stats.delivery_rate, Some(rate));
+        assert_eq!(stats.max_delivery_rate, Some(rate));
+
+        // An app-limited sample updates the current rate, but not the maximum.
+        sender.on_app_limited();
+        let mut p = sent::make_packet(10, now + RTT, mtu);
+        sender.on_packet_sent(&mut p, RTT, now + RTT);
+        sender.on_packets_acked(&[p], &rtt_est, now + RTT * 2, &mut stats);
+        assert_eq!(stats.delivery_rate, Some(to_u64(mtu * 10)));
+        assert_eq!(stats.max_delivery_rate, Some(rate));
+    }
 }
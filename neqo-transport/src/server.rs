@@ -8,35 +8,180 @@
 
 use std::{
     cell::RefCell,
-    cmp::min,
-    collections::VecDeque,
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque, hash_map::RandomState},
     fmt::{self, Display, Formatter},
+    hash::BuildHasher as _,
+    net::{IpAddr, SocketAddr},
     num::NonZeroUsize,
     ops::{Deref, DerefMut},
     path::PathBuf,
-    rc::Rc,
-    time::Instant,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
 };
 
 use neqo_common::{
-    Datagram, Role, Tos, event::Provider as _, hex::Hex, qdebug, qerror, qinfo, qlog::Qlog, qtrace,
+    Datagram, Role, Tos, event::Provider, hex::Hex, qdebug, qerror, qinfo, qlog::Qlog, qtrace,
     qwarn,
 };
 use nss::{
     AntiReplay, Cipher, PrivateKey, PublicKey, ZeroRttCheckResult, ZeroRttChecker,
     encode_ech_config,
 };
-use rustc_hash::FxHashSet as HashSet;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 
-pub use crate::addr_valid::ValidateAddress;
 use crate::{
-    ConnectionParameters, OutputBatch, Res, Version,
+    ConnectionParameters, DropReason, OutputBatch, PreferredAddressOutcome, Res, ServerStats,
+    Version,
     addr_valid::{AddressValidation, AddressValidationResult},
-    cid::{ConnectionId, ConnectionIdGenerator, ConnectionIdRef},
-    connection::{Connection, Output, State},
+    admission::HalfOpen,
+    cid::{ConnectionId, ConnectionIdGenerator, ConnectionIdRef, LocalConnectionIdChange},
+    connection::{Connection, HandoffKey, Output, State},
     packet::{self, MIN_INITIAL_PACKET_SIZE, Public},
     saved::SavedDatagram,
+    stateless_reset,
+    tparams::PreferredAddress,
+    version,
+    vhost::PendingHello,
 };
+pub use crate::{
+    addr_valid::{
+        DEFAULT_KEY_GRACE_PERIOD, DEFAULT_KEY_ROTATION_PERIOD, TokenKey, ValidateAddress,
+    },
+    admission::{Admission, AdmissionPolicy, HandshakeLimits, ServerLoad},
+    vhost::{VirtualHost, VirtualHostSelector},
+};
+
+/// Chooses the preferred address that a server advertises to a new connection.
+///
+/// This is passed the local and remote addresses of the datagram that created the
+/// connection.  Returning `None` disables the preferred address for that connection.
+/// A selector can be used to steer connections that arrive on a shared (anycast)
+/// address toward a pool of unicast addresses.
+pub trait PreferredAddressSelector {
+    fn select(&mut self, local: SocketAddr, remote: SocketAddr) -> Option<PreferredAddress>;
+}
+
+impl<F> PreferredAddressSelector for F
+where
+    F: FnMut(SocketAddr, SocketAddr) -> Option<PreferredAddress>,
+{
+    fn select(&mut self, local: SocketAddr, remote: SocketAddr) -> Option<PreferredAddress> {
+        self(local, remote)
+    }
+}
+
+/// Events that concern the server, rather than an individual connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// The outcome of advertising a preferred address to a client is known.
+    PreferredAddress {
+        connection: ConnectionRef,
+        outcome: PreferredAddressOutcome,
+    },
+}
+
+/// Identifies a connection by the address of its allocation.
+pub(crate) type ConnectionKey = *const RefCell<Connection>;
+
+fn connection_key(c: &Rc<RefCell<Connection>>) -> ConnectionKey {
+    Rc::as_ptr(c)
+}
+
+/// Limits how many packets are sent to each source address in each period.
+/// Addresses are hashed into a fixed number of buckets, so that a flood from
+/// many addresses can't use more memory.
+struct SourceRateLimit {
+    hasher: RandomState,
+    buckets: Vec<stateless_reset::RateLimit>,
+}
+
+impl SourceRateLimit {
+    const BUCKETS: u64 = 1024;
+
+    fn new(limit: usize) -> Self {
+        Self {
+            hasher: RandomState::new(),
+            buckets: (0..Self::BUCKETS)
+                .map(|_| stateless_reset::RateLimit::new(limit))
+                .collect(),
+        }
+    }
+
+    /// Determine whether a packet can be sent to `source` at `now`.
+    fn allow(&mut self, source: IpAddr, now: Instant) -> bool {
+        let i = usize::try_from(self.hasher.hash_one(source) % Self::BUCKETS).unwrap_or_default();
+        self.buckets[i].allow(now)
+    }
+}
+
+/// Connections that might have something to send, in the order that they became ready.
+#[derive(Debug, Default)]
+struct ReadyConnections {
+    queue: VecDeque<Rc<RefCell<Connection>>>,
+    queued: HashSet<ConnectionKey>,
+    /// Connections that have been ready since the application last found them
+    /// without events.  Only these can have events, so only these are checked
+    /// when the application asks for active connections.
+    active: HashMap<ConnectionKey, Rc<RefCell<Connection>>>,
+}
+
+impl ReadyConnections {
+    fn push(&mut self, c: &Rc<RefCell<Connection>>) {
+        let key = connection_key(c);
+        if self.queued.insert(key) {
+            self.queue.push_back(Rc::clone(c));
+        }
+        self.active.entry(key).or_insert_with(|| Rc::clone(c));
+    }
+
+    fn pop(&mut self) -> Option<Rc<RefCell<Connection>>> {
+        let c = self.queue.pop_front()?;
+        self.queued.remove(&connection_key(&c));
+        Some(c)
+    }
+
+    /// Stop tracking a connection that was removed.
+    fn remove(&mut self, key: &ConnectionKey) {
+        self.active.remove(key);
+    }
+
+    /// The connections that have events, after dropping those that don't.
+    fn active(&mut self) -> impl Iterator<Item = &Rc<RefCell<Connection>>> {
+        self.active.retain(|_, c| c.borrow().has_events());
+        self.active.values()
+    }
+}
+
+/// When a connection next needs attention.  This is ordered so that
+/// a `BinaryHeap` yields the earliest timer first.
+struct Timer {
+    time: Instant,
+    c: Rc<RefCell<Connection>>,
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .cmp(&self.time)
+            .then_with(|| connection_key(&other.c).cmp(&connection_key(&self.c)))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
 
 /// A `ServerZeroRttChecker` is a simple wrapper around a single checker.
 /// It uses `RefCell` so that the wrapped checker can be shared between
@@ -61,11 +206,13 @@ impl ZeroRttChecker for ServerZeroRttChecker {
 }
 
 /// `InitialDetails` holds important information for processing `Initial` packets.
-struct InitialDetails {
+pub struct InitialDetails {
     src_cid: ConnectionId,
     dst_cid: ConnectionId,
     token: Vec<u8>,
     version: Version,
+    /// Whether a Retry or `NEW_TOKEN` token validated the client's address.
+    validated: bool,
 }
 
 impl InitialDetails {
@@ -75,8 +222,33 @@ impl InitialDetails {
             dst_cid: ConnectionId::from(packet.dcid()),
             token: packet.token().to_vec(),
             version: packet.version().expect("packet has version"),
+            validated: false,
         }
     }
+
+    /// The source connection ID that the client chose.
+    #[must_use]
+    pub const fn src_cid(&self) -> &ConnectionId {
+        &self.src_cid
+    }
+
+    /// The destination connection ID that the client chose.
+    #[must_use]
+    pub const fn dst_cid(&self) -> &ConnectionId {
+        &self.dst_cid
+    }
+
+    /// The token from the packet, which is empty if there was none.
+    #[must_use]
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// The QUIC version of the packet.
+    #[must_use]
+    pub const fn version(&self) -> Version {
+        self.version
+    }
 }
 
 struct EchConfig {
@@ -116,7 +288,16 @@ pub struct Server {
     /// Connection parameters.
     conn_params: ConnectionParameters,
     /// All connections.
-    connections: Vec<Rc<RefCell<Connection>>>,
+    connections: HashMap<ConnectionKey, Rc<RefCell<Connection>>>,
+    /// Connections, indexed by each of their active local connection IDs.
+    cids: HashMap<ConnectionId, Rc<RefCell<Connection>>>,
+    /// Connections that received input, or that the application accessed, and
+    /// might have something to send.  This is shared with [`ConnectionRef`].
+    ready: Rc<RefCell<ReadyConnections>>,
+    /// Connection timers.  Entries that don't match `deadlines` are stale.
+    timers: BinaryHeap<Timer>,
+    /// The time that each connection next needs attention, if any.
+    deadlines: HashMap<ConnectionKey, Instant>,
     /// Address validation logic, which determines whether we send a Retry.
     address_validation: Rc<RefCell<AddressValidation>>,
     /// Directory to create qlog traces in
@@ -129,6 +310,32 @@ pub struct Server {
     /// datagrams. To be processed on consecutive calls to
     /// [`Server::process_multiple`].
     saved_datagrams: VecDeque<SavedDatagram>,
+    /// Chooses the preferred address for each new connection.  If this is not
+    /// set, the preferred address from `conn_params` is used.
+    preferred_address_selector: Option<Box<dyn PreferredAddressSelector>>,
+    /// Events that are waiting to be collected by the application.
+    events: VecDeque<ServerEvent>,
+    /// Limits the rate at which stateless resets are sent.
+    stateless_reset_limit: stateless_reset::RateLimit,
+    /// Limits the rate at which Version Negotiation packets are sent to each source.
+    version_negotiation_limit: Option<SourceRateLimit>,
+    /// Decides what to do with Initial packets for new connections.
+    admission_policy: Option<Box<dyn AdmissionPolicy>>,
+    /// Chooses the version for each new connection, when clients offer compatible versions.
+    version_policy: Option<Rc<RefCell<dyn version::Policy>>>,
+    /// Chooses the host for each new connection.  If this is not set, every
+    /// connection uses the certificates, protocols and parameters of the server.
+    virtual_host_selector: Option<Box<dyn VirtualHostSelector>>,
+    /// New connections that are waiting for the rest of their `ClientHello`
+    /// before a host can be chosen, indexed by the connection ID that the client chose.
+    pending_hellos: HashMap<ConnectionId, PendingHello>,
+    /// The peer addresses of connections that haven't completed their handshake.
+    half_open: HalfOpen,
+    /// The salts of exports that this server imported, with the time at which
+    /// they expire.  Exports that are imported again are rejected.
+    handoffs: HashMap<[u8; HandoffKey::SALT_LEN], Instant>,
+    /// Statistics for events that aren't specific to a connection.
+    stats: ServerStats,
 }
 
 impl Server {
@@ -162,11 +369,28 @@ impl Server {
             zero_rtt_checker: ServerZeroRttChecker::new(zero_rtt_checker),
             cid_generator,
             conn_params,
-            connections: Vec::new(),
+            connections: HashMap::default(),
+            cids: HashMap::default(),
+            ready: Rc::default(),
+            timers: BinaryHeap::new(),
+            deadlines: HashMap::default(),
             address_validation: Rc::new(RefCell::new(validation)),
             qlog_dir: None,
             ech_config: None,
             saved_datagrams: VecDeque::new(),
+            preferred_address_selector: None,
+            events: VecDeque::new(),
+            stateless_reset_limit: stateless_reset::RateLimit::new(
+                stateless_reset::RateLimit::DEFAULT_LIMIT,
+            ),
+            version_negotiation_limit: None,
+            admission_policy: None,
+            version_policy: None,
+            virtual_host_selector: None,
+            pending_hellos: HashMap::default(),
+            half_open: HalfOpen::default(),
+            handoffs: HashMap::default(),
+            stats: ServerStats::default(),
         })
     }
 
@@ -180,6 +404,118 @@ impl Server {
         self.address_validation.borrow_mut().set_validation(v);
     }
 
+    /// Set how often the keys that protect Retry and `NEW_TOKEN` tokens are
+    /// replaced, and how long tokens from the previous key are still accepted.
+    /// Setting `None` stops scheduled rotation.
+    pub fn set_token_key_rotation(&self, period: Option<Duration>, grace: Duration) {
+        self.address_validation
+            .borrow_mut()
+            .set_key_rotation(period, grace);
+    }
+
+    /// Set the keys that protect Retry and `NEW_TOKEN` tokens.  The first key is
+    /// used for new tokens and all of them are accepted.  A pool of servers that
+    /// share these keys can validate each other's tokens.  This stops scheduled
+    /// rotation, so the application needs to replace keys itself.
+    ///
+    /// # Errors
+    ///
+    /// When `keys` is empty or contains duplicate identifiers.
+    pub fn set_token_keys(&mut self, keys: Vec<TokenKey>, now: Instant) -> Res<()> {
+        let id = keys.first().map(TokenKey::id);
+        self.address_validation.borrow_mut().set_keys(keys)?;
+        if let Some(id) = id {
+            self.token_key_updated(id, now);
+        }
+        Ok(())
+    }
+
+    /// Rotate token keys if they are due.
+    fn rotate_token_keys(&mut self, now: Instant) {
+        let res = self.address_validation.borrow_mut().maybe_rotate(now);
+        match res {
+            Ok(Some(id)) => self.token_key_updated(id, now),
+            Ok(None) => {}
+            Err(e) => qwarn!("[{self}] Unable to rotate token keys: {e}"),
+        }
+    }
+
+    /// Record a change of token keys in the qlog of every connection,
+    /// as each of them might mint `NEW_TOKEN` tokens with the new key.
+    fn token_key_updated(&self, id: u8, now: Instant) {
+        qinfo!("[{self}] Token key is now {id}");
+        for c in self.connections.values() {
+            crate::qlog::token_key_updated(c.borrow_mut().qlog_mut(), id, now);
+        }
+    }
+
+    /// Statistics for events that aren't specific to a connection.
+    #[must_use]
+    pub fn stats(&self) -> ServerStats {
+        ServerStats {
+            token_key_rotations: self.address_validation.borrow().rotations(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Set or clear a policy that decides whether to accept each new connection.
+    /// The policy runs before address validation: a connection that it accepts
+    /// can still be sent a Retry, depending on [`Server::set_validation`].
+    pub fn set_admission_policy(&mut self, policy: Option<Box<dyn AdmissionPolicy>>) {
+        self.half_open
+            .set_prefix_lens(policy.as_ref().and_then(|p| p.prefix_lens()));
+        self.admission_policy = policy;
+    }
+
+    /// Set or clear a policy that chooses the version for each new connection, from the
+    /// compatible versions that the client offers.  Without a policy, the server upgrades
+    /// to the first of those versions in the order given by [`ConnectionParameters::versions`].
+    pub fn set_version_policy(&mut self, policy: Option<Rc<RefCell<dyn version::Policy>>>) {
+        self.version_policy = policy;
+    }
+
+    /// Set or clear a selector that chooses the host for each new connection, based on
+    /// the server name in the client's `ClientHello`.  The host determines the certificates,
+    /// ALPN values and [`ConnectionParameters`] of the connection; the values that were
+    /// passed to [`Server::new`] are only used for version negotiation.
+    ///
+    /// The `ClientHello` can span several datagrams, so the server holds on to the first
+    /// datagrams of a connection until it has read the server name.
+    pub fn set_virtual_host_selector(&mut self, selector: Option<Box<dyn VirtualHostSelector>>) {
+        self.virtual_host_selector = selector;
+    }
+
+    /// Set or clear a selector that chooses the preferred address for each new connection.
+    /// The server reports [`ServerEvent::PreferredAddress`] once it knows whether each client
+    /// migrated to its preferred address.
+    pub fn set_preferred_address_selector(
+        &mut self,
+        selector: Option<Box<dyn PreferredAddressSelector>>,
+    ) {
+        self.preferred_address_selector = selector;
+    }
+
+    /// Set the number of stateless resets that can be sent each second.
+    /// Stateless resets are only sent if [`ConnectionParameters::stateless_reset_key`]
+    /// is set.
+    pub const fn set_stateless_reset_limit(&mut self, limit: usize) {
+        self.stateless_reset_limit.set_limit(limit);
+    }
+
+    /// Set or clear a limit on the number of Version Negotiation packets that
+    /// are sent to each source address each second.  Packets with an unsupported
+    /// version from a source that reaches the limit are dropped.  To bound the
+    /// memory used, addresses are spread over a fixed number of buckets, so
+    /// addresses that share a bucket also share a limit.  There is no limit by default.
+    pub fn set_version_negotiation_limit(&mut self, limit: Option<usize>) {
+        self.version_negotiation_limit = limit.map(SourceRateLimit::new);
+    }
+
+    /// Count a datagram that was dropped.
+    fn dropped(&mut self, reason: DropReason) {
+        self.stats.dropped[reason] += 1;
+    }
+
     /// Set the cipher suites that should be used.  Set an empty value to use
     /// default values.
     pub fn set_ciphers<A: AsRef<[Cipher]>>(&mut self, ciphers: A) {
@@ -204,6 +540,62 @@ impl Server {
         self.ech_config.as_ref().map_or(&[], |cfg| &cfg.encoded)
     }
 
+    /// Restore a connection from the output of [`Connection::export`], perhaps from
+    /// another server.  The connection is ready to use immediately; any datagrams that
+    /// arrive for its connection IDs are passed to it.  This server needs to use the
+    /// same [`ConnectionParameters`] as the server that exported the connection.
+    ///
+    /// Each export can only be imported once and only shortly after it was created.
+    /// Replays are only detected by this `Server` instance: servers that share a
+    /// [`HandoffKey`] don't coordinate, so the application needs to ensure that each
+    /// export is given to one server.
+    ///
+    /// # Errors
+    ///
+    /// When `blob` can't be opened with `key`, doesn't contain a valid connection,
+    /// has expired, or was already imported.
+    pub fn import_connection(
+        &mut self,
+        key: &HandoffKey,
+        blob: &[u8],
+        now: Instant,
+    ) -> Res<ConnectionRef> {
+        // Check for a replay before doing any work.  An export can't be imported
+        // after `MAX_AGE`, so it only needs to be remembered until then.
+        let salt = HandoffKey::salt(blob)?;
+        self.handoffs.retain(|_, expires| *expires > now);
+        if self.handoffs.contains_key(&salt) {
+            qwarn!("[{self}] Rejected an export that was already imported");
+            return Err(crate::Error::InvalidInput);
+        }
+        self.handoffs.insert(salt, now + HandoffKey::MAX_AGE);
+
+        let c = Connection::new_server(
+            &self.certs,
+            &self.protocols,
+            Rc::clone(&self.cid_generator),
+            self.conn_params.clone(),
+        )
+        .and_then(|mut c| {
+            c.track_local_cids();
+            c.import(key, blob, now)?;
+            Ok(c)
+        });
+        // An export that couldn't be imported can be tried again.
+        let mut c = c.inspect_err(|_| {
+            self.handoffs.remove(&salt);
+        })?;
+        c.set_validation(&self.address_validation);
+        if let Some(cid) = c.local_cids().next() {
+            let qlog = self.create_qlog_trace(cid.as_cid_ref(), now);
+            c.set_qlog(qlog);
+        }
+        qinfo!("[{self}] Imported connection {c}");
+        let c = Rc::new(RefCell::new(c));
+        self.add_connection(Rc::clone(&c));
+        Ok(self.connection_ref(&c))
+    }
+
     /// Writes address validation fuzzing corpus data.
     #[cfg(feature = "build-fuzzing-corpus")]
     fn write_addr_valid_corpus(peer: std::net::SocketAddr, token: &[u8]) {
@@ -234,14 +626,58 @@ impl Server {
         qdebug!("[{self}] Handle initial");
         #[cfg(feature = "build-fuzzing-corpus")]
         Self::write_addr_valid_corpus(dgram.source(), &initial.token);
+        let admission = self
+            .admission_policy
+            .as_mut()
+            .map_or(Admission::Accept, |p| {
+                let load = ServerLoad::new(self.connections.len(), &self.half_open);
+                p.admit(&initial, dgram.source(), &load)
+            });
+        qdebug!("[{self}] Admission {admission:?} for {:?}", initial.dst_cid);
+        match admission {
+            Admission::Accept => self.stats.admission_accepted += 1,
+            Admission::Retry => self.stats.admission_retried += 1,
+            Admission::Refuse => {
+                self.stats.admission_refused += 1;
+                return self.refuse_connection(initial, dgram, Vec::new(), now);
+            }
+            Admission::Drop => {
+                self.stats.admission_dropped += 1;
+                self.dropped(DropReason::Admission);
+                return Output::None;
+            }
+        }
+
         let res = self
             .address_validation
             .borrow()
             .validate(&initial.token, dgram.source(), now);
+        let res = match res {
+            AddressValidationResult::Pass | AddressValidationResult::ValidToken
+                if admission == Admission::Retry =>
+            {
+                AddressValidationResult::Validate
+            }
+            res => res,
+        };
         match res {
-            AddressValidationResult::Invalid => Output::None,
+            AddressValidationResult::Invalid => {
+                self.dropped(DropReason::InvalidToken);
+                Output::None
+            }
             AddressValidationResult::Pass => self.accept_connection(initial, dgram, None, now),
+            AddressValidationResult::ValidToken => {
+                let initial = InitialDetails {
+                    validated: true,
+                    ..initial
+                };
+                self.accept_connection(initial, dgram, None, now)
+            }
             AddressValidationResult::ValidRetry(orig_dcid) => {
+                let initial = InitialDetails {
+                    validated: true,
+                    ..initial
+                };
                 self.accept_connection(initial, dgram, Some(orig_dcid), now)
             }
             AddressValidationResult::Validate => {
@@ -255,6 +691,7 @@ impl Server {
                         "[{self}] DCID too short ({} bytes), dropping packet",
                         initial.dst_cid.len()
                     );
+                    self.dropped(DropReason::RetryFailed);
                     return Output::None;
                 }
 
@@ -265,43 +702,43 @@ impl Server {
                 );
                 let Ok(token) = res else {
                     qerror!("[{self}] unable to generate token, dropping packet");
+                    self.dropped(DropReason::RetryFailed);
                     return Output::None;
                 };
-                if let Some(new_dcid) = self.cid_generator.borrow_mut().generate_cid() {
-                    let packet = packet::Builder::retry(
-                        initial.version,
-                        &initial.src_cid,
-                        &new_dcid,
-                        &token,
-                        &initial.dst_cid,
-                    );
-                    packet.map_or_else(
-                        |_| {
-                            qerror!("[{self}] unable to encode retry, dropping packet");
-                            Output::None
-                        },
-                        |p| {
-                            qdebug!(
-                                "[{self}] type={:?} path:{} {}->{} {:?} len {}",
-                                packet::Type::Retry,
-                                initial.dst_cid,
-                                dgram.destination(),
-                                dgram.source(),
-                                Tos::default(),
-                                p.len(),
-                            );
-                            Output::Datagram(Datagram::new(
-                                dgram.destination(),
-                                dgram.source(),
-                                Tos::default(),
-                                p,
-                            ))
-                        },
-                    )
-                } else {
+                let new_dcid = self.cid_generator.borrow_mut().generate_cid();
+                let Some(new_dcid) = new_dcid else {
                     qerror!("[{self}] no connection ID for retry, dropping packet");
-                    Output::None
-                }
+                    self.dropped(DropReason::RetryFailed);
+                    return Output::None;
+                };
+                let packet = packet::Builder::retry(
+                    initial.version,
+                    &initial.src_cid,
+                    &new_dcid,
+                    &token,
+                    &initial.dst_cid,
+                );
+                let Ok(p) = packet else {
+                    qerror!("[{self}] unable to encode retry, dropping packet");
+                    self.dropped(DropReason::RetryFailed);
+                    return Output::None;
+                };
+                qdebug!(
+                    "[{self}] type={:?} path:{} {}->{} {:?} len {}",
+                    packet::Type::Retry,
+                    initial.dst_cid,
+                    dgram.destination(),
+                    dgram.source(),
+                    Tos::default(),
+                    p.len(),
+                );
+                self.stats.retries_tx += 1;
+                Output::Datagram(Datagram::new(
+                    dgram.destination(),
+                    dgram.source(),
+                    Tos::default(),
+                    p,
+                ))
             }
         }
     }
@@ -340,6 +777,7 @@ impl Server {
             // There was a retry, so set the connection IDs for.
             c.set_retry_cids(odcid, initial.src_cid, &initial.dst_cid);
         }
+        c.track_local_cids();
         c.set_validation(&self.address_validation);
         c.set_qlog(self.create_qlog_trace(orig_dcid.unwrap_or(initial.dst_cid).as_cid_ref(), now));
         if let Some(cfg) = &self.ech_config
@@ -356,6 +794,101 @@ impl Server {
         dgram: Datagram<impl AsRef<[u8]> + AsMut<[u8]>>,
         orig_dcid: Option<ConnectionId>,
         now: Instant,
+    ) -> Output {
+        if self.virtual_host_selector.is_some() {
+            let pending = PendingHello::new(initial, orig_dcid, now);
+            return self.continue_hello(pending, dgram.to_owned(), now);
+        }
+        self.create_connection(initial, None, dgram, Vec::new(), orig_dcid, now)
+    }
+
+    /// Save a datagram for a connection that is waiting for its `ClientHello`.
+    /// Once the server name is known, choose a host and create the connection.
+    fn continue_hello(
+        &mut self,
+        mut pending: PendingHello,
+        dgram: Datagram,
+        now: Instant,
+    ) -> Output {
+        pending.add(dgram, self.cid_generator.borrow().as_decoder());
+        let Some(sni) = pending.sni() else {
+            if pending.is_full() {
+                qdebug!("[{self}] ClientHello for {} is too long", pending.dcid());
+                self.stats.dropped[DropReason::ClientHello] += PendingHello::MAX_DATAGRAMS;
+            } else {
+                self.insert_hello(pending, now);
+            }
+            return Output::None;
+        };
+        qdebug!("[{self}] Choose host for {sni:?}");
+        let host = self
+            .virtual_host_selector
+            .as_mut()
+            .map(|selector| selector.select(sni));
+        let (initial, orig_dcid, mut dgrams) = pending.take();
+        let dgram = dgrams.remove(0);
+        match host {
+            None => self.create_connection(initial, None, dgram, dgrams, orig_dcid, now),
+            Some(Some(host)) => {
+                self.create_connection(initial, Some(&host), dgram, dgrams, orig_dcid, now)
+            }
+            Some(None) => {
+                self.stats.virtual_host_refused += 1;
+                self.refuse_connection(initial, dgram, dgrams, now)
+            }
+        }
+    }
+
+    /// Wait for the rest of the `ClientHello` for a connection.  Connections that
+    /// are waiting count as handshakes for admission control.  When too many
+    /// connections are waiting, the new one is dropped.
+    fn insert_hello(&mut self, pending: PendingHello, now: Instant) {
+        if self.pending_hellos.len() >= PendingHello::MAX_PENDING {
+            self.expire_hellos(now);
+        }
+        if self.pending_hellos.len() >= PendingHello::MAX_PENDING {
+            qdebug!("[{self}] Too many incomplete ClientHello messages");
+            self.stats.dropped[DropReason::ClientHello] += pending.datagrams();
+            return;
+        }
+        if let Some(peer) = pending.peer() {
+            self.half_open.insert_pending(pending.dcid().clone(), peer);
+        }
+        self.pending_hellos.insert(pending.dcid().clone(), pending);
+    }
+
+    fn remove_hello(&mut self, dcid: ConnectionIdRef<'_>) -> Option<PendingHello> {
+        let pending = self.pending_hellos.remove(&dcid[..])?;
+        self.half_open.remove_pending(pending.dcid());
+        Some(pending)
+    }
+
+    /// Datagrams for connections that are waiting for their `ClientHello` are
+    /// dropped if the rest of it doesn't arrive in time.
+    fn expire_hellos(&mut self, now: Instant) {
+        let mut dropped = 0;
+        let half_open = &mut self.half_open;
+        self.pending_hellos.retain(|dcid, p| {
+            let keep = now.saturating_duration_since(p.start()) < PendingHello::TIMEOUT;
+            if !keep {
+                dropped += p.datagrams();
+                half_open.remove_pending(dcid);
+            }
+            keep
+        });
+        self.stats.dropped[DropReason::ClientHello] += dropped;
+    }
+
+    /// Create a connection, with the configuration from `host` if there is one.
+    /// `more` holds any datagrams that arrived after `dgram`.
+    fn create_connection(
+        &mut self,
+        initial: InitialDetails,
+        host: Option<&VirtualHost>,
+        dgram: Datagram<impl AsRef<[u8]> + AsMut<[u8]>>,
+        more: Vec<Datagram>,
+        orig_dcid: Option<ConnectionId>,
+        now: Instant,
     ) -> Output {
         qinfo!(
             "[{self}] Accept connection {:?}",
@@ -364,24 +897,52 @@ impl Server {
         // The internal connection ID manager that we use is not used directly.
         // Instead, wrap it so that we can save connection IDs.
 
-        let mut params = self.conn_params.clone();
+        let mut params = host
+            .map_or(&self.conn_params, VirtualHost::conn_params)
+            .clone();
+        if !params.get_versions().all().contains(&initial.version) {
+            qwarn!("[{self}] Host doesn't support {:?}", initial.version);
+            self.dropped(DropReason::ConnectionFailed);
+            return Output::None;
+        }
         params.get_versions_mut().set_initial(initial.version);
+        if let Some(selector) = &mut self.preferred_address_selector {
+            params = match selector.select(dgram.destination(), dgram.source()) {
+                Some(spa) => params.preferred_address(spa),
+                None => params.disable_preferred_address(),
+            };
+        }
         let sconn = Connection::new_server(
-            &self.certs,
-            &self.protocols,
+            host.map_or(&self.certs[..], VirtualHost::certs),
+            host.map_or(&self.protocols[..], VirtualHost::protocols),
             Rc::clone(&self.cid_generator),
             params,
         );
 
         match sconn {
             Ok(mut c) => {
+                if let Some(policy) = &self.version_policy {
+                    c.set_version_policy(Rc::clone(policy), dgram.source(), initial.validated);
+                }
                 self.setup_connection(&mut c, initial, orig_dcid, now);
-                let out = c.process(Some(dgram), now);
-                self.connections.push(Rc::new(RefCell::new(c)));
+                let peer = dgram.source().ip();
+                let out = if more.is_empty() {
+                    c.process(Some(dgram), now)
+                } else {
+                    c.process_input(dgram, now);
+                    for d in more {
+                        c.process_input(d, now);
+                    }
+                    c.process_output(now)
+                };
+                let c = Rc::new(RefCell::new(c));
+                self.half_open.insert(connection_key(&c), peer);
+                self.add_connection(c);
                 out
             }
             Err(e) => {
                 qwarn!("[{self}] Unable to create connection");
+                self.dropped(DropReason::ConnectionFailed);
                 if e == crate::Error::VersionNegotiation {
                     crate::qlog::server_version_information_failed(
                         &mut self.create_qlog_trace(
@@ -446,22 +1007,30 @@ impl Server {
                 Public::decode_server(&mut dgram[..], self.cid_generator.borrow().as_decoder());
             let Ok((packet, _remainder)) = res else {
                 qtrace!("[{self}] Discarding {dgram:?}");
+                self.dropped(DropReason::Malformed);
                 continue;
             };
 
             // Finding an existing connection. Should be the most common case.
-            if let Some(c) = self
-                .connections
-                .iter_mut()
-                .find(|c| c.borrow().is_valid_local_cid(packet.dcid()))
-            {
+            if let Some(c) = self.cids.get(&packet.dcid()[..]).map(Rc::clone) {
                 c.borrow_mut().process_input(dgram, now);
+                self.update_cids(&c);
+                self.update_half_open(&c);
+                self.ready.borrow_mut().push(&c);
                 continue;
             }
 
             if packet.packet_type() == packet::Type::Short {
-                // TODO send a stateless reset here.
                 qtrace!("[{self}] Short header packet for an unknown connection");
+                let reset = self.stateless_reset(packet.dcid(), len, destination, source, now);
+                if let Some(reset) = reset {
+                    self.saved_datagrams.extend(dgrams.map(|d| SavedDatagram {
+                        d: d.to_owned(),
+                        t: now,
+                    }));
+                    return OutputBatch::DatagramBatch(reset.into());
+                }
+                self.dropped(DropReason::UnknownConnection);
                 continue;
             }
 
@@ -475,6 +1044,16 @@ impl Server {
             {
                 if len < MIN_INITIAL_PACKET_SIZE {
                     qdebug!("[{self}] Unsupported version: too short");
+                    self.dropped(DropReason::UnsupportedVersionTooShort);
+                    continue;
+                }
+                if self
+                    .version_negotiation_limit
+                    .as_mut()
+                    .is_some_and(|l| !l.allow(source.ip(), now))
+                {
+                    qdebug!("[{self}] Version Negotiation rate limit reached for {source}");
+                    self.dropped(DropReason::VersionNegotiationLimit);
                     continue;
                 }
 
@@ -505,15 +1084,30 @@ impl Server {
                     t: now,
                 }));
 
+                self.stats.version_negotiations_tx += 1;
                 return OutputBatch::DatagramBatch(
                     Datagram::new(destination, source, Tos::default(), vn).into(),
                 );
             }
 
+            // Datagrams for a connection that is waiting for its `ClientHello`.
+            if let Some(pending) = self.remove_hello(packet.dcid()) {
+                if let o @ Output::Datagram(_) = self.continue_hello(pending, dgram.to_owned(), now)
+                {
+                    self.saved_datagrams.extend(dgrams.map(|d| SavedDatagram {
+                        d: d.to_owned(),
+                        t: now,
+                    }));
+                    return o.into();
+                }
+                continue;
+            }
+
             match packet.packet_type() {
                 packet::Type::Initial => {
                     if len < MIN_INITIAL_PACKET_SIZE {
                         qdebug!("[{self}] Drop initial: too short");
+                        self.dropped(DropReason::InitialTooShort);
                         continue;
                     }
                     // Copy values from `packet` because they are currently still borrowing from
@@ -532,10 +1126,12 @@ impl Server {
                         "[{self}] Dropping 0-RTT for unknown connection {}",
                         ConnectionId::from(packet.dcid())
                     );
+                    self.dropped(DropReason::ZeroRtt);
                 }
                 packet::Type::OtherVersion => unreachable!(),
                 _ => {
                     qtrace!("[{self}] Not an initial packet");
+                    self.dropped(DropReason::NotInitial);
                 }
             }
         }
@@ -543,30 +1139,203 @@ impl Server {
         OutputBatch::None
     }
 
-    /// Iterate through the pending connections looking for any that might want
-    /// to send a datagram.  Stop at the first one that does.
+    /// Make a stateless reset in response to a datagram of `len` bytes for
+    /// an unknown connection ID, if a key is configured and the rate limit allows it.
+    fn stateless_reset(
+        &mut self,
+        dcid: ConnectionIdRef<'_>,
+        len: usize,
+        local: SocketAddr,
+        remote: SocketAddr,
+        now: Instant,
+    ) -> Option<Datagram> {
+        let key = self.conn_params.get_stateless_reset_key()?;
+        let Some(size) = stateless_reset::response_len(len) else {
+            qdebug!("[{self}] Datagram too short for a stateless reset");
+            return None;
+        };
+        if !self.stateless_reset_limit.allow(now) {
+            qdebug!("[{self}] Stateless reset rate limit reached");
+            return None;
+        }
+        let token = key
+            .token(&dcid[..])
+            .inspect_err(|e| qwarn!("[{self}] Unable to derive stateless reset token: {e:?}"))
+            .ok()?;
+        qdebug!("[{self}] Send stateless reset for {dcid} len {size}");
+        Some(Datagram::new(
+            local,
+            remote,
+            Tos::default(),
+            stateless_reset::build(&token, size),
+        ))
+    }
+
+    fn add_connection(&mut self, c: Rc<RefCell<Connection>>) {
+        self.stats.connections_created += 1;
+        self.update_cids(&c);
+        self.ready.borrow_mut().push(&c);
+        self.connections.insert(connection_key(&c), c);
+    }
+
+    /// Create a connection only to close it with `CONNECTION_REFUSED`.  The connection
+    /// is kept until it closes, so that it can respond to retransmissions.
+    fn refuse_connection(
+        &mut self,
+        initial: InitialDetails,
+        dgram: Datagram<impl AsRef<[u8]> + AsMut<[u8]>>,
+        more: Vec<Datagram>,
+        now: Instant,
+    ) -> Output {
+        qinfo!("[{self}] Refuse connection {:?}", initial.dst_cid);
+        let mut params = self.conn_params.clone();
+        params.get_versions_mut().set_initial(initial.version);
+        let Ok(mut c) = Connection::new_server(
+            &self.certs,
+            &self.protocols,
+            Rc::clone(&self.cid_generator),
+            params,
+        ) else {
+            qwarn!("[{self}] Unable to create connection to refuse");
+            self.dropped(DropReason::ConnectionFailed);
+            return Output::None;
+        };
+        self.setup_connection(&mut c, initial, None, now);
+        c.process_input(dgram, now);
+        for d in more {
+            c.process_input(d, now);
+        }
+        c.close_with_error(now, crate::Error::ConnectionRefused);
+        let out = c.process_output(now);
+        self.add_connection(Rc::new(RefCell::new(c)));
+        out
+    }
+
+    fn remove_connection(&mut self, c: &Rc<RefCell<Connection>>) {
+        let key = connection_key(c);
+        if self.connections.remove(&key).is_some() {
+            self.stats.connections_closed += 1;
+        }
+        self.deadlines.remove(&key);
+        self.half_open.remove(&key);
+        self.ready.borrow_mut().remove(&key);
+        for cid in c.borrow().local_cids() {
+            if self.cids.get(cid).is_some_and(|e| Rc::ptr_eq(e, c)) {
+                self.cids.remove(cid);
+            }
+        }
+    }
+
+    /// Stop counting `c` as half-open once it completes its handshake or closes.
+    fn update_half_open(&mut self, c: &Rc<RefCell<Connection>>) {
+        let key = connection_key(c);
+        if self.half_open.contains_key(&key) && *c.borrow().state() >= State::Connected {
+            self.half_open.remove(&key);
+        }
+    }
+
+    /// Bring the connection ID index up to date with any changes to the
+    /// connection IDs for `c`.
+    fn update_cids(&mut self, c: &Rc<RefCell<Connection>>) {
+        for change in c.borrow_mut().take_local_cid_changes() {
+            match change {
+                LocalConnectionIdChange::Added(cid) => {
+                    self.cids.insert(cid, Rc::clone(c));
+                }
+                LocalConnectionIdChange::Retired(cid) => {
+                    if self.cids.get(&cid).is_some_and(|e| Rc::ptr_eq(e, c)) {
+                        self.cids.remove(&cid);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set when `c` next needs attention, or clear it if `time` is `None`.
+    fn set_timer(&mut self, c: &Rc<RefCell<Connection>>, time: Option<Instant>) {
+        let key = connection_key(c);
+        let Some(time) = time else {
+            self.deadlines.remove(&key);
+            return;
+        };
+        if self.deadlines.insert(key, time) == Some(time) {
+            return;
+        }
+        self.timers.push(Timer {
+            time,
+            c: Rc::clone(c),
+        });
+        // Stale entries are only removed when they reach the top of the heap,
+        // so clean up if they start to dominate.
+        if self.timers.len() > 2 * self.deadlines.len() + 16 {
+            let deadlines = &self.deadlines;
+            self.timers
+                .retain(|t| deadlines.get(&connection_key(&t.c)) == Some(&t.time));
+        }
+    }
+
+    /// Whether a timer is current, rather than stale.
+    fn is_current(&self, t: &Timer) -> bool {
+        self.deadlines.get(&connection_key(&t.c)) == Some(&t.time)
+    }
+
+    /// Process the connections that are ready, or whose timers have expired,
+    /// looking for any that might want to send a datagram.  Stop at the first
+    /// one that does.  Connections that are otherwise idle are not visited.
     fn process_next_output(&mut self, now: Instant, max_datagrams: NonZeroUsize) -> OutputBatch {
         assert!(
             self.saved_datagrams.is_empty(),
             "Always process all inbound datagrams first."
         );
-        let mut callback = None;
 
-        for connection in &mut self.connections {
-            match connection
-                .borrow_mut()
-                .process_multiple_output(now, max_datagrams)
-            {
-                OutputBatch::None => {}
-                d @ OutputBatch::DatagramBatch(_) => return d,
-                OutputBatch::Callback(next) => match callback {
-                    Some(previous) => callback = Some(min(previous, next)),
-                    None => callback = Some(next),
-                },
+        while self.timers.peek().is_some_and(|t| t.time <= now) {
+            let t = self.timers.pop().expect("a timer");
+            if self.is_current(&t) {
+                self.deadlines.remove(&connection_key(&t.c));
+                self.ready.borrow_mut().push(&t.c);
+            }
+        }
+
+        loop {
+            let c = self.ready.borrow_mut().pop();
+            let Some(c) = c else {
+                break;
+            };
+            if !self.connections.contains_key(&connection_key(&c)) {
+                continue;
+            }
+            let out = c.borrow_mut().process_multiple_output(now, max_datagrams);
+            self.update_cids(&c);
+            self.update_half_open(&c);
+            let outcome = c.borrow_mut().take_preferred_address_outcome();
+            if let Some(outcome) = outcome {
+                self.events.push_back(ServerEvent::PreferredAddress {
+                    connection: self.connection_ref(&c),
+                    outcome,
+                });
+            }
+            match out {
+                OutputBatch::None => {
+                    self.set_timer(&c, None);
+                    if matches!(c.borrow().state(), State::Closed(_)) {
+                        self.remove_connection(&c);
+                    }
+                }
+                d @ OutputBatch::DatagramBatch(_) => {
+                    // There might be more to send, so come back to this one.
+                    self.ready.borrow_mut().push(&c);
+                    return d;
+                }
+                OutputBatch::Callback(next) => self.set_timer(&c, Some(now + next)),
             }
         }
 
-        callback.map_or(OutputBatch::None, OutputBatch::Callback)
+        while self.timers.peek().is_some_and(|t| !self.is_current(t)) {
+            self.timers.pop();
+        }
+        self.timers.peek().map_or(OutputBatch::None, |t| {
+            OutputBatch::Callback(t.time.saturating_duration_since(now))
+        })
     }
 
     /// Short-hand for [`Server::process`] without an input datagram.
@@ -595,23 +1364,22 @@ impl Server {
         now: Instant,
         max_datagrams: NonZeroUsize,
     ) -> OutputBatch {
+        self.rotate_token_keys(now);
+        self.expire_hellos(now);
         if let o @ OutputBatch::DatagramBatch(_) = self.process_multiple_input(dgrams, now) {
             // Return immediately. Do any maintenance on next call.
             return o;
         }
 
         // Process output datagrams.
-        let maybe_callback = match self.process_next_output(now, max_datagrams) {
-            // Return immediately. Do any maintenance on next call.
-            o @ OutputBatch::DatagramBatch(_) => return o,
-            o @ (OutputBatch::Callback(_) | OutputBatch::None) => o,
-        };
-
-        // Clean-up closed connections.
-        self.connections
-            .retain(|c| !matches!(c.borrow().state(), State::Closed(_)));
+        self.process_next_output(now, max_datagrams)
+    }
 
-        maybe_callback
+    fn connection_ref(&self, c: &Rc<RefCell<Connection>>) -> ConnectionRef {
+        ConnectionRef {
+            c: Rc::clone(c),
+            ready: Rc::downgrade(&self.ready),
+        }
     }
 
     /// This lists the connections that have received new events
@@ -622,10 +1390,10 @@ impl Server {
     )]
     #[must_use]
     pub fn active_connections(&self) -> HashSet<ConnectionRef> {
-        self.connections
-            .iter()
-            .filter(|c| c.borrow().has_events())
-            .map(|c| ConnectionRef { c: Rc::clone(c) })
+        self.ready
+            .borrow_mut()
+            .active()
+            .map(|c| self.connection_ref(c))
             .collect()
     }
 
@@ -633,28 +1401,56 @@ impl Server {
     /// `process()`.
     #[must_use]
     pub fn has_active_connections(&self) -> bool {
-        self.connections.iter().any(|c| c.borrow().has_events())
+        self.ready.borrow_mut().active().next().is_some()
+    }
+}
+
+impl Provider for Server {
+    type Event = ServerEvent;
+
+    fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    fn next_event(&mut self) -> Option<Self::Event> {
+        self.events.pop_front()
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct ConnectionRef {
     c: Rc<RefCell<Connection>>,
+    /// The server only looks for output from connections that might have
+    /// changed, so mutable access is reported here.
+    ready: Weak<RefCell<ReadyConnections>>,
 }
 
 impl ConnectionRef {
+    /// Tell the server that the application might have changed this connection.
+    fn wake(&self) {
+        if let Some(ready) = self.ready.upgrade() {
+            ready.borrow_mut().push(&self.c);
+        }
+    }
+
     #[must_use]
     pub fn borrow(&self) -> impl Deref<Target = Connection> + '_ {
         self.c.borrow()
     }
 
+    /// Access the connection mutably.  The server checks the connection for
+    /// output the next time that it is processed.
     #[must_use]
     pub fn borrow_mut(&self) -> impl DerefMut<Target = Connection> + '_ {
+        self.wake();
         self.c.borrow_mut()
     }
 
+    /// Get the connection.  As this allows the connection to be changed, the
+    /// server checks the connection for output the next time that it is processed.
     #[must_use]
     pub fn connection(&self) -> Rc<RefCell<Connection>> {
+        self.wake();
         Rc::clone(&self.c)
     }
 }
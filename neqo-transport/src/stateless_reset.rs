@@ -6,10 +6,18 @@
 
 //! Stateless Reset Token implementation.
 
+use std::{
+    fmt::{self, Debug, Formatter},
+    time::{Duration, Instant},
+};
+
 use neqo_common::Decoder;
-use nss::random;
+use nss::{
+    Mode, RecordProtection as Aead, RecordProtectionOps as _, SymKey, TLS_AES_128_GCM_SHA256,
+    TLS_VERSION_1_3, hkdf, random, randomize,
+};
 
-use crate::Error;
+use crate::{Error, Res, packet};
 
 /// A stateless reset token is a 16-byte value that is used to identify
 /// a stateless reset packet.
@@ -75,6 +83,129 @@ impl AsRef<[u8]> for Token {
     }
 }
 
+/// A static key that is used to derive stateless reset tokens from connection IDs,
+/// as described in Section 10.3.2 of RFC 9000.
+///
+/// A server that uses the same key after a restart can send stateless resets
+/// for connections that it no longer has state for.
+pub struct Key(SymKey);
+
+impl Key {
+    /// The label prefix used when deriving a per-connection ID key.
+    const LABEL_PREFIX: &str = "quic sr ";
+
+    /// Create a key from the given secret.
+    ///
+    /// # Errors
+    /// When the secret cannot be imported.
+    pub fn new(secret: &[u8]) -> Res<Self> {
+        Ok(Self(hkdf::import_key(TLS_VERSION_1_3, secret)?))
+    }
+
+    /// Derive the stateless reset token for a connection ID.
+    ///
+    /// This uses HKDF-Extract, keyed with the static key, over the connection ID.
+    /// The token is the authentication tag from encrypting an empty message
+    /// with the resulting secret.
+    ///
+    /// # Errors
+    /// When the connection ID is empty or the cryptographic operations fail.
+    pub fn token(&self, cid: &[u8]) -> Res<Token> {
+        if cid.is_empty() {
+            return Err(Error::InvalidInput);
+        }
+        let ikm = hkdf::import_key(TLS_VERSION_1_3, cid)?;
+        let secret = hkdf::extract(TLS_VERSION_1_3, TLS_AES_128_GCM_SHA256, Some(&self.0), &ikm)?;
+        let aead = Aead::new(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            &secret,
+            Self::LABEL_PREFIX,
+            Mode::Encrypt,
+        )?;
+        let mut buf = [0; Token::LEN];
+        Token::try_from(aead.encrypt(0, &[], &[], &mut buf)?)
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "stateless_reset::Key")
+    }
+}
+
+/// The smallest stateless reset: a short header byte, 32 unpredictable bits,
+/// and the token.  This is the 38 unpredictable bits required by RFC 9000.
+pub const MIN_LEN: usize = 1 + 4 + Token::LEN;
+/// Stateless resets are only made as large as this.  RFC 9000 recommends
+/// making them one byte shorter than a packet that is this size or smaller.
+pub const MAX_LEN: usize = 43;
+
+/// The size of the stateless reset to send in response to a datagram of
+/// `received` bytes, if any.  The reset is always smaller than the datagram
+/// that triggered it, so that two endpoints cannot loop forever.
+#[must_use]
+pub fn response_len(received: usize) -> Option<usize> {
+    (received > MIN_LEN).then(|| received.saturating_sub(1).min(MAX_LEN))
+}
+
+/// Build a stateless reset of `len` bytes carrying `token`.
+/// The packet is made to look like a short header packet.
+#[must_use]
+pub fn build(token: &Token, len: usize) -> Vec<u8> {
+    debug_assert!(len >= MIN_LEN);
+    let mut d = vec![0; len - Token::LEN];
+    randomize(&mut d);
+    d[0] = (d[0] & !packet::BIT_LONG) | packet::BIT_FIXED_QUIC;
+    d.extend_from_slice(token.as_bytes());
+    d
+}
+
+/// Limits how many stateless resets are sent in each period.
+/// The server also uses this to limit Version Negotiation packets.
+#[derive(Debug)]
+pub struct RateLimit {
+    /// The number of stateless resets allowed in each period.
+    limit: usize,
+    /// When the current period started.
+    start: Option<Instant>,
+    /// The number of stateless resets sent in the current period.
+    count: usize,
+}
+
+impl RateLimit {
+    /// By default, allow this many stateless resets in each period.
+    pub const DEFAULT_LIMIT: usize = 100;
+    /// The period over which stateless resets are counted.
+    const PERIOD: Duration = Duration::from_secs(1);
+
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            start: None,
+            count: 0,
+        }
+    }
+
+    pub const fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Determine whether a stateless reset can be sent at `now`.  If it can,
+    /// it is counted against the limit.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.start.is_none_or(|start| now >= start + Self::PERIOD) {
+            self.start = Some(now);
+            self.count = 0;
+        }
+        if self.count >= self.limit {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -124,4 +255,51 @@ mod tests {
         let result = Token::try_from(&bytes[..]);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn derived_token() {
+        nss::init().unwrap();
+        let key = Key::new(&[7; 32]).unwrap();
+        let token = key.token(&[1, 2, 3, 4]).unwrap();
+        // The same key and connection ID produce the same token.
+        assert_eq!(
+            token,
+            Key::new(&[7; 32]).unwrap().token(&[1, 2, 3, 4]).unwrap()
+        );
+        // A different connection ID or key produces a different token.
+        assert_ne!(token, key.token(&[1, 2, 3, 5]).unwrap());
+        assert_ne!(
+            token,
+            Key::new(&[8; 32]).unwrap().token(&[1, 2, 3, 4]).unwrap()
+        );
+        assert!(key.token(&[]).is_err());
+    }
+
+    #[test]
+    fn response_len_smaller() {
+        assert_eq!(response_len(MIN_LEN), None);
+        assert_eq!(response_len(MIN_LEN + 1), Some(MIN_LEN));
+        assert_eq!(response_len(MAX_LEN), Some(MAX_LEN - 1));
+        assert_eq!(response_len(1200), Some(MAX_LEN));
+    }
+
+    #[test]
+    fn build_reset() {
+        nss::init().unwrap();
+        let token = Token::new([9; Token::LEN]);
+        let d = build(&token, MIN_LEN);
+        assert_eq!(d.len(), MIN_LEN);
+        assert_eq!(d[0] & 0xc0, 0x40);
+        assert_eq!(&d[MIN_LEN - Token::LEN..], token.as_bytes());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let now = test_fixture::now();
+        let mut limit = RateLimit::new(2);
+        assert!(limit.allow(now));
+        assert!(limit.allow(now));
+        assert!(!limit.allow(now));
+        assert!(limit.allow(now + RateLimit::PERIOD));
+    }
 }
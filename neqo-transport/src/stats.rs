@@ -9,16 +9,21 @@
 use std::{
     cell::RefCell,
     fmt::{self, Debug},
+    num::NonZeroU64,
     ops::{Deref, DerefMut},
     rc::Rc,
     time::Duration,
 };
 
-use enum_map::EnumMap;
+use enum_map::{Enum, EnumMap};
 use neqo_common::{Dscp, Ecn, qdebug};
 use strum::IntoEnumIterator as _;
 
-use crate::{cc::CongestionTrigger, ecn, packet, version::Version};
+use crate::{
+    cc::CongestionTrigger,
+    ecn, packet,
+    version::{self, Version},
+};
 
 #[derive(Default, Clone, PartialEq, Eq)]
 pub struct FrameStats {
@@ -134,6 +139,9 @@ pub struct DatagramStats {
     /// The number of datagrams dropped due to reaching the limit of the
     /// outgoing queue.
     pub dropped_queue_full: usize,
+    /// The number of datagrams dropped because they were not sent before
+    /// they expired.
+    pub expired: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -236,6 +244,10 @@ pub struct CongestionControlStats {
     /// occurred or Cubic is not in use. Recorded as a stat to approximate a connection's ideal
     /// congestion window in metrics.
     pub w_max: Option<f64>,
+    /// The congestion window that careful resume jumped to, if it did.
+    pub careful_resume_jump: Option<usize>,
+    /// Whether careful resume retreated because of congestion before the jump was validated.
+    pub careful_resume_retreat: bool,
 }
 
 impl Debug for CongestionControlStats {
@@ -366,6 +378,8 @@ pub struct Stats {
     /// The QUIC version in use. After the handshake completes this reflects the
     /// version negotiated via compatible version negotiation (RFC 9368).
     pub version: Version,
+    /// How the version was negotiated, once the handshake has settled on one.
+    pub version_negotiation: Option<version::Negotiation>,
 
     /// Total packets received, including all the bad ones.
     pub packets_rx: usize,
@@ -388,6 +402,10 @@ pub struct Stats {
     pub pto_ack: usize,
     /// Number of times we had to drop an unacknowledged ACK range.
     pub unacked_range_dropped: usize,
+    /// Number of times that the thresholds for loss detection were widened after
+    /// a spurious loss, or reset after a run of real losses.
+    /// This only changes if adaptive reordering is enabled.
+    pub reordering_adaptations: usize,
     /// Number of PMTUD probes sent.
     pub pmtud_tx: usize,
     /// Number of PMTUD probes ACK'ed.
@@ -433,6 +451,15 @@ pub struct Stats {
     pub bytes_lost: usize,
     /// Total UDP payload bytes in acknowledged packets.
     pub bytes_acked: usize,
+    /// The most recent delivery rate sample on the primary path, in bytes per
+    /// second.  This is the goodput that the connection currently achieves.
+    pub delivery_rate: Option<u64>,
+    /// The highest delivery rate sample on the primary path that was not
+    /// app-limited, in bytes per second.
+    pub max_delivery_rate: Option<u64>,
+    /// The maximum send rate on the primary path, in bits per second.  This is
+    /// the lower of the rate set by the application and any SCONE advice.
+    pub max_send_rate: Option<NonZeroU64>,
 
     /// ECN path validation count, indexed by validation outcome.
     pub ecn_path_validation: ecn::ValidationCount,
@@ -499,7 +526,11 @@ impl Stats {
 impl Debug for Stats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "stats for {}", self.info)?;
-        writeln!(f, "  version: {:?}", self.version)?;
+        writeln!(
+            f,
+            "  version: {:?} negotiation {:?}",
+            self.version, self.version_negotiation
+        )?;
         writeln!(
             f,
             "  rx: {} drop {} dup {} saved {}",
@@ -507,8 +538,13 @@ impl Debug for Stats {
         )?;
         writeln!(
             f,
-            "  tx: {} lost {} lateack {} ptoack {} unackdrop {}",
-            self.packets_tx, self.lost, self.late_ack, self.pto_ack, self.unacked_range_dropped
+            "  tx: {} lost {} lateack {} ptoack {} unackdrop {} reorder {}",
+            self.packets_tx,
+            self.lost,
+            self.late_ack,
+            self.pto_ack,
+            self.unacked_range_dropped,
+            self.reordering_adaptations
         )?;
         writeln!(f, "  cc:")?;
         self.cc.fmt(f)?;
@@ -546,6 +582,12 @@ impl Debug for Stats {
             "  bytes: rx {} lost {} acked {}",
             self.bytes_rx, self.bytes_lost, self.bytes_acked
         )?;
+        writeln!(
+            f,
+            "  delivery_rate: {:?} max {:?}",
+            self.delivery_rate, self.max_delivery_rate
+        )?;
+        writeln!(f, "  max_send_rate: {:?}", self.max_send_rate)?;
         writeln!(f, "  rtt: {:?} rttvar: {:?}", self.rtt, self.rttvar)?;
         writeln!(f, "  min_rtt: {:?}", self.min_rtt)
     }
@@ -569,6 +611,69 @@ impl Debug for StatsCell {
     }
 }
 
+/// Why a server dropped a datagram without passing it to a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum DropReason {
+    /// The first packet in the datagram could not be parsed.
+    Malformed,
+    /// A short header packet for an unknown connection, which was not answered
+    /// with a stateless reset.
+    UnknownConnection,
+    /// A packet with an unsupported version that was too short to be answered
+    /// with Version Negotiation.
+    UnsupportedVersionTooShort,
+    /// A packet with an unsupported version from a source that has reached
+    /// the Version Negotiation rate limit.
+    VersionNegotiationLimit,
+    /// An Initial packet in a datagram that was too short.
+    InitialTooShort,
+    /// An Initial packet with a token that was not valid.
+    InvalidToken,
+    /// An Initial packet that needed a Retry, but one couldn't be made.
+    RetryFailed,
+    /// An Initial packet that the admission policy dropped.
+    Admission,
+    /// An Initial packet for which a connection couldn't be created.
+    ConnectionFailed,
+    /// A 0-RTT packet for an unknown connection.
+    ZeroRtt,
+    /// Another long header packet for an unknown connection.
+    NotInitial,
+    /// A datagram for a new connection whose `ClientHello` didn't arrive in time
+    /// or was too long for a virtual host to be chosen.
+    ClientHello,
+}
+
+/// Server statistics, covering events that aren't specific to a connection.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Number of times that the keys protecting address validation tokens
+    /// changed, either on schedule or because the application set them.
+    pub token_key_rotations: usize,
+    /// Number of new connections that the admission policy accepted.  Without a
+    /// policy, this counts every Initial packet that would start a connection.
+    pub admission_accepted: usize,
+    /// Number of new connections that the admission policy sent a Retry.
+    pub admission_retried: usize,
+    /// Number of new connections that the admission policy refused.
+    pub admission_refused: usize,
+    /// Number of Initial packets that the admission policy dropped.
+    pub admission_dropped: usize,
+    /// Number of datagrams that were dropped, for each reason.
+    pub dropped: EnumMap<DropReason, usize>,
+    /// Number of Version Negotiation packets sent.
+    pub version_negotiations_tx: usize,
+    /// Number of Retry packets sent.
+    pub retries_tx: usize,
+    /// Number of connections created, including those that were refused.
+    pub connections_created: usize,
+    /// Number of connections that closed and were removed.
+    pub connections_closed: usize,
+    /// Number of new connections that were refused because the virtual host
+    /// selector didn't choose a host for them.
+    pub virtual_host_refused: usize,
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -621,9 +726,9 @@ fn debug() {
     assert_eq!(
         format!("{stats:?}"),
         "stats for\u{0020}
-  version: Version1
+  version: Version1 negotiation None
   rx: 0 drop 0 dup 0 saved 0
-  tx: 0 lost 0 lateack 0 ptoack 0 unackdrop 0
+  tx: 0 lost 0 lateack 0 ptoack 0 unackdrop 0 reorder 0
   cc:
     cwnd 0 in_flight 0
     ce_loss 0 ce_ecn 0 ce_spurious 0
@@ -652,10 +757,12 @@ fn debug() {
     tx:
     acked:
     rx:
-    path validation outcomes: ValidationCount({Capable: 0, NotCapable(BlackHole): 0, NotCapable(Bleaching): 0, NotCapable(ReceivedUnsentECT1): 0})
+    path validation outcomes: ValidationCount({Capable: 0, NotCapable(BlackHole): 0, NotCapable(Bleaching): 0, NotCapable(ReceivedUnsentECT0): 0, NotCapable(ReceivedUnsentECT1): 0})
     mark transitions:
   dscp:\x20
   bytes: rx 0 lost 0 acked 0
+  delivery_rate: None max None
+  max_send_rate: None
   rtt: 0ns rttvar: 0ns
   min_rtt: 0ns\n"
     );
@@ -12,7 +12,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neqo_common::{Buffer, Role, qtrace, qwarn};
+use neqo_common::{Buffer, Decoder, Encoder, Role, qtrace, qwarn};
 
 use crate::{
     AppError, ConnectionEvents, Error, Res,
@@ -386,6 +386,32 @@ impl Streams {
         self.recv.clear();
     }
 
+    /// Write out the flow control state, so that it can be restored with [`Self::import`].
+    /// This only works if there are no streams.
+    pub(crate) fn export(&self, enc: &mut Encoder) -> Res<()> {
+        if !self.send.is_empty() || !self.recv.is_empty() {
+            return Err(Error::ConnectionState);
+        }
+        self.sender_fc.borrow().export(enc);
+        self.receiver_fc.borrow().export(enc)?;
+        for st in [StreamType::BiDi, StreamType::UniDi] {
+            self.remote_stream_limits[st].export(enc)?;
+            self.local_stream_limits[st].export(enc);
+        }
+        Ok(())
+    }
+
+    /// Restore the flow control state from the output of [`Self::export`].
+    pub(crate) fn import(&mut self, dec: &mut Decoder) -> Res<()> {
+        self.sender_fc.borrow_mut().import(dec)?;
+        self.receiver_fc.borrow_mut().import(dec)?;
+        for st in [StreamType::BiDi, StreamType::UniDi] {
+            self.remote_stream_limits[st].import(dec)?;
+            self.local_stream_limits[st].import(dec)?;
+        }
+        Ok(())
+    }
+
     /// # Errors
     /// When the stream does not exist or has no more data.
     ///
@@ -395,6 +421,21 @@ impl Streams {
         self.recv.read(stream_id, data)
     }
 
+    /// # Errors
+    /// When the stream does not exist or has no more data.
+    pub fn recv_peek(&self, stream_id: StreamId) -> Res<&[u8]> {
+        self.recv.get(stream_id)?.peek()
+    }
+
+    /// # Errors
+    /// When the stream does not exist or has no more data.
+    ///
+    /// # Returns
+    /// `(bytes_consumed, fin)` where `fin` is `true` when the stream has ended.
+    pub fn recv_consume(&mut self, stream_id: StreamId, n: usize) -> Res<(usize, bool)> {
+        self.recv.consume(stream_id, n)
+    }
+
     /// # Errors
     /// When the stream does not exist.
     pub fn stop_sending(&mut self, stream_id: StreamId, err: AppError) -> Res<()> {
@@ -645,4 +686,28 @@ impl Streams {
     pub fn need_keep_alive(&self) -> bool {
         self.recv.need_keep_alive()
     }
+
+    /// Set a deadline for data written to a send stream.
+    ///
+    /// # Errors
+    /// When the stream does not exist or is already closed.
+    pub fn set_send_deadline(
+        &mut self,
+        stream_id: StreamId,
+        deadline: Option<Instant>,
+        err: AppError,
+    ) -> Res<()> {
+        self.send.set_deadline(stream_id, deadline, err)
+    }
+
+    /// When data on a send stream next expires.
+    #[must_use]
+    pub fn next_send_deadline(&self) -> Option<Instant> {
+        self.send.next_deadline()
+    }
+
+    /// Reset send streams with data that was not acknowledged by its deadline.
+    pub fn expire_send(&mut self, now: Instant) {
+        self.send.expire(now);
+    }
 }
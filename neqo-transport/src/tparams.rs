@@ -721,6 +721,8 @@ pub struct TransportParametersHandler {
     role: Role,
     versions: version::Config,
     version_selected: bool,
+    /// On a server, chooses the version for a compatible upgrade.
+    version_policy: Option<version::PolicyState>,
     local: TransportParameters,
     remote_handshake: Option<TransportParameters>,
     remote_0rtt: Option<TransportParameters>,
@@ -735,6 +737,7 @@ impl TransportParametersHandler {
             role,
             versions,
             version_selected: false,
+            version_policy: None,
             local,
             remote_handshake: None,
             remote_0rtt: None,
@@ -749,6 +752,21 @@ impl TransportParametersHandler {
         self.local.set_versions(self.role, &self.versions);
     }
 
+    /// Set the policy that chooses the version for a compatible upgrade.
+    pub(crate) fn set_version_policy(&mut self, policy: version::PolicyState) {
+        debug_assert_eq!(self.role, Role::Server);
+        self.version_policy = Some(policy);
+    }
+
+    /// Provide `ClientHello` data, so that a version policy can see the server name.
+    pub(crate) fn client_hello(&mut self, data: &[u8]) {
+        if !self.version_selected
+            && let Some(policy) = &mut self.version_policy
+        {
+            policy.client_hello(data);
+        }
+    }
+
     /// # Panics
     /// When this function is called before the peer has provided transport parameters.
     /// Do not call this function if you are not also able to send data.
@@ -803,7 +821,10 @@ impl TransportParametersHandler {
                 return Err(Error::TransportParameter);
             }
 
-            if let Some(preferred) = self.versions.preferred_compatible(other) {
+            if let Some(mut preferred) = self.versions.preferred_compatible(other) {
+                if let Some(policy) = &mut self.version_policy {
+                    preferred = policy.choose(&self.versions, preferred, other);
+                }
                 if preferred != self.versions.initial() {
                     qinfo!(
                         "Compatible upgrade {:?} ==> {preferred:?}",
@@ -831,6 +852,16 @@ impl TransportParametersHandler {
         &mut self.local
     }
 
+    /// Set the version and the transport parameters from the peer
+    /// for a connection that was established elsewhere.
+    pub(crate) fn import(&mut self, version: Version, remote: TransportParameters) {
+        debug_assert_eq!(self.role, Role::Server);
+        self.versions.set_initial(version);
+        self.local.compatible_upgrade(version);
+        self.version_selected = true;
+        self.remote_handshake = Some(remote);
+    }
+
     pub fn set_remote_0rtt(&mut self, remote_0rtt: Option<TransportParameters>) {
         self.remote_0rtt = remote_0rtt;
     }
@@ -4,10 +4,17 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::{
+    cell::RefCell,
+    fmt::{self, Debug, Formatter},
+    net::SocketAddr,
+    rc::Rc,
+};
+
 use enum_map::Enum;
 use neqo_common::qdebug;
 
-use crate::{Error, Res};
+use crate::{Error, Res, sni::find_sni};
 
 pub type Wire = u32;
 
@@ -235,6 +242,151 @@ impl Default for Config {
     }
 }
 
+/// How a connection arrived at its version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Negotiation {
+    /// The connection uses the version from the client's first Initial packet.
+    None,
+    /// The connection was upgraded to a compatible version (RFC 9368).
+    Compatible,
+    /// The client received a Version Negotiation packet and started again with
+    /// another version.  Only a client can tell that this happened.
+    Incompatible,
+}
+
+/// What a server knows about a connection when it chooses a version.
+#[derive(Debug, Clone)]
+pub struct Context {
+    initial: Version,
+    offered: Vec<Version>,
+    server_name: Option<String>,
+    peer: SocketAddr,
+    validated: bool,
+}
+
+impl Context {
+    const fn new(peer: SocketAddr, validated: bool) -> Self {
+        Self {
+            initial: Version::Version1,
+            offered: Vec::new(),
+            server_name: None,
+            peer,
+            validated,
+        }
+    }
+
+    /// The version of the client's Initial packet.
+    #[must_use]
+    pub const fn initial(&self) -> Version {
+        self.initial
+    }
+
+    /// The compatible versions that both the client and the server support,
+    /// in the server's order of preference.
+    #[must_use]
+    pub fn offered(&self) -> &[Version] {
+        &self.offered
+    }
+
+    /// The server name from the client's `ClientHello`, if it had one.
+    #[must_use]
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The address of the client.
+    #[must_use]
+    pub const fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Whether the client's address was validated, with either a Retry or a
+    /// token from a `NEW_TOKEN` frame.
+    #[must_use]
+    pub const fn validated(&self) -> bool {
+        self.validated
+    }
+}
+
+/// Chooses the version that a server uses for a connection, when the client
+/// offers compatible versions.
+pub trait Policy {
+    /// Choose between the initial version and the versions that were offered.
+    /// Returning `None`, or a version that is neither, uses the server's preference.
+    fn choose(&mut self, context: &Context) -> Option<Version>;
+}
+
+impl<F> Policy for F
+where
+    F: FnMut(&Context) -> Option<Version>,
+{
+    fn choose(&mut self, context: &Context) -> Option<Version> {
+        self(context)
+    }
+}
+
+/// A [`Policy`] and what it needs to know about one connection.
+pub(crate) struct PolicyState {
+    policy: Rc<RefCell<dyn Policy>>,
+    context: Context,
+    /// The start of the `ClientHello`, until the server name is found.
+    client_hello: Vec<u8>,
+}
+
+impl PolicyState {
+    /// Stop looking for the server name after this much of the `ClientHello`.
+    const MAX_CLIENT_HELLO: usize = 16384;
+
+    pub(crate) fn new(policy: Rc<RefCell<dyn Policy>>, peer: SocketAddr, validated: bool) -> Self {
+        Self {
+            policy,
+            context: Context::new(peer, validated),
+            client_hello: Vec::new(),
+        }
+    }
+
+    /// Look for the server name in `ClientHello` data as it arrives.
+    pub(crate) fn client_hello(&mut self, data: &[u8]) {
+        if self.context.server_name.is_some()
+            || self.client_hello.len() + data.len() > Self::MAX_CLIENT_HELLO
+        {
+            return;
+        }
+        self.client_hello.extend_from_slice(data);
+        if let Some(range) = find_sni(&self.client_hello) {
+            self.context.server_name =
+                Some(String::from_utf8_lossy(&self.client_hello[range]).into_owned());
+            self.client_hello = Vec::new();
+        }
+    }
+
+    /// Choose a version, given the server's `preferred` version.
+    pub(crate) fn choose(
+        &mut self,
+        config: &Config,
+        preferred: Version,
+        other: &[Wire],
+    ) -> Version {
+        self.client_hello = Vec::new();
+        self.context.initial = config.initial();
+        self.context.offered = config
+            .compatible()
+            .filter(|v| other.contains(&v.wire_version()))
+            .copied()
+            .collect();
+        match self.policy.borrow_mut().choose(&self.context) {
+            Some(v) if v == self.context.initial || self.context.offered.contains(&v) => v,
+            _ => preferred,
+        }
+    }
+}
+
+impl Debug for PolicyState {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "version policy for {:?}", self.context)
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
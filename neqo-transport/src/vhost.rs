@@ -0,0 +1,217 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Virtual hosting for a server, based on the server name that a client asks for.
+
+use std::{
+    net::IpAddr,
+    rc::Rc,
+    str,
+    time::{Duration, Instant},
+};
+
+use neqo_common::{Datagram, Decoder, qdebug, qtrace};
+
+use crate::{
+    ConnectionParameters,
+    cid::{ConnectionId, ConnectionIdDecoder},
+    crypto::CryptoStates,
+    frame::Frame,
+    packet::{self, Public},
+    recv_stream::RxStreamOrderer,
+    server::InitialDetails,
+    sni::find_sni,
+};
+
+/// The configuration for one of the hosts that a server serves.
+#[derive(Debug, Clone)]
+pub struct VirtualHost {
+    /// The names of certificates.
+    certs: Vec<String>,
+    /// The ALPN values that the host supports.
+    protocols: Vec<String>,
+    /// Connection parameters.
+    conn_params: ConnectionParameters,
+}
+
+impl VirtualHost {
+    /// Create a host that uses `certs`, the preference list of ALPN values in `protocols`,
+    /// and `conn_params` for its connections.
+    ///
+    /// Versions are negotiated before the host is chosen, so `conn_params` needs to
+    /// enable the versions that are enabled for the server.
+    #[must_use]
+    pub fn new<A1: AsRef<str>, A2: AsRef<str>>(
+        certs: &[A1],
+        protocols: &[A2],
+        conn_params: ConnectionParameters,
+    ) -> Self {
+        Self {
+            certs: certs.iter().map(|x| String::from(x.as_ref())).collect(),
+            protocols: protocols.iter().map(|x| String::from(x.as_ref())).collect(),
+            conn_params,
+        }
+    }
+
+    /// The names of certificates.
+    #[must_use]
+    pub fn certs(&self) -> &[String] {
+        &self.certs
+    }
+
+    /// The ALPN values that the host supports.
+    #[must_use]
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Connection parameters.
+    #[must_use]
+    pub const fn conn_params(&self) -> &ConnectionParameters {
+        &self.conn_params
+    }
+}
+
+/// Chooses the host that serves a new connection.
+///
+/// This is passed the server name from the client's `ClientHello`, or `None` if the client
+/// didn't include one.  The name is passed as it was sent, so the selector needs to ignore
+/// case if that matters.  Returning `None` refuses the connection.
+pub trait VirtualHostSelector {
+    fn select(&mut self, sni: Option<&str>) -> Option<Rc<VirtualHost>>;
+}
+
+impl<F> VirtualHostSelector for F
+where
+    F: FnMut(Option<&str>) -> Option<Rc<VirtualHost>>,
+{
+    fn select(&mut self, sni: Option<&str>) -> Option<Rc<VirtualHost>> {
+        self(sni)
+    }
+}
+
+/// A new connection that is waiting for the rest of its `ClientHello`
+/// before a host can be chosen for it.
+pub struct PendingHello {
+    initial: InitialDetails,
+    orig_dcid: Option<ConnectionId>,
+    /// Initial keys for reading the client's packets.
+    states: CryptoStates,
+    /// `CRYPTO` frames that arrived out of order.
+    orderer: RxStreamOrderer,
+    /// The contiguous start of the `ClientHello`.
+    hello: Vec<u8>,
+    /// The datagrams that are passed to the connection once it is created.
+    dgrams: Vec<Datagram>,
+    /// When the first datagram arrived.
+    start: Instant,
+}
+
+impl PendingHello {
+    /// The number of datagrams that can carry a `ClientHello`.
+    pub const MAX_DATAGRAMS: usize = 4;
+    /// How long to wait for the rest of the `ClientHello`.
+    pub const TIMEOUT: Duration = Duration::from_secs(5);
+    /// How many connections can wait for the rest of their `ClientHello` at once.
+    pub const MAX_PENDING: usize = 1_024;
+
+    pub fn new(initial: InitialDetails, orig_dcid: Option<ConnectionId>, now: Instant) -> Self {
+        Self {
+            initial,
+            orig_dcid,
+            states: CryptoStates::default(),
+            orderer: RxStreamOrderer::new(),
+            hello: Vec::new(),
+            dgrams: Vec::new(),
+            start: now,
+        }
+    }
+
+    /// The connection ID that datagrams for this connection are sent to.
+    pub const fn dcid(&self) -> &ConnectionId {
+        self.initial.dst_cid()
+    }
+
+    pub const fn start(&self) -> Instant {
+        self.start
+    }
+
+    pub const fn datagrams(&self) -> usize {
+        self.dgrams.len()
+    }
+
+    /// The address of the client, from the first datagram.
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.dgrams.first().map(|d| d.source().ip())
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.datagrams() >= Self::MAX_DATAGRAMS
+    }
+
+    /// Save `dgram` and read any `ClientHello` bytes from the Initial packets in it.
+    pub fn add(&mut self, dgram: Datagram, dcid_decoder: &dyn ConnectionIdDecoder) {
+        let mut copy = dgram.clone();
+        self.dgrams.push(dgram);
+        let mut data = &mut copy[..];
+        while !data.is_empty() {
+            let Ok((packet, remainder)) = Public::decode_server(data, dcid_decoder) else {
+                break;
+            };
+            data = remainder;
+            if packet.packet_type() != packet::Type::Initial
+                || packet.dcid() != *self.initial.dst_cid()
+            {
+                continue;
+            }
+            let Some(version) = packet.version() else {
+                continue;
+            };
+            if self
+                .states
+                .init_server(version, self.initial.dst_cid(), false)
+                .is_err()
+            {
+                break;
+            }
+            let Ok(payload) = packet.decrypt(&mut self.states, self.start) else {
+                qtrace!("Unable to decrypt Initial for {}", self.initial.dst_cid());
+                continue;
+            };
+            let mut dec = Decoder::from(&payload[..]);
+            while dec.remaining() > 0 {
+                match Frame::decode(&mut dec) {
+                    Ok(Frame::Crypto { offset, data }) => self.orderer.inbound_frame(offset, data),
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        }
+        self.orderer.read_to_end(&mut self.hello);
+    }
+
+    /// The server name from the `ClientHello`.  This is `None` if more of the
+    /// `ClientHello` is needed, and `Some(None)` if it doesn't include a server name.
+    pub fn sni(&self) -> Option<Option<&str>> {
+        if let Some(range) = find_sni(&self.hello) {
+            return Some(str::from_utf8(&self.hello[range]).ok());
+        }
+        let mut dec = Decoder::from(&self.hello[..]);
+        if dec.decode_uint::<u8>()? == 1 {
+            // Wait for the rest of the message.
+            dec.decode_vec(3)?;
+        }
+        qdebug!(
+            "ClientHello for {} has no server name",
+            self.initial.dst_cid()
+        );
+        Some(None)
+    }
+
+    pub fn take(self) -> (InitialDetails, Option<ConnectionId>, Vec<Datagram>) {
+        (self.initial, self.orig_dcid, self.dgrams)
+    }
+}
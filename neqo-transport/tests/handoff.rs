@@ -0,0 +1,182 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{connect, default_server};
+use neqo_common::event::Provider as _;
+use neqo_transport::{
+    Connection, ConnectionEvent, Error, HandoffKey, State, StreamType,
+    server::{ConnectionRef, Server},
+};
+use test_fixture::{default_client, now};
+
+const SECRET: &[u8] = &[0x5a; 32];
+
+/// Connect and wait for the client to acknowledge everything that the server sent,
+/// so that the server connection can be exported.
+fn idle_connection(client: &mut Connection, server: &mut Server) -> (ConnectionRef, Instant) {
+    let server_conn = connect(client, server);
+    let now = now() + client.process_output(now()).callback();
+    let ack = client.process_output(now).dgram();
+    assert!(ack.is_some());
+    assert!(server.process(ack, now).dgram().is_none());
+    (server_conn, now)
+}
+
+#[test]
+fn handoff() {
+    let key = HandoffKey::new(SECRET, now()).unwrap();
+    let mut client = default_client();
+    let mut server_a = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server_a);
+
+    let blob = server_conn.borrow_mut().export(&key, now).unwrap();
+    assert!(server_conn.borrow().state().closed());
+
+    // The connection continues on another server.
+    let mut server_b = default_server();
+    let imported = server_b.import_connection(&key, &blob, now).unwrap();
+    assert_eq!(*imported.borrow().state(), State::Confirmed);
+    assert!(imported.borrow().tls_info().is_none());
+    assert_eq!(server_b.stats().connections_created, 1);
+
+    let stream_id = client.stream_create(StreamType::BiDi).unwrap();
+    client.stream_send(stream_id, b"hello").unwrap();
+    let dgram = client.process_output(now).dgram();
+    assert!(dgram.is_some());
+    _ = server_b.process(dgram, now);
+    assert!(imported.borrow_mut().events().any(
+        |e| matches!(e, ConnectionEvent::RecvStreamReadable { stream_id: id } if id == stream_id)
+    ));
+    let mut buf = [0; 16];
+    let (len, _) = imported
+        .borrow_mut()
+        .stream_recv(stream_id, &mut buf)
+        .unwrap();
+    assert_eq!(&buf[..len], b"hello");
+
+    imported
+        .borrow_mut()
+        .stream_send(stream_id, b"world")
+        .unwrap();
+    let dgram = server_b.process_output(now).dgram();
+    assert!(dgram.is_some());
+    client.process_input(dgram.unwrap(), now);
+    let (len, _) = client.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"world");
+    assert_eq!(*client.state(), State::Confirmed);
+}
+
+#[test]
+fn handoff_wrong_key() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server);
+    let blob = server_conn
+        .borrow_mut()
+        .export(&HandoffKey::new(SECRET, now()).unwrap(), now)
+        .unwrap();
+
+    let other = HandoffKey::new(&[0xa5; 32], now()).unwrap();
+    assert!(
+        default_server()
+            .import_connection(&other, &blob, now)
+            .is_err()
+    );
+}
+
+#[test]
+fn handoff_tampered() {
+    let key = HandoffKey::new(SECRET, now()).unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server);
+    let mut blob = server_conn.borrow_mut().export(&key, now).unwrap();
+
+    let last = blob.len() - 1;
+    blob[last] ^= 1;
+    assert!(
+        default_server()
+            .import_connection(&key, &blob, now)
+            .is_err()
+    );
+
+    // An unknown format is rejected too.
+    blob[last] ^= 1;
+    blob[0] ^= 0x80;
+    assert_eq!(
+        default_server()
+            .import_connection(&key, &blob, now)
+            .unwrap_err(),
+        Error::InvalidInput
+    );
+}
+
+#[test]
+fn handoff_busy() {
+    let key = HandoffKey::new(SECRET, now()).unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server);
+
+    // A connection with an open stream can't be exported.
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, &[1, 2, 3]).unwrap();
+    _ = server.process(client.process_output(now).dgram(), now);
+    assert_eq!(
+        server_conn.borrow_mut().export(&key, now).unwrap_err(),
+        Error::ConnectionState
+    );
+    assert_eq!(*server_conn.borrow().state(), State::Confirmed);
+}
+
+#[test]
+fn handoff_replayed() {
+    let key = HandoffKey::new(SECRET, now()).unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server);
+    let blob = server_conn.borrow_mut().export(&key, now).unwrap();
+
+    // A tampered copy fails, but doesn't stop the real export from being imported.
+    let mut tampered = blob.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    let mut server_b = default_server();
+    assert!(server_b.import_connection(&key, &tampered, now).is_err());
+
+    // Importing the same export twice would reuse keys and packet numbers.
+    assert!(server_b.import_connection(&key, &blob, now).is_ok());
+    assert_eq!(
+        server_b.import_connection(&key, &blob, now).unwrap_err(),
+        Error::InvalidInput
+    );
+    assert_eq!(server_b.stats().connections_created, 1);
+}
+
+#[test]
+fn handoff_expired() {
+    let key = HandoffKey::new(SECRET, now()).unwrap();
+    let mut client = default_client();
+    let mut server = default_server();
+    let (server_conn, now) = idle_connection(&mut client, &mut server);
+    let blob = server_conn.borrow_mut().export(&key, now).unwrap();
+
+    assert_eq!(
+        default_server()
+            .import_connection(&key, &blob, now + Duration::from_secs(11))
+            .unwrap_err(),
+        Error::InvalidInput
+    );
+    assert!(
+        default_server()
+            .import_connection(&key, &blob, now + Duration::from_secs(9))
+            .is_ok()
+    );
+}
@@ -4,15 +4,24 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{ops::Range, time::Duration};
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+    time::{Duration, Instant},
+};
 
-use neqo_transport::{CloseReason, ConnectionParameters, Error, State};
+use neqo_common::qlog::Qlog;
+use neqo_transport::{
+    CloseReason, CongestionControl, CongestionControlStats, CongestionController,
+    CongestionControllerFactory, ConnectionParameters, Error, Pmtud, RateSample, RttEstimate,
+    SentPacket, State,
+};
 use test_fixture::{
     boxed,
     sim::{
         Simulator,
         connection::{Node, ReachState, ReceiveData, SendData},
-        network::{Aqm, Drop, RandomDelay, TailDrop},
+        network::{Aqm, Delay, Drop, RandomDelay, TailDrop},
     },
     simulate,
 };
@@ -23,6 +32,8 @@ const ZERO: Duration = Duration::from_millis(0);
 const DELAY: Duration = Duration::from_millis(50);
 const DELAY_RANGE: Range<Duration> = DELAY..Duration::from_millis(55);
 const JITTER: Duration = Duration::from_millis(10);
+/// How long reordered datagrams are held back for.
+const REORDER: Duration = Duration::from_millis(15);
 
 const fn weeks(m: u32) -> Duration {
     Duration::from_secs(m as u64 * 60 * 60 * 24 * 7)
@@ -182,6 +193,42 @@ simulate!(
     ],
 );
 
+simulate!(
+    transfer_taildrop_reorder,
+    [
+        Node::new_client(
+            ConnectionParameters::default().adaptive_reordering(true),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_downlink().reorder(5, REORDER),
+        Node::new_server(
+            ConnectionParameters::default().adaptive_reordering(true),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_uplink().reorder(5, REORDER),
+    ],
+);
+
+simulate!(
+    transfer_delay_reorder,
+    [
+        Node::new_client(
+            ConnectionParameters::default().adaptive_reordering(true),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        Delay::new(DELAY).reorder(10, REORDER),
+        Node::new_server(
+            ConnectionParameters::default().adaptive_reordering(true),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        Delay::new(DELAY).reorder(10, REORDER),
+    ],
+);
+
 simulate!(
     transfer_taildrop_ecn,
     [
@@ -192,6 +239,207 @@ simulate!(
     ],
 );
 
+simulate!(
+    transfer_taildrop_bbr,
+    [
+        Node::new_client(
+            ConnectionParameters::default().congestion_control(CongestionControl::Bbr),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_downlink(),
+        Node::new_server(
+            ConnectionParameters::default().congestion_control(CongestionControl::Bbr),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_uplink(),
+    ],
+);
+
+/// A congestion controller with a fixed window, implemented using only the public API.
+#[derive(Debug)]
+struct FixedWindow {
+    pmtud: Pmtud,
+    bytes_in_flight: usize,
+}
+
+impl FixedWindow {
+    const CWND: usize = 64 * 1024;
+}
+
+impl Display for FixedWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FixedWindow")
+    }
+}
+
+impl CongestionController for FixedWindow {
+    fn set_qlog(&mut self, _qlog: Qlog) {}
+
+    fn cwnd(&self) -> usize {
+        Self::CWND
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    fn cwnd_avail(&self) -> usize {
+        Self::CWND.saturating_sub(self.bytes_in_flight)
+    }
+
+    fn cwnd_min(&self) -> usize {
+        self.pmtud.plpmtu() * 2
+    }
+
+    fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[SentPacket],
+        _rate_sample: &RateSample,
+        _rtt_est: &RttEstimate,
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) {
+        for pkt in acked_pkts.iter().filter(|pkt| pkt.cc_outstanding()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+    }
+
+    fn on_packets_lost(
+        &mut self,
+        _first_rtt_sample_time: Option<Instant>,
+        _prev_largest_acked_sent: Option<Instant>,
+        _pto: Duration,
+        lost_packets: &[SentPacket],
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        for pkt in lost_packets.iter().filter(|pkt| pkt.cc_in_flight()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+        false
+    }
+
+    fn on_ecn_ce_received(
+        &mut self,
+        _largest_acked_pkt: &SentPacket,
+        _ce_marks: u64,
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        false
+    }
+
+    fn recovery_packet(&self) -> bool {
+        false
+    }
+
+    fn discard(&mut self, pkt: &SentPacket, _now: Instant) {
+        if pkt.cc_outstanding() {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+    }
+
+    fn on_packet_sent(&mut self, pkt: &SentPacket, _now: Instant) {
+        if pkt.cc_in_flight() {
+            self.bytes_in_flight += pkt.len();
+        }
+    }
+
+    fn discard_in_flight(&mut self, _now: Instant) {
+        self.bytes_in_flight = 0;
+    }
+}
+
+#[derive(Debug)]
+struct FixedWindowFactory;
+
+impl CongestionControllerFactory for FixedWindowFactory {
+    fn create(&self, pmtud: Pmtud, _now: Instant) -> Box<dyn CongestionController> {
+        Box::new(FixedWindow {
+            pmtud,
+            bytes_in_flight: 0,
+        })
+    }
+}
+
+simulate!(
+    transfer_taildrop_custom_cc,
+    [
+        Node::new_client(
+            ConnectionParameters::default()
+                .congestion_controller_factory(Box::new(FixedWindowFactory)),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_downlink(),
+        Node::new_server(
+            ConnectionParameters::default()
+                .congestion_controller_factory(Box::new(FixedWindowFactory)),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_uplink(),
+    ],
+);
+
+simulate!(
+    transfer_delay_drop_bbr,
+    [
+        Node::new_client(
+            ConnectionParameters::default().congestion_control(CongestionControl::Bbr),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+        Node::new_server(
+            ConnectionParameters::default().congestion_control(CongestionControl::Bbr),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+    ],
+);
+
+simulate!(
+    transfer_taildrop_l4s,
+    [
+        Node::new_client(
+            ConnectionParameters::default().l4s(true),
+            [],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::new(
+            1_000_000,
+            65_536,
+            Aqm::step(Duration::from_millis(1)),
+            Duration::from_millis(50)
+        ),
+        Node::new_server(
+            ConnectionParameters::default().l4s(true),
+            [],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::new(
+            200_000,
+            16_384,
+            Aqm::step(Duration::from_millis(1)),
+            Duration::from_millis(50)
+        ),
+    ],
+);
+
 /// This test is a nasty piece of work.  Delays are anything from 0 to 50ms and 1% of
 /// packets get dropped.
 #[test]
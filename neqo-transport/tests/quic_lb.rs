@@ -0,0 +1,146 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{self, Debug},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use neqo_common::Datagram;
+use neqo_transport::{
+    ConnectionParameters, Output, QuicLbConfig, QuicLbConnectionIdGenerator, QuicLbRouter, State,
+    server::Server,
+};
+use nss::AllowZeroRtt;
+use test_fixture::{
+    boxed, now,
+    sim::{
+        self, Simulator,
+        connection::{Node, ReachState, SendData},
+    },
+};
+
+const KEY: &[u8; 16] = &[
+    0xfd, 0xf7, 0x26, 0xa9, 0x89, 0x3e, 0xc0, 0x5c, 0x06, 0x32, 0xd3, 0x95, 0x66, 0x95, 0xba, 0xbf,
+];
+const SERVER_IDS: &[&[u8]] = &[&[0x11, 0x11], &[0x22, 0x22], &[0x33, 0x33]];
+
+fn config() -> QuicLbConfig {
+    // Longer than the 8 byte connection IDs that clients choose, so that
+    // the first Initial is never mistaken for a routable connection ID.
+    QuicLbConfig::encrypted(1, 2, 9, KEY).unwrap()
+}
+
+/// A load balancer that routes datagrams to one of several servers,
+/// using the server ID in the destination connection ID.
+struct LoadBalancer {
+    router: QuicLbRouter,
+    servers: Vec<(&'static [u8], Server)>,
+    /// The number of datagrams that were routed by connection ID, for each server.
+    routed: Rc<RefCell<Vec<usize>>>,
+    /// The number of datagrams that had to be routed some other way.
+    unrouted: Rc<Cell<usize>>,
+}
+
+impl LoadBalancer {
+    fn new(routed: &Rc<RefCell<Vec<usize>>>, unrouted: &Rc<Cell<usize>>) -> Self {
+        let servers = SERVER_IDS
+            .iter()
+            .map(|&id| {
+                let generator = QuicLbConnectionIdGenerator::new(config(), id).unwrap();
+                let server = Server::new(
+                    now(),
+                    test_fixture::DEFAULT_KEYS,
+                    test_fixture::DEFAULT_ALPN,
+                    test_fixture::anti_replay(),
+                    Box::new(AllowZeroRtt {}),
+                    Rc::new(RefCell::new(generator)),
+                    ConnectionParameters::default(),
+                )
+                .unwrap();
+                (id, server)
+            })
+            .collect();
+        routed.replace(vec![0; SERVER_IDS.len()]);
+        Self {
+            router: QuicLbRouter::new(config()),
+            servers,
+            routed: Rc::clone(routed),
+            unrouted: Rc::clone(unrouted),
+        }
+    }
+
+    fn select(&self, d: &Datagram) -> usize {
+        if let Some(id) = self.router.route(&d[..]) {
+            let i = self
+                .servers
+                .iter()
+                .position(|(sid, _)| *sid == id.as_slice())
+                .unwrap();
+            self.routed.borrow_mut()[i] += 1;
+            i
+        } else {
+            // Pick a server based on the connection ID that the client chose.
+            self.unrouted.set(self.unrouted.get() + 1);
+            let dcid = &d[6..6 + usize::from(d[5])];
+            dcid.iter().map(|&b| usize::from(b)).sum::<usize>() % self.servers.len()
+        }
+    }
+}
+
+impl sim::Node for LoadBalancer {
+    fn process(&mut self, d: Option<Datagram>, now: Instant) -> Output {
+        if let Some(d) = d {
+            let i = self.select(&d);
+            if let Output::Datagram(out) = self.servers[i].1.process(Some(d), now) {
+                return Output::Datagram(out);
+            }
+        }
+        let mut next: Option<Duration> = None;
+        for (_, server) in &mut self.servers {
+            match server.process_output(now) {
+                Output::Datagram(d) => return Output::Datagram(d),
+                Output::Callback(t) => next = Some(next.map_or(t, |n| n.min(t))),
+                Output::None => {}
+            }
+        }
+        next.map_or(Output::None, Output::Callback)
+    }
+}
+
+impl Debug for LoadBalancer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "load balancer for {} servers", self.servers.len())
+    }
+}
+
+/// A client connects through a load balancer to one of several servers.
+/// Once the client uses a connection ID that the server chose, every datagram
+/// is routed to that server.
+#[test]
+fn route_by_connection_id() {
+    let routed = Rc::default();
+    let unrouted = Rc::default();
+    Simulator::new(
+        "route_by_connection_id",
+        boxed![
+            Node::default_client(boxed![
+                ReachState::new(State::Confirmed),
+                SendData::new(100_000)
+            ]),
+            LoadBalancer::new(&routed, &unrouted),
+        ],
+    )
+    .run();
+
+    let routed = routed.borrow();
+    assert_eq!(routed.iter().filter(|&&n| n > 0).count(), 1);
+    assert!(routed.iter().sum::<usize>() > 0);
+    // The client's first flight can't be routed by server ID.
+    assert!(unrouted.get() > 0);
+}
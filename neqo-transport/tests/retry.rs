@@ -17,7 +17,7 @@ use common::{assert_dscp, connected_server, default_server, generate_ticket};
 use neqo_common::{Datagram, Encoder, Role, hex::HexWithLen, qdebug, qtrace};
 use neqo_transport::{
     CloseReason, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, State, StreamType,
-    server::ValidateAddress,
+    server::{Server, TokenKey, ValidateAddress},
 };
 use nss::{AuthenticationStatus, RecordProtectionOps as _, generate_ech_keys};
 use test_fixture::{
@@ -38,6 +38,7 @@ fn retry_basic() {
     _ = server.process(dgram, now()).dgram().unwrap(); // Retry
     let dgram = server.process(dgram2, now()).dgram().unwrap(); // Retry
     assertions::assert_retry(&dgram);
+    assert_eq!(server.stats().retries_tx, 2);
 
     let dgram = client.process(Some(dgram), now()).dgram(); // Initial w/token
     let dgram2 = client.process_output(now()).dgram(); // Initial
@@ -358,6 +359,87 @@ fn retry_bad_token() {
     assert_dscp(&client.stats());
 }
 
+fn shared_token_key_server(keys: &[(u8, u8)]) -> Server {
+    let mut server = default_server();
+    let keys = keys
+        .iter()
+        .map(|&(id, v)| TokenKey::new(id, &[v; 32]).unwrap())
+        .collect();
+    server.set_token_keys(keys, now()).unwrap();
+    server
+}
+
+/// A Retry token from one server is accepted by another server that shares its key,
+/// even when that key isn't the one the second server uses for new tokens.
+#[test]
+fn retry_shared_token_keys() {
+    let mut client = default_client();
+    let mut retry_server = shared_token_key_server(&[(1, 0x11)]);
+    retry_server.set_validation(ValidateAddress::Always);
+    let mut server = shared_token_key_server(&[(2, 0x22), (1, 0x11)]);
+    server.set_validation(ValidateAddress::Always);
+
+    let client_initial1 = client.process_output(now()).dgram();
+    assert!(client_initial1.is_some());
+    let retry = retry_server.process(client_initial1, now()).dgram();
+    assertions::assert_retry(retry.as_ref().unwrap());
+    let client_initial2 = client.process(retry, now()).dgram();
+    assert!(client_initial2.is_some());
+
+    let dgram = server.process(client_initial2, now()).dgram();
+    assert!(dgram.is_some());
+    assert_eq!(server.stats().token_key_rotations, 1);
+}
+
+/// Without a shared key, the second server can't validate the token.
+#[test]
+fn retry_unshared_token_keys() {
+    let mut client = default_client();
+    let mut retry_server = shared_token_key_server(&[(1, 0x11)]);
+    retry_server.set_validation(ValidateAddress::Always);
+    let mut server = shared_token_key_server(&[(1, 0x22)]);
+
+    let client_initial1 = client.process_output(now()).dgram();
+    let retry = retry_server.process(client_initial1, now()).dgram();
+    assert!(retry.is_some());
+    let client_initial2 = client.process(retry, now()).dgram();
+    assert!(client_initial2.is_some());
+
+    let dgram = server.process(client_initial2, now()).dgram();
+    assert!(dgram.is_none());
+}
+
+fn retry_across_rotation(grace: Duration) -> Option<Datagram> {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    server.set_token_key_rotation(Some(Duration::from_secs(1)), grace);
+    let mut client = default_client();
+
+    let dgram = client.process_output(now()).dgram(); // Initial
+    let dgram = server.process(dgram, now()).dgram(); // Retry
+    assertions::assert_retry(dgram.as_ref().unwrap());
+    let dgram = client.process(dgram, now()).dgram(); // Initial w/token
+    assert!(dgram.is_some());
+
+    // The key rotates before the token expires.
+    let later = now() + Duration::from_secs(2);
+    let dgram = server.process(dgram, later).dgram();
+    assert_eq!(server.stats().token_key_rotations, 1);
+    dgram
+}
+
+/// A Retry token is accepted after a key rotation, during the grace period.
+#[test]
+fn retry_key_rotation_grace() {
+    assert!(retry_across_rotation(Duration::from_secs(5)).is_some());
+}
+
+/// A Retry token is rejected once the grace period for its key has passed.
+#[test]
+fn retry_key_rotation_no_grace() {
+    assert!(retry_across_rotation(Duration::ZERO).is_none());
+}
+
 // This is really a client test, but we need a server with Retry to test it.
 // In this test, the client sends Initial on PTO.  The Retry should cause
 // all loss recovery timers to be reset, but we had a bug where the PTO timer
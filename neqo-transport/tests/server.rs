@@ -9,12 +9,17 @@ mod common;
 use std::{cell::RefCell, net::SocketAddr, rc::Rc, time::Duration};
 
 use common::{connect, connected_server, default_server, find_ticket, generate_ticket, new_server};
-use neqo_common::{Datagram, Decoder, Encoder, Role, qtrace};
+use neqo_common::{Datagram, Decoder, Encoder, Role, event::Provider as _, qtrace};
 use neqo_transport::{
-    CloseReason, Connection, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, Output, State,
-    StreamType, Version,
-    server::{ConnectionRef, Server, ValidateAddress},
-    version,
+    CloseReason, Connection, ConnectionEvent, ConnectionParameters, DropReason, Error,
+    MIN_INITIAL_PACKET_SIZE, Output, PreferredAddressOutcome, State, StatelessResetKey, StreamType,
+    Version,
+    server::{
+        Admission, ConnectionRef, HandshakeLimits, InitialDetails, Server, ServerEvent, ServerLoad,
+        ValidateAddress, VirtualHost,
+    },
+    tparams::PreferredAddress,
+    version::{self, Negotiation},
 };
 use nss::{
     AllowZeroRtt, AuthenticationStatus, RecordProtectionOps as _, ZeroRttCheckResult,
@@ -221,6 +226,27 @@ fn same_initial_after_connected() {
     assert_eq!(server.active_connections().len(), 2);
 }
 
+#[test]
+fn active_connections_drained() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+    assert!(server.has_active_connections());
+
+    // Once the application has taken all of the events, the connection isn't active.
+    while server_conn.borrow_mut().next_event().is_some() {}
+    assert!(!server.has_active_connections());
+    assert!(server.active_connections().is_empty());
+
+    // Data from the client makes it active again.
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream, &[1, 2, 3]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    drop(server.process(dgram, now()));
+    assert!(server.has_active_connections());
+    assert_eq!(server.active_connections().len(), 1);
+}
+
 #[test]
 fn drop_non_initial() {
     const CID: &[u8] = &[55; 8]; // not a real connection ID
@@ -276,6 +302,107 @@ fn drop_short_header_packet_for_unknown_connection() {
     assert!(server.process(Some(bogus), now()).dgram().is_none());
 }
 
+const STATELESS_RESET_KEY: &[u8] = &[0x5a; 32];
+
+fn stateless_reset_params(key: &[u8]) -> ConnectionParameters {
+    test_fixture::fixture_init();
+    ConnectionParameters::default().stateless_reset_key(StatelessResetKey::new(key).unwrap())
+}
+
+/// Connect, then have the client send data to a server that has lost all state.
+/// Returns the datagram from the client and any response from the new server.
+fn send_after_restart(client: &mut Connection, restarted: &mut Server) -> (Datagram, Output) {
+    let mut server = new_server(stateless_reset_params(STATELESS_RESET_KEY));
+    connect(client, &mut server);
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream, &[0; 100]).unwrap();
+    let dgram = client.process_output(now()).dgram().unwrap();
+    let out = restarted.process(Some(dgram.clone()), now());
+    (dgram, out)
+}
+
+/// A server that restarts with the same key sends a stateless reset
+/// that the client accepts.
+#[test]
+fn stateless_reset_after_restart() {
+    let mut client = default_client();
+    let mut restarted = new_server(stateless_reset_params(STATELESS_RESET_KEY));
+    let (dgram, out) = send_after_restart(&mut client, &mut restarted);
+
+    let reset = out.dgram().expect("a stateless reset");
+    assert!(reset.len() < dgram.len());
+    assert_eq!(reset.source(), dgram.destination());
+    assert_eq!(reset.destination(), dgram.source());
+
+    client.process_input(reset, now());
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Transport(Error::StatelessReset),
+            ..
+        }
+    ));
+}
+
+/// A stateless reset from a server with a different key is ignored.
+#[test]
+fn stateless_reset_wrong_key() {
+    let mut client = default_client();
+    let mut restarted = new_server(stateless_reset_params(&[0xa5; 32]));
+    let (_, out) = send_after_restart(&mut client, &mut restarted);
+
+    client.process_input(out.dgram().expect("a stateless reset"), now());
+    assert_eq!(*client.state(), State::Confirmed);
+}
+
+/// Stateless resets are rate limited.
+#[test]
+fn stateless_reset_rate_limit() {
+    let mut client = default_client();
+    let mut restarted = new_server(stateless_reset_params(STATELESS_RESET_KEY));
+    restarted.set_stateless_reset_limit(1);
+    let (dgram, out) = send_after_restart(&mut client, &mut restarted);
+    assert!(out.dgram().is_some());
+
+    // The limit is reached, so the next one is dropped.
+    assert!(
+        restarted
+            .process(Some(dgram.clone()), now())
+            .dgram()
+            .is_none()
+    );
+    // After a while, another stateless reset can be sent.
+    let later = now() + Duration::from_secs(1);
+    assert!(restarted.process(Some(dgram), later).dgram().is_some());
+}
+
+/// The server does not send a stateless reset that isn't smaller than the
+/// packet that triggered it.
+#[test]
+fn stateless_reset_too_short() {
+    const CID: &[u8] = &[55; 8]; // not a real connection ID
+    let mut server = new_server(stateless_reset_params(STATELESS_RESET_KEY));
+
+    let mut header = Encoder::default();
+    header
+        .encode_byte(0x40) // short header
+        .encode_vec(1, CID)
+        .encode_byte(1);
+    let mut bogus_data: Vec<u8> = header.into();
+    bogus_data.resize(21, 66);
+    assert!(
+        server
+            .process(Some(datagram(bogus_data.clone())), now())
+            .dgram()
+            .is_none()
+    );
+
+    bogus_data.push(66);
+    let reset = server.process(Some(datagram(bogus_data)), now()).dgram();
+    assert_eq!(reset.map(|d| d.len()), Some(21));
+}
+
 /// Verify that the server can read 0-RTT properly.  A more robust server would buffer
 /// 0-RTT before the handshake begins and let 0-RTT arrive for a short period after
 /// the handshake completes, but ours is for testing so it only allows 0-RTT while
@@ -709,6 +836,15 @@ fn version_negotiation_and_compatible() {
     let sconn = connected_server(&server);
     assert_eq!(client.version(), COMPAT_VERSION);
     assert_eq!(sconn.borrow().version(), COMPAT_VERSION);
+    assert_eq!(
+        client.stats().version_negotiation,
+        Some(Negotiation::Incompatible)
+    );
+    // The server can't tell that the client received Version Negotiation.
+    assert_eq!(
+        sconn.borrow().stats().version_negotiation,
+        Some(Negotiation::Compatible)
+    );
 }
 
 /// When a client resumes it remembers the version that the connection last used.
@@ -964,3 +1100,501 @@ fn saved_datagrams() {
         .dgram()
         .expect("fourth packet triggers third vn");
 }
+
+/// Complete the handshake for a client that might migrate to a preferred address,
+/// then have the client send data so that the server sees any migration.
+fn preferred_address_exchange(client: &mut Connection, server: &mut Server) -> ConnectionRef {
+    let mut dgram = None;
+    while *client.state() != State::Confirmed {
+        _ = test_fixture::maybe_authenticate(client);
+        let out = client.process(dgram, now());
+        dgram = server.process(out.dgram(), now()).dgram();
+    }
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    for _ in 0..5 {
+        client.stream_send(stream, &[0; 10]).unwrap();
+        let out = client.process(dgram, now());
+        dgram = server.process(out.dgram(), now()).dgram();
+    }
+    connected_server(server)
+}
+
+fn preferred_address_server(preferred: SocketAddr) -> Server {
+    let mut server = default_server();
+    server.set_preferred_address_selector(Some(Box::new(
+        move |_local: SocketAddr, _remote: SocketAddr| {
+            Some(PreferredAddress::new_any(None, Some(preferred)))
+        },
+    )));
+    server
+}
+
+/// The server reports when a client migrates to the preferred address
+/// that was chosen for the connection.
+#[test]
+fn preferred_address_migrated() {
+    let mut preferred = test_fixture::DEFAULT_ADDR;
+    preferred.set_port(preferred.port() + 1);
+    let mut server = preferred_address_server(preferred);
+    let mut client = default_client();
+
+    let server_conn = preferred_address_exchange(&mut client, &mut server);
+    let events = server.events().collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [ServerEvent::PreferredAddress {
+            connection: server_conn,
+            outcome: PreferredAddressOutcome::Migrated {
+                local: preferred,
+                remote: test_fixture::DEFAULT_ADDR,
+            },
+        }]
+    );
+}
+
+/// The server reports when a client doesn't migrate to the preferred address.
+#[test]
+fn preferred_address_not_migrated() {
+    let mut preferred = test_fixture::DEFAULT_ADDR;
+    preferred.set_port(preferred.port() + 1);
+    let mut server = preferred_address_server(preferred);
+    let mut client = new_client::<CountingConnectionIdGenerator>(
+        ConnectionParameters::default().disable_preferred_address(),
+    );
+
+    let server_conn = preferred_address_exchange(&mut client, &mut server);
+    assert!(!server.has_events());
+
+    // Once the client has had ample time to migrate, the server gives up.
+    _ = server.process_output(now() + Duration::from_secs(5));
+    let events = server.events().collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [ServerEvent::PreferredAddress {
+            connection: server_conn,
+            outcome: PreferredAddressOutcome::NotMigrated,
+        }]
+    );
+}
+
+/// A selector that returns `None` disables the preferred address.
+#[test]
+fn preferred_address_selector_none() {
+    let mut server = default_server();
+    server.set_preferred_address_selector(Some(Box::new(
+        |_local: SocketAddr, _remote: SocketAddr| None,
+    )));
+    let mut client = default_client();
+
+    preferred_address_exchange(&mut client, &mut server);
+    _ = server.process_output(now() + Duration::from_secs(5));
+    assert!(!server.has_events());
+    assert_eq!(client.stats().frame_tx.path_challenge, 0);
+}
+
+/// The server routes packets using connection IDs that it issues after the handshake.
+#[test]
+fn route_new_connection_id() {
+    let mut server = default_server();
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    // Migrating makes the client use a connection ID from `NEW_CONNECTION_ID`.
+    let mut local = test_fixture::DEFAULT_ADDR;
+    local.set_port(local.port() + 1);
+    client.migrate(Some(local), None, false, now()).unwrap();
+    let probe = client.process_output(now()).dgram().unwrap();
+    assert_eq!(probe.source(), local);
+
+    let resp = server.process(Some(probe), now()).dgram().unwrap();
+    assert_eq!(resp.destination(), local);
+}
+
+/// Changes that the application makes to a connection are noticed, even though
+/// the server only looks at connections that might have something to send.
+#[test]
+fn application_wakes_connection() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let server_conn = connect(&mut client, &mut server);
+
+    // Let the server settle, so that it is waiting on a timer.
+    while server.process_output(now()).dgram().is_some() {}
+
+    let stream = server_conn
+        .borrow_mut()
+        .stream_create(StreamType::UniDi)
+        .unwrap();
+    server_conn
+        .borrow_mut()
+        .stream_send(stream, &[1; 10])
+        .unwrap();
+    assert!(server.process_output(now()).dgram().is_some());
+}
+
+/// Start a handshake from a new client, returning the server's response to its first datagram.
+fn start_handshake(server: &mut Server) -> (Connection, Option<Datagram>) {
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    assert!(dgram.is_some());
+    let out = server.process(dgram, now()).dgram();
+    (client, out)
+}
+
+/// Once the handshake backlog reaches the threshold, new clients are sent a Retry.
+#[test]
+fn admission_retry_over_threshold() {
+    let mut server = default_server();
+    server.set_admission_policy(Some(Box::new(
+        HandshakeLimits::default().retry_threshold(1),
+    )));
+
+    _ = start_handshake(&mut server);
+    let (_client2, out) = start_handshake(&mut server);
+    assertions::assert_retry(out.as_ref().unwrap());
+
+    let stats = server.stats();
+    assert_eq!(stats.admission_accepted, 1);
+    assert_eq!(stats.admission_retried, 1);
+}
+
+/// Completed handshakes no longer count toward the backlog.
+#[test]
+fn admission_after_handshake() {
+    let mut server = default_server();
+    server.set_admission_policy(Some(Box::new(
+        HandshakeLimits::default().retry_threshold(1),
+    )));
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    _ = start_handshake(&mut server);
+    let stats = server.stats();
+    assert_eq!(stats.admission_accepted, 2);
+    assert_eq!(stats.admission_retried, 0);
+}
+
+/// A refused client is closed with `CONNECTION_REFUSED`.
+#[test]
+fn admission_refuse() {
+    let mut server = default_server();
+    server.set_admission_policy(Some(Box::new(
+        |_: &InitialDetails, _: SocketAddr, _: &ServerLoad| Admission::Refuse,
+    )));
+
+    let (mut client, out) = start_handshake(&mut server);
+    client.process_input(out.unwrap(), now());
+    assert!(matches!(
+        client.state(),
+        State::Draining { error: CloseReason::Transport(Error::Peer(code)), .. }
+            if *code == Error::ConnectionRefused.code()
+    ));
+    assert_eq!(server.stats().admission_refused, 1);
+}
+
+/// Initial packets from a source prefix are dropped once it has too many handshakes.
+#[test]
+fn admission_prefix_limit() {
+    let mut server = default_server();
+    server.set_admission_policy(Some(Box::new(
+        HandshakeLimits::default().prefix_limit(1, 24, 64),
+    )));
+
+    _ = start_handshake(&mut server);
+    let (_client2, out) = start_handshake(&mut server);
+    assert!(out.is_none());
+
+    let stats = server.stats();
+    assert_eq!(stats.admission_accepted, 1);
+    assert_eq!(stats.admission_dropped, 1);
+}
+
+/// Version Negotiation packets to a source are limited, if a limit is set.
+#[test]
+fn version_negotiation_limit() {
+    const VN_VERSION: Version = Version::Draft29;
+    let mut server =
+        new_server(ConnectionParameters::default().versions(VN_VERSION, vec![VN_VERSION]));
+    server.set_version_negotiation_limit(Some(1));
+    let mut client = default_client();
+
+    let dgram = client.process_output(now()).dgram().unwrap();
+    let vn = server.process(Some(dgram.clone()), now()).dgram();
+    assertions::assert_vn(vn.as_ref().unwrap());
+    assert!(server.process(Some(dgram.clone()), now()).dgram().is_none());
+    let later = now() + Duration::from_secs(1);
+    assert!(server.process(Some(dgram), later).dgram().is_some());
+
+    let stats = server.stats();
+    assert_eq!(stats.version_negotiations_tx, 2);
+    assert_eq!(stats.dropped[DropReason::VersionNegotiationLimit], 1);
+}
+
+/// Datagrams that the server drops are counted by reason.
+#[test]
+fn stats_dropped() {
+    const CID: &[u8] = &[55; 8];
+    let mut server = default_server();
+    assert!(
+        server
+            .process(Some(datagram(Vec::new())), now())
+            .dgram()
+            .is_none()
+    );
+
+    let mut header = Encoder::default();
+    header
+        .encode_byte(0xca)
+        .encode_uint(4, Version::default().wire_version())
+        .encode_vec(1, CID)
+        .encode_vec(1, CID);
+    let mut short_initial: Vec<u8> = header.into();
+    short_initial.resize(MIN_INITIAL_PACKET_SIZE - 1, 66);
+    assert!(
+        server
+            .process(Some(datagram(short_initial)), now())
+            .dgram()
+            .is_none()
+    );
+
+    let stats = server.stats();
+    assert_eq!(stats.dropped[DropReason::Malformed], 1);
+    assert_eq!(stats.dropped[DropReason::InitialTooShort], 1);
+    assert_eq!(stats.connections_created, 0);
+}
+
+/// Connections are counted when they are created and when they are removed.
+#[test]
+fn stats_connections() {
+    let mut server = default_server();
+    let mut client = default_client();
+    let sconn = connect(&mut client, &mut server);
+    assert_eq!(server.stats().connections_created, 1);
+    assert_eq!(server.stats().connections_closed, 0);
+
+    let mut now = now();
+    sconn.borrow_mut().close(now, 0, "");
+    for _ in 0..10 {
+        match server.process_output(now) {
+            Output::Datagram(_) => {}
+            Output::Callback(t) => now += t,
+            Output::None => break,
+        }
+    }
+    assert_eq!(server.stats().connections_closed, 1);
+}
+
+/// Only upgrade clients to a compatible version once their address is validated.
+fn upgrade_if_validated(context: &version::Context) -> Option<Version> {
+    (!context.validated()).then_some(context.initial())
+}
+
+#[test]
+fn version_policy_not_validated() {
+    let mut server = default_server();
+    server.set_version_policy(Some(Rc::new(RefCell::new(upgrade_if_validated))));
+    let mut client = default_client();
+    let sconn = connect(&mut client, &mut server);
+
+    assert_eq!(client.version(), Version::Version1);
+    assert_eq!(sconn.borrow().version(), Version::Version1);
+    assert_eq!(client.stats().version_negotiation, Some(Negotiation::None));
+    assert!(
+        sconn
+            .borrow_mut()
+            .events()
+            .any(|e| e == ConnectionEvent::VersionNegotiated(Negotiation::None))
+    );
+}
+
+/// A token from a `NEW_TOKEN` frame validates the client's address too.
+#[test]
+fn version_policy_new_token() {
+    let mut server = default_server();
+    let token = generate_ticket(&mut server);
+    server.set_validation(ValidateAddress::NoToken);
+    let validated = Rc::new(RefCell::new(None));
+    let v = Rc::clone(&validated);
+    server.set_version_policy(Some(Rc::new(RefCell::new(
+        move |context: &version::Context| {
+            *v.borrow_mut() = Some(context.validated());
+            None
+        },
+    ))));
+
+    let mut client = default_client();
+    client.enable_resumption(now(), &token).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    let dgram2 = client.process_output(now()).dgram();
+    _ = server.process(dgram, now());
+    let out = server.process(dgram2, now()).dgram();
+    assertions::assert_initial(out.as_ref().unwrap(), false);
+    assert_eq!(*validated.borrow(), Some(true));
+}
+
+#[test]
+fn version_policy_validated() {
+    let mut server = default_server();
+    server.set_version_policy(Some(Rc::new(RefCell::new(upgrade_if_validated))));
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+
+    let dgram = client.process_output(now()).dgram();
+    let dgram2 = client.process_output(now()).dgram();
+    _ = server.process(dgram, now()).dgram();
+    let retry = server.process(dgram2, now()).dgram();
+    assertions::assert_retry(retry.as_ref().unwrap());
+    let dgram = client.process(retry, now()).dgram();
+    let dgram2 = client.process_output(now()).dgram();
+    _ = server.process(dgram, now()).dgram();
+    let dgram = server.process(dgram2, now()).dgram();
+    let sconn = complete_connection(&mut client, &mut server, dgram);
+
+    assert_eq!(client.version(), Version::Version2);
+    assert_eq!(sconn.borrow().version(), Version::Version2);
+    assert_eq!(
+        sconn.borrow().stats().version_negotiation,
+        Some(Negotiation::Compatible)
+    );
+    assert!(
+        sconn
+            .borrow_mut()
+            .events()
+            .any(|e| e == ConnectionEvent::VersionNegotiated(Negotiation::Compatible))
+    );
+}
+
+/// A version policy can pin the version for a server name.
+#[test]
+fn version_policy_server_name() {
+    let mut server = default_server();
+    server.set_version_policy(Some(Rc::new(RefCell::new(|c: &version::Context| {
+        (c.server_name() == Some(test_fixture::DEFAULT_SERVER_NAME)).then_some(Version::Version1)
+    }))));
+    let mut client = default_client();
+    let sconn = connect(&mut client, &mut server);
+
+    assert_eq!(client.version(), Version::Version1);
+    assert_eq!(sconn.borrow().version(), Version::Version1);
+}
+
+/// A server that has no usable configuration of its own, and that serves `host`
+/// to clients that ask for the default server name.
+fn virtual_host_server(host: VirtualHost) -> Server {
+    let mut server = Server::new(
+        now(),
+        &["missing"],
+        &["other"],
+        test_fixture::anti_replay(),
+        Box::new(AllowZeroRtt {}),
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default())),
+        ConnectionParameters::default(),
+    )
+    .unwrap();
+    let host = Rc::new(host);
+    server.set_virtual_host_selector(Some(Box::new(move |sni: Option<&str>| {
+        (sni == Some(test_fixture::DEFAULT_SERVER_NAME)).then(|| Rc::clone(&host))
+    })));
+    server
+}
+
+fn default_virtual_host() -> VirtualHost {
+    VirtualHost::new(
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        ConnectionParameters::default().max_streams(StreamType::BiDi, 1),
+    )
+}
+
+/// The certificates, ALPN values and parameters of the chosen host are used.
+#[test]
+fn virtual_host() {
+    let mut server = virtual_host_server(default_virtual_host());
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    client.stream_create(StreamType::BiDi).unwrap();
+    assert_eq!(
+        client.stream_create(StreamType::BiDi).unwrap_err(),
+        Error::StreamLimit
+    );
+    assert_eq!(server.stats().connections_created, 1);
+}
+
+/// A `ClientHello` that spans two datagrams is read before the connection is created.
+#[test]
+fn virtual_host_split_client_hello() {
+    let mut server = virtual_host_server(default_virtual_host());
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    let dgram2 = client.process_output(now()).dgram();
+    assert!(server.process(dgram, now()).dgram().is_none());
+    assert_eq!(server.stats().connections_created, 0);
+    let out = server.process(dgram2, now()).dgram();
+    assert!(out.is_some());
+    assert_eq!(server.stats().connections_created, 1);
+    complete_connection(&mut client, &mut server, out);
+}
+
+/// A `ClientHello` that fits in one datagram doesn't need to wait.
+#[test]
+fn virtual_host_single_datagram() {
+    let mut server = virtual_host_server(default_virtual_host());
+    let mut client =
+        new_client::<CountingConnectionIdGenerator>(ConnectionParameters::default().mlkem(false));
+    let dgram = client.process_output(now()).dgram();
+    let out = server.process(dgram, now()).dgram();
+    assert!(out.is_some());
+    complete_connection(&mut client, &mut server, out);
+}
+
+/// A client is refused if no host is chosen for it.
+#[test]
+fn virtual_host_refused() {
+    let mut server = default_server();
+    server.set_virtual_host_selector(Some(Box::new(|_: Option<&str>| None)));
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    let dgram2 = client.process_output(now()).dgram();
+    _ = server.process(dgram, now());
+    let out = server.process(dgram2, now()).dgram();
+    client.process_input(out.unwrap(), now());
+    assert!(matches!(
+        client.state(),
+        State::Draining { error: CloseReason::Transport(Error::Peer(code)), .. }
+            if *code == Error::ConnectionRefused.code()
+    ));
+    assert_eq!(server.stats().virtual_host_refused, 1);
+}
+
+/// The start of a `ClientHello` is dropped if the rest doesn't arrive in time.
+#[test]
+fn virtual_host_timeout() {
+    let mut server = virtual_host_server(default_virtual_host());
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    assert!(server.process(dgram, now()).dgram().is_none());
+
+    _ = server.process_output(now() + Duration::from_secs(5));
+    let stats = server.stats();
+    assert_eq!(stats.dropped[DropReason::ClientHello], 1);
+    assert_eq!(stats.connections_created, 0);
+}
+
+/// Connections that are waiting for the rest of their `ClientHello` count
+/// toward handshake limits.
+#[test]
+fn virtual_host_pending_admission() {
+    let mut server = virtual_host_server(default_virtual_host());
+    server.set_admission_policy(Some(Box::new(
+        HandshakeLimits::default().prefix_limit(1, 32, 128),
+    )));
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    assert!(server.process(dgram, now()).dgram().is_none());
+
+    let mut client2 = default_client();
+    let dgram = client2.process_output(now()).dgram();
+    assert!(server.process(dgram, now()).dgram().is_none());
+    assert_eq!(server.stats().admission_dropped, 1);
+}
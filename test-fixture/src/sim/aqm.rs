@@ -132,7 +132,7 @@ pub(super) enum MarkResult {
     Dropped,
 }
 
-/// CE-mark an ECT(0) datagram in place; forward CE unchanged; drop if not ECT-capable.
+/// CE-mark an ECT datagram in place; forward CE unchanged; drop if not ECT-capable.
 fn mark_ce(mut dgram: Datagram) -> Option<Datagram> {
     let tos = dgram.tos();
     let ecn = Ecn::from(tos);
@@ -140,7 +140,6 @@ fn mark_ce(mut dgram: Datagram) -> Option<Datagram> {
         // Already marked; forwarding again is a no-op (RFC 3168 §5).
         Some(dgram)
     } else if ecn.is_ect() {
-        assert_ne!(ecn, Ecn::Ect1, "ECT(1)/L4S is not implemented");
         qtrace!("taildrop marking {} bytes CE", dgram.len());
         dgram.set_tos(Tos::from((Dscp::from(tos), Ecn::Ce)));
         Some(dgram)
@@ -152,7 +151,8 @@ fn mark_ce(mut dgram: Datagram) -> Option<Datagram> {
 
 /// Congestion-signalling mode for a [`TailDrop`](super::taildrop::TailDrop) queue.
 ///
-/// The inner state types are opaque; use [`Aqm::codel()`] and [`Aqm::red()`] to create instances.
+/// The inner state types are opaque; use [`Aqm::codel()`], [`Aqm::red()`] and [`Aqm::step()`] to
+/// create instances.
 #[derive(Clone, Default)]
 pub enum Aqm {
     /// No AQM; packets are dropped only on buffer overflow (pure tail-drop).
@@ -162,6 +162,10 @@ pub enum Aqm {
     CoDel(CodelState),
     /// RED (Random Early Detection) ECN marking; requires RNG initialisation via `Node::init`.
     Red(RedState),
+    /// Step marking of L4S traffic, as in the low-latency queue of a `DualQ` (RFC 9332): ECT(1)
+    /// packets are CE-marked whenever their sojourn time exceeds the threshold. Other packets
+    /// are only subject to tail-drop.
+    Step(Duration),
 }
 
 impl Aqm {
@@ -179,6 +183,12 @@ impl Aqm {
         Self::Red(RedState::default())
     }
 
+    /// Create a [`Step`](Aqm::Step) instance that marks above a sojourn time of `threshold`.
+    #[must_use]
+    pub const fn step(threshold: Duration) -> Self {
+        Self::Step(threshold)
+    }
+
     /// Wire up the RNG for [`Aqm::Red`]; no-op for other variants.
     pub(super) fn init_rng(&mut self, rng: Rng) {
         if let Self::Red(state) = self {
@@ -198,6 +208,7 @@ impl Aqm {
         let should_signal = match self {
             Self::CoDel(state) => state.update(sojourn, used == 0, now),
             Self::Red(state) => Ecn::from(pkt.tos()).is_ect() && state.should_mark(used, capacity),
+            Self::Step(threshold) => Ecn::from(pkt.tos()) == Ecn::Ect1 && sojourn > *threshold,
             Self::None => false,
         };
         if should_signal {
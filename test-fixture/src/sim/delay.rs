@@ -16,7 +16,7 @@ use std::{
 use neqo_common::Datagram;
 use neqo_transport::Output;
 
-use super::{Node, Rng};
+use super::{Node, Rng, reorder::Reorder};
 
 /// An iterator that shares a `Random` instance and produces uniformly
 /// random `Duration`s within a specified range.
@@ -104,6 +104,7 @@ impl Debug for RandomDelay {
 pub struct Delay {
     delay: Duration,
     queue: VecDeque<(Instant, Datagram)>,
+    reorder: Reorder,
 }
 
 impl Delay {
@@ -112,16 +113,27 @@ impl Delay {
         Self {
             delay,
             queue: VecDeque::default(),
+            reorder: Reorder::default(),
         }
     }
 
+    /// Hold back `pct` percent of datagrams for an additional `extra`,
+    /// so that they arrive out of order.
+    #[must_use]
+    pub fn reorder(mut self, pct: u8, extra: Duration) -> Self {
+        self.reorder = Reorder::new(pct, extra);
+        self
+    }
+
     fn insert(&mut self, d: Datagram, now: Instant) {
-        self.queue.push_back((now + self.delay, d));
+        self.reorder.insert(&mut self.queue, now + self.delay, d);
     }
 }
 
 impl Node for Delay {
-    fn init(&mut self, _rng: Rng, _now: Instant) {}
+    fn init(&mut self, rng: Rng, _now: Instant) {
+        self.reorder.init(rng);
+    }
 
     fn process(&mut self, d: Option<Datagram>, now: Instant) -> Output {
         if let Some(dgram) = d {
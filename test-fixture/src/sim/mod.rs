@@ -13,6 +13,7 @@ mod delay;
 mod drop;
 pub mod http3_connection;
 mod mtu;
+mod reorder;
 pub mod rng;
 mod taildrop;
 
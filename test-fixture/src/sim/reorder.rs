@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![expect(clippy::unwrap_used, reason = "This is test code.")]
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use neqo_common::{Datagram, qtrace};
+
+use super::Rng;
+
+/// Holds back some datagrams for a little longer than others, so that they are
+/// overtaken by datagrams that were sent after them.
+#[derive(Clone, Default)]
+pub struct Reorder {
+    /// Out of every 1000 datagrams, how many are held back.
+    threshold: u64,
+    /// How long datagrams are held back for.
+    extra: Duration,
+    rng: Option<Rng>,
+    /// The number of datagrams that were held back.
+    count: usize,
+}
+
+impl Reorder {
+    #[must_use]
+    pub fn new(pct: u8, extra: Duration) -> Self {
+        Self {
+            threshold: u64::from(pct) * 10,
+            extra,
+            rng: None,
+            count: 0,
+        }
+    }
+
+    pub fn init(&mut self, rng: Rng) {
+        self.rng = Some(rng);
+    }
+
+    /// The extra delay for the next datagram.
+    fn delay(&mut self) -> Duration {
+        if self.threshold == 0 {
+            return Duration::ZERO;
+        }
+        let r = self.rng.as_ref().unwrap().borrow_mut().random_from(0..1000);
+        if r < self.threshold {
+            self.count += 1;
+            self.extra
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Add `d` to `queue`, which is ordered by delivery time, to be delivered at `t`
+    /// unless this datagram is held back.
+    pub fn insert(&mut self, queue: &mut VecDeque<(Instant, Datagram)>, t: Instant, d: Datagram) {
+        let extra = self.delay();
+        if !extra.is_zero() {
+            qtrace!("reorder: holding back {} for {extra:?}", d.len());
+        }
+        let t = t + extra;
+        let idx = queue.partition_point(|(q, _)| *q <= t);
+        queue.insert(idx, (t, d));
+    }
+
+    #[must_use]
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+}
@@ -10,6 +10,7 @@ use std::{
     cmp::{max, min},
     collections::VecDeque,
     fmt::{self, Debug, Display},
+    rc::Rc,
     time::{Duration, Instant},
 };
 
@@ -19,6 +20,7 @@ use neqo_transport::Output;
 use super::{
     Node, Rng,
     aqm::{Aqm, MarkResult},
+    reorder::Reorder,
 };
 
 /// One second in nanoseconds.
@@ -122,6 +124,7 @@ pub struct TailDrop {
 
     stats: Stats,
     aqm: Aqm,
+    reorder: Reorder,
 }
 
 impl TailDrop {
@@ -153,9 +156,18 @@ impl TailDrop {
             on_link: VecDeque::new(),
             stats: Stats::default(),
             aqm,
+            reorder: Reorder::default(),
         }
     }
 
+    /// Hold back `pct` percent of datagrams on the link for an additional `extra`,
+    /// so that they arrive out of order.
+    #[must_use]
+    pub fn reorder(mut self, pct: u8, extra: Duration) -> Self {
+        self.reorder = Reorder::new(pct, extra);
+        self
+    }
+
     /// A tail drop queue on a 10Mbps link (approximated to 1 million bytes per second)
     /// with a fat 32k buffer (about 30ms), and the default forward delay of 50ms.
     #[must_use]
@@ -197,7 +209,7 @@ impl TailDrop {
         // Now work out when the packet is fully received at the other end of
         // the link. Setup to deliver the packet then.
         let delivery_time = deque_time + self.delay;
-        self.on_link.push_back((delivery_time, d));
+        self.reorder.insert(&mut self.on_link, delivery_time, d);
     }
 
     /// Enqueue for sending.  Maybe.  If this overflows the queue, drop it instead.
@@ -263,7 +275,8 @@ impl TailDrop {
 
 impl Node for TailDrop {
     fn init(&mut self, rng: Rng, _now: Instant) {
-        self.aqm.init_rng(rng);
+        self.aqm.init_rng(Rc::clone(&rng));
+        self.reorder.init(rng);
     }
 
     fn prepare(&mut self, now: Instant) {
@@ -295,7 +308,11 @@ impl Node for TailDrop {
     }
 
     fn print_summary(&self, test_name: &str) {
-        qinfo!("{test_name}: taildrop: {stats}", stats = self.stats);
+        qinfo!(
+            "{test_name}: taildrop: {stats} reordered {reordered}",
+            stats = self.stats,
+            reordered = self.reorder.count()
+        );
     }
 }
 
@@ -520,6 +537,43 @@ mod test {
         assert!(td.stats.delivered > 0);
     }
 
+    /// Step marking CE-marks ECT(1) packets that queue above the threshold and leaves
+    /// other packets alone.
+    #[test]
+    fn step_marks_only_l4s() {
+        // 100 kB/s link: each packet takes ~13ms to transmit, so only the first packets in a
+        // burst see a sojourn time below the threshold.
+        let threshold = Duration::from_millis(15);
+        let mut td = TailDrop::new(
+            100_000,
+            500_000,
+            Aqm::step(threshold),
+            Duration::from_millis(1),
+        );
+        let t0 = now();
+        td.prepare(t0);
+
+        for _ in 0..10 {
+            td.process(Some(make_datagram(Ecn::Ect0)), t0);
+        }
+        drain(&mut td, t0);
+        assert_eq!(
+            td.stats.marked, 0,
+            "classic ECT(0) traffic is not step-marked"
+        );
+
+        for _ in 0..10 {
+            td.process(Some(make_datagram(Ecn::Ect1)), t0);
+        }
+        drain(&mut td, t0);
+        assert_eq!(td.stats.dropped, 0);
+        assert!(td.stats.marked > 0);
+        assert!(
+            td.stats.marked < 10,
+            "packets below the threshold are not marked"
+        );
+    }
+
     /// Step `state` in 1 ms increments until `n` signals fire; return their timestamps.
     fn codel_marks(state: &mut CodelState, n: usize, t0: Instant) -> Vec<Instant> {
         let mut times = Vec::new();